@@ -0,0 +1,110 @@
+/*
+ * Sends a frame every 10 ms and prints its TX timestamp, or prints the frames it
+ * receives. Shows the C API of libtsn, see include/tsn.h
+ *
+ *   ./send_recv send <interface> <VLAN ID> [count]
+ *   ./send_recv recv <interface> <VLAN ID>
+ */
+
+#include <errno.h>
+#include <signal.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#include <tsn.h>
+
+#define ETHERTYPE_EXAMPLE 0x1337
+#define PERIOD_NS 10000000L
+
+static volatile sig_atomic_t running = 1;
+
+static void stop(int signum) {
+    (void)signum;
+    running = 0;
+}
+
+static void fail(const char* what) {
+    fprintf(stderr, "%s: %s (%s)\n", what, strerror(errno), tsn_last_error());
+    exit(1);
+}
+
+static void do_send(int sock, long count) {
+    uint8_t frame[64] = {0};
+    struct timespec next;
+    struct timespec ts;
+    uint32_t id;
+
+    if (tsn_enable_timestamps(sock, NULL) < 0 && errno != EOPNOTSUPP) {
+        fail("tsn_enable_timestamps");
+    }
+    memset(frame, 0xff, 6); /* broadcast */
+    frame[12] = ETHERTYPE_EXAMPLE >> 8;
+    frame[13] = ETHERTYPE_EXAMPLE & 0xff;
+
+    tsn_time_analyze();
+    clock_gettime(CLOCK_REALTIME, &next);
+    for (long i = 0; running && i < count; i++) {
+        next.tv_nsec = (next.tv_nsec / PERIOD_NS + 1) * PERIOD_NS;
+        if (next.tv_nsec >= 1000000000L) {
+            next.tv_sec += 1;
+            next.tv_nsec -= 1000000000L;
+        }
+        tsn_time_sleep_until(&next);
+
+        memcpy(frame + 14, &i, sizeof(i));
+        if (tsn_send(sock, frame, sizeof(frame)) < 0) {
+            fail("tsn_send");
+        }
+        if (tsn_get_tx_timestamp(sock, &ts, &id) < 0) {
+            fprintf(stderr, "No TX timestamp of frame %ld: %s\n", i, tsn_last_error());
+            continue;
+        }
+        printf("%u: %ld.%09ld\n", id, (long)ts.tv_sec, ts.tv_nsec);
+    }
+}
+
+static void do_recv(int sock) {
+    uint8_t frame[1514];
+    struct timeval timeout = {.tv_sec = 1, .tv_usec = 0};
+
+    /* Time out now and then to notice SIGINT */
+    if (tsn_sock_set_timeout(sock, &timeout) < 0) {
+        fail("tsn_sock_set_timeout");
+    }
+    while (running) {
+        ssize_t len = tsn_recv(sock, frame, sizeof(frame));
+        if (len < 0) {
+            if (errno == EAGAIN || errno == EINTR) {
+                continue;
+            }
+            fail("tsn_recv");
+        }
+        printf("%zd bytes from %02x:%02x:%02x:%02x:%02x:%02x\n", len, frame[6], frame[7],
+               frame[8], frame[9], frame[10], frame[11]);
+    }
+}
+
+int main(int argc, char** argv) {
+    if (argc < 4) {
+        fprintf(stderr, "Usage: %s send|recv <interface> <VLAN ID> [count]\n", argv[0]);
+        return 1;
+    }
+    int send = strcmp(argv[1], "send") == 0;
+    long count = argc > 4 ? strtol(argv[4], NULL, 10) : 100;
+
+    signal(SIGINT, stop);
+    int sock = tsn_sock_open(argv[2], (uint16_t)atoi(argv[3]), 3, ETHERTYPE_EXAMPLE);
+    if (sock < 0) {
+        fail("tsn_sock_open");
+    }
+    if (send) {
+        do_send(sock, count);
+    } else {
+        do_recv(sock);
+    }
+    if (tsn_sock_close(sock) < 0) {
+        fail("tsn_sock_close");
+    }
+    return 0;
+}
@@ -0,0 +1,161 @@
+/* Generated by cbindgen from src/capi.rs, do not edit */
+
+#ifndef TSN_H
+#define TSN_H
+
+#include <stdint.h>
+#include <stddef.h>
+#include <sys/types.h>
+#include <sys/socket.h>
+#include <sys/time.h>
+#include <sys/uio.h>
+#include <time.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Open a socket on the VLAN `vlanid` of `ifname`, creating the VLAN if needed.
+ *
+ * The interface is set up from the config file at `CONFIG_PATH`, `./config.yaml` by
+ * default. Returns the fd of the socket.
+ *
+ * # Safety
+ *
+ * `ifname` must be a NUL terminated string.
+ */
+int tsn_sock_open(const char *ifname, uint16_t vlanid, uint32_t priority, uint16_t proto);
+
+/**
+ * Close `sock` and release its VLAN
+ */
+int tsn_sock_close(int sock);
+
+/**
+ * Set the receive timeout of `sock`. A zero timeout blocks forever
+ *
+ * # Safety
+ *
+ * `timeout` must point to a `struct timeval`.
+ */
+int tsn_sock_set_timeout(int sock, const struct timeval *timeout);
+
+/**
+ * Send the frame of `n` bytes at `buf`. Returns the number of bytes sent
+ *
+ * # Safety
+ *
+ * `buf` must point to `n` readable bytes.
+ */
+ssize_t tsn_send(int sock, const void *buf, size_t n);
+
+/**
+ * Receive a frame into the `n` bytes at `buf`. Returns its length
+ *
+ * # Safety
+ *
+ * `buf` must point to `n` writable bytes.
+ */
+ssize_t tsn_recv(int sock, void *buf, size_t n);
+
+/**
+ * `recvmsg` on `sock`, e.g. to read the RX timestamp from the control messages
+ *
+ * # Safety
+ *
+ * `msg` must point to a `struct msghdr` set up for `recvmsg`.
+ */
+ssize_t tsn_recv_msg(int sock, struct msghdr *msg);
+
+/**
+ * Enable TX and RX timestamps. Pass the `iov` frames are received into for RX
+ * timestamps through [`tsn_recv_msg`], or NULL for TX timestamps only.
+ *
+ * Fails with `EOPNOTSUPP` if the NIC cannot timestamp, software timestamps are still
+ * enabled then.
+ *
+ * # Safety
+ *
+ * `iov` must be NULL or point to a `struct iovec`.
+ */
+int tsn_enable_timestamps(int sock, struct iovec *iov);
+
+/**
+ * Store the hardware timestamp config of the interface as
+ * [`tsn_enable_timestamps`] left it: `HWTSTAMP_TX_*` in `tx_type` and
+ * `HWTSTAMP_FILTER_*` in `rx_filter`. It may be wider than the socket asked for, as
+ * it is shared with other sockets and ptp4l. Fails with `ENODATA` before hardware
+ * timestamps are enabled.
+ *
+ * # Safety
+ *
+ * `tx_type` and `rx_filter` must be NULL or point to a `uint32_t`.
+ */
+int tsn_hw_timestamp_config(int sock, uint32_t *tx_type, uint32_t *rx_filter);
+
+/**
+ * Wait up to 1 second for the next TX timestamp and store it in `ts`. `id`, if not
+ * NULL, gets the number of the frame it belongs to, counted from 0 since timestamps
+ * were enabled
+ *
+ * # Safety
+ *
+ * `ts` must point to a `struct timespec`, `id` must be NULL or point to a `uint32_t`.
+ */
+int tsn_get_tx_timestamp(int sock, struct timespec *ts, uint32_t *id);
+
+/**
+ * Measure the errors of the clock and of sleeping, used by [`tsn_time_sleep_until`]
+ */
+void tsn_time_analyze(void);
+
+/**
+ * Sleep until `realtime` of `CLOCK_REALTIME`, spinning for the last part
+ *
+ * # Safety
+ *
+ * `realtime` must point to a `struct timespec`.
+ */
+int tsn_time_sleep_until(const struct timespec *realtime);
+
+/**
+ * Read `clock`: `CLOCK_REALTIME`, `CLOCK_MONOTONIC`, `CLOCK_TAI` or a PHC as
+ * `FD_TO_CLOCKID(fd)`
+ *
+ * # Safety
+ *
+ * `ts` must point to a `struct timespec`.
+ */
+int tsn_time_now(clockid_t clock, struct timespec *ts);
+
+/**
+ * Sleep until `deadline` of `clock`, spinning for the last part. Clocks as
+ * [`tsn_time_now`]
+ *
+ * # Safety
+ *
+ * `deadline` must point to a `struct timespec`.
+ */
+int tsn_time_sleep_until_clock(clockid_t clock, const struct timespec *deadline);
+
+/**
+ * Convert the UTC time `utc` of `CLOCK_REALTIME` to TAI, counting a pending leap
+ * second
+ *
+ * # Safety
+ *
+ * `utc` and `tai` must point to a `struct timespec`.
+ */
+int tsn_time_utc_to_tai(const struct timespec *utc, struct timespec *tai);
+
+/**
+ * Description of the last error of the calling thread. Valid until its next failure
+ */
+const char *tsn_last_error(void);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* TSN_H */
@@ -0,0 +1,95 @@
+"""Tests of the bindings on the virtual wire, which needs neither root nor a NIC.
+
+Run after `maturin develop --features python`: `python -m unittest discover python/tests`
+"""
+
+import errno
+import os
+import tempfile
+import unittest
+
+import tsn
+
+ETH_P_TEST = 0x1337
+
+
+def frame(payload: bytes) -> bytes:
+    return b'\xff' * 6 + b'\x02\x00\x00\x00\x00\x01' + ETH_P_TEST.to_bytes(2, 'big') + payload
+
+
+class SocketTest(unittest.TestCase):
+    def open(self, name):
+        return tsn.TsnSocket(f'{name}-{os.getpid()}', proto=ETH_P_TEST, backend='virtual', timeout=0.2)
+
+    def test_send_recv(self):
+        with self.open('py-send-recv') as a, self.open('py-send-recv') as b:
+            self.assertEqual(a.send(frame(b'hello')), 19)
+            self.assertEqual(b.recv(), frame(b'hello'))
+            self.assertEqual(len(a.mac_address()), 6)
+
+    def test_send_to_recv_from(self):
+        with self.open('py-send-to') as a, self.open('py-send-to') as b:
+            self.assertEqual(a.send_to(b'\xff' * 6, ETH_P_TEST, b'\x00' * 46), 60)
+            data, (src, ethertype, pkttype, vlan) = b.recv_from()
+            self.assertEqual(data[14:], b'\x00' * 46)
+            self.assertEqual((src, ethertype, pkttype, vlan), (a.mac_address(), ETH_P_TEST, 'broadcast', None))
+
+    def test_timestamps(self):
+        with self.open('py-timestamps') as a, self.open('py-timestamps') as b:
+            # Software timestamps only, as on a NIC without hardware ones
+            with self.assertRaises(tsn.TsnError) as cm:
+                a.enable_timestamps()
+            self.assertEqual(cm.exception.errno, errno.EOPNOTSUPP)
+            a.send(frame(b'1'))
+            tx_id, tx_ns, source = a.get_tx_timestamp()
+            self.assertEqual((tx_id, source), (0, 'sw'))
+            data, (rx_ns, _) = b.recv_with_timestamp()
+            self.assertEqual(data, frame(b'1'))
+            self.assertGreaterEqual(rx_ns, tx_ns)
+
+    def test_errors(self):
+        sock = self.open('py-errors')
+        with self.assertRaises(tsn.TsnError) as cm:
+            sock.recv()
+        self.assertEqual(cm.exception.errno, errno.EAGAIN)
+        sock.close()
+        with self.assertRaises(tsn.TsnError) as cm:
+            sock.send(frame(b''))
+        self.assertEqual(cm.exception.errno, errno.EBADF)
+        with self.assertRaises(ValueError):
+            tsn.TsnSocket('eth0', backend='nope')
+
+
+class ConfigTest(unittest.TestCase):
+    def read(self, content):
+        with tempfile.NamedTemporaryFile('w', suffix='.yaml') as f:
+            f.write(content)
+            f.flush()
+            return tsn.read_config(f.name)
+
+    def test_tas(self):
+        configs = self.read('''
+nics:
+  eth1:
+    tas:
+      schedule:
+        - time: 300us
+          prio: [5]
+        - time: 700us
+          prio: [-1]
+''')
+        config = configs['eth1']
+        self.assertTrue(config.has_tas)
+        self.assertFalse(config.has_cbs)
+        commands = config.commands('eth1', 10)
+        self.assertTrue(commands[0].startswith('ip link add link eth1 name eth1.10 type vlan id 10'))
+        self.assertTrue(any('taprio' in cmd for cmd in commands))
+
+    def test_invalid(self):
+        with self.assertRaises(tsn.TsnError) as cm:
+            self.read('interfaces: {}')
+        self.assertEqual(cm.exception.errno, errno.EINVAL)
+
+
+if __name__ == '__main__':
+    unittest.main()
@@ -0,0 +1,33 @@
+"""TSN sockets and VLAN setup, with the code path and config schema of the Rust library.
+
+Errors are raised as `TsnError`, an `OSError` with the errno of the C API.
+"""
+
+from ._tsn import (
+    Config,
+    TsnError,
+    TsnSocket,
+    VlanHold,
+    create_vlan,
+    delete_vlan,
+    gc_vlans,
+    read_config,
+    setup_cbs,
+    setup_tas,
+)
+from .env import environment, run
+
+__all__ = [
+    'Config',
+    'TsnError',
+    'TsnSocket',
+    'VlanHold',
+    'create_vlan',
+    'delete_vlan',
+    'environment',
+    'gc_vlans',
+    'read_config',
+    'run',
+    'setup_cbs',
+    'setup_tas',
+]
@@ -0,0 +1,53 @@
+"""Run a command with TSN VLANs set up: `python -m tsn -v eth1.10 -- ./app`."""
+
+import argparse
+import logging
+import sys
+
+from . import env
+from ._tsn import TsnError
+
+
+def vlan(arg: str):
+    ifname, _, vlanid = arg.rpartition('.')
+    if not ifname or not vlanid.isdigit():
+        raise argparse.ArgumentTypeError(f'{arg} is not <interface>.<VLAN ID>')
+    return ifname, int(vlanid)
+
+
+def main():
+    parser = argparse.ArgumentParser(prog='tsn-run')
+    parser.add_argument(
+        '-c', '--config',
+        default='config.yaml',
+        help='YAML config filename')
+    parser.add_argument(
+        '-v', '--vlan',
+        action='append', type=vlan, default=[],
+        help='VLAN to set up, as <interface>.<VLAN ID>. Can be given multiple times')
+    parser.add_argument(
+        '--log-level',
+        default='INFO',
+        help='Log level, INFO by default')
+    parser.add_argument(
+        'command', nargs='+',
+        help='Command to execute. prepend -- before command to prevent problems')
+
+    parsed = parser.parse_args()
+
+    handler = logging.StreamHandler()
+    handler.formatter = logging.Formatter(
+        '%(asctime)s:%(name)s:%(levelname)s: %(message)s',
+        datefmt='%Y-%m-%d %H:%M:%S %z')
+    env.logger.addHandler(handler)
+    env.logger.setLevel(parsed.log_level)
+
+    try:
+        return env.run(parsed.command, parsed.vlan, parsed.config)
+    except TsnError as e:
+        env.logger.error(e)
+        return 1
+
+
+if __name__ == '__main__':
+    sys.exit(main())
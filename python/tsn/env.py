@@ -0,0 +1,40 @@
+"""Set up VLANs, run a command on them, clean up."""
+
+import logging
+import os
+import subprocess
+
+from contextlib import ExitStack, contextmanager
+from typing import Iterable, Iterator, List, Sequence, Tuple
+
+from ._tsn import VlanHold
+
+logger = logging.getLogger('TSN')
+
+
+@contextmanager
+def environment(vlans: Iterable[Tuple[str, int]], config: str = 'config.yaml') -> Iterator[List[VlanHold]]:
+    """Hold the VLANs, given as `(ifname, vlanid)`, with the qdiscs of `config` until the block exits.
+
+    The holds are counted with those of sockets, so the VLANs stay while the block runs and are deleted
+    after the last socket on them closed.
+    """
+    with ExitStack() as stack:
+        holds = []
+        for ifname, vlanid in vlans:
+            hold = stack.enter_context(VlanHold(ifname, vlanid, config))
+            logger.info(f'Holding {hold.name}')
+            holds.append(hold)
+        stack.callback(logger.info, 'Cleaning up environments')
+        yield holds
+
+
+def run(command: Sequence[str], vlans: Iterable[Tuple[str, int]], config: str = 'config.yaml') -> int:
+    """Run `command` in the `environment` of `vlans`. Returns its exit code.
+
+    `CONFIG_PATH` is set to `config` for the command, so its sockets are set up the same way.
+    """
+    with environment(vlans, config):
+        logger.info('Starting application')
+        env = dict(os.environ, CONFIG_PATH=config)
+        return subprocess.call(command, env=env)
@@ -0,0 +1,171 @@
+//! tokio integration of [`TsnSocket`]
+//!
+//! The socket is switched to non-blocking mode and registered with the reactor, so
+//! sends and receives wait for readiness instead of blocking a runtime thread. TX
+//! timestamps are read from the error queue when it signals `POLLPRI`/`POLLERR`.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures_core::Stream;
+use nix::libc;
+use tokio::io::unix::AsyncFd;
+use tokio::io::{Interest, Ready};
+
+use crate::{RxTimestamp, TsnError, TsnSocket, TxTimestamp};
+
+/// [`TsnSocket`] driven by the tokio reactor
+pub struct AsyncTsnSocket {
+    inner: AsyncFd<TsnSocket>,
+}
+
+impl AsyncTsnSocket {
+    /// Register `sock` with the current tokio runtime.
+    ///
+    /// Only plain `AF_PACKET` sockets are supported: ring and XDP sockets are not
+    /// driven through the socket queue, and the virtual wire delays frames itself.
+    pub fn new(sock: TsnSocket) -> Result<AsyncTsnSocket, TsnError> {
+        sock.check_no_ring(true, "AsyncTsnSocket")?;
+        sock.check_no_ring(false, "AsyncTsnSocket")?;
+        sock.check_not_xdp("AsyncTsnSocket")?;
+        sock.check_not_wire("AsyncTsnSocket")?;
+        set_nonblocking(sock.fd, true)?;
+
+        let interest = Interest::READABLE | Interest::WRITABLE | Interest::PRIORITY;
+        // SAFETY: the fd is only closed with the `TsnSocket`, after it leaves the `AsyncFd`
+        let inner = unsafe { AsyncFd::register_with_interest(sock, interest) }.map_err(|e| {
+            TsnError::Socket {
+                op: "Register",
+                source: e.into(),
+            }
+        })?;
+        Ok(AsyncTsnSocket { inner })
+    }
+
+    pub fn get_ref(&self) -> &TsnSocket {
+        self.inner.get_ref()
+    }
+
+    /// Deregister the socket and put it back in blocking mode
+    pub fn into_inner(self) -> Result<TsnSocket, TsnError> {
+        let sock = self.inner.into_inner();
+        set_nonblocking(sock.fd, false)?;
+        Ok(sock)
+    }
+
+    pub async fn send(&self, buf: &[u8]) -> Result<isize, TsnError> {
+        self.io(Interest::WRITABLE, |sock| crate::send(sock, buf))
+            .await
+    }
+
+    pub async fn recv(&self, buf: &mut [u8]) -> Result<isize, TsnError> {
+        self.io(Interest::READABLE, |sock| crate::recv(sock, buf))
+            .await
+    }
+
+    pub async fn recv_with_timestamp(
+        &self,
+        buf: &mut [u8],
+    ) -> Result<(usize, RxTimestamp), TsnError> {
+        self.io(Interest::READABLE, |sock| {
+            crate::recv_with_timestamp(sock, buf)
+        })
+        .await
+    }
+
+    /// Wait for the next TX timestamp on the error queue.
+    ///
+    /// Needs [`enable_timestamps`](crate::enable_timestamps). Unlike
+    /// [`get_tx_timestamp`](crate::get_tx_timestamp) this never times out.
+    pub async fn next_tx_timestamp(&self) -> Result<TxTimestamp, TsnError> {
+        if !self.get_ref().tx_timestamp_enabled {
+            return Err(TsnError::TimestampNotEnabled);
+        }
+        loop {
+            // Messages may already be queued before the reactor saw any event
+            if let Some(ts) = crate::wait_tx_timestamp(self.get_ref(), 0)? {
+                return Ok(ts);
+            }
+            let mut guard = self
+                .inner
+                .ready(Interest::PRIORITY | Interest::ERROR)
+                .await
+                .map_err(|source| TsnError::Socket { op: "Poll", source })?;
+            match crate::wait_tx_timestamp(guard.get_inner(), 0)? {
+                Some(ts) => return Ok(ts),
+                None => guard.clear_ready_matching(Ready::PRIORITY | Ready::ERROR),
+            }
+        }
+    }
+
+    /// Stream of TX timestamps, see [`next_tx_timestamp`](Self::next_tx_timestamp)
+    pub fn tx_timestamps(&self) -> TxTimestamps<'_> {
+        TxTimestamps {
+            sock: self,
+            next: None,
+        }
+    }
+
+    pub fn close(self) -> Result<(), TsnError> {
+        crate::sock_close(&mut self.inner.into_inner())
+    }
+
+    /// Run `f` until it stops failing with `EAGAIN`, waiting for `interest` in between
+    async fn io<R>(
+        &self,
+        interest: Interest,
+        mut f: impl FnMut(&TsnSocket) -> Result<R, TsnError>,
+    ) -> Result<R, TsnError> {
+        loop {
+            let mut guard = self
+                .inner
+                .ready(interest)
+                .await
+                .map_err(|source| TsnError::Socket { op: "Poll", source })?;
+            match f(guard.get_inner()) {
+                Err(e) if e.raw_os_error() == Some(libc::EAGAIN) => guard.clear_ready(),
+                res => return res,
+            }
+        }
+    }
+}
+
+type TxTimestampFuture<'a> =
+    Pin<Box<dyn Future<Output = Result<TxTimestamp, TsnError>> + Send + 'a>>;
+
+/// Returned by [`AsyncTsnSocket::tx_timestamps`]. Never ends on its own
+pub struct TxTimestamps<'a> {
+    sock: &'a AsyncTsnSocket,
+    next: Option<TxTimestampFuture<'a>>,
+}
+
+impl<'a> Stream for TxTimestamps<'a> {
+    type Item = Result<TxTimestamp, TsnError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let sock = self.sock;
+        let next = self
+            .next
+            .get_or_insert_with(|| Box::pin(sock.next_tx_timestamp()));
+        let res = std::task::ready!(next.as_mut().poll(cx));
+        self.next = None;
+        Poll::Ready(Some(res))
+    }
+}
+
+fn set_nonblocking(fd: i32, nonblocking: bool) -> Result<(), TsnError> {
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+    if flags < 0 {
+        return Err(TsnError::socket("Get flags"));
+    }
+    let flags = if nonblocking {
+        flags | libc::O_NONBLOCK
+    } else {
+        flags & !libc::O_NONBLOCK
+    };
+    if unsafe { libc::fcntl(fd, libc::F_SETFL, flags) } < 0 {
+        return Err(TsnError::socket("Set flags"));
+    }
+    Ok(())
+}
@@ -0,0 +1,17 @@
+//! Parts of the latency and throughput tools which are the same in both
+
+use pnet::util::MacAddr;
+use tsn::filter::Filter;
+
+pub const ETHERTYPE_PERF: u16 = 0x1337;
+
+/// Let only perf frames reach the socket, only those of `peer` if it is unicast
+pub fn attach_perf_filter(sock: &tsn::TsnSocket, peer: Option<MacAddr>) {
+    let mut filter = Filter::new().ethertype(ETHERTYPE_PERF);
+    if let Some(peer) = peer.filter(|peer| !peer.is_multicast()) {
+        filter = filter.src(peer.octets());
+    }
+    if let Err(e) = sock.attach_filter(&filter) {
+        eprintln!("Failed to attach socket filter: {}", e);
+    }
+}
@@ -0,0 +1,125 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use clap::{arg, crate_authors, crate_version, value_parser, Command};
+use signal_hook::{consts::SIGINT, iterator::Signals};
+
+use tsn::gptp::servo::ServoAction;
+use tsn::gptp::{self, Event, GptpConfig, LocalClock, Port};
+use tsn::TsnSocket;
+
+// AF_XDP has no TX timestamps
+const BACKENDS: [&str; 2] = ["packet", "virtual"];
+
+static RUNNING: AtomicBool = AtomicBool::new(true);
+
+fn main() {
+    let defaults = GptpConfig::default();
+    let matches = Command::new("tsn-gptp")
+        .about("gPTP (IEEE 802.1AS) end station")
+        .author(crate_authors!())
+        .version(crate_version!())
+        .arg(
+            arg!(-i --interface <interface> "Interface to use")
+                .value_parser(value_parser!(String))
+                .required(true),
+        )
+        .arg(
+            arg!(--priority1 <priority> "priority1 of the BMCA, lower wins")
+                .value_parser(value_parser!(u8))
+                .default_value("248")
+                .required(false),
+        )
+        .arg(
+            arg!(--priority2 <priority> "priority2 of the BMCA, lower wins")
+                .value_parser(value_parser!(u8))
+                .default_value("248")
+                .required(false),
+        )
+        .arg(arg!(--"slave-only" "Never become grandmaster").required(false))
+        .arg(arg!(--"free-running" "Do not adjust the PHC").required(false))
+        .arg(
+            arg!(--"neighbor-prop-delay-thresh" <ns> "Largest link delay of a gPTP neighbor, raise it for software timestamps")
+                .value_parser(value_parser!(f64))
+                .default_value("800")
+                .required(false),
+        )
+        .arg(
+            arg!(--backend <backend> "Socket backend")
+                .value_parser(BACKENDS)
+                .default_value("packet")
+                .required(false),
+        )
+        .get_matches();
+
+    let interface = matches.get_one::<String>("interface").unwrap();
+    let backend = match matches.get_one::<String>("backend").unwrap().as_str() {
+        "virtual" => tsn::Backend::Virtual(tsn::wire::WireConfig::default()),
+        _ => tsn::Backend::Packet,
+    };
+    let config = GptpConfig {
+        priority1: *matches.get_one("priority1").unwrap(),
+        priority2: *matches.get_one("priority2").unwrap(),
+        gm_capable: !matches.is_present("slave-only"),
+        free_running: matches.is_present("free-running"),
+        neighbor_prop_delay_thresh: *matches.get_one("neighbor-prop-delay-thresh").unwrap(),
+        ..defaults
+    };
+
+    // gPTP is untagged, no VLAN or qdisc to set up
+    let sock = match TsnSocket::builder(interface)
+        .protocol(gptp::ETHERTYPE)
+        .backend(backend)
+        .open()
+    {
+        Ok(sock) => sock,
+        Err(e) => panic!("Failed to open TSN socket: {}", e),
+    };
+    let mut port = match Port::new(sock, config) {
+        Ok(port) => port,
+        Err(e) => panic!("Failed to start gPTP: {}", e),
+    };
+    match port.clock() {
+        LocalClock::Phc(phc) => eprintln!("Clock: {} (HW timestamps)", phc.path()),
+        LocalClock::System => eprintln!("Clock: system clock (SW timestamps), not adjusted"),
+    }
+    eprintln!("Clock identity: {}", port.identity().clock);
+
+    let mut signals = Signals::new([SIGINT]).unwrap();
+    thread::spawn(move || {
+        for _ in signals.forever() {
+            RUNNING.store(false, Ordering::Relaxed);
+        }
+    });
+
+    let mut path_delay = 0.0;
+    while RUNNING.load(Ordering::Relaxed) {
+        let event = match port.poll(Duration::from_millis(100)) {
+            Ok(Some(event)) => event,
+            Ok(None) => continue,
+            Err(e) => panic!("gPTP failed: {}", e),
+        };
+        match event {
+            Event::State { state, grandmaster } => match grandmaster {
+                Some(grandmaster) => println!("port state {}, grandmaster {}", state, grandmaster),
+                None => println!("port state {}", state),
+            },
+            Event::AsCapable(as_capable) => println!("asCapable {}", as_capable),
+            Event::PathDelay {
+                mean_link_delay, ..
+            } => path_delay = mean_link_delay,
+            Event::Offset { offset, action } => {
+                let action = match action {
+                    Some(ServoAction::Frequency(ppb)) => format!("freq {:+7.0} ppb", ppb),
+                    Some(ServoAction::Step(ns)) => format!("step {} ns", ns),
+                    None => "free running".to_string(),
+                };
+                println!(
+                    "master offset {:9} ns {} path delay {:9.0} ns",
+                    offset, action, path_delay
+                );
+            }
+        }
+    }
+}
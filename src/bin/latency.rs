@@ -2,6 +2,8 @@
 // It does not cause any issues, so we can ignore it
 #![allow(unexpected_cfgs)]
 
+mod common;
+
 use std::collections::HashMap;
 use std::option::Option;
 use std::thread;
@@ -19,18 +21,22 @@ use pnet_macros::packet;
 use pnet_macros_support::types::u32be;
 use pnet_packet::{MutablePacket, Packet};
 
-use pnet::datalink::{self, NetworkInterface};
-use pnet::packet::ethernet::{EtherType, EthernetPacket, MutableEthernetPacket};
+use pnet::packet::ethernet::{EtherType, MutableEthernetPacket};
 use pnet::util::MacAddr;
+use tsn::pmc::{PmcClient, SyncStatus};
 use tsn::time::tsn_time_sleep_until;
 
+use common::{attach_perf_filter, ETHERTYPE_PERF};
+
 extern crate socket as soc;
 
-const ETHERTYPE_PERF: u16 = 0x1337;
-// const ETH_P_PERF: u16 = libc::ETH_P_ALL as u16; // FIXME: use ETHERTYPE_PERF
-const ETH_P_PERF: u16 = ETHERTYPE_PERF;
 const TIMEOUT_SEC: u64 = 1;
 
+#[cfg(feature = "xdp")]
+const BACKENDS: [&str; 4] = ["packet", "xdp", "xdp-generic", "virtual"];
+#[cfg(not(feature = "xdp"))]
+const BACKENDS: [&str; 2] = ["packet", "virtual"];
+
 static mut RUNNING: bool = false;
 
 /// Packet format for Perf tool
@@ -64,6 +70,7 @@ struct ServerArgs {
     vlan_id: u16,
     vlan_pri: u32,
     tstamp: TstampMode,
+    backend: tsn::Backend,
 }
 
 struct ClientArgs {
@@ -78,6 +85,7 @@ struct ClientArgs {
     oneway: bool,
     precise: bool,
     tstamp: TstampMode,
+    backend: tsn::Backend,
 }
 
 fn main() {
@@ -105,6 +113,12 @@ fn main() {
                 .value_parser(["hw", "sw"])
                 .default_value("hw")
                 .required(false),
+        )
+        .arg(
+            arg!(--backend <backend> "Socket backend")
+                .value_parser(BACKENDS)
+                .default_value("packet")
+                .required(false),
         );
 
     let client_command = Command::new("client")
@@ -164,6 +178,12 @@ fn main() {
                 .value_parser(["hw", "sw"])
                 .default_value("hw")
                 .required(false),
+        )
+        .arg(
+            arg!(--backend <backend> "Socket backend")
+                .value_parser(BACKENDS)
+                .default_value("packet")
+                .required(false),
         );
 
     let matched_command = Command::new("latency")
@@ -188,6 +208,7 @@ fn main() {
                     Some("sw") => TstampMode::Sw,
                     _ => TstampMode::Hw,
                 },
+                backend: parse_backend(sub_matches.get_one::<String>("backend").unwrap()),
             };
 
             do_server(server_args)
@@ -211,6 +232,7 @@ fn main() {
                     Some("sw") => TstampMode::Sw,
                     _ => TstampMode::Hw,
                 },
+                backend: parse_backend(sub_matches.get_one::<String>("backend").unwrap()),
             };
 
             do_client(client_args)
@@ -219,16 +241,29 @@ fn main() {
     }
 }
 
-fn do_server(args: ServerArgs) {
-    let interface_name_match = |iface: &NetworkInterface| iface.name == args.interface;
-    let interfaces = datalink::interfaces();
-    let interface = interfaces.into_iter().find(interface_name_match).unwrap();
-    let my_mac = interface.mac.unwrap();
+fn parse_backend(name: &str) -> tsn::Backend {
+    match name {
+        #[cfg(feature = "xdp")]
+        "xdp" => tsn::Backend::Xdp(tsn::xdp::XdpConfig::new(tsn::xdp::XdpMode::Native)),
+        #[cfg(feature = "xdp")]
+        "xdp-generic" => tsn::Backend::Xdp(tsn::xdp::XdpConfig::new(tsn::xdp::XdpMode::Generic)),
+        "virtual" => tsn::Backend::Virtual(tsn::wire::WireConfig::default()),
+        _ => tsn::Backend::Packet,
+    }
+}
 
-    let mut sock = match tsn::sock_open(&args.interface, args.vlan_id, args.vlan_pri, ETH_P_PERF) {
+fn do_server(args: ServerArgs) {
+    let mut sock = match tsn::sock_open_with(
+        &args.interface,
+        args.vlan_id,
+        args.vlan_pri,
+        ETHERTYPE_PERF,
+        args.backend,
+    ) {
         Ok(sock) => sock,
         Err(e) => panic!("Failed to open TSN socket: {}", e),
     };
+    attach_perf_filter(&sock, None);
 
     if let Err(e) = sock.set_timeout(Duration::from_secs(TIMEOUT_SEC)) {
         panic!("Failed to set timeout: {}", e)
@@ -256,6 +291,9 @@ fn do_server(args: ServerArgs) {
         match sock.enable_timestamps(Some(&mut iov)) {
             Ok(()) => {
                 eprintln!("Socket RX timestamp enabled (HW)");
+                if let Some(config) = sock.hw_timestamp_config() {
+                    eprintln!("NIC timestamps: {}", config);
+                }
             }
             Err(e) => {
                 eprintln!("Failed to set sock timestamp: {}", e);
@@ -267,6 +305,7 @@ fn do_server(args: ServerArgs) {
     // TX and SYNC may arrive out of order on the wire; pair by id when both sides are seen.
     let mut pending_tx_server_rx: HashMap<u32, SystemTime> = HashMap::new();
     let mut pending_sync_client_tx: HashMap<u32, SystemTime> = HashMap::new();
+    let mut ptp_checked = false;
     while unsafe { RUNNING } {
         // TODO: Cleanup this code
         let (rx_timestamp, mut eth_pkt) = match recv_perf_packet(&sock, &mut packet) {
@@ -277,6 +316,10 @@ fn do_server(args: ServerArgs) {
 
         match PerfOp::from_u8(perf_pkt.get_op()) {
             Some(PerfOp::Tx) => {
+                if !ptp_checked {
+                    check_ptp_sync();
+                    ptp_checked = true;
+                }
                 let tx_id = perf_pkt.get_id();
                 if let Some(client_tx) = pending_sync_client_tx.remove(&tx_id) {
                     print_latency(tx_id as usize, rx_timestamp, client_tx);
@@ -296,9 +339,11 @@ fn do_server(args: ServerArgs) {
             }
             Some(PerfOp::Ping) => {
                 perf_pkt.set_op(PerfOp::Pong as u8);
-                eth_pkt.set_destination(eth_pkt.get_source());
-                eth_pkt.set_source(my_mac);
-                if sock.send(eth_pkt.packet()).is_err() {
+                let dst = eth_pkt.get_source().octets();
+                if sock
+                    .send_to(dst, ETHERTYPE_PERF, eth_pkt.payload())
+                    .is_err()
+                {
                     eprintln!("Failed to send packet");
                 };
             }
@@ -312,30 +357,30 @@ fn do_server(args: ServerArgs) {
 }
 
 fn do_client(args: ClientArgs) {
-    let interface_name_match = |iface: &NetworkInterface| iface.name == args.interface;
-    let interfaces = datalink::interfaces();
-    let interface = interfaces
-        .into_iter()
-        .find(interface_name_match)
-        .unwrap_or_else(|| {
-            eprintln!("Interface not found: {}", args.interface);
-            std::process::exit(1);
-        });
-    let my_mac = interface.mac.expect("Failed to get MAC address");
-
     if args.precise {
         tsn::time::tsn_time_analyze();
     }
 
-    let mut sock = match tsn::sock_open(&args.interface, args.vlan_id, args.vlan_pri, ETH_P_PERF) {
+    let mut sock = match tsn::sock_open_with(
+        &args.interface,
+        args.vlan_id,
+        args.vlan_pri,
+        ETHERTYPE_PERF,
+        args.backend,
+    ) {
         Ok(sock) => sock,
         Err(e) => panic!("Failed to open TSN socket: {}", e),
     };
-
-    if !args.oneway {
-        if let Err(e) = sock.set_timeout(Duration::from_secs(TIMEOUT_SEC)) {
-            panic!("Failed to set timeout: {}", e)
-        }
+    let my_mac = MacAddr::from(sock.mac_address().unwrap_or_else(|e| {
+        eprintln!("Failed to get MAC address: {}", e);
+        std::process::exit(1);
+    }));
+    attach_perf_filter(&sock, Some(args.target));
+
+    if args.oneway {
+        check_ptp_sync();
+    } else if let Err(e) = sock.set_timeout(Duration::from_secs(TIMEOUT_SEC)) {
+        panic!("Failed to set timeout: {}", e)
     }
 
     unsafe {
@@ -377,6 +422,9 @@ fn do_client(args: ClientArgs) {
         false
     } else {
         eprintln!("Socket TX/RX timestamp enabled (HW)");
+        if let Some(config) = sock.hw_timestamp_config() {
+            eprintln!("NIC timestamps: {}", config);
+        }
         true
     };
     let mut tx_ts_failures: u32 = 0;
@@ -396,19 +444,25 @@ fn do_client(args: ClientArgs) {
         if args.precise {
             now = SystemTime::now();
             let duration = now.duration_since(UNIX_EPOCH).unwrap();
-            tsn_time_sleep_until(&Duration::new(duration.as_secs() + 1, 0))
-                .expect("Failed to sleep");
+            // Interrupted by SIGINT
+            if tsn_time_sleep_until(&Duration::new(duration.as_secs() + 1, 0)).is_err() {
+                break;
+            }
         }
 
+        let tx_id = sock.next_tx_id();
         if let Err(e) = sock.send(eth_pkt.packet()) {
             eprintln!("Failed to send packet: {}", e);
             continue;
         }
         let tx_timestamp = if is_tx_ts_enabled && !use_sw_tx_fallback {
-            match sock.get_tx_timestamp() {
+            match get_tx_timestamp(&sock, tx_id) {
                 Ok(ts) => {
                     tx_ts_failures = 0;
-                    UNIX_EPOCH + Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32)
+                    if ts.source == tsn::TimestampSource::Sw {
+                        eprintln!("SW TX timestamp(from driver) used");
+                    }
+                    UNIX_EPOCH + Duration::new(ts.time.tv_sec as u64, ts.time.tv_nsec as u32)
                 }
                 Err(e) => {
                     tx_ts_failures += 1;
@@ -445,15 +499,11 @@ fn do_client(args: ClientArgs) {
             perf_pkt.set_op(PerfOp::Sync as u8);
 
             eth_pkt.set_payload(perf_pkt.packet());
+            // Timestamp of this frame is skipped by ID when waiting for the next one
             if let Err(e) = sock.send(eth_pkt.packet()) {
                 eprintln!("Failed to send packet: {}", e);
                 continue;
             }
-
-            // Must consume packet's timestamp (skip if already in SW fallback to avoid poll timeout)
-            if is_tx_ts_enabled && !use_sw_tx_fallback {
-                let _ = sock.get_tx_timestamp();
-            }
         } else {
             timestamps.insert(ping_id as u32, tx_timestamp);
             let (rx_timestamp, rx_eth_pkt) = match recv_perf_packet(&sock, &mut rx_eth_buff) {
@@ -517,48 +567,81 @@ fn do_client(args: ClientArgs) {
     }
 }
 
+/// TX timestamp of the frame sent as `tx_id`, skipping those of earlier frames
+fn get_tx_timestamp(sock: &tsn::TsnSocket, tx_id: u32) -> Result<tsn::TxTimestamp, tsn::TsnError> {
+    loop {
+        let ts = sock.get_tx_timestamp()?;
+        // IDs wrap around, so compare the distance rather than the values
+        match ts.id.wrapping_sub(tx_id) as i32 {
+            0 => return Ok(ts),
+            d if d > 0 => {
+                return Err(tsn::TsnError::TimestampMissing(format!(
+                    "TX timestamp of frame {} was lost",
+                    tx_id
+                )))
+            }
+            _ => continue,
+        }
+    }
+}
+
+/// One-way latencies subtract timestamps of two hosts, so warn unless ptp4l keeps
+/// the local clock in step
+fn check_ptp_sync() {
+    match ptp_sync_status() {
+        Ok(status) if status.is_synchronized() => eprintln!(
+            "PTP: {}, offset {} ns from grandmaster {}",
+            status.state, status.offset_from_master, status.grandmaster
+        ),
+        Ok(status) => eprintln!(
+            "WARNING: PTP port is {}, offset {} ns; one-way latencies may be off",
+            status.state, status.offset_from_master
+        ),
+        Err(e) => eprintln!(
+            "WARNING: Cannot query ptp4l ({}); one-way latencies assume synchronized clocks",
+            e
+        ),
+    }
+}
+
+fn ptp_sync_status() -> Result<SyncStatus, tsn::TsnError> {
+    let mut pmc = PmcClient::connect()?;
+    // dist/gPTP.conf sets transportSpecific 1, the default profile of ptp4l 0
+    pmc.transport_specific = 1;
+    match pmc.sync_status() {
+        Err(e) if e.raw_os_error() == Some(libc::EAGAIN) => {
+            pmc.transport_specific = 0;
+            pmc.sync_status()
+        }
+        result => result,
+    }
+}
+
 fn recv_perf_packet<'a>(
     sock: &tsn::TsnSocket,
     packet: &'a mut [u8; 1514],
 ) -> Option<(SystemTime, MutableEthernetPacket<'a>)> {
     let start = Instant::now();
     while start.elapsed().as_secs() < TIMEOUT_SEC {
-        let mut rx_timestamp;
+        let rx_timestamp;
         let recv_bytes = {
             if sock.rx_timestamp_enabled {
-                const CONTROL_SIZE: usize = 1024;
-                let mut control = [0u8; CONTROL_SIZE];
-                let mut iov = libc::iovec {
-                    iov_base: packet.as_mut_ptr() as *mut libc::c_void,
-                    iov_len: packet.len(),
+                let (size, ts) = match sock.recv_with_timestamp(packet) {
+                    Ok(v) if v.0 > 0 => v,
+                    _ => continue,
                 };
-                let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
-                msg.msg_name = std::ptr::null_mut();
-                msg.msg_namelen = 0;
-                msg.msg_iov = &mut iov;
-                msg.msg_iovlen = 1;
-                msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
-                msg.msg_controllen = control.len();
-                msg.msg_flags = 0;
-
-                let res = unsafe { libc::recvmsg(sock.fd, &mut msg, 0) };
-                rx_timestamp = SystemTime::now(); // Fallback to SW timestamp
-
-                if res == -1 {
-                    continue;
-                } else if res == 0 {
-                    continue;
-                }
 
-                if msg.msg_flags & libc::MSG_CTRUNC != 0 {
-                    eprintln!("RX control data truncated; falling back to SW timestamp");
-                } else if let Some(ts) = parse_rx_timestamp(&msg) {
-                    rx_timestamp = ts;
-                } else {
-                    eprintln!("Failed to parse RX HW timestamp; falling back to SW timestamp");
+                match ts.source {
+                    tsn::TimestampSource::User => {
+                        eprintln!("Failed to get RX HW timestamp; falling back to SW timestamp")
+                    }
+                    tsn::TimestampSource::Sw => eprintln!("SW RX timestamp used"),
+                    _ => {}
                 }
+                rx_timestamp =
+                    UNIX_EPOCH + Duration::new(ts.time.tv_sec as u64, ts.time.tv_nsec as u32);
 
-                res as usize
+                size
             } else {
                 match sock.recv(packet) {
                     Ok(size) => {
@@ -572,12 +655,6 @@ fn recv_perf_packet<'a>(
             }
         };
 
-        let bytes = &packet[..recv_bytes];
-        let eth = EthernetPacket::new(bytes).unwrap();
-        if eth.get_ethertype() != EtherType(ETHERTYPE_PERF) {
-            continue;
-        }
-
         let eth_pkt = MutableEthernetPacket::new(&mut packet[..recv_bytes]).unwrap();
         return Some((rx_timestamp, eth_pkt));
     }
@@ -585,38 +662,6 @@ fn recv_perf_packet<'a>(
     None
 }
 
-fn parse_rx_timestamp(msg: &libc::msghdr) -> Option<SystemTime> {
-    let mut cmsg = unsafe { libc::CMSG_FIRSTHDR(msg as *const _ as *mut libc::msghdr) };
-    while !cmsg.is_null() {
-        let cmsg_level = unsafe { (*cmsg).cmsg_level };
-        let cmsg_type = unsafe { (*cmsg).cmsg_type };
-
-        if cmsg_level == libc::SOL_SOCKET && cmsg_type == libc::SO_TIMESTAMPING {
-            let ts = unsafe { *(libc::CMSG_DATA(cmsg) as *const [libc::timespec; 3]) };
-            let selected = if ts[2].tv_sec != 0 || ts[2].tv_nsec != 0 {
-                Some(ts[2])
-            } else if ts[1].tv_sec != 0 || ts[1].tv_nsec != 0 {
-                Some(ts[1])
-            } else if ts[0].tv_sec != 0 || ts[0].tv_nsec != 0 {
-                eprintln!("SW RX timestamp used");
-                Some(ts[0])
-            } else {
-                None
-            };
-
-            if let Some(ts) = selected {
-                if ts.tv_sec >= 0 && ts.tv_nsec >= 0 {
-                    return Some(UNIX_EPOCH + Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32));
-                }
-            }
-            return None;
-        }
-
-        cmsg = unsafe { libc::CMSG_NXTHDR(msg as *const _ as *mut libc::msghdr, cmsg) };
-    }
-    None
-}
-
 fn print_latency(id: usize, rx_timestamp: SystemTime, tx_timestamp: SystemTime) {
     // elapsed could be negative for some reason
     let tx_ns = tx_timestamp.duration_since(UNIX_EPOCH).unwrap().as_nanos();
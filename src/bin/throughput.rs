@@ -2,6 +2,8 @@
 // It does not cause any issues, so we can ignore it
 #![allow(unexpected_cfgs)]
 
+mod common;
+
 use std::thread;
 use std::time::Duration;
 use std::time::Instant;
@@ -10,18 +12,25 @@ use clap::{arg, crate_authors, crate_version, Command};
 use num_format::{Locale, ToFormattedString};
 use signal_hook::{consts::SIGINT, iterator::Signals};
 
-use pnet::datalink::{self, NetworkInterface};
 use pnet::packet::ethernet::{EtherType, EthernetPacket, MutableEthernetPacket};
 use pnet::util::MacAddr;
 use pnet_macros::packet;
 use pnet_macros_support::types::u32be;
+use pnet_packet::MutablePacket;
 use pnet_packet::Packet;
 use pnet_packet::PrimitiveValues;
 
+use common::{attach_perf_filter, ETHERTYPE_PERF};
+
 const NS_IN_SEC: u64 = 1_000_000_000;
 
-const ETHERTYPE_PERF: u16 = 0x1337;
-const ETH_P_PERF: u16 = libc::ETH_P_ALL as u16; // FIXME: use ETHERTYPE_PERF
+// Maximum number of frames the server receives with one recvmmsg()
+const RECV_BATCH: usize = 64;
+
+#[cfg(feature = "xdp")]
+const BACKENDS: [&str; 4] = ["packet", "xdp", "xdp-generic", "virtual"];
+#[cfg(not(feature = "xdp"))]
+const BACKENDS: [&str; 2] = ["packet", "virtual"];
 
 static mut RUNNING: bool = false;
 static mut TEST_RUNNING: bool = false;
@@ -115,6 +124,12 @@ fn main() {
             arg!(--pcp <prio> "VLAN priority (PCP, 0-7)")
                 .required(false)
                 .default_value("0"),
+        )
+        .arg(
+            arg!(--backend <backend> "Socket backend")
+                .value_parser(BACKENDS)
+                .required(false)
+                .default_value("packet"),
         );
 
     let client_command = Command::new("client")
@@ -142,6 +157,11 @@ fn main() {
                 .required(false)
                 .default_value("1000000000"), // 1 Gbps
         )
+        .arg(
+            arg!(batch: -B --batch <batch> "frames per sendmmsg() call")
+                .required(false)
+                .default_value("32"),
+        )
         .arg(
             arg!(--vlanid <id> "VLAN ID (1-4094)")
                 .required(true),
@@ -150,6 +170,12 @@ fn main() {
             arg!(--pcp <prio> "VLAN priority (PCP, 0-7)")
                 .required(false)
                 .default_value("0"),
+        )
+        .arg(
+            arg!(--backend <backend> "Socket backend")
+                .value_parser(BACKENDS)
+                .required(false)
+                .default_value("packet"),
         );
 
     let matched_command = Command::new("throughput")
@@ -166,7 +192,8 @@ fn main() {
             let iface = server_matches.value_of("interface").unwrap().to_string();
             let vlan_id: u16 = server_matches.value_of("vlanid").unwrap().parse().unwrap();
             let vlan_pri: u32 = server_matches.value_of("pcp").unwrap().parse().unwrap();
-            do_server(iface, vlan_id, vlan_pri)
+            let backend = parse_backend(server_matches.value_of("backend").unwrap());
+            do_server(iface, vlan_id, vlan_pri, backend)
         }
         ("client", client_matches) => {
             let iface = client_matches.value_of("interface").unwrap().to_string();
@@ -179,25 +206,37 @@ fn main() {
                 .unwrap();
             let warmup: usize = client_matches.value_of("warmup").unwrap().parse().unwrap();
             let bitrate: usize = client_matches.value_of("bitrate").unwrap().parse().unwrap();
+            let batch: usize = client_matches.value_of("batch").unwrap().parse().unwrap();
             let vlan_id: u16 = client_matches.value_of("vlanid").unwrap().parse().unwrap();
             let vlan_pri: u32 = client_matches.value_of("pcp").unwrap().parse().unwrap();
+            let backend = parse_backend(client_matches.value_of("backend").unwrap());
 
-            do_client(iface, target, size, duration, warmup, bitrate, vlan_id, vlan_pri)
+            do_client(
+                iface, target, size, duration, warmup, bitrate, batch, vlan_id, vlan_pri, backend,
+            )
         }
         _ => panic!("Invalid command"),
     }
 }
 
-fn do_server(iface_name: String, vlan_id: u16, vlan_pri: u32) {
-    let interface_name_match = |iface: &NetworkInterface| iface.name == iface_name;
-    let interfaces = datalink::interfaces();
-    let interface = interfaces.into_iter().find(interface_name_match).unwrap();
-    let my_mac = interface.mac.unwrap();
+fn parse_backend(name: &str) -> tsn::Backend {
+    match name {
+        #[cfg(feature = "xdp")]
+        "xdp" => tsn::Backend::Xdp(tsn::xdp::XdpConfig::new(tsn::xdp::XdpMode::Native)),
+        #[cfg(feature = "xdp")]
+        "xdp-generic" => tsn::Backend::Xdp(tsn::xdp::XdpConfig::new(tsn::xdp::XdpMode::Generic)),
+        "virtual" => tsn::Backend::Virtual(tsn::wire::WireConfig::default()),
+        _ => tsn::Backend::Packet,
+    }
+}
 
-    let mut sock = match tsn::sock_open(&iface_name, vlan_id, vlan_pri, ETH_P_PERF) {
-        Ok(sock) => sock,
-        Err(e) => panic!("Failed to open TSN socket: {}", e),
-    };
+fn do_server(iface_name: String, vlan_id: u16, vlan_pri: u32, backend: tsn::Backend) {
+    let mut sock =
+        match tsn::sock_open_with(&iface_name, vlan_id, vlan_pri, ETHERTYPE_PERF, backend) {
+            Ok(sock) => sock,
+            Err(e) => panic!("Failed to open TSN socket: {}", e),
+        };
+    attach_perf_filter(&sock, None);
 
     if let Err(e) = sock.set_timeout(Duration::from_secs(1)) {
         panic!("Failed to set timeout: {}", e)
@@ -216,117 +255,98 @@ fn do_server(iface_name: String, vlan_id: u16, vlan_pri: u32) {
         }
     });
 
+    let mut packets = vec![[0u8; 1514]; RECV_BATCH];
     while unsafe { RUNNING } {
-        let mut packet = [0u8; 1514];
-        let packet_size;
-
-        match sock.recv(&mut packet) {
-            Ok(n) => packet_size = n as usize,
+        let received = match sock.recv_batch_from(&mut packets) {
+            Ok(received) => received,
             Err(_) => continue,
         };
 
-        let recv_eth_pkt: EthernetPacket = EthernetPacket::new(&packet).unwrap();
-        if recv_eth_pkt.get_ethertype() != EtherType(ETHERTYPE_PERF) {
-            continue;
-        }
-
-        let recv_perf_pkt: PerfPacket = PerfPacket::new(recv_eth_pkt.payload()).unwrap();
+        for (packet, (packet_size, info)) in packets.iter().zip(received) {
+            let recv_eth_pkt: EthernetPacket = EthernetPacket::new(packet).unwrap();
 
-        match recv_perf_pkt.get_op() {
-            PerfOpFieldValues::ReqStart => {
-                println!("Received ReqStart");
+            let recv_perf_pkt: PerfPacket = PerfPacket::new(recv_eth_pkt.payload()).unwrap();
 
-                if unsafe { TEST_RUNNING } {
-                    println!("Already running");
-                    continue;
-                }
+            match recv_perf_pkt.get_op() {
+                PerfOpFieldValues::ReqStart => {
+                    println!("Received ReqStart");
 
-                let req_start: PerfStartReqPacket =
-                    PerfStartReqPacket::new(recv_perf_pkt.payload()).unwrap();
-                let duration: Duration = Duration::from_secs(req_start.get_duration().into());
-                let warmup: Duration = Duration::from_secs(req_start.get_warmup().into());
-
-                unsafe {
-                    STATS.duration = duration.as_secs() as usize;
-                    STATS.warmup = warmup.as_secs() as usize;
-                    STATS.pkt_count = 0;
-                    STATS.total_bytes = 0;
-                    STATS.last_id = 0;
-                    STATS.warmup_state = if STATS.warmup > 0 {
-                        WarmupState::Ready
-                    } else {
-                        WarmupState::None
-                    };
-                    TEST_RUNNING = true;
-                }
+                    if unsafe { TEST_RUNNING } {
+                        println!("Already running");
+                        continue;
+                    }
 
-                // Make thread for statistics
-                thread::spawn(stats_worker);
+                    let req_start: PerfStartReqPacket =
+                        PerfStartReqPacket::new(recv_perf_pkt.payload()).unwrap();
+                    let duration: Duration = Duration::from_secs(req_start.get_duration().into());
+                    let warmup: Duration = Duration::from_secs(req_start.get_warmup().into());
+
+                    unsafe {
+                        STATS.duration = duration.as_secs() as usize;
+                        STATS.warmup = warmup.as_secs() as usize;
+                        STATS.pkt_count = 0;
+                        STATS.total_bytes = 0;
+                        STATS.last_id = 0;
+                        STATS.warmup_state = if STATS.warmup > 0 {
+                            WarmupState::Ready
+                        } else {
+                            WarmupState::None
+                        };
+                        TEST_RUNNING = true;
+                    }
 
-                let mut perf_buffer = vec![0; 8];
-                let mut eth_buffer = vec![0; 14 + 8];
+                    // Make thread for statistics
+                    thread::spawn(stats_worker);
 
-                let mut perf_pkt = MutablePerfPacket::new(&mut perf_buffer).unwrap();
-                perf_pkt.set_id(recv_perf_pkt.get_id());
-                perf_pkt.set_op(PerfOpFieldValues::ResStart);
+                    let mut perf_buffer = vec![0; 8];
 
-                let mut eth_pkt = MutableEthernetPacket::new(&mut eth_buffer).unwrap();
-                eth_pkt.set_destination(recv_eth_pkt.get_source());
-                eth_pkt.set_source(my_mac);
-                eth_pkt.set_ethertype(EtherType(ETHERTYPE_PERF));
+                    let mut perf_pkt = MutablePerfPacket::new(&mut perf_buffer).unwrap();
+                    perf_pkt.set_id(recv_perf_pkt.get_id());
+                    perf_pkt.set_op(PerfOpFieldValues::ResStart);
 
-                eth_pkt.set_payload(perf_pkt.packet());
-                if let Err(e) = sock.send(eth_pkt.packet()) {
-                    eprintln!("Failed to send packet: {}", e)
+                    if let Err(e) = sock.send_to(info.src, ETHERTYPE_PERF, perf_pkt.packet()) {
+                        eprintln!("Failed to send packet: {}", e)
+                    }
                 }
-            }
-            PerfOpFieldValues::Data => {
-                unsafe {
+                PerfOpFieldValues::Data => unsafe {
                     STATS.last_id = recv_perf_pkt.get_id();
                     if STATS.warmup_state == WarmupState::Finished
                         || STATS.warmup_state == WarmupState::None
                     {
                         STATS.pkt_count += 1;
-                        STATS.total_bytes += packet_size + 4/* hidden VLAN tag */;
+                        STATS.total_bytes += packet_size + info.vlan.map_or(0, |_| 4);
                     }
-                }
-            }
-            PerfOpFieldValues::ReqEnd => {
-                println!("Received ReqEnd");
-
-                unsafe { TEST_RUNNING = false }
+                },
+                PerfOpFieldValues::ReqEnd => {
+                    println!("Received ReqEnd");
 
-                let mut perf_buffer = vec![0; 8];
-                let mut eth_buffer = vec![0; 14 + 8];
+                    unsafe { TEST_RUNNING = false }
 
-                let mut perf_pkt = MutablePerfPacket::new(&mut perf_buffer).unwrap();
-                perf_pkt.set_id(recv_perf_pkt.get_id());
-                perf_pkt.set_op(PerfOpFieldValues::ResEnd);
+                    let mut perf_buffer = vec![0; 8];
 
-                let mut eth_pkt = MutableEthernetPacket::new(&mut eth_buffer).unwrap();
-                eth_pkt.set_destination(recv_eth_pkt.get_source());
-                eth_pkt.set_source(my_mac);
-                eth_pkt.set_ethertype(EtherType(ETHERTYPE_PERF));
+                    let mut perf_pkt = MutablePerfPacket::new(&mut perf_buffer).unwrap();
+                    perf_pkt.set_id(recv_perf_pkt.get_id());
+                    perf_pkt.set_op(PerfOpFieldValues::ResEnd);
 
-                eth_pkt.set_payload(perf_pkt.packet());
-                if let Err(e) = sock.send(eth_pkt.packet()) {
-                    eprintln!("Failed to send packet: {}", e)
-                }
+                    if let Err(e) = sock.send_to(info.src, ETHERTYPE_PERF, perf_pkt.packet()) {
+                        eprintln!("Failed to send packet: {}", e)
+                    }
 
-                // Print statistics
-                unsafe {
-                    let pkt_count = STATS.pkt_count;
-                    let total_bytes = STATS.total_bytes;
-                    let duration = STATS.duration;
-                    println!(
-                        "{} packets, {} bytes {} bps",
-                        pkt_count,
-                        total_bytes,
-                        total_bytes * 8 / duration
-                    );
+                    // Print statistics
+                    unsafe {
+                        let pkt_count = STATS.pkt_count;
+                        let total_bytes = STATS.total_bytes;
+                        let duration = STATS.duration;
+                        println!(
+                            "{} packets, {} bytes {} bps",
+                            pkt_count,
+                            total_bytes,
+                            total_bytes * 8 / duration
+                        );
+                    }
                 }
+                _ => {}
             }
-            _ => {}
         }
     }
 
@@ -336,6 +356,7 @@ fn do_server(iface_name: String, vlan_id: u16, vlan_pri: u32) {
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn do_client(
     iface_name: String,
     target: String,
@@ -343,20 +364,20 @@ fn do_client(
     duration: usize,
     warmup: usize,
     bitrate: usize,
+    batch: usize,
     vlan_id: u16,
     vlan_pri: u32,
+    backend: tsn::Backend,
 ) {
-    let interface_name_match = |iface: &NetworkInterface| iface.name == iface_name;
-    let interfaces = datalink::interfaces();
-    let interface = interfaces.into_iter().find(interface_name_match).unwrap();
-    let my_mac = interface.mac.unwrap();
-
     let target: MacAddr = target.parse().expect("Invalid MAC address");
 
-    let mut sock = match tsn::sock_open(&iface_name, vlan_id, vlan_pri, ETH_P_PERF) {
-        Ok(sock) => sock,
-        Err(e) => panic!("Failed to open TSN socket: {}", e),
-    };
+    let mut sock =
+        match tsn::sock_open_with(&iface_name, vlan_id, vlan_pri, ETHERTYPE_PERF, backend) {
+            Ok(sock) => sock,
+            Err(e) => panic!("Failed to open TSN socket: {}", e),
+        };
+    let my_mac = MacAddr::from(sock.mac_address().unwrap());
+    attach_perf_filter(&sock, Some(target));
 
     if let Err(e) = sock.set_timeout(Duration::from_secs(1)) {
         panic!("Failed to set timeout: {}", e)
@@ -419,19 +440,24 @@ fn do_client(
 
     // Send data
     println!("Sending data");
-    let mut perf_buffer = vec![0; 8 + size];
-    let mut eth_buffer = vec![0; 14 + 8 + size];
-    let mut eth_pkt = MutableEthernetPacket::new(&mut eth_buffer).unwrap();
-    eth_pkt.set_destination(target);
-    eth_pkt.set_source(my_mac);
-    eth_pkt.set_ethertype(EtherType(ETHERTYPE_PERF));
+    let batch = batch.max(1);
+    let mut frames = vec![vec![0; 14 + 8 + size]; batch];
+    for frame in frames.iter_mut() {
+        let mut eth_pkt = MutableEthernetPacket::new(frame).unwrap();
+        eth_pkt.set_destination(target);
+        eth_pkt.set_source(my_mac);
+        eth_pkt.set_ethertype(EtherType(ETHERTYPE_PERF));
+
+        let mut perf_pkt = MutablePerfPacket::new(eth_pkt.payload_mut()).unwrap();
+        perf_pkt.set_op(PerfOpFieldValues::Data);
+    }
 
     let now = Instant::now();
-    let mut last_id = 0;
+    let mut last_id: u32 = 0;
 
-    // Calculate interval between packets to achieve the desired bitrate
+    // Calculate interval between batches to achieve the desired bitrate
     let packet_bits = (14 + 8 + size + 4) * 8; // Ethernet header + Perf header + payload + VLAN tag
-    let interval_sec = packet_bits as f64 / bitrate as f64;
+    let interval_sec = (packet_bits * batch) as f64 / bitrate as f64;
     let interval_ns = (interval_sec * NS_IN_SEC as f64) as u64;
     let mut last_send_time = Instant::now();
 
@@ -452,14 +478,13 @@ fn do_client(
 
         last_send_time = Instant::now();
 
-        let mut perf_pkt = MutablePerfPacket::new(&mut perf_buffer).unwrap();
-        perf_pkt.set_id(last_id); // TODO: Randomize
-        perf_pkt.set_op(PerfOpFieldValues::Data);
-
-        eth_pkt.set_payload(perf_pkt.packet());
-        if sock.send(eth_pkt.packet()).is_err() {}
+        for frame in frames.iter_mut() {
+            let mut perf_pkt = MutablePerfPacket::new(&mut frame[14..]).unwrap();
+            perf_pkt.set_id(last_id); // TODO: Randomize
+            last_id = last_id.wrapping_add(1);
+        }
 
-        last_id += 1;
+        let _ = sock.send_batch(&frames);
     }
 
     // Request end
@@ -508,10 +533,6 @@ fn wait_for_response(sock: &mut tsn::TsnSocket, op: PerfOpField) -> Result<(), (
         }
 
         let eth_pkt: EthernetPacket = EthernetPacket::new(&packet).unwrap();
-        if eth_pkt.get_ethertype() != EtherType(ETHERTYPE_PERF) {
-            continue;
-        }
-
         let perf_pkt: PerfPacket = PerfPacket::new(eth_pkt.payload()).unwrap();
         if perf_pkt.get_op() == op {
             return Ok(());
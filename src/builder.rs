@@ -0,0 +1,224 @@
+//! Socket setup without `sock_open`'s defaults
+//!
+//! ```no_run
+//! use std::time::Duration;
+//! use tsn::{TimestampMode, TsnSocket};
+//!
+//! let sock = TsnSocket::builder("eth0")
+//!     .vlan(10)
+//!     .priority(3)
+//!     .protocol(0x22f0)
+//!     .config("/etc/tsn/config.yaml")
+//!     .rcvbuf(1 << 20)
+//!     .timestamps(TimestampMode::Hardware)
+//!     .timeout(Duration::from_secs(1))
+//!     .open()?;
+//! # Ok::<(), tsn::TsnError>(())
+//! ```
+
+use std::time::Duration;
+
+use nix::libc;
+
+use crate::config::Config;
+use crate::{Backend, TsnError, TsnSocket};
+
+/// Where [`TsnSocketBuilder`] takes the TAS/CBS setup of the interface from
+#[derive(Clone, Default)]
+pub enum ConfigSource {
+    /// File at `CONFIG_PATH`, `./config.yaml` by default. Used by [`sock_open`](crate::sock_open)
+    #[default]
+    Env,
+    /// Config file at this path, looked up by interface name
+    Path(String),
+    /// Config of the interface itself
+    Config(Box<Config>),
+}
+
+impl ConfigSource {
+    pub(crate) fn resolve(&self, ifname: &str) -> Result<Config, TsnError> {
+        match self {
+            ConfigSource::Env => crate::get_config(ifname),
+            ConfigSource::Path(path) => crate::read_config_of(ifname, path),
+            ConfigSource::Config(config) => Ok(config.as_ref().clone()),
+        }
+    }
+}
+
+impl From<Config> for ConfigSource {
+    fn from(config: Config) -> Self {
+        ConfigSource::Config(Box::new(config))
+    }
+}
+
+impl From<&str> for ConfigSource {
+    fn from(path: &str) -> Self {
+        ConfigSource::Path(path.to_string())
+    }
+}
+
+impl From<String> for ConfigSource {
+    fn from(path: String) -> Self {
+        ConfigSource::Path(path)
+    }
+}
+
+/// Timestamping to enable when the socket is opened
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TimestampMode {
+    #[default]
+    Off,
+    /// Hardware timestamps where the NIC supports them, software ones otherwise
+    Any,
+    /// Fail with [`TsnError::HwTimestampUnsupported`] if the NIC cannot timestamp
+    Hardware,
+}
+
+/// Builder of a [`TsnSocket`], see [`TsnSocket::builder`]
+#[derive(Clone)]
+pub struct TsnSocketBuilder {
+    ifname: String,
+    vlanid: Option<u16>,
+    priority: u32,
+    proto: u16,
+    backend: Backend,
+    config: ConfigSource,
+    rcvbuf: Option<usize>,
+    sndbuf: Option<usize>,
+    timestamps: TimestampMode,
+    timeout: Option<Duration>,
+}
+
+impl TsnSocketBuilder {
+    pub(crate) fn new(ifname: &str) -> TsnSocketBuilder {
+        TsnSocketBuilder {
+            ifname: ifname.to_string(),
+            vlanid: None,
+            priority: 0,
+            proto: libc::ETH_P_ALL as u16,
+            backend: Backend::Packet,
+            config: ConfigSource::Env,
+            rcvbuf: None,
+            sndbuf: None,
+            timestamps: TimestampMode::Off,
+            timeout: None,
+        }
+    }
+
+    /// Open the socket on VLAN `vlanid`, creating it with the qdiscs of the config.
+    ///
+    /// Without it the socket is bound to the interface itself and no VLAN is created.
+    pub fn vlan(mut self, vlanid: u16) -> Self {
+        self.vlanid = Some(vlanid);
+        self
+    }
+
+    /// `SO_PRIORITY` of sent frames, 0 by default
+    pub fn priority(mut self, priority: u32) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Ethertype to receive, `ETH_P_ALL` by default
+    pub fn protocol(mut self, proto: u16) -> Self {
+        self.proto = proto;
+        self
+    }
+
+    pub fn backend(mut self, backend: Backend) -> Self {
+        self.backend = backend;
+        self
+    }
+
+    /// A config file path or a [`Config`]. [`ConfigSource::Env`] by default, only read
+    /// with a [`vlan`](Self::vlan)
+    pub fn config(mut self, config: impl Into<ConfigSource>) -> Self {
+        self.config = config.into();
+        self
+    }
+
+    /// `SO_RCVBUF` in bytes. The kernel doubles it and caps it at `rmem_max`
+    pub fn rcvbuf(mut self, size: usize) -> Self {
+        self.rcvbuf = Some(size);
+        self
+    }
+
+    /// `SO_SNDBUF` in bytes. The kernel doubles it and caps it at `wmem_max`
+    pub fn sndbuf(mut self, size: usize) -> Self {
+        self.sndbuf = Some(size);
+        self
+    }
+
+    pub fn timestamps(mut self, mode: TimestampMode) -> Self {
+        self.timestamps = mode;
+        self
+    }
+
+    /// Receive timeout, see [`sock_set_timeout`](crate::sock_set_timeout)
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn open(&self) -> Result<TsnSocket, TsnError> {
+        let (ifname, vlanid) = (self.ifname.as_str(), self.vlanid);
+        let (priority, proto) = (self.priority, self.proto);
+        // Only the qdiscs of a VLAN come from the config, the interface itself is left alone
+        let config = match (&self.backend, vlanid) {
+            (Backend::Virtual(_), _) | (_, None) => Config::default(),
+            _ => self.config.resolve(ifname)?,
+        };
+        // From here on, errors drop `sock`, which closes it and releases the VLAN
+        let mut sock = match self.backend {
+            Backend::Packet => crate::open_socket(ifname, vlanid, &config, priority, proto, None)?,
+            Backend::Ring(ring) => {
+                crate::open_socket(ifname, vlanid, &config, priority, proto, Some(ring))?
+            }
+            #[cfg(feature = "xdp")]
+            Backend::Xdp(xdp) => crate::open_xdp(ifname, vlanid, &config, priority, proto, xdp)?,
+            Backend::Virtual(wire) => crate::open_wire(ifname, vlanid, proto, wire)?,
+        };
+
+        if let Some(size) = self.rcvbuf {
+            set_buf_size(&sock, libc::SO_RCVBUF, size, "Set SO_RCVBUF")?;
+        }
+        if let Some(size) = self.sndbuf {
+            set_buf_size(&sock, libc::SO_SNDBUF, size, "Set SO_SNDBUF")?;
+        }
+        if let Some(timeout) = self.timeout {
+            sock.set_timeout(timeout)?;
+        }
+        let res = match self.timestamps {
+            TimestampMode::Off => Ok(()),
+            TimestampMode::Any | TimestampMode::Hardware => sock.enable_timestamps(None),
+        };
+        match res {
+            Err(TsnError::HwTimestampUnsupported(_)) if self.timestamps == TimestampMode::Any => {}
+            res => res?,
+        }
+        Ok(sock)
+    }
+}
+
+fn set_buf_size(
+    sock: &TsnSocket,
+    option: libc::c_int,
+    size: usize,
+    op: &'static str,
+) -> Result<(), TsnError> {
+    let size = libc::c_int::try_from(size).unwrap_or(libc::c_int::MAX);
+    let res = unsafe {
+        libc::setsockopt(
+            sock.fd,
+            libc::SOL_SOCKET,
+            option,
+            &size as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as u32,
+        )
+    };
+    if res < 0 {
+        Err(TsnError::socket(op))
+    } else {
+        Ok(())
+    }
+}
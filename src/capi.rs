@@ -0,0 +1,410 @@
+//! C ABI, for applications written against the C `libtsn`
+//!
+//! As in the C library, sockets are referred to by their fd and functions return -1
+//! with `errno` set on failure. [`tsn_last_error`] describes the last failure of the
+//! calling thread in more detail.
+//!
+//! `include/tsn.h` is generated from this module:
+//!
+//! ```sh
+//! cbindgen --config cbindgen.toml --output include/tsn.h
+//! ```
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::ffi::{CStr, CString};
+use std::io;
+use std::slice;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+use nix::libc;
+
+use crate::{time, TsnError, TsnSocket};
+
+/// Sockets opened with [`tsn_sock_open`], by fd. A call holds its socket only for as
+/// long as it runs, so a blocking receive does not hold up other sockets
+static SOCKETS: Mutex<BTreeMap<i32, Arc<RwLock<TsnSocket>>>> = Mutex::new(BTreeMap::new());
+
+thread_local! {
+    static LAST_ERROR: RefCell<CString> = RefCell::new(CString::default());
+}
+
+/// Open a socket on the VLAN `vlanid` of `ifname`, creating the VLAN if needed.
+///
+/// The interface is set up from the config file at `CONFIG_PATH`, `./config.yaml` by
+/// default. Returns the fd of the socket.
+///
+/// # Safety
+///
+/// `ifname` must be a NUL terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn tsn_sock_open(
+    ifname: *const libc::c_char,
+    vlanid: u16,
+    priority: u32,
+    proto: u16,
+) -> libc::c_int {
+    if ifname.is_null() {
+        return fail_with(libc::EINVAL, "ifname is NULL");
+    }
+    let Ok(ifname) = CStr::from_ptr(ifname).to_str() else {
+        return fail_with(libc::EINVAL, "ifname is not UTF-8");
+    };
+    match crate::sock_open(ifname, vlanid, priority, proto) {
+        Ok(sock) => {
+            let fd = sock.fd;
+            SOCKETS
+                .lock()
+                .unwrap()
+                .insert(fd, Arc::new(RwLock::new(sock)));
+            fd
+        }
+        Err(e) => fail(e),
+    }
+}
+
+/// Close `sock` and release its VLAN
+#[no_mangle]
+pub extern "C" fn tsn_sock_close(sock: libc::c_int) -> libc::c_int {
+    let Some(entry) = SOCKETS.lock().unwrap().remove(&sock) else {
+        return fail(not_a_socket());
+    };
+    // A call still running in another thread closes it when done
+    let Ok(sock) = Arc::try_unwrap(entry) else {
+        return 0;
+    };
+    sock.into_inner().unwrap().close().map_or_else(fail, |_| 0)
+}
+
+/// Set the receive timeout of `sock`. A zero timeout blocks forever
+///
+/// # Safety
+///
+/// `timeout` must point to a `struct timeval`.
+#[no_mangle]
+pub unsafe extern "C" fn tsn_sock_set_timeout(
+    sock: libc::c_int,
+    timeout: *const libc::timeval,
+) -> libc::c_int {
+    let Some(timeout) = timeout.as_ref() else {
+        return fail_with(libc::EINVAL, "timeout is NULL");
+    };
+    if timeout.tv_sec < 0 || !(0..1_000_000).contains(&timeout.tv_usec) {
+        return fail_with(libc::EINVAL, "timeout is out of range");
+    }
+    let timeout = Duration::new(timeout.tv_sec as u64, timeout.tv_usec as u32 * 1000);
+    with_socket(sock, |sock| sock.set_timeout(timeout)).map_or_else(fail, |_| 0)
+}
+
+/// Send the frame of `n` bytes at `buf`. Returns the number of bytes sent
+///
+/// # Safety
+///
+/// `buf` must point to `n` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn tsn_send(
+    sock: libc::c_int,
+    buf: *const libc::c_void,
+    n: libc::size_t,
+) -> libc::ssize_t {
+    let buf = bytes(buf as *const u8, n);
+    with_socket(sock, |sock| sock.send(buf)).unwrap_or_else(|e| fail(e) as isize)
+}
+
+/// Receive a frame into the `n` bytes at `buf`. Returns its length
+///
+/// # Safety
+///
+/// `buf` must point to `n` writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn tsn_recv(
+    sock: libc::c_int,
+    buf: *mut libc::c_void,
+    n: libc::size_t,
+) -> libc::ssize_t {
+    let buf = bytes_mut(buf as *mut u8, n);
+    with_socket(sock, |sock| sock.recv(buf)).unwrap_or_else(|e| fail(e) as isize)
+}
+
+/// `recvmsg` on `sock`, e.g. to read the RX timestamp from the control messages
+///
+/// # Safety
+///
+/// `msg` must point to a `struct msghdr` set up for `recvmsg`.
+#[no_mangle]
+pub unsafe extern "C" fn tsn_recv_msg(sock: libc::c_int, msg: *mut libc::msghdr) -> libc::ssize_t {
+    let Some(msg) = msg.as_mut() else {
+        return fail_with(libc::EINVAL, "msg is NULL") as isize;
+    };
+    with_socket(sock, |sock| sock.recv_msg(msg)).unwrap_or_else(|e| fail(e) as isize)
+}
+
+/// Enable TX and RX timestamps. Pass the `iov` frames are received into for RX
+/// timestamps through [`tsn_recv_msg`], or NULL for TX timestamps only.
+///
+/// Fails with `EOPNOTSUPP` if the NIC cannot timestamp, software timestamps are still
+/// enabled then.
+///
+/// # Safety
+///
+/// `iov` must be NULL or point to a `struct iovec`.
+#[no_mangle]
+pub unsafe extern "C" fn tsn_enable_timestamps(
+    sock: libc::c_int,
+    iov: *mut libc::iovec,
+) -> libc::c_int {
+    let iov = iov.as_mut();
+    with_socket_mut(sock, |sock| sock.enable_timestamps(iov)).map_or_else(fail, |_| 0)
+}
+
+/// Store the hardware timestamp config of the interface as
+/// [`tsn_enable_timestamps`] left it: `HWTSTAMP_TX_*` in `tx_type` and
+/// `HWTSTAMP_FILTER_*` in `rx_filter`. It may be wider than the socket asked for, as
+/// it is shared with other sockets and ptp4l. Fails with `ENODATA` before hardware
+/// timestamps are enabled.
+///
+/// # Safety
+///
+/// `tx_type` and `rx_filter` must be NULL or point to a `uint32_t`.
+#[no_mangle]
+pub unsafe extern "C" fn tsn_hw_timestamp_config(
+    sock: libc::c_int,
+    tx_type: *mut u32,
+    rx_filter: *mut u32,
+) -> libc::c_int {
+    match with_socket(sock, |sock| Ok(sock.hw_timestamp_config())) {
+        Ok(Some(config)) => {
+            if let Some(tx_type) = tx_type.as_mut() {
+                *tx_type = config.tx_type;
+            }
+            if let Some(rx_filter) = rx_filter.as_mut() {
+                *rx_filter = config.rx_filter;
+            }
+            0
+        }
+        Ok(None) => fail_with(libc::ENODATA, "hardware timestamps are not enabled"),
+        Err(e) => fail(e),
+    }
+}
+
+/// Wait up to 1 second for the next TX timestamp and store it in `ts`. `id`, if not
+/// NULL, gets the number of the frame it belongs to, counted from 0 since timestamps
+/// were enabled
+///
+/// # Safety
+///
+/// `ts` must point to a `struct timespec`, `id` must be NULL or point to a `uint32_t`.
+#[no_mangle]
+pub unsafe extern "C" fn tsn_get_tx_timestamp(
+    sock: libc::c_int,
+    ts: *mut libc::timespec,
+    id: *mut u32,
+) -> libc::c_int {
+    let Some(ts) = ts.as_mut() else {
+        return fail_with(libc::EINVAL, "ts is NULL");
+    };
+    match with_socket(sock, |sock| sock.get_tx_timestamp()) {
+        Ok(tx) => {
+            ts.tv_sec = tx.time.tv_sec;
+            ts.tv_nsec = tx.time.tv_nsec;
+            if let Some(id) = id.as_mut() {
+                *id = tx.id;
+            }
+            0
+        }
+        Err(e) => fail(e),
+    }
+}
+
+/// Measure the errors of the clock and of sleeping, used by [`tsn_time_sleep_until`]
+#[no_mangle]
+pub extern "C" fn tsn_time_analyze() {
+    time::tsn_time_analyze();
+}
+
+/// Sleep until `realtime` of `CLOCK_REALTIME`, spinning for the last part
+///
+/// # Safety
+///
+/// `realtime` must point to a `struct timespec`.
+#[no_mangle]
+pub unsafe extern "C" fn tsn_time_sleep_until(realtime: *const libc::timespec) -> libc::c_int {
+    let Some(realtime) = realtime.as_ref() else {
+        return fail_with(libc::EINVAL, "realtime is NULL");
+    };
+    let deadline = time::Timespec {
+        tv_sec: realtime.tv_sec,
+        tv_nsec: realtime.tv_nsec,
+    };
+    match time::sleep_until(time::Clock::Realtime, deadline) {
+        Ok(()) => 0,
+        Err(e) => fail(e),
+    }
+}
+
+/// Read `clock`: `CLOCK_REALTIME`, `CLOCK_MONOTONIC`, `CLOCK_TAI` or a PHC as
+/// `FD_TO_CLOCKID(fd)`
+///
+/// # Safety
+///
+/// `ts` must point to a `struct timespec`.
+#[no_mangle]
+pub unsafe extern "C" fn tsn_time_now(
+    clock: libc::clockid_t,
+    ts: *mut libc::timespec,
+) -> libc::c_int {
+    let Some(ts) = ts.as_mut() else {
+        return fail_with(libc::EINVAL, "ts is NULL");
+    };
+    let Some(clock) = time::Clock::from_clockid(clock) else {
+        return fail_with(libc::EINVAL, "clock is not supported");
+    };
+    match clock.now() {
+        Ok(now) => {
+            ts.tv_sec = now.tv_sec;
+            ts.tv_nsec = now.tv_nsec;
+            0
+        }
+        Err(e) => fail(e),
+    }
+}
+
+/// Sleep until `deadline` of `clock`, spinning for the last part. Clocks as
+/// [`tsn_time_now`]
+///
+/// # Safety
+///
+/// `deadline` must point to a `struct timespec`.
+#[no_mangle]
+pub unsafe extern "C" fn tsn_time_sleep_until_clock(
+    clock: libc::clockid_t,
+    deadline: *const libc::timespec,
+) -> libc::c_int {
+    let Some(deadline) = deadline.as_ref() else {
+        return fail_with(libc::EINVAL, "deadline is NULL");
+    };
+    let Some(clock) = time::Clock::from_clockid(clock) else {
+        return fail_with(libc::EINVAL, "clock is not supported");
+    };
+    let deadline = time::Timespec {
+        tv_sec: deadline.tv_sec,
+        tv_nsec: deadline.tv_nsec,
+    };
+    match time::sleep_until(clock, deadline) {
+        Ok(()) => 0,
+        Err(e) => fail(e),
+    }
+}
+
+/// Convert the UTC time `utc` of `CLOCK_REALTIME` to TAI, counting a pending leap
+/// second
+///
+/// # Safety
+///
+/// `utc` and `tai` must point to a `struct timespec`.
+#[no_mangle]
+pub unsafe extern "C" fn tsn_time_utc_to_tai(
+    utc: *const libc::timespec,
+    tai: *mut libc::timespec,
+) -> libc::c_int {
+    let (Some(utc), Some(tai)) = (utc.as_ref(), tai.as_mut()) else {
+        return fail_with(libc::EINVAL, "utc or tai is NULL");
+    };
+    let utc = time::Timespec {
+        tv_sec: utc.tv_sec,
+        tv_nsec: utc.tv_nsec,
+    };
+    match time::utc_to_tai(utc) {
+        Ok(ts) => {
+            tai.tv_sec = ts.tv_sec;
+            tai.tv_nsec = ts.tv_nsec;
+            0
+        }
+        Err(e) => fail(e),
+    }
+}
+
+/// Description of the last error of the calling thread. Valid until its next failure
+#[no_mangle]
+pub extern "C" fn tsn_last_error() -> *const libc::c_char {
+    LAST_ERROR.with(|msg| msg.borrow().as_ptr())
+}
+
+fn not_a_socket() -> TsnError {
+    TsnError::Socket {
+        op: "Find socket",
+        source: io::Error::from_raw_os_error(libc::EBADF),
+    }
+}
+
+/// Run `f` on the socket `fd`
+fn with_socket<T>(
+    fd: i32,
+    f: impl FnOnce(&TsnSocket) -> Result<T, TsnError>,
+) -> Result<T, TsnError> {
+    let sock = SOCKETS.lock().unwrap().get(&fd).cloned();
+    let sock = sock.ok_or_else(not_a_socket)?;
+    let res = f(&sock.read().unwrap());
+    res
+}
+
+/// Like [`with_socket`], waiting for calls on the socket in other threads to finish
+fn with_socket_mut<T>(
+    fd: i32,
+    f: impl FnOnce(&mut TsnSocket) -> Result<T, TsnError>,
+) -> Result<T, TsnError> {
+    let sock = SOCKETS.lock().unwrap().get(&fd).cloned();
+    let sock = sock.ok_or_else(not_a_socket)?;
+    let res = f(&mut sock.write().unwrap());
+    res
+}
+
+/// Record `e` for [`tsn_last_error`], set `errno` and return -1
+fn fail(e: TsnError) -> i32 {
+    fail_with(errno(&e), &e.to_string())
+}
+
+fn fail_with(errno: i32, msg: &str) -> i32 {
+    LAST_ERROR.with(|last| {
+        *last.borrow_mut() = CString::new(msg.replace('\0', "")).unwrap_or_default();
+    });
+    unsafe { *libc::__errno_location() = errno };
+    -1
+}
+
+/// errno closest to `e`, its cause if it came from a syscall
+pub(crate) fn errno(e: &TsnError) -> i32 {
+    match e {
+        // Tells the caller that software timestamps are enabled nevertheless
+        TsnError::HwTimestampUnsupported(_) => libc::EOPNOTSUPP,
+        TsnError::ConfigParse { .. }
+        | TsnError::ConfigInvalid(_)
+        | TsnError::ConfigNotFound { .. }
+        | TsnError::TimestampNotEnabled => libc::EINVAL,
+        TsnError::InterfaceNotFound(_) => libc::ENODEV,
+        TsnError::LinkSpeedUnknown(_) => libc::ENODATA,
+        TsnError::Unsupported(_) => libc::EOPNOTSUPP,
+        TsnError::TimestampTimeout => libc::EAGAIN,
+        TsnError::TimestampMissing(_) => libc::ENOMSG,
+        TsnError::TxtimeDropped { .. } => libc::ECANCELED,
+        TsnError::PtpManagement { .. } => libc::EPROTO,
+        _ => e.raw_os_error().unwrap_or(libc::EIO),
+    }
+}
+
+unsafe fn bytes<'a>(buf: *const u8, n: usize) -> &'a [u8] {
+    if n == 0 {
+        &[]
+    } else {
+        slice::from_raw_parts(buf, n)
+    }
+}
+
+unsafe fn bytes_mut<'a>(buf: *mut u8, n: usize) -> &'a mut [u8] {
+    if n == 0 {
+        &mut []
+    } else {
+        slice::from_raw_parts_mut(buf, n)
+    }
+}
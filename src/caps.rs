@@ -0,0 +1,325 @@
+//! What an interface can do for TSN, probed before sockets and qdiscs are set up
+//!
+//! [`probe`] asks the driver over ethtool. Socket and qdisc setup use the result to
+//! pick hardware timestamps and offloads where they work, and to refuse configs the
+//! interface cannot honour up front instead of failing halfway through.
+//!
+//! ```no_run
+//! let caps = tsn::caps::probe("eth0")?;
+//! println!("TX queues: {:?}, TC offload: {}", caps.tx_queues, caps.tc_offload);
+//! # Ok::<(), tsn::TsnError>(())
+//! ```
+
+use std::fmt;
+use std::fs;
+
+use nix::libc;
+use nix::net::if_::if_nametoindex;
+
+use crate::netlink::{attr_string, attrs, Message, Netlink, GENL_HDRLEN};
+use crate::phc::{self, TsInfo};
+use crate::sys::ethtool::*;
+use crate::tas::{TasConfig, TasMode};
+use crate::TsnError;
+
+/// Feature of an interface, as ethtool reports it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Feature {
+    /// Could not be probed, e.g. the kernel is too old to tell
+    Unknown,
+    /// The driver does not have it
+    Unsupported,
+    /// Supported but turned off, ethtool can turn it on
+    Off,
+    On,
+}
+
+impl fmt::Display for Feature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Feature::Unknown => "unknown",
+            Feature::Unsupported => "unsupported",
+            Feature::Off => "off",
+            Feature::On => "on",
+        })
+    }
+}
+
+/// Timestamping and TSN capabilities of an interface
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Capabilities {
+    pub ifname: String,
+    /// `None` if the driver does not answer `ETHTOOL_GET_TS_INFO`
+    pub timestamping: Option<TsInfo>,
+    /// Number of TX queues, one per traffic class of taprio and mqprio
+    pub tx_queues: Option<u32>,
+    /// `hw-tc-offload`, the NIC runs qdiscs such as taprio, cbs and etf. Whether the
+    /// driver implements a given qdisc only shows when setting it up
+    pub tc_offload: Feature,
+    /// Frame preemption, the MAC merge layer of 802.3br
+    pub preemption: Feature,
+}
+
+/// Probe `ifname`. Only a missing interface is an error, what cannot be probed is
+/// left unknown
+pub fn probe(ifname: &str) -> Result<Capabilities, TsnError> {
+    if if_nametoindex(ifname).is_err() {
+        return Err(TsnError::InterfaceNotFound(ifname.to_string()));
+    }
+    let ethtool = Ethtool::open();
+    let ethtool = ethtool.as_ref();
+    Ok(Capabilities {
+        ifname: ifname.to_string(),
+        timestamping: phc::ts_info(ifname).ok(),
+        tx_queues: tx_queues(ifname),
+        tc_offload: ethtool.map_or(Feature::Unknown, |e| e.feature(ifname, "hw-tc-offload")),
+        preemption: ethtool.map_or(Feature::Unknown, |e| e.preemption(ifname)),
+    })
+}
+
+impl Capabilities {
+    /// Nothing known, e.g. for a dry run on an interface which does not exist here.
+    /// Setup then trusts the config and offloads by default
+    pub fn unknown(ifname: &str) -> Capabilities {
+        Capabilities {
+            ifname: ifname.to_string(),
+            timestamping: None,
+            tx_queues: None,
+            tc_offload: Feature::Unknown,
+            preemption: Feature::Unknown,
+        }
+    }
+
+    /// Whether the NIC can timestamp sent frames
+    pub fn hw_tx_timestamps(&self) -> Option<bool> {
+        self.timestamping.map(|info| {
+            info.so_timestamping & libc::SOF_TIMESTAMPING_TX_HARDWARE != 0
+                && info.tx_types & (1 << libc::HWTSTAMP_TX_ON) != 0
+        })
+    }
+
+    /// Widest `HWTSTAMP_FILTER_*` to timestamp received frames with, `None` if the NIC
+    /// cannot or it is unknown
+    pub fn rx_filter(&self) -> Option<u32> {
+        let info = self.timestamping?;
+        if info.so_timestamping & libc::SOF_TIMESTAMPING_RX_HARDWARE == 0 {
+            return None;
+        }
+        [
+            libc::HWTSTAMP_FILTER_ALL,
+            libc::HWTSTAMP_FILTER_PTP_V2_EVENT,
+            libc::HWTSTAMP_FILTER_PTP_V2_L2_EVENT,
+        ]
+        .into_iter()
+        .find(|filter| info.rx_filters & (1 << filter) != 0)
+    }
+
+    /// Fail unless there is a TX queue for each of `num_tc` traffic classes
+    pub fn check_queues(&self, num_tc: i64) -> Result<(), TsnError> {
+        match self.tx_queues {
+            Some(queues) if (queues as i64) < num_tc => Err(TsnError::Unsupported(format!(
+                "{} traffic classes need as many TX queues, {} has {}",
+                num_tc, self.ifname, queues
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Mode to run the taprio of `config` in, never [`TasMode::Auto`]
+    pub fn tas_mode(&self, config: &TasConfig) -> Result<TasMode, TsnError> {
+        match config.mode {
+            TasMode::Auto if config.txtime_delay != 0 => Ok(TasMode::TxtimeAssist),
+            TasMode::Auto => Ok(match self.tc_offload {
+                Feature::On | Feature::Unknown => TasMode::FullOffload,
+                Feature::Off | Feature::Unsupported => TasMode::Software,
+            }),
+            TasMode::FullOffload => self
+                .offload(Some(true), "taprio")
+                .map(|_| TasMode::FullOffload),
+            mode => Ok(mode),
+        }
+    }
+
+    /// Whether to offload `qdisc`, given the `offload` of its config. `None` offloads
+    /// if the NIC has TC offload, or if that is unknown
+    pub fn offload(&self, requested: Option<bool>, qdisc: &str) -> Result<bool, TsnError> {
+        match (requested, self.tc_offload) {
+            (Some(true), Feature::Unsupported) => Err(TsnError::Unsupported(format!(
+                "{} offload is requested, but {} has no hw-tc-offload",
+                qdisc, self.ifname
+            ))),
+            (Some(true), Feature::Off) => Err(TsnError::Unsupported(format!(
+                "{} offload is requested, but hw-tc-offload is off. \
+                 Turn it on with `ethtool -K {} hw-tc-offload on`",
+                qdisc, self.ifname
+            ))),
+            (Some(offload), _) => Ok(offload),
+            (None, feature) => Ok(matches!(feature, Feature::On | Feature::Unknown)),
+        }
+    }
+}
+
+/// Probe `ifname`, or [`Capabilities::unknown`] if it does not exist
+pub(crate) fn probe_or_unknown(ifname: &str) -> Capabilities {
+    probe(ifname).unwrap_or_else(|_| Capabilities::unknown(ifname))
+}
+
+fn tx_queues(ifname: &str) -> Option<u32> {
+    let entries = fs::read_dir(format!("/sys/class/net/{}/queues", ifname)).ok()?;
+    let count = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_name().to_string_lossy().starts_with("tx-"))
+        .count();
+    Some(count as u32)
+}
+
+/// Generic netlink socket of the ethtool family, kernel 5.6 and later
+struct Ethtool {
+    nl: Netlink,
+    family: u16,
+}
+
+impl Ethtool {
+    fn open() -> Option<Ethtool> {
+        let nl = Netlink::open_generic().ok()?;
+        let family = nl.family_id(ETHTOOL_GENL_NAME).ok()?;
+        Some(Ethtool { nl, family })
+    }
+
+    /// Attributes of the reply to `cmd` for `ifname`, `what` describes it in errors
+    fn get(
+        &self,
+        cmd: u8,
+        header_attr: u16,
+        ifname: &str,
+        what: &str,
+    ) -> Result<Vec<u8>, TsnError> {
+        let header = libc::genlmsghdr {
+            cmd,
+            version: ETHTOOL_GENL_VERSION,
+            reserved: 0,
+        };
+        let mut msg = Message::new(self.family, 0, &header);
+        msg.nested(header_attr, |msg| {
+            msg.attr_str(ETHTOOL_A_HEADER_DEV_NAME, ifname);
+        });
+        let op = format!("Get {} of {}", what, ifname);
+        let mut replies = self.nl.query(msg, &op)?;
+        let reply = replies.pop().unwrap_or_default();
+        Ok(reply.get(GENL_HDRLEN..).unwrap_or_default().to_vec())
+    }
+
+    /// State of the netdev feature `name`, e.g. `hw-tc-offload`
+    fn feature(&self, ifname: &str, name: &str) -> Feature {
+        let Ok(reply) = self.get(
+            ETHTOOL_MSG_FEATURES_GET,
+            ETHTOOL_A_FEATURES_HEADER,
+            ifname,
+            "features",
+        ) else {
+            return Feature::Unknown;
+        };
+        // HW has the features which can be changed set, ACTIVE those which are on
+        let has = |set: u16| {
+            attrs(&reply)
+                .filter(|(attr_type, _)| *attr_type == set)
+                .any(|(_, bitset)| bitset_has(bitset, name))
+        };
+        if has(ETHTOOL_A_FEATURES_ACTIVE) {
+            Feature::On
+        } else if has(ETHTOOL_A_FEATURES_HW) {
+            Feature::Off
+        } else {
+            Feature::Unsupported
+        }
+    }
+
+    fn preemption(&self, ifname: &str) -> Feature {
+        let reply = match self.get(ETHTOOL_MSG_MM_GET, ETHTOOL_A_MM_HEADER, ifname, "MAC merge") {
+            Ok(reply) => reply,
+            // The driver has no MAC merge layer, or the kernel does not know it (< 6.3)
+            Err(e) if e.raw_os_error() == Some(libc::EOPNOTSUPP) => return Feature::Unsupported,
+            Err(_) => return Feature::Unknown,
+        };
+        let enabled = |attr: u16| {
+            attrs(&reply).any(|(attr_type, value)| attr_type == attr && value.first() == Some(&1))
+        };
+        if enabled(ETHTOOL_A_MM_PMAC_ENABLED) && enabled(ETHTOOL_A_MM_TX_ENABLED) {
+            Feature::On
+        } else {
+            Feature::Off
+        }
+    }
+}
+
+/// Whether the bit `name` of an ethtool bitset is set
+fn bitset_has(bitset: &[u8], name: &str) -> bool {
+    let list = attrs(bitset).any(|(attr_type, _)| attr_type == ETHTOOL_A_BITSET_NOMASK);
+    attrs(bitset)
+        .filter(|(attr_type, _)| *attr_type == ETHTOOL_A_BITSET_BITS)
+        .flat_map(|(_, bits)| attrs(bits))
+        .filter(|(attr_type, _)| *attr_type == ETHTOOL_A_BITSET_BITS_BIT)
+        .any(|(_, bit)| {
+            let named = attrs(bit).any(|(attr_type, value)| {
+                attr_type == ETHTOOL_A_BITSET_BIT_NAME && attr_string(value) == name
+            });
+            named
+                && (list
+                    || attrs(bit).any(|(attr_type, _)| attr_type == ETHTOOL_A_BITSET_BIT_VALUE))
+        })
+}
+
+/// Names of the `SOF_TIMESTAMPING_*` capabilities in `flags`, as `ethtool -T` shows them
+pub fn timestamping_names(flags: u32) -> Vec<&'static str> {
+    bit_names(
+        flags,
+        &[
+            "hardware-transmit",
+            "software-transmit",
+            "hardware-receive",
+            "software-receive",
+            "software-system-clock",
+            "hardware-legacy-clock",
+            "hardware-raw-clock",
+        ],
+    )
+}
+
+/// Names of the `HWTSTAMP_TX_*` types in the mask `tx_types`
+pub fn tx_type_names(tx_types: u32) -> Vec<&'static str> {
+    bit_names(tx_types, &["off", "on", "onestep-sync", "onestep-p2p"])
+}
+
+/// Names of the `HWTSTAMP_FILTER_*` filters in the mask `rx_filters`
+pub fn rx_filter_names(rx_filters: u32) -> Vec<&'static str> {
+    bit_names(
+        rx_filters,
+        &[
+            "none",
+            "all",
+            "some",
+            "ptpv1-l4-event",
+            "ptpv1-l4-sync",
+            "ptpv1-l4-delay-req",
+            "ptpv2-l4-event",
+            "ptpv2-l4-sync",
+            "ptpv2-l4-delay-req",
+            "ptpv2-l2-event",
+            "ptpv2-l2-sync",
+            "ptpv2-l2-delay-req",
+            "ptpv2-event",
+            "ptpv2-sync",
+            "ptpv2-delay-req",
+            "ntp-all",
+        ],
+    )
+}
+
+fn bit_names(mask: u32, names: &[&'static str]) -> Vec<&'static str> {
+    names
+        .iter()
+        .enumerate()
+        .filter(|(bit, _)| mask & (1 << bit) != 0)
+        .map(|(_, name)| *name)
+        .collect()
+}
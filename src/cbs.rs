@@ -1,7 +1,8 @@
+use crate::tas::to_opt_bool;
+use crate::TsnError;
 use serde_yaml::{self, Value};
 use std::collections::HashMap;
 use std::process::Command;
-use std::str;
 #[derive(Clone)]
 pub struct CbsChild {
     #[allow(dead_code)] // Leave this field since it might be used for debug
@@ -20,6 +21,8 @@ pub struct CbsCredit {
 
 #[derive(Clone)]
 pub struct CbsConfig {
+    /// `None` to offload if the NIC has TC offload
+    pub offload: Option<bool>,
     pub tc_map: HashMap<i64, i64>,
     pub num_tc: i64,
     pub queues: Vec<String>,
@@ -28,37 +31,43 @@ pub struct CbsConfig {
     pub streams: HashMap<char, Vec<CbsChild>>,
 }
 
-pub fn get_linkspeed(ifname: &str) -> Result<String, String> {
-    let interface = interfaces::Interface::get_by_name(ifname);
-    if interface.is_err() || interface.unwrap().is_none() {
-        return Err(format!("Interface {} not found", ifname));
+pub fn get_linkspeed(ifname: &str) -> Result<String, TsnError> {
+    match interfaces::Interface::get_by_name(ifname) {
+        Ok(Some(_)) => {}
+        _ => return Err(TsnError::InterfaceNotFound(ifname.to_string())),
     }
 
-    let output = Command::new("ethtool").arg(ifname).output();
-    match output {
-        Ok(output) => {
-            let out = str::from_utf8(&output.stdout).unwrap();
-            let pattern = regex::Regex::new(r"Speed: (?P<speed>\d+(?:|k|M|G)b[p/]?s)").unwrap();
-            match pattern.captures(out) {
-                Some(matched) => {
-                    return Ok(matched.name("speed").unwrap().as_str().to_string());
-                }
-                None => {
-                    return Err(format!("Speed cannot be found for {}", ifname));
-                }
-            }
-        }
-        Err(e) => Err(e.to_string()),
+    let cmd = format!("ethtool {}", ifname);
+    let output = Command::new("ethtool")
+        .arg(ifname)
+        .output()
+        .map_err(|source| TsnError::CommandSpawn {
+            cmd: cmd.clone(),
+            source,
+        })?;
+    if !output.status.success() {
+        return Err(TsnError::CommandFailed {
+            cmd,
+            status: output.status.code(),
+            stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+        });
+    }
+    let out = String::from_utf8_lossy(&output.stdout);
+    let pattern = regex::Regex::new(r"Speed: (?P<speed>\d+(?:|k|M|G)b[p/]?s)").unwrap();
+    match pattern.captures(&out) {
+        Some(matched) => Ok(matched.name("speed").unwrap().as_str().to_string()),
+        // `Speed: Unknown!` while the link is down
+        None => Err(TsnError::LinkSpeedUnknown(ifname.to_string())),
     }
 }
-pub fn to_bits(input: &Value) -> Result<i64, String> {
+pub fn to_bits(input: &Value) -> Result<i64, TsnError> {
     if let Some(value) = input.as_str() {
         let matched =
             regex::Regex::new(r"^(?P<v>[\d_]+)\s*(?P<modifier>|k|M|G|ki|Mi|Gi)(?P<b>b|B)$")
                 .unwrap()
                 .captures(value)
-                .unwrap();
-        let v = matched.name("v").unwrap().as_str().parse::<i64>().unwrap();
+                .ok_or_else(|| TsnError::ConfigInvalid(format!("{} is not valid size", value)))?;
+        let v = parse_number(matched.name("v").unwrap().as_str(), value)?;
         let modifier = matched.name("modifier").unwrap().as_str();
         let b = matched.name("b").unwrap().as_str();
         let multiplier_bits = match b {
@@ -78,16 +87,18 @@ pub fn to_bits(input: &Value) -> Result<i64, String> {
         };
         return Ok(v * multiplier_bits * multiplier_modifier);
     }
-    Ok(input.as_i64().unwrap())
+    input
+        .as_i64()
+        .ok_or_else(|| TsnError::ConfigInvalid(format!("Cannot convert {:?} to bits", input)))
 }
 
-pub fn to_bps(input: &Value) -> Result<i64, String> {
+pub fn to_bps(input: &Value) -> Result<i64, TsnError> {
     if let Some(value) = input.as_str() {
         let matched = regex::Regex::new(r"^(?P<v>[\d_]+)\s*(?P<modifier>|k|M|G)(?P<b>b|B)[p/]s$")
             .unwrap()
             .captures(value)
-            .unwrap();
-        let v = matched.name("v").unwrap().as_str().parse::<i64>().unwrap();
+            .ok_or_else(|| TsnError::ConfigInvalid(format!("{} is not valid bandwidth", value)))?;
+        let v = parse_number(matched.name("v").unwrap().as_str(), value)?;
         let modifier = matched.name("modifier").unwrap().as_str();
         return {
             match modifier {
@@ -95,11 +106,23 @@ pub fn to_bps(input: &Value) -> Result<i64, String> {
                 "k" => Ok(v * 1000),
                 "M" => Ok(v * 1000 * 1000),
                 "G" => Ok(v * 1000 * 1000 * 1000),
-                _ => Err(format!("{} is not valid bandwidth", value)),
+                _ => Err(TsnError::ConfigInvalid(format!(
+                    "{} is not valid bandwidth",
+                    value
+                ))),
             }
         };
     }
-    Ok(input.as_i64().unwrap())
+    input
+        .as_i64()
+        .ok_or_else(|| TsnError::ConfigInvalid(format!("Cannot convert {:?} to bps", input)))
+}
+
+fn parse_number(digits: &str, value: &str) -> Result<i64, TsnError> {
+    digits
+        .replace('_', "")
+        .parse::<i64>()
+        .map_err(|e| TsnError::ConfigInvalid(format!("{} is not a valid number: {}", value, e)))
 }
 
 pub fn calc_credits(
@@ -144,7 +167,7 @@ pub fn calc_credits(
     (credits_a, credits_b)
 }
 
-pub fn normalise_cbs(ifname: &str, config: &Value) -> Result<CbsConfig, String> {
+pub fn normalise_cbs(ifname: &str, config: &Value) -> Result<CbsConfig, TsnError> {
     let mut tc_map = HashMap::new();
     let mut ret_map = HashMap::new();
     let link = get_linkspeed(ifname);
@@ -157,34 +180,39 @@ pub fn normalise_cbs(ifname: &str, config: &Value) -> Result<CbsConfig, String>
         Ok(speed) => to_bps(&Value::String(speed))?,
         Err(_) => 1_000_000_000, // 1000Mbps
     };
-    for (prio, priomap) in config.as_mapping().unwrap() {
-        if !tc_map.contains_key(&prio.as_i64().unwrap()) {
-            tc_map.insert(prio.as_i64().unwrap(), tc_map.len() as i64);
+    let offload = to_opt_bool(config, "offload")?;
+    let config = config
+        .as_mapping()
+        .ok_or_else(|| TsnError::ConfigInvalid("cbs should be a dictionary".into()))?;
+    for (prio, priomap) in config {
+        if prio.as_str() == Some("offload") {
+            continue;
+        }
+        let prio = prio
+            .as_i64()
+            .ok_or_else(|| TsnError::ConfigInvalid(format!("cbs key {:?} is not a prio", prio)))?;
+        if !tc_map.contains_key(&prio) {
+            tc_map.insert(prio, tc_map.len() as i64);
         }
         let child = CbsChild {
-            prio: prio.as_i64().unwrap(),
-            max_frame: to_bits(
-                priomap
-                    .get(&Value::String("max_frame".to_string()))
-                    .expect("max_frame should be present"),
-            )?,
-            bandwidth: to_bps(
-                priomap
-                    .get(&Value::String("bandwidth".to_string()))
-                    .expect("bandwidth should be present"),
-            )?,
+            prio,
+            max_frame: to_bits(priomap.get("max_frame").ok_or_else(|| {
+                TsnError::ConfigInvalid(format!("max_frame should be present for prio {}", prio))
+            })?)?,
+            bandwidth: to_bps(priomap.get("bandwidth").ok_or_else(|| {
+                TsnError::ConfigInvalid(format!("bandwidth should be present for prio {}", prio))
+            })?)?,
         };
         let index = priomap
-            .get(Value::String("class".to_string()).as_str().unwrap())
-            .expect("class should be present")
-            .as_str()
-            .expect("class should be a string")
-            .chars()
-            .next()
-            .expect("Cannot parse class");
+            .get("class")
+            .and_then(|class| class.as_str())
+            .and_then(|class| class.chars().next())
+            .ok_or_else(|| {
+                TsnError::ConfigInvalid(format!("class should be present for prio {}", prio))
+            })?;
         streams
             .get_mut(&index)
-            .expect("Cannot parse cbs class")
+            .ok_or_else(|| TsnError::ConfigInvalid(format!("Unknown cbs class {}", index)))?
             .push(child);
     }
     tc_map.insert(-1, tc_map.len() as i64);
@@ -203,6 +231,7 @@ pub fn normalise_cbs(ifname: &str, config: &Value) -> Result<CbsConfig, String>
         queues.push(format!("1@{}", i));
     }
     Ok(CbsConfig {
+        offload,
         tc_map: ret_map,
         num_tc,
         queues,
@@ -1,5 +1,7 @@
 use crate::cbs::{normalise_cbs, CbsConfig};
+use crate::ptp::{normalise_ptp, PtpConfig};
 use crate::tas::{normalise_tas, TasConfig};
+use crate::TsnError;
 use serde_yaml::{self, Value};
 use std::collections::HashMap;
 use std::fs::File;
@@ -10,6 +12,8 @@ use std::str;
 pub struct Config {
     pub tas: Option<TasConfig>,
     pub cbs: Option<CbsConfig>,
+    /// linuxptp settings, see `tsnlib ptp-config`
+    pub ptp: Option<PtpConfig>,
 }
 
 impl Config {
@@ -17,55 +21,52 @@ impl Config {
         Config {
             tas: None,
             cbs: None,
+            ptp: None,
         }
     }
 }
 
-pub fn read_config(config_path: &str) -> Result<HashMap<String, Config>, i64> {
-    let file = File::open(config_path).expect("failed to open config.yaml");
+impl Default for Config {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub fn read_config(config_path: &str) -> Result<HashMap<String, Config>, TsnError> {
+    let file = File::open(config_path).map_err(|source| TsnError::ConfigIo {
+        path: config_path.to_string(),
+        source,
+    })?;
     let reader = BufReader::new(file);
-    let config: Value = serde_yaml::from_reader(reader).expect("failed to parse YAML");
+    let config: Value =
+        serde_yaml::from_reader(reader).map_err(|source| TsnError::ConfigParse {
+            path: config_path.to_string(),
+            source,
+        })?;
     let config = config
         .as_mapping()
-        .expect("config file should be a dictionary")
+        .ok_or_else(|| TsnError::ConfigInvalid("config file should be a dictionary".into()))?
         .get(&Value::String("nics".to_string()))
-        .expect("config file should have a 'nics' key")
+        .ok_or_else(|| TsnError::ConfigInvalid("config file should have a 'nics' key".into()))?
         .as_mapping()
-        .expect("'nics' value should be a dictionary");
-    let mut ifname;
+        .ok_or_else(|| TsnError::ConfigInvalid("'nics' value should be a dictionary".into()))?;
     let mut ret = HashMap::new();
     for (key, value) in config {
         let mut info = Config::new();
-        let value = value
-            .as_mapping()
-            .expect("config value should be a dictionary");
-        ifname = key.as_str().unwrap();
-        if value.contains_key(&Value::String("tas".to_string())) {
-            match normalise_tas(
-                value
-                    .get(&Value::String("tas".to_string()))
-                    .expect("tas should be a dictionary"),
-            ) {
-                Ok(tas) => info.tas = Some(tas),
-                Err(e) => {
-                    eprintln!("{}", e);
-                    return Err(-1);
-                }
-            }
+        let ifname = key.as_str().ok_or_else(|| {
+            TsnError::ConfigInvalid(format!("{:?} is not an interface name", key))
+        })?;
+        let value = value.as_mapping().ok_or_else(|| {
+            TsnError::ConfigInvalid(format!("{} value should be a dictionary", ifname))
+        })?;
+        if let Some(tas) = value.get(&Value::String("tas".to_string())) {
+            info.tas = Some(normalise_tas(tas)?);
+        }
+        if let Some(cbs) = value.get(&Value::String("cbs".to_string())) {
+            info.cbs = Some(normalise_cbs(ifname, cbs)?);
         }
-        if value.contains_key(&Value::String("cbs".to_string())) {
-            match normalise_cbs(
-                ifname,
-                value
-                    .get(&Value::String("cbs".to_string()))
-                    .expect("cbs should be a dictionary"),
-            ) {
-                Ok(cbs) => info.cbs = Some(cbs),
-                Err(e) => {
-                    eprintln!("{}", e);
-                    return Err(-1);
-                }
-            }
+        if let Some(ptp) = value.get(&Value::String("ptp".to_string())) {
+            info.ptp = Some(normalise_ptp(ptp)?);
         }
         ret.insert(ifname.to_string(), info);
     }
@@ -0,0 +1,202 @@
+use std::{fmt, io};
+
+/// Error type shared by every public function of the library
+#[derive(Debug)]
+pub enum TsnError {
+    /// Config file could not be opened or read
+    ConfigIo { path: String, source: io::Error },
+    /// Config file is not valid YAML
+    ConfigParse {
+        path: String,
+        source: serde_yaml::Error,
+    },
+    /// Config file is valid YAML but a key is missing or has a wrong value
+    ConfigInvalid(String),
+    /// Interface has no entry under `nics` in the config file
+    ConfigNotFound { ifname: String, path: String },
+    /// Network interface does not exist
+    InterfaceNotFound(String),
+    /// ethtool reports no link speed of the interface, e.g. while its link is down
+    LinkSpeedUnknown(String),
+    /// Requested setup is not supported (e.g. TAS and CBS on the same interface)
+    Unsupported(String),
+    /// `ip`/`tc`/`ethtool` could not be spawned
+    CommandSpawn { cmd: String, source: io::Error },
+    /// `ip`/`tc`/`ethtool` ran but exited with failure
+    CommandFailed {
+        cmd: String,
+        status: Option<i32>,
+        stderr: String,
+    },
+    /// Kernel refused an rtnetlink request. `message` is its extended ACK, if any
+    Netlink {
+        op: String,
+        source: io::Error,
+        message: Option<String>,
+    },
+    /// VLAN or qdisc setup/teardown failed
+    Vlan {
+        op: &'static str,
+        name: String,
+        source: Box<TsnError>,
+    },
+    /// Holder file of the VLAN refcount could not be used
+    Holder { op: &'static str, source: io::Error },
+    /// Socket syscall failed, `source` carries the errno
+    Socket { op: &'static str, source: io::Error },
+    /// PHC or system clock could not be opened, read or adjusted
+    Clock { op: &'static str, source: io::Error },
+    /// Timestamping was not enabled on the socket
+    TimestampNotEnabled,
+    /// The NIC has no hardware timestamps or `SIOCSHWTSTAMP` failed. Software
+    /// timestamps are still enabled
+    HwTimestampUnsupported(io::Error),
+    /// No timestamp arrived on the error queue in time
+    TimestampTimeout,
+    /// Message was received but carried no usable timestamp
+    TimestampMissing(String),
+    /// ETF qdisc dropped a frame sent with [`send_at`](crate::send_at)
+    TxtimeDropped {
+        txtime: u64,
+        reason: TxtimeDropReason,
+    },
+    /// ptp4l refused a management request with `MANAGEMENT_ERROR_STATUS`
+    PtpManagement {
+        id: u16,
+        error: u16,
+        message: String,
+    },
+}
+
+/// Why the ETF qdisc dropped a frame, from `SO_EE_CODE_TXTIME_*`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TxtimeDropReason {
+    /// Clock id or flags do not match the qdisc
+    InvalidParam,
+    /// Launch time was already in the past when the frame was dequeued
+    Missed,
+    Unknown(u8),
+}
+
+impl TsnError {
+    pub(crate) fn socket(op: &'static str) -> TsnError {
+        TsnError::Socket {
+            op,
+            source: io::Error::last_os_error(),
+        }
+    }
+
+    pub(crate) fn clock(op: &'static str) -> TsnError {
+        TsnError::Clock {
+            op,
+            source: io::Error::last_os_error(),
+        }
+    }
+
+    pub(crate) fn holder(op: &'static str) -> impl FnOnce(io::Error) -> TsnError {
+        move |source| TsnError::Holder { op, source }
+    }
+
+    /// The OS error code that caused this error, if any
+    pub fn raw_os_error(&self) -> Option<i32> {
+        match self {
+            TsnError::ConfigIo { source, .. }
+            | TsnError::CommandSpawn { source, .. }
+            | TsnError::Netlink { source, .. }
+            | TsnError::Holder { source, .. }
+            | TsnError::Socket { source, .. }
+            | TsnError::Clock { source, .. }
+            | TsnError::HwTimestampUnsupported(source) => source.raw_os_error(),
+            TsnError::Vlan { source, .. } => source.raw_os_error(),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for TsnError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TsnError::ConfigIo { path, source } => write!(f, "Read config {} fails: {}", path, source),
+            TsnError::ConfigParse { path, source } => {
+                write!(f, "Parse config {} fails: {}", path, source)
+            }
+            TsnError::ConfigInvalid(msg) => write!(f, "Invalid config: {}", msg),
+            TsnError::ConfigNotFound { ifname, path } => write!(f, "{} not found in {}", ifname, path),
+            TsnError::InterfaceNotFound(ifname) => write!(f, "Interface {} not found", ifname),
+            TsnError::LinkSpeedUnknown(ifname) => {
+                write!(f, "Link speed of {} is unknown, is the link up?", ifname)
+            }
+            TsnError::Unsupported(msg) => write!(f, "{}", msg),
+            TsnError::CommandSpawn { cmd, source } => write!(f, "Cannot run `{}`: {}", cmd, source),
+            TsnError::CommandFailed {
+                cmd,
+                status,
+                stderr,
+            } => {
+                match status {
+                    Some(code) => write!(f, "`{}` exited with {}", cmd, code)?,
+                    None => write!(f, "`{}` was killed by a signal", cmd)?,
+                }
+                if !stderr.trim().is_empty() {
+                    write!(f, ": {}", stderr.trim())?;
+                }
+                Ok(())
+            }
+            TsnError::Netlink {
+                op,
+                source,
+                message,
+            } => {
+                write!(f, "{} fails: {}", op, source)?;
+                if let Some(message) = message {
+                    write!(f, " ({})", message)?;
+                }
+                Ok(())
+            }
+            TsnError::Vlan { op, name, source } => write!(f, "{} vlan {} fails: {}", op, name, source),
+            TsnError::Holder { op, source } => write!(f, "{} vlan holder fails: {}", op, source),
+            TsnError::Socket { op, source } => write!(f, "{} error: {}", op, source),
+            TsnError::Clock { op, source } => write!(f, "{} fails: {}", op, source),
+            TsnError::TimestampNotEnabled => write!(f, "Timestamp not enabled"),
+            TsnError::HwTimestampUnsupported(source) => write!(
+                f,
+                "Hardware timestamping is not supported ({}). Falling back to software timestamping.",
+                source
+            ),
+            TsnError::TimestampTimeout => write!(f, "Timestamp poll timeout"),
+            TsnError::TimestampMissing(msg) => write!(f, "No timestamp: {}", msg),
+            TsnError::TxtimeDropped { txtime, reason } => {
+                write!(f, "Frame with txtime {} dropped: ", txtime)?;
+                match reason {
+                    TxtimeDropReason::InvalidParam => write!(f, "invalid parameter"),
+                    TxtimeDropReason::Missed => write!(f, "deadline missed"),
+                    TxtimeDropReason::Unknown(code) => write!(f, "unknown reason {}", code),
+                }
+            }
+            TsnError::PtpManagement { id, error, message } => {
+                write!(f, "PTP management {:#06x} fails with error {:#06x}", id, error)?;
+                if !message.is_empty() {
+                    write!(f, ": {}", message)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for TsnError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            TsnError::ConfigIo { source, .. }
+            | TsnError::CommandSpawn { source, .. }
+            | TsnError::Netlink { source, .. }
+            | TsnError::Holder { source, .. }
+            | TsnError::Socket { source, .. }
+            | TsnError::Clock { source, .. }
+            | TsnError::HwTimestampUnsupported(source) => Some(source),
+            TsnError::ConfigParse { source, .. } => Some(source),
+            TsnError::Vlan { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
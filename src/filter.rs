@@ -0,0 +1,160 @@
+//! Socket filters, so only the frames of a stream wake the receiver up
+//!
+//! A [`Filter`] is a list of predicates which all have to hold. It compiles to a
+//! classic BPF program attached with `SO_ATTACH_FILTER` by
+//! [`attach_filter`](crate::attach_filter), which runs in the kernel before a frame is
+//! queued to the socket. Frames are matched as the socket gets them: without the
+//! 802.1Q tag, which the kernel keeps next to the frame. [`Filter::pcp`] only works on
+//! sockets which still see the tag: those bound to all protocols (`ETH_P_ALL`) of an
+//! interface without VLAN. The kernel drops the tag before it hands a frame to a VLAN
+//! interface or to sockets bound to one protocol.
+//!
+//! ```no_run
+//! use tsn::filter::Filter;
+//! use tsn::TsnSocket;
+//!
+//! let sock = TsnSocket::builder("eth0").vlan(10).protocol(0x22f0).open()?;
+//! // Only stream 7 of 00:11:22:33:44:55, its ID in the first 2 bytes of the payload
+//! let filter = Filter::new()
+//!     .src([0x00, 0x11, 0x22, 0x33, 0x44, 0x55])
+//!     .payload(0, &7u16.to_be_bytes());
+//! sock.attach_filter(&filter)?;
+//! # Ok::<(), tsn::TsnError>(())
+//! ```
+
+use nix::libc;
+
+use crate::TsnError;
+
+/// Offset of the EtherType in a frame
+const ETHERTYPE_OFFSET: u32 = 12;
+/// Offset of the payload in a frame, the VLAN tag is never in the frame data
+const PAYLOAD_OFFSET: u32 = 14;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Check {
+    /// `value` at `offset` of the frame
+    Bytes { offset: u32, value: Vec<u8> },
+    /// PCP of the VLAN tag kept by the kernel
+    Pcp(u8),
+}
+
+/// Predicates a frame has to match to reach the socket. Matches every frame if empty
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Filter {
+    checks: Vec<Check>,
+}
+
+impl Filter {
+    pub fn new() -> Filter {
+        Filter::default()
+    }
+
+    /// EtherType after the VLAN tag, if any
+    pub fn ethertype(self, ethertype: u16) -> Filter {
+        self.bytes(ETHERTYPE_OFFSET, &ethertype.to_be_bytes())
+    }
+
+    /// Source MAC address
+    pub fn src(self, mac: [u8; 6]) -> Filter {
+        self.bytes(6, &mac)
+    }
+
+    /// Destination MAC address
+    pub fn dst(self, mac: [u8; 6]) -> Filter {
+        self.bytes(0, &mac)
+    }
+
+    /// PCP of the VLAN tag, frames without tag do not match
+    pub fn pcp(mut self, pcp: u8) -> Filter {
+        self.checks.push(Check::Pcp(pcp & 0x7));
+        self
+    }
+
+    /// `value` at `offset` of the payload after the Ethernet header, e.g. a stream ID
+    pub fn payload(self, offset: u32, value: &[u8]) -> Filter {
+        self.bytes(PAYLOAD_OFFSET + offset, value)
+    }
+
+    fn bytes(mut self, offset: u32, value: &[u8]) -> Filter {
+        self.checks.push(Check::Bytes {
+            offset,
+            value: value.to_vec(),
+        });
+        self
+    }
+
+    /// Whether the filter matches on the VLAN tag
+    pub(crate) fn uses_vlan_tag(&self) -> bool {
+        self.checks
+            .iter()
+            .any(|check| matches!(check, Check::Pcp(_)))
+    }
+
+    /// Classic BPF program of the filter for frames starting at `base` of what the
+    /// socket receives, e.g. after a header of the virtual wire
+    pub fn program(&self, base: u32) -> Result<Vec<libc::sock_filter>, TsnError> {
+        const LD_W: u16 = (libc::BPF_LD | libc::BPF_W | libc::BPF_ABS) as u16;
+        const LD_H: u16 = (libc::BPF_LD | libc::BPF_H | libc::BPF_ABS) as u16;
+        const LD_B: u16 = (libc::BPF_LD | libc::BPF_B | libc::BPF_ABS) as u16;
+        const RSH_K: u16 = (libc::BPF_ALU | libc::BPF_RSH | libc::BPF_K) as u16;
+        const AND_K: u16 = (libc::BPF_ALU | libc::BPF_AND | libc::BPF_K) as u16;
+        const JEQ_K: u16 = (libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K) as u16;
+        const RET_K: u16 = (libc::BPF_RET | libc::BPF_K) as u16;
+        let ancillary = |offset: libc::c_int| (libc::SKF_AD_OFF + offset) as u32;
+
+        // Jumps to the final "drop" are resolved once the length is known
+        const DROP: u8 = u8::MAX;
+        let mut insns = Vec::new();
+        let mut insn = |code: u16, jt: u8, jf: u8, k: u32| {
+            insns.push(libc::sock_filter { code, jt, jf, k });
+        };
+        for check in &self.checks {
+            match check {
+                Check::Bytes { offset, value } => {
+                    let mut at = 0;
+                    while at < value.len() {
+                        let (code, size) = match value.len() - at {
+                            4.. => (LD_W, 4),
+                            2..=3 => (LD_H, 2),
+                            _ => (LD_B, 1),
+                        };
+                        let k = value[at..at + size]
+                            .iter()
+                            .fold(0u32, |k, byte| k << 8 | *byte as u32);
+                        insn(code, 0, 0, base + offset + at as u32);
+                        insn(JEQ_K, 0, DROP, k);
+                        at += size;
+                    }
+                }
+                Check::Pcp(pcp) => {
+                    insn(LD_W, 0, 0, ancillary(libc::SKF_AD_VLAN_TAG_PRESENT));
+                    insn(JEQ_K, DROP, 0, 0);
+                    insn(LD_W, 0, 0, ancillary(libc::SKF_AD_VLAN_TAG));
+                    insn(RSH_K, 0, 0, 13);
+                    insn(AND_K, 0, 0, 0x7);
+                    insn(JEQ_K, 0, DROP, *pcp as u32);
+                }
+            }
+        }
+        insn(RET_K, 0, 0, u32::MAX);
+        insn(RET_K, 0, 0, 0);
+
+        // Jump offsets are relative to the next instruction and have 8 bits
+        let drop = insns.len() - 1;
+        if drop > DROP as usize {
+            return Err(TsnError::Unsupported(format!(
+                "Filter of {} instructions is too long",
+                insns.len()
+            )));
+        }
+        for (pc, insn) in insns.iter_mut().enumerate() {
+            for jump in [&mut insn.jt, &mut insn.jf] {
+                if *jump == DROP {
+                    *jump = (drop - pc - 1) as u8;
+                }
+            }
+        }
+        Ok(insns)
+    }
+}
@@ -0,0 +1,840 @@
+//! gPTP (IEEE 802.1AS) time-aware end station
+//!
+//! A [`Port`] runs gPTP on a [`TsnSocket`] of an interface: it measures the link delay
+//! to its neighbor with Pdelay_Req/Pdelay_Resp, picks the grandmaster with the best
+//! master clock algorithm of [`bmca`] and then either sends Sync/Follow_Up as
+//! grandmaster, or receives them as slave and disciplines the PHC of the interface
+//! with the [`servo`]. Messages go to the gPTP multicast address without VLAN, as
+//! in `dist/gPTP.conf` for ptp4l.
+//!
+//! Hardware timestamps are used where the NIC has them. Without them, e.g. on a veth
+//! pair or the virtual wire, timestamps are software ones of the system clock: offsets
+//! are measured the same way, but the system clock is left alone and the link delay
+//! is far above the 800 ns of [`GptpConfig::neighbor_prop_delay_thresh`], which has
+//! to be raised then.
+//!
+//! ```no_run
+//! use std::time::Duration;
+//! use tsn::config::Config;
+//! use tsn::gptp::{self, GptpConfig, Port};
+//! use tsn::TsnSocket;
+//!
+//! let sock = TsnSocket::builder("eth0")
+//!     .protocol(gptp::ETHERTYPE)
+//!     .config(Config::default())
+//!     .open()?;
+//! let mut port = Port::new(sock, GptpConfig::default())?;
+//! loop {
+//!     if let Some(event) = port.poll(Duration::from_secs(1))? {
+//!         println!("{:?}", event);
+//!     }
+//! }
+//! # Ok::<(), tsn::TsnError>(())
+//! ```
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use nix::libc;
+
+use crate::phc::Phc;
+use crate::time::{self, Timespec};
+use crate::{RxTimestamp, TimestampSource, TsnError, TsnSocket};
+
+pub mod bmca;
+pub mod message;
+pub mod servo;
+
+use bmca::{PriorityVector, SystemIdentity};
+use message::{Announce, Body, Header, Message};
+use servo::{PiServo, ServoAction};
+
+/// EtherType of PTP over Ethernet
+pub const ETHERTYPE: u16 = 0x88f7;
+/// Destination of all gPTP messages, never forwarded by bridges
+pub const MULTICAST_ADDR: [u8; 6] = [0x01, 0x80, 0xc2, 0x00, 0x00, 0x0e];
+
+/// Port number of the only port of an end station
+const PORT_NUMBER: u16 = 1;
+const ETH_HLEN: usize = 14;
+/// Shorter messages are padded to the minimum Ethernet frame
+const MIN_PAYLOAD: usize = 46;
+/// `timeSource` of a grandmaster running on its own oscillator
+const INTERNAL_OSCILLATOR: u8 = 0xa0;
+/// TAI minus UTC since 2017, announced as not valid if the kernel does not know it
+const DEFAULT_UTC_OFFSET: i16 = 37;
+/// Frequency adjustment limit in ppb of clocks which do not tell theirs
+const DEFAULT_MAX_FREQUENCY: f64 = 500_000.0;
+/// Neighbor rate ratios further off than this are measurement errors, well above the
+/// ±100 ppm two Ethernet oscillators can be apart
+const MAX_RATE_DEVIATION: f64 = 0.001;
+
+/// EUI-64 identifying a clock
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ClockIdentity(pub [u8; 8]);
+
+impl ClockIdentity {
+    /// Identity derived from the MAC address of the interface, like ptp4l does
+    pub fn from_mac(mac: [u8; 6]) -> ClockIdentity {
+        ClockIdentity([mac[0], mac[1], mac[2], 0xff, 0xfe, mac[3], mac[4], mac[5]])
+    }
+}
+
+impl fmt::Display for ClockIdentity {
+    /// `001122.fffe.334455` as printed by ptp4l and pmc
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let id = &self.0;
+        write!(
+            f,
+            "{:02x}{:02x}{:02x}.{:02x}{:02x}.{:02x}{:02x}{:02x}",
+            id[0], id[1], id[2], id[3], id[4], id[5], id[6], id[7]
+        )
+    }
+}
+
+/// A port of a clock
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PortIdentity {
+    pub clock: ClockIdentity,
+    pub port: u16,
+}
+
+impl fmt::Display for PortIdentity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.clock, self.port)
+    }
+}
+
+/// How good a grandmaster claims to be, compared by the BMCA in field order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ClockQuality {
+    /// `clockClass`, 248 for a clock which may become grandmaster
+    pub class: u8,
+    /// `clockAccuracy`, 0xfe if unknown
+    pub accuracy: u8,
+    pub offset_scaled_log_variance: u16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PortState {
+    /// Waiting for announces before taking over as grandmaster
+    Listening,
+    /// Grandmaster, sending Announce and Sync
+    Master,
+    /// Following the grandmaster of the best announce
+    Slave,
+}
+
+impl fmt::Display for PortState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            PortState::Listening => "listening",
+            PortState::Master => "master",
+            PortState::Slave => "slave",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Settings of a [`Port`], the defaults are those of `dist/gPTP.conf`
+#[derive(Debug, Clone, PartialEq)]
+pub struct GptpConfig {
+    pub priority1: u8,
+    pub priority2: u8,
+    pub clock_quality: ClockQuality,
+    /// Whether the station may become grandmaster, `gmCapable`
+    pub gm_capable: bool,
+    pub log_announce_interval: i8,
+    pub log_sync_interval: i8,
+    pub log_pdelay_req_interval: i8,
+    /// Announce intervals without an announce before the master is given up
+    pub announce_receipt_timeout: u8,
+    /// Sync intervals without a Sync before the master is given up
+    pub sync_receipt_timeout: u8,
+    /// Link delay in ns above which the neighbor is not `asCapable`
+    pub neighbor_prop_delay_thresh: f64,
+    /// Pdelay_Req in a row without response before the neighbor is not `asCapable`
+    pub allowed_lost_responses: u32,
+    /// Measure offsets, but leave the clock alone
+    pub free_running: bool,
+    /// Offset in ns above which the first Sync steps the clock, 0 to never step
+    pub first_step_threshold: i64,
+    /// Offset in ns above which later Syncs step the clock, 0 to never step
+    pub step_threshold: i64,
+}
+
+impl Default for GptpConfig {
+    fn default() -> Self {
+        GptpConfig {
+            priority1: 248,
+            priority2: 248,
+            clock_quality: ClockQuality {
+                class: 248,
+                accuracy: 0xfe,
+                offset_scaled_log_variance: 0x436a,
+            },
+            gm_capable: true,
+            log_announce_interval: 0,
+            log_sync_interval: -3,
+            log_pdelay_req_interval: 0,
+            announce_receipt_timeout: 3,
+            sync_receipt_timeout: 3,
+            neighbor_prop_delay_thresh: 800.0,
+            allowed_lost_responses: 3,
+            free_running: false,
+            first_step_threshold: 20_000,
+            step_threshold: 0,
+        }
+    }
+}
+
+/// Clock the timestamps of a [`Port`] are in
+pub enum LocalClock {
+    /// PHC of the interface with hardware timestamps, disciplined by the servo
+    Phc(Phc),
+    /// System clock with software timestamps, taken as TAI. Never adjusted
+    System,
+}
+
+/// What [`Port::poll`] saw happen
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Event {
+    /// Port state or grandmaster changed
+    State {
+        state: PortState,
+        grandmaster: Option<ClockIdentity>,
+    },
+    /// Neighbor became capable of gPTP, or stopped being so
+    AsCapable(bool),
+    /// Pdelay exchange completed, the delay in ns
+    PathDelay {
+        mean_link_delay: f64,
+        neighbor_rate_ratio: f64,
+    },
+    /// Offset in ns from the grandmaster, measured with a Sync as slave, and what the
+    /// servo did about it. `None` if the clock is not adjusted
+    Offset {
+        offset: i64,
+        action: Option<ServoAction>,
+    },
+}
+
+/// Snapshot of a [`Port`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Status {
+    pub state: PortState,
+    pub as_capable: bool,
+    /// This clock as master, the one of the announce followed as slave
+    pub grandmaster: Option<ClockIdentity>,
+    /// Port Sync comes from as slave
+    pub parent: Option<PortIdentity>,
+    /// Link delay to the neighbor in ns
+    pub mean_link_delay: Option<f64>,
+    /// Rate of the neighbor's clock over ours
+    pub neighbor_rate_ratio: f64,
+    /// Last offset from the grandmaster in ns
+    pub offset: Option<i64>,
+}
+
+/// Best announce received and when it is given up
+struct ForeignMaster {
+    vector: PriorityVector,
+    expires: Instant,
+}
+
+/// Pdelay_Req sent by us and what came back so far, times in ns of PTP time
+#[derive(Default)]
+struct PdelayExchange {
+    sequence_id: u16,
+    /// Waiting for the response
+    pending: bool,
+    t1: Option<i64>,
+    t2: Option<i64>,
+    t4: Option<i64>,
+    /// Correction of the Pdelay_Resp
+    correction: i64,
+    responder: Option<PortIdentity>,
+}
+
+/// Sync waiting for its Follow_Up
+struct PendingSync {
+    sequence_id: u16,
+    t2: i64,
+    correction: i64,
+}
+
+/// gPTP port of an end station, see the [module](self)
+pub struct Port {
+    sock: TsnSocket,
+    clock: LocalClock,
+    config: GptpConfig,
+    identity: PortIdentity,
+    /// TAI minus UTC in ns as the kernel knows it, added to software timestamps
+    utc_offset: i64,
+    state: PortState,
+    parent: Option<PortIdentity>,
+    as_capable: bool,
+    master: Option<ForeignMaster>,
+    events: VecDeque<Event>,
+    servo: PiServo,
+
+    listen_until: Instant,
+    next_announce: Instant,
+    next_sync: Instant,
+    next_pdelay: Instant,
+    sync_deadline: Option<Instant>,
+    announce_seq: u16,
+    sync_seq: u16,
+
+    pdelay: PdelayExchange,
+    /// Responder, t3 and t4 of the last exchange, for the rate ratio
+    last_pdelay: Option<(PortIdentity, i64, i64)>,
+    lost_responses: u32,
+    mean_link_delay: Option<f64>,
+    neighbor_rate_ratio: f64,
+    pending_sync: Option<PendingSync>,
+    offset: Option<i64>,
+}
+
+impl Port {
+    /// Run gPTP on `sock`, which should be bound to [`ETHERTYPE`] without VLAN.
+    ///
+    /// Turns on timestamps and joins [`MULTICAST_ADDR`]. The PHC of the interface is
+    /// the local clock if the NIC timestamps in hardware, the system clock otherwise
+    pub fn new(mut sock: TsnSocket, config: GptpConfig) -> Result<Port, TsnError> {
+        if !sock.tx_timestamp_enabled {
+            match sock.enable_timestamps(None) {
+                Ok(()) | Err(TsnError::HwTimestampUnsupported(_)) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        sock.join_multicast(MULTICAST_ADDR)?;
+        let clock = match sock.hw_timestamp_config() {
+            Some(_) if config.free_running => LocalClock::Phc(Phc::open_interface(&sock.ifname)?),
+            Some(_) => LocalClock::Phc(Phc::open_interface_adjustable(&sock.ifname)?),
+            None => LocalClock::System,
+        };
+        Port::with_clock(sock, clock, config)
+    }
+
+    /// Run gPTP on `sock` with timestamps already on, in the time base of `clock`.
+    /// Timestamps of other sources are not used
+    pub fn with_clock(
+        sock: TsnSocket,
+        clock: LocalClock,
+        config: GptpConfig,
+    ) -> Result<Port, TsnError> {
+        let identity = PortIdentity {
+            clock: ClockIdentity::from_mac(sock.mac_address()?),
+            port: PORT_NUMBER,
+        };
+        let max_frequency = match &clock {
+            LocalClock::Phc(phc) => phc.max_frequency().unwrap_or(DEFAULT_MAX_FREQUENCY),
+            LocalClock::System => DEFAULT_MAX_FREQUENCY,
+        };
+        let mut servo = PiServo::new(
+            interval(config.log_sync_interval).as_secs_f64(),
+            max_frequency,
+        );
+        servo.first_step_threshold = config.first_step_threshold;
+        servo.step_threshold = config.step_threshold;
+        let now = Instant::now();
+        Ok(Port {
+            sock,
+            clock,
+            identity,
+            utc_offset: time::tai_offset()?,
+            state: PortState::Listening,
+            parent: None,
+            as_capable: false,
+            master: None,
+            events: VecDeque::new(),
+            servo,
+            listen_until: now + announce_timeout(&config, config.log_announce_interval),
+            next_announce: now,
+            next_sync: now,
+            next_pdelay: now,
+            sync_deadline: None,
+            announce_seq: 0,
+            sync_seq: 0,
+            pdelay: PdelayExchange::default(),
+            last_pdelay: None,
+            lost_responses: 0,
+            mean_link_delay: None,
+            neighbor_rate_ratio: 1.0,
+            pending_sync: None,
+            offset: None,
+            config,
+        })
+    }
+
+    pub fn identity(&self) -> PortIdentity {
+        self.identity
+    }
+
+    pub fn state(&self) -> PortState {
+        self.state
+    }
+
+    pub fn clock(&self) -> &LocalClock {
+        &self.clock
+    }
+
+    pub fn status(&self) -> Status {
+        Status {
+            state: self.state,
+            as_capable: self.as_capable,
+            grandmaster: self.grandmaster(),
+            parent: self.parent,
+            mean_link_delay: self.mean_link_delay,
+            neighbor_rate_ratio: self.neighbor_rate_ratio,
+            offset: self.offset,
+        }
+    }
+
+    /// Run the protocol for up to `timeout`, returning at the first [`Event`]
+    pub fn poll(&mut self, timeout: Duration) -> Result<Option<Event>, TsnError> {
+        let deadline = Instant::now() + timeout;
+        let mut buf = [0u8; 1514];
+        loop {
+            if let Some(event) = self.events.pop_front() {
+                return Ok(Some(event));
+            }
+            let now = Instant::now();
+            self.run_timers(now)?;
+            if !self.events.is_empty() {
+                continue;
+            }
+            if now >= deadline {
+                return Ok(None);
+            }
+            // A timeout of 0 would wait forever
+            let wait = self.next_timer().min(deadline) - now;
+            self.sock
+                .set_timeout(wait.max(Duration::from_micros(100)))?;
+            match self.sock.recv_with_timestamp(&mut buf) {
+                Ok((len, ts)) => self.handle_frame(&buf[..len], ts)?,
+                Err(e) if matches!(e.raw_os_error(), Some(libc::EAGAIN | libc::EINTR)) => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn system_identity(&self) -> SystemIdentity {
+        SystemIdentity {
+            priority1: self.config.priority1,
+            clock_quality: self.config.clock_quality,
+            priority2: self.config.priority2,
+            clock_identity: self.identity.clock,
+        }
+    }
+
+    fn grandmaster(&self) -> Option<ClockIdentity> {
+        match (self.state, &self.master) {
+            (PortState::Master, _) => Some(self.identity.clock),
+            (PortState::Slave, Some(master)) => Some(master.vector.root.clock_identity),
+            _ => None,
+        }
+    }
+
+    fn next_timer(&self) -> Instant {
+        let mut next = self.next_pdelay;
+        match self.state {
+            PortState::Listening => next = next.min(self.listen_until),
+            PortState::Master => next = next.min(self.next_announce).min(self.next_sync),
+            PortState::Slave => {}
+        }
+        if let Some(deadline) = self.sync_deadline {
+            next = next.min(deadline);
+        }
+        if let Some(master) = &self.master {
+            next = next.min(master.expires);
+        }
+        next
+    }
+
+    fn run_timers(&mut self, now: Instant) -> Result<(), TsnError> {
+        if now >= self.next_pdelay {
+            self.next_pdelay = now + interval(self.config.log_pdelay_req_interval);
+            self.send_pdelay_req()?;
+        }
+        let master_gone = self.master.as_ref().is_some_and(|m| now >= m.expires)
+            || self.sync_deadline.is_some_and(|deadline| now >= deadline);
+        if master_gone {
+            self.master = None;
+            self.select(now);
+        }
+        match self.state {
+            PortState::Listening if now >= self.listen_until => {
+                self.listen_until =
+                    now + announce_timeout(&self.config, self.config.log_announce_interval);
+                self.select(now);
+            }
+            PortState::Master => {
+                // Timers run on while the neighbor is not capable, so they do not pile up
+                if now >= self.next_announce {
+                    self.next_announce = now + interval(self.config.log_announce_interval);
+                    if self.as_capable {
+                        self.send_announce()?;
+                    }
+                }
+                if now >= self.next_sync {
+                    self.next_sync = now + interval(self.config.log_sync_interval);
+                    if self.as_capable {
+                        self.send_sync()?;
+                    }
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Pick the port state from the best announce, clause 10.3.5
+    fn select(&mut self, now: Instant) {
+        let own = self.system_identity();
+        let (state, parent) = match &self.master {
+            Some(master) if master.vector.root < own => {
+                (PortState::Slave, Some(master.vector.source))
+            }
+            _ if self.config.gm_capable => (PortState::Master, None),
+            _ => (PortState::Listening, None),
+        };
+        let grandmaster = self.master.as_ref().map(|m| m.vector.root.clock_identity);
+        if parent != self.parent {
+            self.servo.reset();
+            self.pending_sync = None;
+            self.offset = None;
+        }
+        let changed = state != self.state
+            || parent != self.parent
+            || (state == PortState::Slave && grandmaster != self.grandmaster());
+        if !changed {
+            return;
+        }
+        match state {
+            PortState::Listening => {
+                self.listen_until =
+                    now + announce_timeout(&self.config, self.config.log_announce_interval);
+            }
+            PortState::Master => {
+                self.next_announce = now;
+                self.next_sync = now;
+            }
+            PortState::Slave => {}
+        }
+        self.sync_deadline = match state {
+            PortState::Slave => {
+                Some(now + sync_timeout(&self.config, self.config.log_sync_interval))
+            }
+            _ => None,
+        };
+        self.state = state;
+        self.parent = parent;
+        self.events.push_back(Event::State {
+            state,
+            grandmaster: self.grandmaster(),
+        });
+    }
+
+    fn set_as_capable(&mut self, as_capable: bool) {
+        if as_capable != self.as_capable {
+            self.as_capable = as_capable;
+            self.events.push_back(Event::AsCapable(as_capable));
+        }
+    }
+
+    /// Timestamp in ns of PTP time, `None` if it is not in the time base of the clock
+    fn ptp_time(&self, time: Timespec, source: TimestampSource) -> Option<i64> {
+        match (&self.clock, source) {
+            (LocalClock::Phc(_), TimestampSource::HwRaw) => Some(time.as_nanos()),
+            (LocalClock::System, TimestampSource::Sw) => Some(time.as_nanos() + self.utc_offset),
+            _ => None,
+        }
+    }
+
+    fn send(&self, msg: &Message) -> Result<(), TsnError> {
+        let mut payload = msg.encode();
+        if payload.len() < MIN_PAYLOAD {
+            payload.resize(MIN_PAYLOAD, 0);
+        }
+        self.sock.send_to(MULTICAST_ADDR, ETHERTYPE, &payload)?;
+        Ok(())
+    }
+
+    /// Send `msg` and wait for its TX timestamp, `None` if there is none
+    fn send_timestamped(&self, msg: &Message) -> Result<Option<i64>, TsnError> {
+        let id = self.sock.next_tx_id();
+        self.send(msg)?;
+        loop {
+            match self.sock.get_tx_timestamp() {
+                // Of an earlier message, which gave up waiting for it
+                Ok(ts) if (id.wrapping_sub(ts.id) as i32) > 0 => continue,
+                Ok(ts) if ts.id == id => return Ok(self.ptp_time(ts.time, ts.source)),
+                Ok(_) | Err(TsnError::TimestampTimeout) => return Ok(None),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn send_announce(&mut self) -> Result<(), TsnError> {
+        self.announce_seq = self.announce_seq.wrapping_add(1);
+        let mut header = Header::new(self.identity, self.announce_seq);
+        header.log_interval = self.config.log_announce_interval;
+        header.flags = message::FLAG_PTP_TIMESCALE;
+        let current_utc_offset = if self.utc_offset != 0 {
+            header.flags |= message::FLAG_UTC_OFFSET_VALID;
+            (self.utc_offset / 1_000_000_000) as i16
+        } else {
+            DEFAULT_UTC_OFFSET
+        };
+        let announce = Announce {
+            current_utc_offset,
+            priority1: self.config.priority1,
+            clock_quality: self.config.clock_quality,
+            priority2: self.config.priority2,
+            grandmaster: self.identity.clock,
+            steps_removed: 0,
+            time_source: INTERNAL_OSCILLATOR,
+            path_trace: vec![self.identity.clock],
+        };
+        self.send(&Message {
+            header,
+            body: Body::Announce(announce),
+        })
+    }
+
+    fn send_sync(&mut self) -> Result<(), TsnError> {
+        self.sync_seq = self.sync_seq.wrapping_add(1);
+        let mut header = Header::new(self.identity, self.sync_seq);
+        header.log_interval = self.config.log_sync_interval;
+        header.flags = message::FLAG_TWO_STEP;
+        let sync = Message {
+            header,
+            body: Body::Sync,
+        };
+        let Some(t1) = self.send_timestamped(&sync)? else {
+            return Ok(());
+        };
+        header.flags = 0;
+        self.send(&Message {
+            header,
+            body: Body::FollowUp {
+                precise_origin: Timespec::from_nanos(t1),
+                rate_ratio: 1.0,
+            },
+        })
+    }
+
+    fn send_pdelay_req(&mut self) -> Result<(), TsnError> {
+        if self.pdelay.pending {
+            self.lost_responses += 1;
+            if self.lost_responses > self.config.allowed_lost_responses {
+                self.set_as_capable(false);
+            }
+        }
+        let sequence_id = self.pdelay.sequence_id.wrapping_add(1);
+        let mut header = Header::new(self.identity, sequence_id);
+        header.log_interval = self.config.log_pdelay_req_interval;
+        let t1 = self.send_timestamped(&Message {
+            header,
+            body: Body::PdelayReq,
+        })?;
+        self.pdelay = PdelayExchange {
+            sequence_id,
+            pending: true,
+            t1,
+            ..Default::default()
+        };
+        Ok(())
+    }
+
+    fn handle_frame(&mut self, frame: &[u8], ts: RxTimestamp) -> Result<(), TsnError> {
+        let Some(msg) = frame.get(ETH_HLEN..).and_then(Message::decode) else {
+            return Ok(());
+        };
+        let header = msg.header;
+        if header.domain != 0 || header.source.clock == self.identity.clock {
+            return Ok(());
+        }
+        let now = Instant::now();
+        let rx_time = self.ptp_time(ts.time, ts.source);
+        match msg.body {
+            Body::PdelayReq => self.respond_pdelay(&header, rx_time)?,
+            Body::PdelayResp {
+                request_receipt,
+                requesting,
+            } => {
+                let exchange = &mut self.pdelay;
+                if exchange.pending
+                    && requesting == self.identity
+                    && header.sequence_id == exchange.sequence_id
+                {
+                    exchange.t2 = Some(request_receipt.as_nanos());
+                    exchange.t4 = rx_time;
+                    exchange.correction = header.correction_ns();
+                    exchange.responder = Some(header.source);
+                }
+            }
+            Body::PdelayRespFollowUp {
+                response_origin,
+                requesting,
+            } => {
+                if requesting == self.identity {
+                    self.complete_pdelay(&header, response_origin);
+                }
+            }
+            Body::Announce(announce) => self.receive_announce(&header, &announce, now),
+            Body::Sync => {
+                if self.state == PortState::Slave && Some(header.source) == self.parent {
+                    self.sync_deadline =
+                        Some(now + sync_timeout(&self.config, header.log_interval));
+                    self.pending_sync = rx_time.map(|t2| PendingSync {
+                        sequence_id: header.sequence_id,
+                        t2,
+                        correction: header.correction_ns(),
+                    });
+                }
+            }
+            Body::FollowUp { precise_origin, .. } => {
+                if self.state == PortState::Slave && Some(header.source) == self.parent {
+                    self.receive_follow_up(&header, precise_origin)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Answer a Pdelay_Req received at `t2`, two-step
+    fn respond_pdelay(&mut self, req: &Header, t2: Option<i64>) -> Result<(), TsnError> {
+        let Some(t2) = t2 else {
+            return Ok(());
+        };
+        let mut header = Header::new(self.identity, req.sequence_id);
+        header.flags = message::FLAG_TWO_STEP;
+        let resp = Message {
+            header,
+            body: Body::PdelayResp {
+                request_receipt: Timespec::from_nanos(t2),
+                requesting: req.source,
+            },
+        };
+        let Some(t3) = self.send_timestamped(&resp)? else {
+            return Ok(());
+        };
+        header.flags = 0;
+        self.send(&Message {
+            header,
+            body: Body::PdelayRespFollowUp {
+                response_origin: Timespec::from_nanos(t3),
+                requesting: req.source,
+            },
+        })
+    }
+
+    /// Link delay and rate ratio from the exchange, clause 11.2.19
+    fn complete_pdelay(&mut self, header: &Header, response_origin: Timespec) {
+        let exchange = &self.pdelay;
+        if !exchange.pending
+            || header.sequence_id != exchange.sequence_id
+            || exchange.responder != Some(header.source)
+        {
+            return;
+        }
+        let (Some(t1), Some(t2), Some(t4)) = (exchange.t1, exchange.t2, exchange.t4) else {
+            return;
+        };
+        let t3 = response_origin.as_nanos() + exchange.correction + header.correction_ns();
+        self.pdelay.pending = false;
+        self.lost_responses = 0;
+
+        if let Some((responder, last_t3, last_t4)) = self.last_pdelay {
+            if responder == header.source && t4 > last_t4 {
+                let ratio = (t3 - last_t3) as f64 / (t4 - last_t4) as f64;
+                if (ratio - 1.0).abs() < MAX_RATE_DEVIATION {
+                    self.neighbor_rate_ratio = ratio;
+                }
+            }
+        }
+        self.last_pdelay = Some((header.source, t3, t4));
+        let delay = ((t4 - t1) as f64 * self.neighbor_rate_ratio - (t3 - t2) as f64) / 2.0;
+        self.mean_link_delay = Some(delay);
+        self.events.push_back(Event::PathDelay {
+            mean_link_delay: delay,
+            neighbor_rate_ratio: self.neighbor_rate_ratio,
+        });
+        self.set_as_capable(delay <= self.config.neighbor_prop_delay_thresh);
+    }
+
+    fn receive_announce(&mut self, header: &Header, announce: &Announce, now: Instant) {
+        if !self.as_capable || !bmca::qualifies(announce, self.identity.clock) {
+            return;
+        }
+        let vector = PriorityVector::of_announce(announce, header.source, PORT_NUMBER);
+        let better = match &self.master {
+            Some(master) => master.vector.source == header.source || vector < master.vector,
+            None => true,
+        };
+        if better {
+            self.master = Some(ForeignMaster {
+                vector,
+                expires: now + announce_timeout(&self.config, header.log_interval),
+            });
+            self.select(now);
+        }
+    }
+
+    /// Offset from the grandmaster with the Sync before, fed to the servo
+    fn receive_follow_up(
+        &mut self,
+        header: &Header,
+        precise_origin: Timespec,
+    ) -> Result<(), TsnError> {
+        let Some(sync) = self.pending_sync.take() else {
+            return Ok(());
+        };
+        let Some(delay) = self.mean_link_delay else {
+            return Ok(());
+        };
+        if sync.sequence_id != header.sequence_id {
+            return Ok(());
+        }
+        let t1 = precise_origin.as_nanos() + sync.correction + header.correction_ns();
+        let offset = sync.t2 - t1 - delay.round() as i64;
+        self.offset = Some(offset);
+
+        let action = match &self.clock {
+            LocalClock::Phc(phc) if !self.config.free_running => {
+                let action = self.servo.sample(offset);
+                match action {
+                    ServoAction::Step(ns) => {
+                        phc.step(ns)?;
+                        // Timestamps taken before the step are off by it
+                        self.last_pdelay = None;
+                        self.pdelay.pending = false;
+                    }
+                    ServoAction::Frequency(ppb) => phc.set_frequency(ppb)?,
+                }
+                Some(action)
+            }
+            _ => None,
+        };
+        self.events.push_back(Event::Offset { offset, action });
+        Ok(())
+    }
+}
+
+/// Interval of messages sent every `2^log` seconds
+fn interval(log: i8) -> Duration {
+    // 127 marks messages which are not sent periodically
+    Duration::from_secs_f64(2f64.powi(log.clamp(-8, 8) as i32))
+}
+
+fn announce_timeout(config: &GptpConfig, log_interval: i8) -> Duration {
+    interval(log_interval) * config.announce_receipt_timeout as u32
+}
+
+fn sync_timeout(config: &GptpConfig, log_interval: i8) -> Duration {
+    interval(log_interval) * config.sync_receipt_timeout as u32
+}
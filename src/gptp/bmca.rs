@@ -0,0 +1,53 @@
+//! Best master clock algorithm of IEEE 802.1AS clause 10.3
+//!
+//! Systems and announces are compared as priority vectors, field by field in the
+//! order of their members. The lower vector is the better one, so `a < b` means `a`
+//! wins over `b`.
+
+use super::message::Announce;
+use super::{ClockIdentity, ClockQuality, PortIdentity};
+
+/// What a time-aware system competes with to become grandmaster
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SystemIdentity {
+    pub priority1: u8,
+    pub clock_quality: ClockQuality,
+    pub priority2: u8,
+    pub clock_identity: ClockIdentity,
+}
+
+/// Grandmaster and path of a received announce
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PriorityVector {
+    pub root: SystemIdentity,
+    pub steps_removed: u16,
+    /// Port which sent the announce
+    pub source: PortIdentity,
+    /// Port number of the port which received it
+    pub port_number: u16,
+}
+
+impl PriorityVector {
+    /// Vector of `announce` as sent by `source` and received on `port_number`
+    pub fn of_announce(announce: &Announce, source: PortIdentity, port_number: u16) -> Self {
+        PriorityVector {
+            root: SystemIdentity {
+                priority1: announce.priority1,
+                clock_quality: announce.clock_quality,
+                priority2: announce.priority2,
+                clock_identity: announce.grandmaster,
+            },
+            steps_removed: announce.steps_removed,
+            source,
+            port_number,
+        }
+    }
+}
+
+/// Whether an announce is considered at all, clause 10.3.11: it must not have come
+/// around to `own` and must not come from too far away
+pub fn qualifies(announce: &Announce, own: ClockIdentity) -> bool {
+    announce.steps_removed < 255
+        && announce.grandmaster != own
+        && !announce.path_trace.contains(&own)
+}
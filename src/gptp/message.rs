@@ -0,0 +1,341 @@
+//! gPTP messages as sent after the Ethernet header, IEEE 802.1AS clauses 10.6 and 11.4
+
+use crate::time::Timespec;
+
+use super::{ClockIdentity, ClockQuality, PortIdentity};
+
+/// Length of the header every message starts with
+pub const HEADER_LEN: usize = 34;
+
+/// `twoStepFlag`, the precise origin timestamp follows in a Follow_Up
+pub const FLAG_TWO_STEP: u16 = 0x0200;
+/// `leap61`, the last minute of the current UTC day has 61 seconds
+pub const FLAG_LEAP61: u16 = 0x0001;
+/// `leap59`, the last minute of the current UTC day has 59 seconds
+pub const FLAG_LEAP59: u16 = 0x0002;
+/// `currentUtcOffsetValid`
+pub const FLAG_UTC_OFFSET_VALID: u16 = 0x0004;
+/// `ptpTimescale`, always set by gPTP
+pub const FLAG_PTP_TIMESCALE: u16 = 0x0008;
+
+/// `majorSdoId` of gPTP, `transportSpecific` of PTP
+const MAJOR_SDO_ID: u8 = 0x1;
+const VERSION_PTP: u8 = 2;
+/// `logMessageInterval` of messages which are not sent periodically
+const NO_INTERVAL: i8 = 0x7f;
+
+const TLV_ORGANIZATION_EXTENSION: u16 = 0x0003;
+const TLV_PATH_TRACE: u16 = 0x0008;
+/// 802.1 OUI and subtype 1 of the Follow_Up information TLV
+const FOLLOW_UP_INFO: [u8; 6] = [0x00, 0x80, 0xc2, 0x00, 0x00, 0x01];
+const FOLLOW_UP_INFO_LEN: usize = 28;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageType {
+    Sync = 0x0,
+    PdelayReq = 0x2,
+    PdelayResp = 0x3,
+    FollowUp = 0x8,
+    PdelayRespFollowUp = 0xa,
+    Announce = 0xb,
+}
+
+impl MessageType {
+    fn from_raw(raw: u8) -> Option<MessageType> {
+        match raw {
+            0x0 => Some(MessageType::Sync),
+            0x2 => Some(MessageType::PdelayReq),
+            0x3 => Some(MessageType::PdelayResp),
+            0x8 => Some(MessageType::FollowUp),
+            0xa => Some(MessageType::PdelayRespFollowUp),
+            0xb => Some(MessageType::Announce),
+            _ => None,
+        }
+    }
+
+    /// `controlField` of PTP v1, still filled in for old receivers
+    fn control(self) -> u8 {
+        match self {
+            MessageType::Sync => 0,
+            MessageType::FollowUp => 2,
+            _ => 5,
+        }
+    }
+}
+
+/// Common header of all messages
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Header {
+    pub domain: u8,
+    /// `FLAG_*` bits
+    pub flags: u16,
+    /// Correction in ns with 16 fractional bits
+    pub correction: i64,
+    pub source: PortIdentity,
+    pub sequence_id: u16,
+    /// log2 of the interval the message is sent at in seconds, 127 if not periodic
+    pub log_interval: i8,
+}
+
+impl Header {
+    pub fn new(source: PortIdentity, sequence_id: u16) -> Header {
+        Header {
+            domain: 0,
+            flags: 0,
+            correction: 0,
+            source,
+            sequence_id,
+            log_interval: NO_INTERVAL,
+        }
+    }
+
+    /// Correction in whole ns
+    pub fn correction_ns(&self) -> i64 {
+        self.correction >> 16
+    }
+}
+
+/// Body of a message, by type
+#[derive(Debug, Clone, PartialEq)]
+pub enum Body {
+    /// Two-step Sync, its time follows in a Follow_Up
+    Sync,
+    FollowUp {
+        precise_origin: Timespec,
+        /// Rate of the grandmaster over the rate of the sender, from
+        /// `cumulativeScaledRateOffset`
+        rate_ratio: f64,
+    },
+    PdelayReq,
+    PdelayResp {
+        /// When the Pdelay_Req was received
+        request_receipt: Timespec,
+        requesting: PortIdentity,
+    },
+    PdelayRespFollowUp {
+        /// When the Pdelay_Resp was sent
+        response_origin: Timespec,
+        requesting: PortIdentity,
+    },
+    Announce(Announce),
+}
+
+/// What a master says about its grandmaster
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Announce {
+    /// TAI minus UTC in seconds
+    pub current_utc_offset: i16,
+    pub priority1: u8,
+    pub clock_quality: ClockQuality,
+    pub priority2: u8,
+    pub grandmaster: ClockIdentity,
+    /// Hops from the grandmaster, 0 when sent by it
+    pub steps_removed: u16,
+    /// `timeSource`, e.g. 0xa0 for the internal oscillator
+    pub time_source: u8,
+    /// Clocks the announce went through, from the path trace TLV
+    pub path_trace: Vec<ClockIdentity>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+    pub header: Header,
+    pub body: Body,
+}
+
+impl Message {
+    pub fn message_type(&self) -> MessageType {
+        match self.body {
+            Body::Sync => MessageType::Sync,
+            Body::FollowUp { .. } => MessageType::FollowUp,
+            Body::PdelayReq => MessageType::PdelayReq,
+            Body::PdelayResp { .. } => MessageType::PdelayResp,
+            Body::PdelayRespFollowUp { .. } => MessageType::PdelayRespFollowUp,
+            Body::Announce(_) => MessageType::Announce,
+        }
+    }
+
+    /// Bytes of the message, without padding to the minimum frame size
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = vec![0u8; HEADER_LEN];
+        match &self.body {
+            Body::Sync => buf.extend_from_slice(&[0; 10]),
+            Body::FollowUp {
+                precise_origin,
+                rate_ratio,
+            } => {
+                put_timestamp(&mut buf, precise_origin);
+                buf.extend_from_slice(&TLV_ORGANIZATION_EXTENSION.to_be_bytes());
+                buf.extend_from_slice(&(FOLLOW_UP_INFO_LEN as u16).to_be_bytes());
+                buf.extend_from_slice(&FOLLOW_UP_INFO);
+                let scaled = ((rate_ratio - 1.0) * (1u64 << 41) as f64) as i32;
+                buf.extend_from_slice(&scaled.to_be_bytes());
+                // gmTimeBaseIndicator, lastGmPhaseChange and scaledLastGmFreqChange
+                buf.extend_from_slice(&[0; 18]);
+            }
+            Body::PdelayReq => buf.extend_from_slice(&[0; 20]),
+            Body::PdelayResp {
+                request_receipt: time,
+                requesting,
+            }
+            | Body::PdelayRespFollowUp {
+                response_origin: time,
+                requesting,
+            } => {
+                put_timestamp(&mut buf, time);
+                put_port_identity(&mut buf, requesting);
+            }
+            Body::Announce(announce) => {
+                buf.extend_from_slice(&[0; 10]);
+                buf.extend_from_slice(&announce.current_utc_offset.to_be_bytes());
+                buf.push(0);
+                buf.push(announce.priority1);
+                buf.push(announce.clock_quality.class);
+                buf.push(announce.clock_quality.accuracy);
+                buf.extend_from_slice(
+                    &announce
+                        .clock_quality
+                        .offset_scaled_log_variance
+                        .to_be_bytes(),
+                );
+                buf.push(announce.priority2);
+                buf.extend_from_slice(&announce.grandmaster.0);
+                buf.extend_from_slice(&announce.steps_removed.to_be_bytes());
+                buf.push(announce.time_source);
+                buf.extend_from_slice(&TLV_PATH_TRACE.to_be_bytes());
+                buf.extend_from_slice(&(announce.path_trace.len() as u16 * 8).to_be_bytes());
+                for clock in &announce.path_trace {
+                    buf.extend_from_slice(&clock.0);
+                }
+            }
+        }
+
+        let header = &self.header;
+        let len = buf.len() as u16;
+        buf[0] = MAJOR_SDO_ID << 4 | self.message_type() as u8;
+        buf[1] = VERSION_PTP;
+        buf[2..4].copy_from_slice(&len.to_be_bytes());
+        buf[4] = header.domain;
+        buf[6..8].copy_from_slice(&header.flags.to_be_bytes());
+        buf[8..16].copy_from_slice(&header.correction.to_be_bytes());
+        buf[20..28].copy_from_slice(&header.source.clock.0);
+        buf[28..30].copy_from_slice(&header.source.port.to_be_bytes());
+        buf[30..32].copy_from_slice(&header.sequence_id.to_be_bytes());
+        buf[32] = self.message_type().control();
+        buf[33] = header.log_interval as u8;
+        buf
+    }
+
+    /// Parse a message. `None` if it is malformed, of another PTP profile or of a
+    /// type an end station does not handle, e.g. Signaling
+    pub fn decode(buf: &[u8]) -> Option<Message> {
+        if buf.len() < HEADER_LEN || buf[0] >> 4 != MAJOR_SDO_ID || buf[1] & 0xf != VERSION_PTP {
+            return None;
+        }
+        let len = u16::from_be_bytes([buf[2], buf[3]]) as usize;
+        // Frames are padded to the minimum size, the message may be shorter
+        if len < HEADER_LEN {
+            return None;
+        }
+        let buf = buf.get(..len)?;
+        let message_type = MessageType::from_raw(buf[0] & 0xf)?;
+        let header = Header {
+            domain: buf[4],
+            flags: u16::from_be_bytes([buf[6], buf[7]]),
+            correction: i64::from_be_bytes(buf[8..16].try_into().unwrap()),
+            source: port_identity(&buf[20..30]),
+            sequence_id: u16::from_be_bytes([buf[30], buf[31]]),
+            log_interval: buf[33] as i8,
+        };
+
+        let body = &buf[HEADER_LEN..];
+        let body = match message_type {
+            MessageType::Sync if body.len() >= 10 => Body::Sync,
+            MessageType::PdelayReq if body.len() >= 20 => Body::PdelayReq,
+            MessageType::FollowUp if body.len() >= 10 => Body::FollowUp {
+                precise_origin: timestamp(&body[..10]),
+                rate_ratio: follow_up_rate_ratio(&body[10..]).unwrap_or(1.0),
+            },
+            MessageType::PdelayResp if body.len() >= 20 => Body::PdelayResp {
+                request_receipt: timestamp(&body[..10]),
+                requesting: port_identity(&body[10..20]),
+            },
+            MessageType::PdelayRespFollowUp if body.len() >= 20 => Body::PdelayRespFollowUp {
+                response_origin: timestamp(&body[..10]),
+                requesting: port_identity(&body[10..20]),
+            },
+            MessageType::Announce if body.len() >= 30 => Body::Announce(Announce {
+                current_utc_offset: i16::from_be_bytes([body[10], body[11]]),
+                priority1: body[13],
+                clock_quality: ClockQuality {
+                    class: body[14],
+                    accuracy: body[15],
+                    offset_scaled_log_variance: u16::from_be_bytes([body[16], body[17]]),
+                },
+                priority2: body[18],
+                grandmaster: ClockIdentity(body[19..27].try_into().unwrap()),
+                steps_removed: u16::from_be_bytes([body[27], body[28]]),
+                time_source: body[29],
+                path_trace: path_trace(&body[30..]),
+            }),
+            _ => return None,
+        };
+        Some(Message { header, body })
+    }
+}
+
+/// 48 bits of seconds and 32 bits of ns
+fn put_timestamp(buf: &mut Vec<u8>, time: &Timespec) {
+    buf.extend_from_slice(&(time.tv_sec as u64).to_be_bytes()[2..]);
+    buf.extend_from_slice(&(time.tv_nsec as u32).to_be_bytes());
+}
+
+fn timestamp(buf: &[u8]) -> Timespec {
+    let mut secs = [0u8; 8];
+    secs[2..].copy_from_slice(&buf[..6]);
+    Timespec {
+        tv_sec: u64::from_be_bytes(secs) as i64,
+        tv_nsec: u32::from_be_bytes(buf[6..10].try_into().unwrap()) as i64,
+    }
+}
+
+fn put_port_identity(buf: &mut Vec<u8>, identity: &PortIdentity) {
+    buf.extend_from_slice(&identity.clock.0);
+    buf.extend_from_slice(&identity.port.to_be_bytes());
+}
+
+fn port_identity(buf: &[u8]) -> PortIdentity {
+    PortIdentity {
+        clock: ClockIdentity(buf[..8].try_into().unwrap()),
+        port: u16::from_be_bytes([buf[8], buf[9]]),
+    }
+}
+
+/// TLVs after the fixed part of a message as `(type, value)`
+fn tlvs(mut buf: &[u8]) -> impl Iterator<Item = (u16, &[u8])> {
+    std::iter::from_fn(move || {
+        let tlv_type = u16::from_be_bytes(buf.get(..2)?.try_into().unwrap());
+        let len = u16::from_be_bytes(buf.get(2..4)?.try_into().unwrap()) as usize;
+        let value = buf.get(4..4 + len)?;
+        buf = &buf[4 + len..];
+        Some((tlv_type, value))
+    })
+}
+
+fn follow_up_rate_ratio(buf: &[u8]) -> Option<f64> {
+    let (_, value) = tlvs(buf).find(|(tlv_type, value)| {
+        *tlv_type == TLV_ORGANIZATION_EXTENSION
+            && value.len() >= FOLLOW_UP_INFO_LEN
+            && value[..6] == FOLLOW_UP_INFO
+    })?;
+    let scaled = i32::from_be_bytes(value[6..10].try_into().unwrap());
+    Some(1.0 + scaled as f64 / (1u64 << 41) as f64)
+}
+
+fn path_trace(buf: &[u8]) -> Vec<ClockIdentity> {
+    tlvs(buf)
+        .filter(|(tlv_type, _)| *tlv_type == TLV_PATH_TRACE)
+        .flat_map(|(_, value)| value.chunks_exact(8))
+        .map(|clock| ClockIdentity(clock.try_into().unwrap()))
+        .collect()
+}
@@ -0,0 +1,70 @@
+//! PI servo turning measured offsets into clock adjustments, like the `pi` servo of
+//! ptp4l
+
+/// Proportional gain
+const KP: f64 = 0.7;
+/// Integral gain per second of sync interval
+const KI: f64 = 0.3;
+
+/// What to do to the local clock after a sample
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ServoAction {
+    /// Move the clock by this many ns at once
+    Step(i64),
+    /// Run the clock this many ppb faster than its oscillator
+    Frequency(f64),
+}
+
+/// PI controller of the local clock's frequency
+#[derive(Debug, Clone)]
+pub struct PiServo {
+    kp: f64,
+    ki: f64,
+    max_frequency: f64,
+    /// Offset in ns above which the first sample steps the clock, 0 to never step
+    pub first_step_threshold: i64,
+    /// Offset in ns above which later samples step the clock, 0 to never step
+    pub step_threshold: i64,
+    /// Integral term, the frequency error of the oscillator in ppb
+    drift: f64,
+    locked: bool,
+}
+
+impl PiServo {
+    /// Servo for offsets measured every `interval` seconds, adjusting by up to
+    /// `max_frequency` ppb. Steps above 20 us on the first sample only
+    pub fn new(interval: f64, max_frequency: f64) -> PiServo {
+        PiServo {
+            kp: KP,
+            ki: KI * interval,
+            max_frequency,
+            first_step_threshold: 20_000,
+            step_threshold: 0,
+            drift: 0.0,
+            locked: false,
+        }
+    }
+
+    /// Adjustment for `offset`, the local clock minus the master in ns
+    pub fn sample(&mut self, offset: i64) -> ServoAction {
+        let threshold = if self.locked {
+            self.step_threshold
+        } else {
+            self.first_step_threshold
+        };
+        self.locked = true;
+        if threshold > 0 && offset.abs() > threshold {
+            return ServoAction::Step(-offset);
+        }
+
+        let offset = offset as f64;
+        self.drift = (self.drift + self.ki * offset).clamp(-self.max_frequency, self.max_frequency);
+        let ppb = (self.kp * offset + self.drift).clamp(-self.max_frequency, self.max_frequency);
+        ServoAction::Frequency(-ppb)
+    }
+
+    /// Start over, e.g. for a new grandmaster. The drift of the oscillator is kept
+    pub fn reset(&mut self) {
+        self.locked = false;
+    }
+}
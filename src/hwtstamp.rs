@@ -0,0 +1,306 @@
+//! Hardware timestamp config of interfaces, shared with ptp4l and other processes
+//!
+//! `SIOCSHWTSTAMP` sets what the NIC timestamps for the whole interface, so
+//! [`enable_timestamps`](crate::enable_timestamps) only widens the config it finds,
+//! and only as far as the socket needs. The first socket to change it records the
+//! config it found in `RUN_DIR/<ifname>.hwtstamp`, held like a VLAN. When the last
+//! holder closes, the recorded config is put back, unless someone else changed the
+//! config in between.
+
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, Write};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+
+use nix::libc;
+
+use crate::caps::{rx_filter_names, tx_type_names};
+use crate::{flock, lock_run_dir, try_flock, TsnError, RUN_DIR};
+
+/// Suffix of the holder files in `RUN_DIR`, VLAN names end in their ID instead
+pub(crate) const HOLDER_SUFFIX: &str = ".hwtstamp";
+
+/// `struct hwtstamp_config` without its flags
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HwTstampConfig {
+    /// `HWTSTAMP_TX_*`
+    pub tx_type: u32,
+    /// `HWTSTAMP_FILTER_*`
+    pub rx_filter: u32,
+}
+
+impl HwTstampConfig {
+    pub const OFF: HwTstampConfig = HwTstampConfig {
+        tx_type: libc::HWTSTAMP_TX_OFF,
+        rx_filter: libc::HWTSTAMP_FILTER_NONE,
+    };
+
+    /// The config widened to timestamp sent frames if `tx`, and received ones with
+    /// `rx_filter`. Sent frames are timestamped by any TX type but off, and received
+    /// ones only move from no filter to a PTP one, or from a PTP one to all
+    pub fn widened(self, tx: bool, rx_filter: Option<u32>) -> HwTstampConfig {
+        let width = |filter: u32| match filter {
+            libc::HWTSTAMP_FILTER_NONE => 0,
+            libc::HWTSTAMP_FILTER_ALL => 2,
+            _ => 1,
+        };
+        HwTstampConfig {
+            tx_type: if tx && self.tx_type == libc::HWTSTAMP_TX_OFF {
+                libc::HWTSTAMP_TX_ON
+            } else {
+                self.tx_type
+            },
+            rx_filter: match rx_filter {
+                Some(filter) if width(filter) > width(self.rx_filter) => filter,
+                _ => self.rx_filter,
+            },
+        }
+    }
+}
+
+impl fmt::Display for HwTstampConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = |names: Vec<&str>, value: u32| {
+            names
+                .first()
+                .map_or(value.to_string(), |name| name.to_string())
+        };
+        write!(
+            f,
+            "tx {}, rx {}",
+            name(tx_type_names(1 << self.tx_type), self.tx_type),
+            name(rx_filter_names(1 << self.rx_filter), self.rx_filter)
+        )
+    }
+}
+
+/// Current config of `ifname`, `None` if the driver cannot tell (no `SIOCGHWTSTAMP`)
+pub fn get(ifname: &str) -> Result<Option<HwTstampConfig>, TsnError> {
+    let mut config = HwTstampConfig::OFF;
+    match ioctl(ifname, libc::SIOCGHWTSTAMP, &mut config) {
+        Ok(()) => Ok(Some(config)),
+        Err(e) if e.raw_os_error() == Some(libc::ENODEV) => {
+            Err(TsnError::InterfaceNotFound(ifname.to_string()))
+        }
+        Err(e)
+            if matches!(
+                e.raw_os_error(),
+                Some(libc::EOPNOTSUPP) | Some(libc::EINVAL)
+            ) =>
+        {
+            Ok(None)
+        }
+        Err(source) => Err(TsnError::Socket {
+            op: "Get hardware timestamp config",
+            source,
+        }),
+    }
+}
+
+/// Set `config` on `ifname`, returns what the driver applied, which may be wider
+fn set(ifname: &str, mut config: HwTstampConfig) -> Result<HwTstampConfig, TsnError> {
+    ioctl(ifname, libc::SIOCSHWTSTAMP, &mut config).map_err(TsnError::HwTimestampUnsupported)?;
+    Ok(config)
+}
+
+fn ioctl(ifname: &str, request: libc::c_ulong, config: &mut HwTstampConfig) -> io::Result<()> {
+    if ifname.len() >= libc::IFNAMSIZ {
+        return Err(io::Error::from_raw_os_error(libc::ENODEV));
+    }
+    let fd = unsafe { libc::socket(libc::AF_INET, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+    let mut ts_cfg = libc::hwtstamp_config {
+        flags: 0,
+        tx_type: config.tx_type as i32,
+        rx_filter: config.rx_filter as i32,
+    };
+    let mut ifr_name: [libc::c_char; libc::IFNAMSIZ] = [0; libc::IFNAMSIZ];
+    for (source, target) in ifname.as_bytes().iter().zip(ifr_name.iter_mut()) {
+        *target = *source as libc::c_char;
+    }
+    let ifreq = libc::ifreq {
+        ifr_name,
+        ifr_ifru: libc::__c_anonymous_ifr_ifru {
+            ifru_data: (&mut ts_cfg as *mut libc::hwtstamp_config) as *mut libc::c_char,
+        },
+    };
+    let res = unsafe {
+        // Not useless conversion because aarch64 has different type
+        #[allow(clippy::useless_conversion)]
+        libc::ioctl(fd.as_raw_fd(), request.try_into().unwrap(), &ifreq)
+    };
+    if res < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    config.tx_type = ts_cfg.tx_type as u32;
+    config.rx_filter = ts_cfg.rx_filter as u32;
+    Ok(())
+}
+
+/// Config found before the first change and the one applied last, the content of a
+/// holder file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Record {
+    original: HwTstampConfig,
+    applied: HwTstampConfig,
+}
+
+impl Record {
+    fn read(file: &mut File) -> Result<Option<Record>, TsnError> {
+        let mut content = String::new();
+        file.rewind()
+            .and_then(|_| file.read_to_string(&mut content))
+            .map_err(TsnError::holder("Read"))?;
+        let fields = content
+            .split_whitespace()
+            .map(|field| field.parse::<u32>().ok())
+            .collect::<Option<Vec<_>>>();
+        Ok(match fields.as_deref() {
+            Some(&[tx_type, rx_filter, applied_tx_type, applied_rx_filter]) => Some(Record {
+                original: HwTstampConfig { tx_type, rx_filter },
+                applied: HwTstampConfig {
+                    tx_type: applied_tx_type,
+                    rx_filter: applied_rx_filter,
+                },
+            }),
+            _ => None,
+        })
+    }
+
+    fn write(&self, file: &mut File) -> Result<(), TsnError> {
+        file.set_len(0)
+            .and_then(|_| file.rewind())
+            .and_then(|_| {
+                writeln!(
+                    file,
+                    "{} {} {} {}",
+                    self.original.tx_type,
+                    self.original.rx_filter,
+                    self.applied.tx_type,
+                    self.applied.rx_filter
+                )
+            })
+            .map_err(TsnError::holder("Write"))
+    }
+}
+
+/// Hold on the hardware timestamp config of an interface, see the module docs
+pub(crate) struct HwTstampHold {
+    ifname: String,
+    file: Option<File>,
+    config: HwTstampConfig,
+}
+
+impl HwTstampHold {
+    /// Take a hold on the config of `ifname`, widened to timestamp sent frames if
+    /// `tx` and received ones with `rx_filter`
+    pub(crate) fn new(
+        ifname: &str,
+        tx: bool,
+        rx_filter: Option<u32>,
+    ) -> Result<HwTstampHold, TsnError> {
+        let _lock = lock_run_dir()?;
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(holder_path(ifname))
+            .map_err(TsnError::holder("Open"))?;
+        let config = match widen(ifname, &mut file, tx, rx_filter) {
+            Ok(config) => config,
+            Err(e) => {
+                // Unless held by others, leave nothing behind for gc_vlans
+                if try_flock(&file, libc::LOCK_EX)? {
+                    restore(ifname, file)?;
+                }
+                return Err(e);
+            }
+        };
+        flock(&file, libc::LOCK_SH)?;
+        Ok(HwTstampHold {
+            ifname: ifname.to_string(),
+            file: Some(file),
+            config,
+        })
+    }
+
+    /// Config of the interface when the hold was taken
+    pub(crate) fn config(&self) -> HwTstampConfig {
+        self.config
+    }
+
+    /// Release the hold, putting the config back if it was the last one
+    pub(crate) fn release(&mut self) -> Result<(), TsnError> {
+        let Some(file) = self.file.take() else {
+            return Ok(());
+        };
+        let _lock = lock_run_dir()?;
+        if try_flock(&file, libc::LOCK_EX)? {
+            restore(&self.ifname, file)?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for HwTstampHold {
+    fn drop(&mut self) {
+        let _ = self.release();
+    }
+}
+
+/// Widen the config of `ifname` and record what was changed in its holder `file`,
+/// returns the config in effect
+fn widen(
+    ifname: &str,
+    file: &mut File,
+    tx: bool,
+    rx_filter: Option<u32>,
+) -> Result<HwTstampConfig, TsnError> {
+    // Left by holders which are gone if nobody else holds it, still to restore
+    let record = Record::read(file)?;
+    let current = get(ifname)?;
+    let wanted = current
+        .unwrap_or(HwTstampConfig::OFF)
+        .widened(tx, rx_filter);
+    Ok(match current {
+        Some(current) if current == wanted => current,
+        _ => {
+            let applied = set(ifname, wanted)?;
+            // Without the config found, there is nothing to put back
+            if let Some(current) = current {
+                let original = match record {
+                    Some(record) if record.applied == current => record.original,
+                    // Changed by someone else since, theirs is the one to put back
+                    _ => current,
+                };
+                Record { original, applied }.write(file)?;
+            }
+            applied
+        }
+    })
+}
+
+/// Put back the config recorded in the holder `file` of `ifname`, which nobody holds
+/// any more, and remove the file. Needs the `RUN_DIR` lock
+pub(crate) fn restore(ifname: &str, mut file: File) -> Result<(), TsnError> {
+    let record = Record::read(&mut file)?;
+    fs::remove_file(holder_path(ifname)).map_err(TsnError::holder("Remove"))?;
+    let Some(record) = record else {
+        return Ok(());
+    };
+    match get(ifname) {
+        Ok(Some(current)) if current == record.applied => set(ifname, record.original).map(|_| ()),
+        // Changed by someone else since, or the interface is gone
+        Ok(_) | Err(TsnError::InterfaceNotFound(_)) => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+fn holder_path(ifname: &str) -> String {
+    format!("{}/{}{}", RUN_DIR, ifname, HOLDER_SUFFIX)
+}
@@ -1,17 +1,25 @@
-use crate::config::Config;
+use itertools::Itertools;
+use tsn::caps::{self, rx_filter_names, timestamping_names, tx_type_names};
+use tsn::config::Config;
+use tsn::hwtstamp;
+
+/// `offload` of a qdisc config, `auto` if left to the capabilities
+fn offload_name(offload: Option<bool>) -> String {
+    offload.map_or("auto".to_string(), |offload| offload.to_string())
+}
 
 pub fn get_info(config: &Config) {
     if let Some(cbs) = &config.cbs {
         println!("  cbs:");
-        let mut n = 1;
-        for (class, value) in &cbs.streams {
+        println!("    offload: {}", offload_name(cbs.offload));
+        // children are numbered in class order: 1 for 'a', 2 for 'b'
+        for (n, (class, value)) in (1..).zip(cbs.streams.iter().sorted_by_key(|(c, _)| **c)) {
             println!("    {}:", class);
             let credit = cbs.children.get(&n).unwrap();
             println!(
                 "      credits: {{hicredit: {}, idleslope: {}, locredit: {}, sendslope: {}}}",
                 credit.hicredit, credit.idleslope, credit.locredit, credit.sendslope
             );
-            n += 1;
             println!("      prios:");
             for prio in value {
                 println!(
@@ -23,6 +31,7 @@ pub fn get_info(config: &Config) {
     }
     if let Some(tas) = &config.tas {
         println!("  tas:");
+        println!("    mode: {}", tas.mode.name());
         println!("    base_time: {}", tas.base_time);
         println!("    schedule:");
         for sch in &tas.schedule {
@@ -30,5 +39,71 @@ pub fn get_info(config: &Config) {
             println!("        time: {}", sch.time);
         }
         println!("    txtime_delay: {}", tas.txtime_delay);
+        if !tas.etf.is_empty() {
+            println!("    etf:");
+            for etf in &tas.etf {
+                println!(
+                    "      - {{prio: {}, delta: {}, offload: {}, deadline_mode: {}, skip_sock_check: {}}}",
+                    etf.prio,
+                    etf.delta,
+                    offload_name(etf.offload),
+                    etf.deadline_mode,
+                    etf.skip_sock_check
+                );
+            }
+        }
+    }
+    if let Some(ptp) = &config.ptp {
+        println!("  ptp:");
+        println!("    profile: {}", ptp.profile.name());
+        println!("    priority1: {}", ptp.priority1);
+        println!("    priority2: {}", ptp.priority2);
+        println!("    log_announce_interval: {}", ptp.log_announce_interval);
+        println!("    log_sync_interval: {}", ptp.log_sync_interval);
+        println!("    log_delay_req_interval: {}", ptp.log_delay_req_interval);
+        println!("    delay_mechanism: {}", ptp.delay_mechanism.name());
+        println!("    transport: {}", ptp.transport.name());
+    }
+}
+
+pub fn get_capabilities(ifname: &str) {
+    let caps = match caps::probe(ifname) {
+        Ok(caps) => caps,
+        Err(e) => {
+            println!("  capabilities: {}", e);
+            return;
+        }
+    };
+    println!("  capabilities:");
+    match caps.timestamping {
+        Some(info) => {
+            println!(
+                "    timestamping: [{}]",
+                timestamping_names(info.so_timestamping).join(", ")
+            );
+            match info.phc_index {
+                Some(index) => println!("    phc: /dev/ptp{}", index),
+                None => println!("    phc: none"),
+            }
+            println!(
+                "    tx_types: [{}]",
+                tx_type_names(info.tx_types).join(", ")
+            );
+            println!(
+                "    rx_filters: [{}]",
+                rx_filter_names(info.rx_filters).join(", ")
+            );
+        }
+        None => println!("    timestamping: unknown"),
+    }
+    match hwtstamp::get(ifname) {
+        Ok(Some(config)) => println!("    hw_timestamps: {}", config),
+        _ => println!("    hw_timestamps: unknown"),
+    }
+    match caps.tx_queues {
+        Some(queues) => println!("    tx_queues: {}", queues),
+        None => println!("    tx_queues: unknown"),
     }
+    println!("    tc_offload: {}", caps.tc_offload);
+    println!("    preemption: {}", caps.preemption);
 }
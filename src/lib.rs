@@ -1,125 +1,644 @@
-use core::slice;
+use nix::libc;
 use nix::net::if_::if_nametoindex;
 use nix::sys::socket::msghdr;
 use nix::sys::time::{TimeSpec, TimeValLike};
 use nix::unistd::close;
-use nix::{
-    fcntl::{fcntl, FcntlArg::F_SETLKW, OFlag},
-    libc::{self, flock, ftruncate, msync, MS_SYNC},
-    sys::{
-        mman::{mmap, munmap, shm_open, shm_unlink, MapFlags, ProtFlags},
-        signal::kill,
-        stat::Mode,
-    },
-    unistd::Pid,
-};
-use std::io::{Error, ErrorKind};
-use std::{env, mem, str};
-use std::{num::NonZeroUsize, os::raw::c_void, process, time::Duration};
+use std::collections::{HashMap, VecDeque};
+use std::fs::{self, File, OpenOptions};
+use std::io::{Error, Write};
+use std::os::fd::{AsRawFd, RawFd};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+use std::{env, iter, mem, str};
 
 extern crate socket;
 
 pub struct TsnSocket {
     pub fd: i32,
     pub ifname: String,
+    /// 0 if opened without [`TsnSocketBuilder::vlan`]
     pub vlanid: u16,
     pub rx_timestamp_enabled: bool,
+    pub tx_timestamp_enabled: bool,
+    pub txtime: Option<TxtimeConfig>,
+    /// ID the kernel gives to the next TX timestamp (`SOF_TIMESTAMPING_OPT_ID`)
+    tx_key: AtomicU32,
+    /// Whether `PACKET_AUXDATA` is on, see [`recv_from`]
+    auxdata: AtomicBool,
+    /// MAC address of `ifname` once read by [`send_to`] or [`recv_from`]
+    mac: OnceLock<[u8; 6]>,
+    /// Error queue messages read while looking for another kind of message
+    errqueue: Mutex<VecDeque<ErrQueueMsg>>,
+    /// `PACKET_MMAP` rings, see [`sock_open_ring`]
+    ring: Option<ring::Ring>,
+    /// AF_XDP state, `fd` is the XDP socket then
+    #[cfg(feature = "xdp")]
+    xdp: Option<xdp::XdpSocket>,
+    /// Hold on the VLAN from [`create_vlan`], `None` once closed
+    vlan_hold: Option<File>,
+    /// Hold on the hardware timestamp config of `ifname`, see [`enable_timestamps`]
+    hwtstamp: Option<hwtstamp::HwTstampHold>,
+    /// Virtual wire port, `fd` is its datagram socket then
+    wire: Option<wire::WirePort>,
 }
 
-mod cbs;
-mod config;
-mod tas;
+/// Where a timestamp came from. The first three are the slots of `SO_TIMESTAMPING`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimestampSource {
+    /// Raw hardware timestamp in the NIC's PHC time base (slot 2)
+    HwRaw,
+    /// Hardware timestamp converted to system time by the driver (slot 1, deprecated)
+    HwLegacy,
+    /// Software timestamp taken by the kernel (slot 0)
+    Sw,
+    /// Kernel gave no timestamp, taken with `CLOCK_REALTIME` right after `recvmsg`
+    User,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RxTimestamp {
+    pub time: time::Timespec,
+    pub source: TimestampSource,
+}
+
+/// Who a received frame was addressed to, `sll_pkttype` of `sockaddr_ll`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PacketType {
+    /// To the MAC address of the interface
+    Host,
+    Broadcast,
+    Multicast,
+    /// To another host, seen in promiscuous mode or on a VLAN nobody listens to
+    OtherHost,
+    /// Sent by this host, seen by sockets bound to all protocols
+    Outgoing,
+    /// Any other `PACKET_*` value
+    Other(u8),
+}
+
+impl PacketType {
+    fn from_raw(pkttype: u8) -> PacketType {
+        match pkttype {
+            libc::PACKET_HOST => PacketType::Host,
+            libc::PACKET_BROADCAST => PacketType::Broadcast,
+            libc::PACKET_MULTICAST => PacketType::Multicast,
+            libc::PACKET_OTHERHOST => PacketType::OtherHost,
+            libc::PACKET_OUTGOING => PacketType::Outgoing,
+            other => PacketType::Other(other),
+        }
+    }
+
+    /// Type of a frame sent to `dst` for backends without `sockaddr_ll`
+    fn of_destination(dst: &[u8], mac: Option<[u8; 6]>) -> PacketType {
+        match dst {
+            [0xff, 0xff, 0xff, 0xff, 0xff, 0xff] => PacketType::Broadcast,
+            [first, ..] if first & 1 != 0 => PacketType::Multicast,
+            _ if mac.is_some_and(|mac| mac == dst) => PacketType::Host,
+            _ => PacketType::OtherHost,
+        }
+    }
+}
+
+/// 802.1Q tag a frame was received with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VlanTag {
+    pub vid: u16,
+    /// `None` on sockets of VLAN interfaces, which only get frames after the 8021q
+    /// module dropped their tag
+    pub pcp: Option<u8>,
+    pub dei: bool,
+}
+
+impl VlanTag {
+    pub fn from_tci(tci: u16) -> VlanTag {
+        VlanTag {
+            vid: tci & 0x0fff,
+            pcp: Some((tci >> 13) as u8),
+            dei: tci & 0x1000 != 0,
+        }
+    }
+}
+
+/// Where a frame of [`recv_from`] came from. The frame itself still starts with its
+/// Ethernet header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecvInfo {
+    /// Source MAC address
+    pub src: [u8; 6],
+    /// EtherType after the VLAN tag, if any
+    pub ethertype: u16,
+    pub pkttype: PacketType,
+    /// Interface the frame came in on, 0 on the virtual wire
+    pub ifindex: u32,
+    pub vlan: Option<VlanTag>,
+}
+
+/// TX timestamp of the frame sent as `id`-th since timestamps were enabled
+#[derive(Debug, Clone, Copy)]
+pub struct TxTimestamp {
+    pub id: u32,
+    pub time: time::Timespec,
+    pub source: TimestampSource,
+}
+
+/// Pairs TX timestamps with the frames that produced them, for senders that keep
+/// several frames in flight before reading timestamps
+pub struct TxTimestampMatcher<T> {
+    pending: HashMap<u32, T>,
+}
+
+impl<T> TxTimestampMatcher<T> {
+    pub fn new() -> TxTimestampMatcher<T> {
+        TxTimestampMatcher {
+            pending: HashMap::new(),
+        }
+    }
+
+    /// Remember `frame` as sent with the ID from [`TsnSocket::next_tx_id`]
+    pub fn sent(&mut self, id: u32, frame: T) {
+        self.pending.insert(id, frame);
+    }
+
+    /// Take the frame `ts` belongs to. `None` if the frame is unknown
+    pub fn matched(&mut self, ts: TxTimestamp) -> Option<(T, TxTimestamp)> {
+        self.pending.remove(&ts.id).map(|frame| (frame, ts))
+    }
+
+    /// Drain all queued timestamps of `sock` and match them
+    pub fn poll(&mut self, sock: &TsnSocket) -> Result<Vec<(T, TxTimestamp)>, TsnError> {
+        Ok(drain_tx_timestamps(sock)?
+            .into_iter()
+            .filter_map(|ts| self.matched(ts))
+            .collect())
+    }
+
+    /// Forget frames whose timestamps will never come, e.g. after a timeout
+    pub fn clear(&mut self) -> Vec<(u32, T)> {
+        self.pending.drain().collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+}
+
+impl<T> Default for TxTimestampMatcher<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Launch time settings of a socket, see `SO_TXTIME`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TxtimeConfig {
+    /// Clock the txtime of [`send_at`] is measured in, usually `CLOCK_TAI`
+    pub clockid: libc::clockid_t,
+    /// Treat txtime as a deadline instead of an exact launch time
+    pub deadline_mode: bool,
+    /// Report dropped frames on the error queue
+    pub report_errors: bool,
+}
+
+impl TxtimeConfig {
+    pub fn new(clockid: libc::clockid_t) -> TxtimeConfig {
+        TxtimeConfig {
+            clockid,
+            deadline_mode: false,
+            report_errors: true,
+        }
+    }
+}
+
+/// How a [`TsnSocket`] exchanges frames, see [`sock_open_with`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Backend {
+    /// `AF_PACKET` socket on the VLAN interface
+    Packet,
+    /// `AF_PACKET` socket with `PACKET_MMAP` rings
+    Ring(ring::RingConfig),
+    /// AF_XDP socket on the queue the priority maps to
+    #[cfg(feature = "xdp")]
+    Xdp(xdp::XdpConfig),
+    /// In-process virtual wire for tests, see the [`wire`] module. No VLAN, qdisc or
+    /// config file is set up
+    Virtual(wire::WireConfig),
+}
+
+#[cfg(feature = "async")]
+pub mod async_socket;
+pub mod builder;
+pub mod capi;
+pub mod caps;
+pub mod cbs;
+pub mod config;
+mod error;
+pub mod filter;
+pub mod gptp;
+pub mod hwtstamp;
+mod netlink;
+pub mod phc;
+pub mod pmc;
+pub mod ptp;
+#[cfg(feature = "python")]
+mod python;
+pub mod ring;
+mod sys;
+pub mod tas;
 pub mod time;
-mod vlan;
-const SHM_SIZE: usize = 128;
+pub mod vlan;
+pub mod wire;
+#[cfg(feature = "xdp")]
+pub mod xdp;
+#[cfg(feature = "async")]
+pub use async_socket::AsyncTsnSocket;
+pub use builder::{ConfigSource, TimestampMode, TsnSocketBuilder};
+pub use error::{TsnError, TxtimeDropReason};
+/// Holder files of the VLANs opened by [`sock_open`] and of the hardware timestamp
+/// configs of [`enable_timestamps`], see [`gc_vlans`]
+const RUN_DIR: &str = "/run/libtsn";
+/// Lock file in `RUN_DIR`, never removed
+const RUN_LOCK: &str = ".lock";
+/// Error queue messages kept for later readers before the oldest are dropped
+const ERRQUEUE_BACKLOG: usize = 1024;
+/// Length of an Ethernet header without VLAN tag
+const ETH_HLEN: usize = 14;
 
 // Make imple for TsnSocket
 impl TsnSocket {
-    pub fn set_timeout(&mut self, timeout: Duration) -> Result<(), String> {
+    /// Start configuring a socket on `ifname`
+    pub fn builder(ifname: &str) -> TsnSocketBuilder {
+        TsnSocketBuilder::new(ifname)
+    }
+
+    fn new(fd: i32, ifname: &str, vlanid: u16) -> TsnSocket {
+        TsnSocket {
+            fd,
+            ifname: ifname.to_string(),
+            vlanid,
+            rx_timestamp_enabled: false,
+            tx_timestamp_enabled: false,
+            txtime: None,
+            tx_key: AtomicU32::new(0),
+            auxdata: AtomicBool::new(false),
+            mac: OnceLock::new(),
+            errqueue: Mutex::new(VecDeque::new()),
+            ring: None,
+            #[cfg(feature = "xdp")]
+            xdp: None,
+            vlan_hold: None,
+            hwtstamp: None,
+            wire: None,
+        }
+    }
+
+    /// Fail for calls the virtual wire cannot serve
+    fn check_not_wire(&self, op: &str) -> Result<(), TsnError> {
+        if self.wire.is_some() {
+            return Err(TsnError::Unsupported(format!(
+                "{} is not available on a virtual wire",
+                op
+            )));
+        }
+        Ok(())
+    }
+
+    /// Fail for calls the AF_XDP backend cannot serve
+    #[cfg_attr(not(feature = "xdp"), allow(unused_variables))]
+    fn check_not_xdp(&self, op: &str) -> Result<(), TsnError> {
+        #[cfg(feature = "xdp")]
+        if self.xdp.is_some() {
+            return Err(TsnError::Unsupported(format!(
+                "{} is not available on an XDP socket",
+                op
+            )));
+        }
+        Ok(())
+    }
+
+    /// Fail if `tx`/rx frames of this socket go through a ring instead of the socket queue
+    fn check_no_ring(&self, tx: bool, op: &str) -> Result<(), TsnError> {
+        match &self.ring {
+            Some(ring) if (tx && ring.has_tx()) || (!tx && ring.has_rx()) => Err(
+                TsnError::Unsupported(format!("{} is not available on a ring socket", op)),
+            ),
+            _ => Ok(()),
+        }
+    }
+
+    /// Account a sent frame for `SOF_TIMESTAMPING_OPT_ID`
+    fn sent(&self, count: u32) {
+        if self.tx_timestamp_enabled {
+            self.tx_key.fetch_add(count, Ordering::Relaxed);
+        }
+    }
+
+    /// ID of the TX timestamp of the next frame sent on this socket.
+    ///
+    /// Only meaningful after [`enable_timestamps`], and only while one thread sends.
+    pub fn next_tx_id(&self) -> u32 {
+        self.tx_key.load(Ordering::Relaxed)
+    }
+
+    /// Source MAC address for frames sent on this socket
+    pub fn mac_address(&self) -> Result<[u8; 6], TsnError> {
+        if let Some(wire) = &self.wire {
+            return Ok(wire.mac_address());
+        }
+        match interfaces::Interface::get_by_name(&self.ifname) {
+            Ok(Some(iface)) => iface
+                .hardware_addr()
+                .ok()
+                .and_then(|addr| addr.as_bytes().try_into().ok())
+                .ok_or_else(|| TsnError::InterfaceNotFound(self.ifname.clone())),
+            _ => Err(TsnError::InterfaceNotFound(self.ifname.clone())),
+        }
+    }
+
+    /// [`mac_address`](Self::mac_address) as read on first use
+    fn cached_mac_address(&self) -> Result<[u8; 6], TsnError> {
+        if let Some(mac) = self.mac.get() {
+            return Ok(*mac);
+        }
+        let mac = self.mac_address()?;
+        Ok(*self.mac.get_or_init(|| mac))
+    }
+
+    pub fn set_timeout(&self, timeout: Duration) -> Result<(), TsnError> {
         sock_set_timeout(self, timeout)
     }
 
-    pub fn send(&self, buf: &[u8]) -> Result<isize, String> {
+    pub fn send(&self, buf: &[u8]) -> Result<isize, TsnError> {
         send(self, buf)
     }
 
-    pub fn recv(&self, buf: &mut [u8]) -> Result<isize, String> {
+    pub fn send_to(&self, dst: [u8; 6], ethertype: u16, payload: &[u8]) -> Result<isize, TsnError> {
+        send_to(self, dst, ethertype, payload)
+    }
+
+    pub fn send_batch<B: AsRef<[u8]>>(&self, bufs: &[B]) -> Result<usize, TsnError> {
+        send_batch(self, bufs)
+    }
+
+    pub fn send_at(
+        &mut self,
+        buf: &[u8],
+        txtime: u64,
+        clockid: libc::clockid_t,
+    ) -> Result<isize, TsnError> {
+        send_at(self, buf, txtime, clockid)
+    }
+
+    pub fn enable_txtime(&mut self, config: TxtimeConfig) -> Result<(), TsnError> {
+        enable_txtime(self, config)
+    }
+
+    pub fn get_txtime_error(&self) -> Result<(), TsnError> {
+        get_txtime_error(self)
+    }
+
+    pub fn attach_filter(&self, filter: &filter::Filter) -> Result<(), TsnError> {
+        attach_filter(self, filter)
+    }
+
+    pub fn detach_filter(&self) -> Result<(), TsnError> {
+        detach_filter(self)
+    }
+
+    pub fn join_multicast(&self, mac: [u8; 6]) -> Result<(), TsnError> {
+        join_multicast(self, mac)
+    }
+
+    pub fn recv(&self, buf: &mut [u8]) -> Result<isize, TsnError> {
         recv(self, buf)
     }
 
-    pub fn recv_msg(&self, msg: &mut msghdr) -> Result<isize, String> {
+    pub fn recv_from(&self, buf: &mut [u8]) -> Result<(usize, RecvInfo), TsnError> {
+        recv_from(self, buf)
+    }
+
+    pub fn recv_with_timestamp(&self, buf: &mut [u8]) -> Result<(usize, RxTimestamp), TsnError> {
+        recv_with_timestamp(self, buf)
+    }
+
+    pub fn recv_batch<B: AsMut<[u8]>>(
+        &self,
+        bufs: &mut [B],
+    ) -> Result<Vec<(usize, Option<RxTimestamp>)>, TsnError> {
+        recv_batch(self, bufs)
+    }
+
+    pub fn recv_batch_from<B: AsMut<[u8]>>(
+        &self,
+        bufs: &mut [B],
+    ) -> Result<Vec<(usize, RecvInfo)>, TsnError> {
+        recv_batch_from(self, bufs)
+    }
+
+    pub fn recv_msg(&self, msg: &mut msghdr) -> Result<isize, TsnError> {
         recv_msg(self, msg)
     }
 
-    pub fn enable_timestamps(&mut self, iov: Option<&mut libc::iovec>) -> Result<(), Error> {
+    pub fn enable_timestamps(&mut self, iov: Option<&mut libc::iovec>) -> Result<(), TsnError> {
         enable_timestamps(self, iov)
     }
 
-    pub fn get_tx_timestamp(&self) -> Result<time::Timespec, Error> {
+    /// Hardware timestamp config of the interface as [`enable_timestamps`] left it,
+    /// which may be wider than the socket needs. `None` without hardware timestamps
+    pub fn hw_timestamp_config(&self) -> Option<hwtstamp::HwTstampConfig> {
+        self.hwtstamp.as_ref().map(|hold| hold.config())
+    }
+
+    pub fn get_tx_timestamp(&self) -> Result<TxTimestamp, TsnError> {
         get_tx_timestamp(self)
     }
 
-    pub fn get_rx_timestamp(&self) -> Result<time::Timespec, Error> {
+    pub fn drain_tx_timestamps(&self) -> Result<Vec<TxTimestamp>, TsnError> {
+        drain_tx_timestamps(self)
+    }
+
+    pub fn get_rx_timestamp(&self) -> Result<time::Timespec, TsnError> {
         get_rx_timestamp(self)
     }
 
-    pub fn close(&mut self) -> Result<(), String> {
+    pub fn next_rx_block(
+        &mut self,
+        timeout: Option<Duration>,
+    ) -> Result<ring::RxBlock<'_>, TsnError> {
+        ring::next_rx_block(self, timeout)
+    }
+
+    pub fn tx_slot(&mut self) -> Result<Option<ring::TxSlot<'_>>, TsnError> {
+        ring::tx_slot(self)
+    }
+
+    pub fn tx_flush(&mut self) -> Result<usize, TsnError> {
+        ring::tx_flush(self)
+    }
+
+    pub fn close(&mut self) -> Result<(), TsnError> {
         sock_close(self)
     }
 }
 
-fn create_vlan(ifname: &str, vlanid: u16) -> Result<String, String> {
-    let config = get_config(ifname)?;
-    let shm_name = get_shmem_name(ifname, vlanid);
-    let shm_fd = get_shmem_fd(&shm_name)?;
-    lock_shmem(&shm_fd)?;
-    let mut vlan_vec = read_shmem(&shm_name)?;
+impl Drop for TsnSocket {
+    fn drop(&mut self) {
+        let _ = sock_close(self);
+    }
+}
+
+impl AsRawFd for TsnSocket {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+}
+
+/// Take a hold on the VLAN `vlanid` of `ifname`, creating it if nobody holds it yet.
+///
+/// Every hold is a shared `flock` on `RUN_DIR/<vlan name>`, so the kernel drops it when
+/// the holder exits, however it exits. The VLAN has no holders left when an exclusive
+/// lock can be taken on that file.
+fn create_vlan(
+    ifname: &str,
+    vlanid: u16,
+    config: &config::Config,
+) -> Result<(String, File), TsnError> {
     let name = vlan::get_vlan_name(ifname, vlanid);
-    // If I am the frist user of this vlan, create it
-    let result = if vlan_vec.is_empty() {
-        vlan::create_vlan(&config, ifname, vlanid)
-    } else {
-        Ok(0)
-    };
-    vlan_vec.push(process::id());
-    write_shmem(&shm_name, &vlan_vec)?;
-    unlock_shmem(&shm_fd)?;
-    match result {
-        Ok(_) => Ok(name),
-        Err(_) => Err(format!("Create vlan fails {}", Error::last_os_error())),
-    }
-}
-
-fn delete_vlan(ifname: &str, vlanid: u16) -> Result<i32, String> {
-    let shm_name = get_shmem_name(ifname, vlanid);
-    let shm_fd = get_shmem_fd(&shm_name)?;
-    lock_shmem(&shm_fd)?;
-    let mut vlan_vec = read_shmem(&shm_name)?;
-    // remove my pid from shmem
-    for i in 0..vlan_vec.len() {
-        if vlan_vec[i] == process::id() {
-            vlan_vec.remove(i);
-            break;
-        }
-    }
-    // delete dead process from vector
-    vlan_vec.retain(|x| kill(Pid::from_raw(*x as i32), None).is_ok());
-    let exit_flag = vlan_vec.is_empty();
-    vlan_vec.resize(SHM_SIZE / size_of::<u32>(), 0);
-    write_shmem(&shm_name, &vlan_vec)?;
-    let result = if exit_flag {
-        if shm_unlink(&*shm_name).is_err() {
-            return Err(format!("Delete shmem fails {}", Error::last_os_error()));
-        }
-        match vlan::delete_vlan(ifname, vlanid) {
-            Ok(v) => Ok(v),
-            Err(_) => Err(format!("Delete vlan fails {}", Error::last_os_error())),
+    let _lock = lock_run_dir()?;
+    let path = holder_path(&name);
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&path)
+        .map_err(TsnError::holder("Open"))?;
+    // If I am the first user of this vlan, create it
+    if try_flock(&file, libc::LOCK_EX)? {
+        // Left behind by holders that exited without closing their sockets
+        if vlan_exists(&name) {
+            let _ = vlan::delete_vlan(ifname, vlanid);
         }
-    } else {
-        Ok(0)
-    };
-    unlock_shmem(&shm_fd)?;
-    result
+        let res = vlan::create_vlan(config, ifname, vlanid)
+            .map_err(|e| TsnError::Vlan {
+                op: "Create",
+                name: name.clone(),
+                source: Box::new(e),
+            })
+            .and_then(|_| {
+                file.set_len(0)
+                    .and_then(|_| writeln!(file, "{} {}", ifname, vlanid))
+                    .map_err(TsnError::holder("Write"))
+            });
+        if let Err(e) = res {
+            // Without the holder file gc cannot find what was set up before the failure
+            let _ = vlan::delete_vlan(ifname, vlanid);
+            let _ = fs::remove_file(&path);
+            return Err(e);
+        }
+    }
+    flock(&file, libc::LOCK_SH)?;
+    Ok((name, file))
+}
+
+/// Release `hold` from [`create_vlan`], deleting the VLAN if it was the last one
+fn delete_vlan(ifname: &str, vlanid: u16, hold: File) -> Result<(), TsnError> {
+    let name = vlan::get_vlan_name(ifname, vlanid);
+    let _lock = lock_run_dir()?;
+    if try_flock(&hold, libc::LOCK_EX)? {
+        fs::remove_file(holder_path(&name)).map_err(TsnError::holder("Remove"))?;
+        vlan::delete_vlan(ifname, vlanid).map_err(|e| TsnError::Vlan {
+            op: "Delete",
+            name,
+            source: Box::new(e),
+        })?;
+    }
+    Ok(())
+}
+
+/// Hold on a VLAN without a socket, e.g. to keep it and its qdiscs set up while other
+/// processes open and close sockets on it. Counted like the hold of a socket
+pub struct VlanHold {
+    ifname: String,
+    vlanid: u16,
+    hold: Option<File>,
+}
+
+impl VlanHold {
+    /// Take a hold on the VLAN `vlanid` of `ifname`, creating it with `config` if
+    /// nobody holds it yet
+    pub fn new(ifname: &str, vlanid: u16, config: &config::Config) -> Result<VlanHold, TsnError> {
+        let (_, hold) = create_vlan(ifname, vlanid, config)?;
+        Ok(VlanHold {
+            ifname: ifname.to_string(),
+            vlanid,
+            hold: Some(hold),
+        })
+    }
+
+    pub fn name(&self) -> String {
+        vlan::get_vlan_name(&self.ifname, self.vlanid)
+    }
+
+    /// Release the hold, deleting the VLAN if it was the last one. Dropping releases it
+    /// too, ignoring errors
+    pub fn release(&mut self) -> Result<(), TsnError> {
+        match self.hold.take() {
+            Some(hold) => delete_vlan(&self.ifname, self.vlanid, hold),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for VlanHold {
+    fn drop(&mut self) {
+        let _ = self.release();
+    }
+}
+
+/// Delete VLANs, and the qdiscs set up with them, whose holders all exited without
+/// [`sock_close`], and put back the hardware timestamp configs they changed. Returns
+/// the names of the deleted VLANs
+pub fn gc_vlans() -> Result<Vec<String>, TsnError> {
+    let _lock = lock_run_dir()?;
+    let mut deleted = Vec::new();
+    for entry in fs::read_dir(RUN_DIR).map_err(TsnError::holder("Read dir"))? {
+        let path = entry.map_err(TsnError::holder("Read dir"))?.path();
+        let name = match path.file_name().and_then(|name| name.to_str()) {
+            Some(name) if name != RUN_LOCK => name.to_string(),
+            _ => continue,
+        };
+        let file = File::open(&path).map_err(TsnError::holder("Open"))?;
+        if !try_flock(&file, libc::LOCK_EX)? {
+            continue;
+        }
+        if let Some(ifname) = name.strip_suffix(hwtstamp::HOLDER_SUFFIX) {
+            hwtstamp::restore(ifname, file)?;
+            continue;
+        }
+        let content = fs::read_to_string(&path).map_err(TsnError::holder("Read"))?;
+        let mut fields = content.split_whitespace();
+        let owner = fields
+            .next()
+            .zip(fields.next().and_then(|id| id.parse::<u16>().ok()));
+        if let Some((ifname, vlanid)) = owner {
+            if vlan_exists(&name) {
+                vlan::delete_vlan(ifname, vlanid).map_err(|e| TsnError::Vlan {
+                    op: "Delete",
+                    name: name.clone(),
+                    source: Box::new(e),
+                })?;
+                deleted.push(name);
+            }
+        }
+        fs::remove_file(&path).map_err(TsnError::holder("Remove"))?;
+    }
+    Ok(deleted)
 }
 
 pub fn sock_open(
@@ -127,41 +646,116 @@ pub fn sock_open(
     vlanid: u16,
     priority: u32,
     proto: u16,
-) -> Result<TsnSocket, String> {
-    let name = match create_vlan(ifname, vlanid) {
-        Ok(v) => v,
-        Err(err_msg) => {
-            return Err(format!("Create vlan fails: {}", err_msg));
-        }
+) -> Result<TsnSocket, TsnError> {
+    sock_open_with(ifname, vlanid, priority, proto, Backend::Packet)
+}
+
+/// Open a socket on the VLAN `vlanid` of `ifname` which uses `backend` for its frames.
+///
+/// See [`TsnSocket::builder`] for the other settings.
+pub fn sock_open_with(
+    ifname: &str,
+    vlanid: u16,
+    priority: u32,
+    proto: u16,
+    backend: Backend,
+) -> Result<TsnSocket, TsnError> {
+    TsnSocket::builder(ifname)
+        .vlan(vlanid)
+        .priority(priority)
+        .protocol(proto)
+        .backend(backend)
+        .open()
+}
+
+/// Like [`sock_open`], but frames are exchanged through `PACKET_MMAP` rings.
+///
+/// See the [`ring`] module for how to receive and send on such a socket.
+pub fn sock_open_ring(
+    ifname: &str,
+    vlanid: u16,
+    priority: u32,
+    proto: u16,
+    config: ring::RingConfig,
+) -> Result<TsnSocket, TsnError> {
+    sock_open_with(ifname, vlanid, priority, proto, Backend::Ring(config))
+}
+
+/// Open an `AF_PACKET` socket on the VLAN `vlanid` of `ifname`, or on `ifname` itself
+fn open_socket(
+    ifname: &str,
+    vlanid: Option<u16>,
+    config: &config::Config,
+    priority: u32,
+    proto: u16,
+    ring_config: Option<ring::RingConfig>,
+) -> Result<TsnSocket, TsnError> {
+    let Some(vlanid) = vlanid else {
+        return open_packet(ifname, ifname, 0, priority, proto, ring_config);
     };
-    let sock;
-    let mut res;
-    let ifindex = if_nametoindex(name.as_bytes()).expect("vlan_ifname index");
-    unsafe {
-        sock = libc::socket(
+    let (name, hold) = create_vlan(ifname, vlanid, config)?;
+    match open_packet(&name, ifname, vlanid, priority, proto, ring_config) {
+        Ok(mut sock) => {
+            sock.vlan_hold = Some(hold);
+            Ok(sock)
+        }
+        Err(e) => {
+            let _ = delete_vlan(ifname, vlanid, hold);
+            Err(e)
+        }
+    }
+}
+
+fn open_packet(
+    name: &str,
+    ifname: &str,
+    vlanid: u16,
+    priority: u32,
+    proto: u16,
+    ring_config: Option<ring::RingConfig>,
+) -> Result<TsnSocket, TsnError> {
+    let ifindex = if_nametoindex(name.as_bytes()).map_err(|e| TsnError::Socket {
+        op: "if_nametoindex",
+        source: e.into(),
+    })?;
+    let sock = unsafe {
+        libc::socket(
             libc::AF_PACKET,
             libc::SOCK_RAW,
             socket::htons(proto) as libc::c_int,
-        );
-    }
+        )
+    };
     if sock < 0 {
-        return Err(Error::last_os_error().to_string());
+        return Err(TsnError::socket("Socket"));
     }
-    let prio: *const u32 = &priority;
-    unsafe {
-        res = libc::setsockopt(
+
+    let res = unsafe {
+        libc::setsockopt(
             sock as libc::c_int,
             libc::SOL_SOCKET,
             libc::SO_PRIORITY,
-            prio as *const libc::c_void,
-            mem::size_of_val(&prio) as u32,
-        );
-    }
-
+            &priority as *const u32 as *const libc::c_void,
+            mem::size_of::<u32>() as u32,
+        )
+    };
     if res < 0 {
-        return Err(format!("Socket option error: {}", Error::last_os_error()));
+        let err = TsnError::socket("Socket option");
+        let _ = close(sock);
+        return Err(err);
     }
 
+    // Rings have to be set up before bind
+    let ring = match ring_config
+        .map(|config| ring::Ring::setup(sock, config))
+        .transpose()
+    {
+        Ok(ring) => ring,
+        Err(err) => {
+            let _ = close(sock);
+            return Err(err);
+        }
+    };
+
     let sock_ll = libc::sockaddr_ll {
         sll_family: libc::AF_PACKET as u16,
         sll_ifindex: ifindex as i32,
@@ -172,36 +766,97 @@ pub fn sock_open(
         sll_pkttype: 0,
     };
 
-    unsafe {
-        res = libc::bind(
+    let res = unsafe {
+        libc::bind(
             sock,
             &sock_ll as *const libc::sockaddr_ll as *const libc::sockaddr,
             mem::size_of_val(&sock_ll) as u32,
-        );
-    }
+        )
+    };
     if res < 0 {
-        return Err(format!("Bind error: {}", Error::last_os_error()));
+        let err = TsnError::socket("Bind");
+        drop(ring);
+        let _ = close(sock);
+        return Err(err);
     }
 
-    Ok(TsnSocket {
-        fd: sock,
-        ifname: ifname.to_string(),
-        vlanid,
-        rx_timestamp_enabled: false,
-    })
+    let mut sock = TsnSocket::new(sock, ifname, vlanid);
+    sock.ring = ring;
+    Ok(sock)
 }
 
-pub fn sock_close(sock: &mut TsnSocket) -> Result<(), String> {
-    match delete_vlan(&sock.ifname, sock.vlanid) {
-        Ok(_) => {
-            close(sock.fd).unwrap();
-            Ok(())
+#[cfg(feature = "xdp")]
+fn open_xdp(
+    ifname: &str,
+    vlanid: Option<u16>,
+    tsn_config: &config::Config,
+    priority: u32,
+    proto: u16,
+    config: xdp::XdpConfig,
+) -> Result<TsnSocket, TsnError> {
+    let vlanid =
+        vlanid.ok_or_else(|| TsnError::Unsupported("XDP sockets need a VLAN".to_string()))?;
+    // The VLAN interface is not used for frames, but keeps the refcount and qdisc setup
+    let (_, hold) = create_vlan(ifname, vlanid, tsn_config)?;
+    let queue = xdp::queue_of(tsn_config, priority);
+    match xdp::XdpSocket::open(ifname, queue, vlanid, priority, proto, config) {
+        Ok(xsk) => {
+            let mut sock = TsnSocket::new(xsk.fd(), ifname, vlanid);
+            sock.xdp = Some(xsk);
+            sock.vlan_hold = Some(hold);
+            Ok(sock)
+        }
+        Err(e) => {
+            let _ = delete_vlan(ifname, vlanid, hold);
+            Err(e)
         }
-        Err(_) => Err(format!("Delete vlan fails: {}", Error::last_os_error())),
     }
 }
 
-pub fn sock_set_timeout(sock: &mut TsnSocket, timeout: Duration) -> Result<(), String> {
+/// Join the virtual wire of `ifname`, or of its VLAN `vlanid`
+fn open_wire(
+    ifname: &str,
+    vlanid: Option<u16>,
+    proto: u16,
+    config: wire::WireConfig,
+) -> Result<TsnSocket, TsnError> {
+    let name = match vlanid {
+        Some(vlanid) => vlan::get_vlan_name(ifname, vlanid),
+        None => ifname.to_string(),
+    };
+    let (fd, port) = wire::WirePort::open(&name, proto, config)?;
+    let mut sock = TsnSocket::new(fd, ifname, vlanid.unwrap_or(0));
+    sock.wire = Some(port);
+    Ok(sock)
+}
+
+/// Close the socket and release its VLAN. Closing twice is a no-op, and dropping the
+/// socket closes it too
+pub fn sock_close(sock: &mut TsnSocket) -> Result<(), TsnError> {
+    let res = match sock.vlan_hold.take() {
+        Some(hold) => delete_vlan(&sock.ifname, sock.vlanid, hold),
+        None => Ok(()),
+    };
+    let res = match sock.hwtstamp.take() {
+        Some(mut hold) => res.and(hold.release()),
+        None => res,
+    };
+    sock.ring = None;
+    sock.wire = None;
+    #[cfg(feature = "xdp")]
+    {
+        sock.xdp = None;
+    }
+    if sock.fd >= 0 {
+        close(mem::replace(&mut sock.fd, -1)).map_err(|e| TsnError::Socket {
+            op: "Close",
+            source: e.into(),
+        })?;
+    }
+    res
+}
+
+pub fn sock_set_timeout(sock: &TsnSocket, timeout: Duration) -> Result<(), TsnError> {
     let sock_timeout = libc::timeval {
         tv_sec: timeout.as_secs() as i64,
         tv_usec: timeout.subsec_micros() as i64,
@@ -217,14 +872,31 @@ pub fn sock_set_timeout(sock: &mut TsnSocket, timeout: Duration) -> Result<(), S
         )
     };
 
+    #[cfg(feature = "xdp")]
+    if let Some(xdp) = &sock.xdp {
+        xdp.set_timeout(timeout);
+    }
+
     if res < 0 {
-        Err(format!("Set timeout error: {}", Error::last_os_error()))
+        Err(TsnError::socket("Set timeout"))
     } else {
         Ok(())
     }
 }
 
-pub fn send(sock: &TsnSocket, buf: &[u8]) -> Result<isize, String> {
+pub fn send(sock: &TsnSocket, buf: &[u8]) -> Result<isize, TsnError> {
+    sock.check_no_ring(true, "send")?;
+    #[cfg(feature = "xdp")]
+    if let Some(xdp) = &sock.xdp {
+        let res = xdp.send(buf)?;
+        sock.sent(1);
+        return Ok(res);
+    }
+    if let Some(wire) = &sock.wire {
+        let res = wire.send(sock.fd, buf, sock.tx_timestamp_enabled, sock.next_tx_id())?;
+        sock.sent(1);
+        return Ok(res);
+    }
     let res = unsafe {
         libc::sendto(
             sock.fd,
@@ -237,13 +909,189 @@ pub fn send(sock: &TsnSocket, buf: &[u8]) -> Result<isize, String> {
     };
 
     if res < 0 {
-        Err(format!("Send error: {}", Error::last_os_error()))
+        Err(TsnError::socket("Send"))
     } else {
+        sock.sent(1);
         Ok(res)
     }
 }
 
-pub fn recv(sock: &TsnSocket, buf: &mut [u8]) -> Result<isize, String> {
+/// Send `payload` to `dst` in a frame with the header built from the MAC address of
+/// the interface and `ethertype`. Returns the number of bytes sent, header included
+pub fn send_to(
+    sock: &TsnSocket,
+    dst: [u8; 6],
+    ethertype: u16,
+    payload: &[u8],
+) -> Result<isize, TsnError> {
+    let src = sock.cached_mac_address()?;
+    let mut frame = Vec::with_capacity(ETH_HLEN + payload.len());
+    frame.extend_from_slice(&dst);
+    frame.extend_from_slice(&src);
+    frame.extend_from_slice(&ethertype.to_be_bytes());
+    frame.extend_from_slice(payload);
+    send(sock, &frame)
+}
+
+/// Send several frames with one `sendmmsg` call. Returns how many were sent
+pub fn send_batch<B: AsRef<[u8]>>(sock: &TsnSocket, bufs: &[B]) -> Result<usize, TsnError> {
+    sock.check_no_ring(true, "send_batch")?;
+    #[cfg(feature = "xdp")]
+    if let Some(xdp) = &sock.xdp {
+        for (sent, buf) in bufs.iter().enumerate() {
+            if let Err(e) = xdp.send(buf.as_ref()) {
+                return if sent == 0 { Err(e) } else { Ok(sent) };
+            }
+        }
+        return Ok(bufs.len());
+    }
+    if sock.wire.is_some() {
+        for buf in bufs {
+            send(sock, buf.as_ref())?;
+        }
+        return Ok(bufs.len());
+    }
+    let mut iovs: Vec<libc::iovec> = bufs
+        .iter()
+        .map(|buf| libc::iovec {
+            iov_base: buf.as_ref().as_ptr() as *mut libc::c_void,
+            iov_len: buf.as_ref().len(),
+        })
+        .collect();
+    let mut msgs: Vec<libc::mmsghdr> = iovs
+        .iter_mut()
+        .map(|iov| {
+            let mut msg: libc::mmsghdr = unsafe { mem::zeroed() };
+            msg.msg_hdr.msg_iov = iov;
+            msg.msg_hdr.msg_iovlen = 1;
+            msg
+        })
+        .collect();
+
+    let res = unsafe { libc::sendmmsg(sock.fd, msgs.as_mut_ptr(), msgs.len() as _, 0) };
+    if res < 0 {
+        Err(TsnError::socket("Send"))
+    } else {
+        sock.sent(res as u32);
+        Ok(res as usize)
+    }
+}
+
+pub fn enable_txtime(sock: &mut TsnSocket, config: TxtimeConfig) -> Result<(), TsnError> {
+    sock.check_not_xdp("SO_TXTIME")?;
+    sock.check_not_wire("SO_TXTIME")?;
+    let mut flags = 0;
+    if config.deadline_mode {
+        flags |= libc::SOF_TXTIME_DEADLINE_MODE;
+    }
+    if config.report_errors {
+        flags |= libc::SOF_TXTIME_REPORT_ERRORS;
+    }
+    let txtime = libc::sock_txtime {
+        clockid: config.clockid,
+        flags,
+    };
+
+    let res = unsafe {
+        libc::setsockopt(
+            sock.fd,
+            libc::SOL_SOCKET,
+            libc::SO_TXTIME,
+            &txtime as *const libc::sock_txtime as *const libc::c_void,
+            mem::size_of::<libc::sock_txtime>() as u32,
+        )
+    };
+    if res < 0 {
+        return Err(TsnError::socket("Set SO_TXTIME"));
+    }
+
+    sock.txtime = Some(config);
+    Ok(())
+}
+
+/// Send a frame which leaves the NIC at `txtime` (ns in `clockid`).
+///
+/// `SO_TXTIME` is enabled on the first call, or re-enabled when the clock changes,
+/// keeping the flags of a previous [`enable_txtime`]. The frame is only held back
+/// if the egress queue has an ETF qdisc, see `etf` in the `tas` config.
+pub fn send_at(
+    sock: &mut TsnSocket,
+    buf: &[u8],
+    txtime: u64,
+    clockid: libc::clockid_t,
+) -> Result<isize, TsnError> {
+    sock.check_no_ring(true, "send_at")?;
+    sock.check_not_xdp("send_at")?;
+    sock.check_not_wire("send_at")?;
+    match sock.txtime {
+        Some(config) if config.clockid == clockid => {}
+        Some(config) => enable_txtime(sock, TxtimeConfig { clockid, ..config })?,
+        None => enable_txtime(sock, TxtimeConfig::new(clockid))?,
+    }
+
+    let mut control = [0u8; 64];
+    let iov = libc::iovec {
+        iov_base: buf.as_ptr() as *mut libc::c_void,
+        iov_len: buf.len(),
+    };
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_iov = &iov as *const _ as *mut libc::iovec;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    msg.msg_controllen = {
+        // aarch64 has msg_controllen as u32, not usize
+        #[allow(clippy::useless_conversion)]
+        unsafe { libc::CMSG_SPACE(mem::size_of::<u64>() as u32) }
+            .try_into()
+            .unwrap()
+    };
+
+    unsafe {
+        let cm = libc::CMSG_FIRSTHDR(&msg);
+        (*cm).cmsg_level = libc::SOL_SOCKET;
+        (*cm).cmsg_type = libc::SCM_TXTIME;
+        (*cm).cmsg_len = {
+            #[allow(clippy::useless_conversion)]
+            libc::CMSG_LEN(mem::size_of::<u64>() as u32)
+                .try_into()
+                .unwrap()
+        };
+        std::ptr::write_unaligned(libc::CMSG_DATA(cm) as *mut u64, txtime);
+    }
+
+    let res = unsafe { libc::sendmsg(sock.fd, &msg, 0) };
+    if res < 0 {
+        Err(TsnError::socket("Send"))
+    } else {
+        sock.sent(1);
+        Ok(res)
+    }
+}
+
+/// Check the error queue for a frame dropped by the ETF qdisc.
+///
+/// Returns `Ok(())` if no error is queued, [`TsnError::TxtimeDropped`] for a dropped
+/// frame. This does not block. TX timestamps read on the way are kept for
+/// [`get_tx_timestamp`].
+pub fn get_txtime_error(sock: &TsnSocket) -> Result<(), TsnError> {
+    if sock.wire.is_some() {
+        return Ok(());
+    }
+    match next_errqueue_msg(sock, ErrQueueMsg::is_txtime_error, 0)? {
+        Some(msg) => msg.txtime_error(),
+        None => Ok(()),
+    }
+}
+
+pub fn recv(sock: &TsnSocket, buf: &mut [u8]) -> Result<isize, TsnError> {
+    sock.check_no_ring(false, "recv")?;
+    #[cfg(feature = "xdp")]
+    if let Some(xdp) = &sock.xdp {
+        return Ok(xdp.recv(buf, true)?.map_or(0, |(len, _)| len) as isize);
+    }
+    if let Some(wire) = &sock.wire {
+        return Ok(wire.recv(sock.fd, buf, 0)?.0 as isize);
+    }
     let res = unsafe {
         libc::recvfrom(
             sock.fd,
@@ -256,34 +1104,541 @@ pub fn recv(sock: &TsnSocket, buf: &mut [u8]) -> Result<isize, String> {
     };
 
     if res < 0 {
-        Err(format!("Recv error: {}", Error::last_os_error()))
+        Err(TsnError::socket("Recv"))
     } else {
         Ok(res)
     }
 }
 
-pub fn recv_msg(sock: &TsnSocket, msg: &mut msghdr) -> Result<isize, String> {
+/// Let only frames matching `filter` reach the socket, replacing any filter before
+pub fn attach_filter(sock: &TsnSocket, filter: &filter::Filter) -> Result<(), TsnError> {
+    sock.check_not_xdp("attach_filter")?;
+    let base = if sock.wire.is_some() {
+        wire::HEADER_LEN as u32
+    } else {
+        0
+    };
+    if filter.uses_vlan_tag() && (sock.wire.is_some() || sock.vlanid != 0) {
+        return Err(TsnError::Unsupported(
+            "VLAN tag filters need a socket without VLAN, the tag is gone before frames \
+             reach a VLAN socket"
+                .to_string(),
+        ));
+    }
+    let mut program = filter.program(base)?;
+    let fprog = libc::sock_fprog {
+        len: program.len() as u16,
+        filter: program.as_mut_ptr(),
+    };
+    let res = unsafe {
+        libc::setsockopt(
+            sock.fd,
+            libc::SOL_SOCKET,
+            libc::SO_ATTACH_FILTER,
+            &fprog as *const libc::sock_fprog as *const libc::c_void,
+            mem::size_of::<libc::sock_fprog>() as u32,
+        )
+    };
+    if res < 0 {
+        return Err(TsnError::socket("Attach filter"));
+    }
+    Ok(())
+}
+
+/// Remove the filter of [`attach_filter`], a socket without one is left as is
+pub fn detach_filter(sock: &TsnSocket) -> Result<(), TsnError> {
+    sock.check_not_xdp("detach_filter")?;
+    // Ignored, but the kernel wants an int
+    let zero: libc::c_int = 0;
+    let res = unsafe {
+        libc::setsockopt(
+            sock.fd,
+            libc::SOL_SOCKET,
+            libc::SO_DETACH_FILTER,
+            &zero as *const libc::c_int as *const libc::c_void,
+            mem::size_of::<libc::c_int>() as u32,
+        )
+    };
+    if res < 0 && Error::last_os_error().raw_os_error() != Some(libc::ENOENT) {
+        return Err(TsnError::socket("Detach filter"));
+    }
+    Ok(())
+}
+
+/// Receive frames sent to the multicast address `mac`, which the NIC drops otherwise,
+/// until the socket is closed. The virtual wire delivers every frame already
+pub fn join_multicast(sock: &TsnSocket, mac: [u8; 6]) -> Result<(), TsnError> {
+    sock.check_not_xdp("join_multicast")?;
+    if sock.wire.is_some() {
+        return Ok(());
+    }
+    let ifindex = if_nametoindex(sock.ifname.as_bytes()).map_err(|e| TsnError::Socket {
+        op: "if_nametoindex",
+        source: e.into(),
+    })?;
+    let mut mreq: libc::packet_mreq = unsafe { mem::zeroed() };
+    mreq.mr_ifindex = ifindex as libc::c_int;
+    mreq.mr_type = libc::PACKET_MR_MULTICAST as libc::c_ushort;
+    mreq.mr_alen = mac.len() as libc::c_ushort;
+    mreq.mr_address[..mac.len()].copy_from_slice(&mac);
+    let res = unsafe {
+        libc::setsockopt(
+            sock.fd,
+            libc::SOL_PACKET,
+            libc::PACKET_ADD_MEMBERSHIP,
+            &mreq as *const libc::packet_mreq as *const libc::c_void,
+            mem::size_of::<libc::packet_mreq>() as u32,
+        )
+    };
+    if res < 0 {
+        return Err(TsnError::socket("Join multicast group"));
+    }
+    Ok(())
+}
+
+/// Receive a frame together with its RX timestamp.
+///
+/// The timestamp is taken from the best filled `SO_TIMESTAMPING` slot. When the
+/// kernel provides none (timestamps not enabled, or control data truncated) the
+/// frame is still returned with a [`TimestampSource::User`] timestamp.
+pub fn recv_with_timestamp(
+    sock: &TsnSocket,
+    buf: &mut [u8],
+) -> Result<(usize, RxTimestamp), TsnError> {
+    sock.check_no_ring(false, "recv_with_timestamp")?;
+    #[cfg(feature = "xdp")]
+    if let Some(xdp) = &sock.xdp {
+        let len = xdp.recv(buf, true)?.map_or(0, |(len, _)| len);
+        let ts = RxTimestamp {
+            time: time::Timespec::now(libc::CLOCK_REALTIME),
+            source: TimestampSource::User,
+        };
+        return Ok((len, ts));
+    }
+    if let Some(wire) = &sock.wire {
+        let (len, time) = wire.recv(sock.fd, buf, 0)?;
+        let source = if sock.tx_timestamp_enabled {
+            TimestampSource::Sw
+        } else {
+            TimestampSource::User
+        };
+        return Ok((len, RxTimestamp { time, source }));
+    }
+    let mut control = [0u8; 1024];
+    let mut iov = libc::iovec {
+        iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+        iov_len: buf.len(),
+    };
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    msg.msg_controllen = {
+        // aarch64 has msg_controllen as u32, not usize
+        #[allow(clippy::useless_conversion)]
+        control.len().try_into().unwrap()
+    };
+
+    let res = unsafe { libc::recvmsg(sock.fd, &mut msg, 0) };
+    if res < 0 {
+        return Err(TsnError::socket("Recv"));
+    }
+    let fallback = RxTimestamp {
+        time: time::Timespec::now(libc::CLOCK_REALTIME),
+        source: TimestampSource::User,
+    };
+
+    if msg.msg_flags & libc::MSG_CTRUNC != 0 {
+        return Ok((res as usize, fallback));
+    }
+    let ts = parse_timestamping(&msg)
+        .and_then(|ts| select_timestamp(&ts))
+        .map(|(time, source)| RxTimestamp { time, source })
+        .unwrap_or(fallback);
+    Ok((res as usize, ts))
+}
+
+/// Receive a frame together with where it came from, see [`RecvInfo`].
+///
+/// The VLAN tag is read from `PACKET_AUXDATA`, which is turned on by the first call.
+/// Sockets without VLAN only get it if bound to all protocols (`ETH_P_ALL`), the
+/// kernel drops the tag of a VLAN nobody listens to before it hands the frame to
+/// sockets bound to one protocol.
+pub fn recv_from(sock: &TsnSocket, buf: &mut [u8]) -> Result<(usize, RecvInfo), TsnError> {
+    sock.check_no_ring(false, "recv_from")?;
+    #[cfg(feature = "xdp")]
+    if let Some(xdp) = &sock.xdp {
+        let (len, vlan) = match xdp.recv(buf, true)? {
+            Some((len, tci)) => (len, Some(VlanTag::from_tci(tci))),
+            None => (0, None),
+        };
+        let ifindex = if_nametoindex(sock.ifname.as_bytes()).unwrap_or(0);
+        return Ok((len, frame_info(sock, &buf[..len], ifindex, vlan)));
+    }
+    if let Some(wire) = &sock.wire {
+        let (len, _) = wire.recv(sock.fd, buf, 0)?;
+        return Ok((len, frame_info(sock, &buf[..len], 0, stripped_vlan(sock))));
+    }
+    enable_auxdata(sock)?;
+
+    let mut addr: libc::sockaddr_ll = unsafe { mem::zeroed() };
+    let mut control = [0u8; 1024];
+    let mut iov = libc::iovec {
+        iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+        iov_len: buf.len(),
+    };
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_name = &mut addr as *mut libc::sockaddr_ll as *mut libc::c_void;
+    msg.msg_namelen = mem::size_of::<libc::sockaddr_ll>() as u32;
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    msg.msg_controllen = {
+        // aarch64 has msg_controllen as u32, not usize
+        #[allow(clippy::useless_conversion)]
+        control.len().try_into().unwrap()
+    };
+
+    let res = unsafe { libc::recvmsg(sock.fd, &mut msg, 0) };
+    if res < 0 {
+        return Err(TsnError::socket("Recv"));
+    }
+    Ok((res as usize, packet_info(sock, &addr, &msg)))
+}
+
+/// Turn on `PACKET_AUXDATA` for the VLAN tags of [`recv_from`]. The kernel fills it
+/// in when a frame is read, so frames queued before are covered too
+fn enable_auxdata(sock: &TsnSocket) -> Result<(), TsnError> {
+    if sock.wire.is_some() || sock.auxdata.load(Ordering::Relaxed) {
+        return Ok(());
+    }
+    #[cfg(feature = "xdp")]
+    if sock.xdp.is_some() {
+        return Ok(());
+    }
+    let one: libc::c_int = 1;
+    let res = unsafe {
+        libc::setsockopt(
+            sock.fd,
+            libc::SOL_PACKET,
+            libc::PACKET_AUXDATA,
+            &one as *const libc::c_int as *const libc::c_void,
+            mem::size_of::<libc::c_int>() as u32,
+        )
+    };
+    if res < 0 {
+        return Err(TsnError::socket("Socket option"));
+    }
+    sock.auxdata.store(true, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Tag of frames on a VLAN socket whose tag is gone before they reach it: the 8021q
+/// module drops it for VLAN interfaces, the virtual wire never carries one
+fn stripped_vlan(sock: &TsnSocket) -> Option<VlanTag> {
+    (sock.vlanid != 0).then_some(VlanTag {
+        vid: sock.vlanid,
+        pcp: None,
+        dei: false,
+    })
+}
+
+/// [`RecvInfo`] of a frame received with `addr` and the control messages of `msg`
+fn packet_info(sock: &TsnSocket, addr: &libc::sockaddr_ll, msg: &libc::msghdr) -> RecvInfo {
+    let mut src = [0u8; 6];
+    if addr.sll_halen == 6 {
+        src.copy_from_slice(&addr.sll_addr[..6]);
+    }
+    let vlan = parse_auxdata(msg)
+        .filter(|aux| aux.tp_status & libc::TP_STATUS_VLAN_VALID != 0)
+        .map(|aux| VlanTag::from_tci(aux.tp_vlan_tci))
+        .or(stripped_vlan(sock));
+    RecvInfo {
+        src,
+        ethertype: u16::from_be(addr.sll_protocol),
+        pkttype: PacketType::from_raw(addr.sll_pkttype),
+        ifindex: addr.sll_ifindex as u32,
+        vlan,
+    }
+}
+
+/// [`RecvInfo`] of a frame read from its header, for backends without `sockaddr_ll`
+fn frame_info(sock: &TsnSocket, frame: &[u8], ifindex: u32, vlan: Option<VlanTag>) -> RecvInfo {
+    let mut src = [0u8; 6];
+    let mut ethertype = 0;
+    if frame.len() >= ETH_HLEN {
+        src.copy_from_slice(&frame[6..12]);
+        ethertype = u16::from_be_bytes([frame[12], frame[13]]);
+    }
+    RecvInfo {
+        src,
+        ethertype,
+        pkttype: PacketType::of_destination(
+            frame.get(..6).unwrap_or_default(),
+            sock.cached_mac_address().ok(),
+        ),
+        ifindex,
+        vlan,
+    }
+}
+
+/// Find the `PACKET_AUXDATA` control message of a received message
+fn parse_auxdata(msg: &libc::msghdr) -> Option<libc::tpacket_auxdata> {
+    if msg.msg_flags & libc::MSG_CTRUNC != 0 {
+        return None;
+    }
+    let mut cm = unsafe { libc::CMSG_FIRSTHDR(msg) };
+    while !cm.is_null() {
+        let cmsg_level = unsafe { (*cm).cmsg_level };
+        let cmsg_type = unsafe { (*cm).cmsg_type };
+
+        if cmsg_level == libc::SOL_PACKET && cmsg_type == libc::PACKET_AUXDATA {
+            return Some(unsafe {
+                std::ptr::read_unaligned(libc::CMSG_DATA(cm) as *const libc::tpacket_auxdata)
+            });
+        }
+
+        cm = unsafe { libc::CMSG_NXTHDR(msg, cm) };
+    }
+    None
+}
+
+/// Receive up to `bufs.len()` frames with one `recvmmsg` call.
+///
+/// Blocks until at least one frame arrives (or the socket timeout expires) and
+/// returns the length and, if the kernel provided one, the RX timestamp of
+/// each frame in the order they fill `bufs`.
+pub fn recv_batch<B: AsMut<[u8]>>(
+    sock: &TsnSocket,
+    bufs: &mut [B],
+) -> Result<Vec<(usize, Option<RxTimestamp>)>, TsnError> {
+    sock.check_no_ring(false, "recv_batch")?;
+    Ok(recv_mmsg(sock, bufs)?
+        .into_iter()
+        .map(|(len, ts, _)| (len, ts))
+        .collect())
+}
+
+/// [`recv_batch`] returning where each frame came from, like [`recv_from`]
+pub fn recv_batch_from<B: AsMut<[u8]>>(
+    sock: &TsnSocket,
+    bufs: &mut [B],
+) -> Result<Vec<(usize, RecvInfo)>, TsnError> {
+    sock.check_no_ring(false, "recv_batch_from")?;
+    enable_auxdata(sock)?;
+    Ok(recv_mmsg(sock, bufs)?
+        .into_iter()
+        .map(|(len, _, info)| (len, info))
+        .collect())
+}
+
+/// Frames of [`recv_batch`] with their RX timestamp and [`RecvInfo`]
+fn recv_mmsg<B: AsMut<[u8]>>(
+    sock: &TsnSocket,
+    bufs: &mut [B],
+) -> Result<Vec<(usize, Option<RxTimestamp>, RecvInfo)>, TsnError> {
+    #[cfg(feature = "xdp")]
+    if let Some(xdp) = &sock.xdp {
+        let ifindex = if_nametoindex(sock.ifname.as_bytes()).unwrap_or(0);
+        let mut received = Vec::new();
+        for buf in bufs.iter_mut() {
+            // Wait for the first frame only, as recvmmsg with MSG_WAITFORONE
+            match xdp.recv(buf.as_mut(), received.is_empty())? {
+                Some((len, tci)) => {
+                    let vlan = Some(VlanTag::from_tci(tci));
+                    let info = frame_info(sock, &buf.as_mut()[..len], ifindex, vlan);
+                    received.push((len, None, info));
+                }
+                None => break,
+            }
+        }
+        return Ok(received);
+    }
+    if let Some(wire) = &sock.wire {
+        let mut received = Vec::new();
+        for buf in bufs.iter_mut() {
+            let flags = if received.is_empty() {
+                0
+            } else {
+                libc::MSG_DONTWAIT
+            };
+            match wire.recv(sock.fd, buf.as_mut(), flags) {
+                Ok((len, time)) => {
+                    let ts = sock.tx_timestamp_enabled.then_some(RxTimestamp {
+                        time,
+                        source: TimestampSource::Sw,
+                    });
+                    let info = frame_info(sock, &buf.as_mut()[..len], 0, stripped_vlan(sock));
+                    received.push((len, ts, info));
+                }
+                Err(e) if !received.is_empty() && e.raw_os_error() == Some(libc::EAGAIN) => break,
+                Err(e) => return Err(e),
+            }
+        }
+        return Ok(received);
+    }
+    const CONTROL_SIZE: usize = 256;
+    let mut control = vec![0u8; CONTROL_SIZE * bufs.len()];
+    let mut addrs: Vec<libc::sockaddr_ll> = vec![unsafe { mem::zeroed() }; bufs.len()];
+    let mut iovs: Vec<libc::iovec> = bufs
+        .iter_mut()
+        .map(|buf| libc::iovec {
+            iov_base: buf.as_mut().as_mut_ptr() as *mut libc::c_void,
+            iov_len: buf.as_mut().len(),
+        })
+        .collect();
+    let mut msgs: Vec<libc::mmsghdr> = iovs
+        .iter_mut()
+        .zip(addrs.iter_mut())
+        .zip(control.chunks_exact_mut(CONTROL_SIZE))
+        .map(|((iov, addr), control)| {
+            let mut msg: libc::mmsghdr = unsafe { mem::zeroed() };
+            msg.msg_hdr.msg_name = addr as *mut libc::sockaddr_ll as *mut libc::c_void;
+            msg.msg_hdr.msg_namelen = mem::size_of::<libc::sockaddr_ll>() as u32;
+            msg.msg_hdr.msg_iov = iov;
+            msg.msg_hdr.msg_iovlen = 1;
+            msg.msg_hdr.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+            msg.msg_hdr.msg_controllen = {
+                // aarch64 has msg_controllen as u32, not usize
+                #[allow(clippy::useless_conversion)]
+                CONTROL_SIZE.try_into().unwrap()
+            };
+            msg
+        })
+        .collect();
+
+    let res = unsafe {
+        libc::recvmmsg(
+            sock.fd,
+            msgs.as_mut_ptr(),
+            msgs.len() as _,
+            libc::MSG_WAITFORONE,
+            std::ptr::null_mut(),
+        )
+    };
+    if res < 0 {
+        return Err(TsnError::socket("Recv"));
+    }
+
+    Ok(msgs[..res as usize]
+        .iter()
+        .zip(&addrs)
+        .map(|(msg, addr)| {
+            let ts = if msg.msg_hdr.msg_flags & libc::MSG_CTRUNC != 0 {
+                None
+            } else {
+                parse_timestamping(&msg.msg_hdr)
+                    .and_then(|ts| select_timestamp(&ts))
+                    .map(|(time, source)| RxTimestamp { time, source })
+            };
+            (
+                msg.msg_len as usize,
+                ts,
+                packet_info(sock, addr, &msg.msg_hdr),
+            )
+        })
+        .collect())
+}
+
+/// Find the `SO_TIMESTAMPING` control message of a received message
+fn parse_timestamping(msg: &libc::msghdr) -> Option<[libc::timespec; 3]> {
+    let mut cm = unsafe { libc::CMSG_FIRSTHDR(msg) };
+    while !cm.is_null() {
+        let cmsg_level = unsafe { (*cm).cmsg_level };
+        let cmsg_type = unsafe { (*cm).cmsg_type };
+
+        if cmsg_level == libc::SOL_SOCKET && cmsg_type == libc::SO_TIMESTAMPING {
+            return Some(unsafe {
+                std::ptr::read_unaligned(libc::CMSG_DATA(cm) as *const [libc::timespec; 3])
+            });
+        }
+
+        cm = unsafe { libc::CMSG_NXTHDR(msg, cm) };
+    }
+    None
+}
+
+/// Pick the most precise non-zero slot of `SO_TIMESTAMPING`
+fn select_timestamp(ts: &[libc::timespec; 3]) -> Option<(time::Timespec, TimestampSource)> {
+    // 0 - SW timestamp
+    // 1 - Legacy HW timestamp
+    // 2 - HW timestamp
+    let (ts, source) = match ts {
+        ts if ts[2].tv_sec != 0 || ts[2].tv_nsec != 0 => (ts[2], TimestampSource::HwRaw),
+        ts if ts[1].tv_sec != 0 || ts[1].tv_nsec != 0 => (ts[1], TimestampSource::HwLegacy),
+        ts if ts[0].tv_sec != 0 || ts[0].tv_nsec != 0 => (ts[0], TimestampSource::Sw),
+        _ => return None,
+    };
+    Some((
+        time::Timespec {
+            tv_sec: ts.tv_sec,
+            tv_nsec: ts.tv_nsec,
+        },
+        source,
+    ))
+}
+
+pub fn recv_msg(sock: &TsnSocket, msg: &mut msghdr) -> Result<isize, TsnError> {
+    sock.check_no_ring(false, "recv_msg")?;
+    sock.check_not_xdp("recv_msg")?;
+    sock.check_not_wire("recv_msg")?;
     let res = unsafe { libc::recvmsg(sock.fd, msg, 0) };
 
     if res < 0 {
-        Err(format!("Recv error: {}", Error::last_os_error()))
+        Err(TsnError::socket("Recv"))
     } else {
         Ok(res)
     }
 }
 
-pub fn enable_timestamps(sock: &mut TsnSocket, iov: Option<&mut libc::iovec>) -> Result<(), Error> {
+/// Timestamp sent and received frames, `iov` marks RX timestamps as wanted for
+/// [`get_rx_timestamp`]. Hardware timestamps are asked for as far as
+/// `ETHTOOL_GET_TS_INFO` says the NIC has them, see [`caps`], and the config of the
+/// interface is only widened, see [`hwtstamp`]. [`TsnError::HwTimestampUnsupported`]
+/// means only software timestamps are on
+pub fn enable_timestamps(
+    sock: &mut TsnSocket,
+    iov: Option<&mut libc::iovec>,
+) -> Result<(), TsnError> {
+    sock.check_not_xdp("Timestamping")?;
+    // The wire takes software timestamps itself, it has no hardware to timestamp
+    if sock.wire.is_some() {
+        if !sock.tx_timestamp_enabled {
+            sock.tx_key.store(0, Ordering::Relaxed);
+            sock.tx_timestamp_enabled = true;
+        }
+        sock.rx_timestamp_enabled = iov.is_some();
+        return Err(TsnError::HwTimestampUnsupported(Error::from_raw_os_error(
+            libc::EOPNOTSUPP,
+        )));
+    }
     let sockfd = sock.fd;
     let interface_name = &sock.ifname;
 
-    let ts_flags: u32 = libc::SOF_TIMESTAMPING_TX_HARDWARE
-        | libc::SOF_TIMESTAMPING_TX_SOFTWARE
-        | libc::SOF_TIMESTAMPING_RX_HARDWARE
+    // Ask for what the NIC has. If the driver cannot tell, ask for everything and
+    // let SIOCSHWTSTAMP decide
+    let caps = caps::Capabilities {
+        timestamping: phc::ts_info(interface_name).ok(),
+        ..caps::Capabilities::unknown(interface_name)
+    };
+    let hw_tx = caps.hw_tx_timestamps().unwrap_or(true);
+    let rx_filter = match caps.timestamping {
+        Some(_) => caps.rx_filter(),
+        None => Some(libc::HWTSTAMP_FILTER_ALL),
+    };
+    let hw_flags = libc::SOF_TIMESTAMPING_SYS_HARDWARE | libc::SOF_TIMESTAMPING_RAW_HARDWARE;
+    let mut ts_flags: u32 = libc::SOF_TIMESTAMPING_TX_SOFTWARE
         | libc::SOF_TIMESTAMPING_RX_SOFTWARE
         | libc::SOF_TIMESTAMPING_SOFTWARE
-        | libc::SOF_TIMESTAMPING_SYS_HARDWARE
-        | libc::SOF_TIMESTAMPING_RAW_HARDWARE
-        | libc::SOF_TIMESTAMPING_OPT_CMSG;
+        | libc::SOF_TIMESTAMPING_OPT_CMSG
+        | libc::SOF_TIMESTAMPING_OPT_ID
+        | libc::SOF_TIMESTAMPING_OPT_TSONLY;
+    if hw_tx {
+        ts_flags |= libc::SOF_TIMESTAMPING_TX_HARDWARE | hw_flags;
+    }
+    if rx_filter.is_some() {
+        ts_flags |= libc::SOF_TIMESTAMPING_RX_HARDWARE | hw_flags;
+    }
 
     let err = unsafe {
         libc::setsockopt(
@@ -295,7 +1650,15 @@ pub fn enable_timestamps(sock: &mut TsnSocket, iov: Option<&mut libc::iovec>) ->
         )
     };
     if err < 0 {
-        return Err(Error::last_os_error());
+        return Err(TsnError::socket("Set SO_TIMESTAMPING"));
+    }
+    if sock.ring.is_some() {
+        ring::enable_timestamps(sockfd)?;
+    }
+    // Kernel restarts the IDs when OPT_ID is newly set
+    if !sock.tx_timestamp_enabled {
+        sock.tx_key.store(0, Ordering::Relaxed);
+        sock.tx_timestamp_enabled = true;
     }
 
     // setsockopt for err queue
@@ -311,59 +1674,170 @@ pub fn enable_timestamps(sock: &mut TsnSocket, iov: Option<&mut libc::iovec>) ->
         )
     };
     if err < 0 {
-        return Err(Error::last_os_error());
+        return Err(TsnError::socket("Set SO_SELECT_ERR_QUEUE"));
+    }
+
+    sock.rx_timestamp_enabled = iov.is_some();
+    if !hw_tx && rx_filter.is_none() {
+        return Err(TsnError::HwTimestampUnsupported(Error::from_raw_os_error(
+            libc::EOPNOTSUPP,
+        )));
     }
 
-    // Always enable timestamps as disabling it will stop ptp4l
+    // Widens the config of the interface, shared with ptp4l and others
+    sock.hwtstamp = Some(hwtstamp::HwTstampHold::new(
+        interface_name,
+        hw_tx,
+        rx_filter,
+    )?);
 
-    // ioctl
-    let mut ts_cfg = libc::hwtstamp_config {
-        tx_type: libc::HWTSTAMP_TX_ON as i32,
-        rx_filter: libc::HWTSTAMP_FILTER_ALL as i32,
-        flags: 0,
-    };
+    Ok(())
+}
+
+/// Wait up to 1 second for the next TX timestamp.
+///
+/// Returns [`TsnError::TxtimeDropped`] instead if a frame sent with [`send_at`]
+/// was dropped, as that frame never gets a timestamp.
+pub fn get_tx_timestamp(sock: &TsnSocket) -> Result<TxTimestamp, TsnError> {
+    wait_tx_timestamp(sock, 1000)?.ok_or(TsnError::TimestampTimeout)
+}
 
-    let mut ifr_name: [libc::c_char; libc::IFNAMSIZ] = [0; libc::IFNAMSIZ];
-    for (source, target) in interface_name.as_bytes().iter().zip(ifr_name.iter_mut()) {
-        *target = *source as libc::c_char;
+/// Next TX timestamp, or `None` if nothing arrived within `timeout_ms`
+pub(crate) fn wait_tx_timestamp(
+    sock: &TsnSocket,
+    timeout_ms: i32,
+) -> Result<Option<TxTimestamp>, TsnError> {
+    // Taken when sending, so waiting brings nothing new
+    if let Some(wire) = &sock.wire {
+        return Ok(wire.next_tx_timestamp());
     }
+    match next_errqueue_msg(sock, |_| true, timeout_ms)? {
+        Some(msg) => {
+            msg.txtime_error()?;
+            msg.tx_timestamp().map(Some)
+        }
+        None => Ok(None),
+    }
+}
 
-    let ifreq = libc::ifreq {
-        ifr_name,
-        ifr_ifru: libc::__c_anonymous_ifr_ifru {
-            ifru_data: (&mut ts_cfg as *mut _) as *mut libc::c_char,
-        },
-    };
+/// All TX timestamps queued so far, without blocking
+pub fn drain_tx_timestamps(sock: &TsnSocket) -> Result<Vec<TxTimestamp>, TsnError> {
+    if let Some(wire) = &sock.wire {
+        return Ok(iter::from_fn(|| wire.next_tx_timestamp()).collect());
+    }
+    let mut ret = Vec::new();
+    while let Some(msg) = next_errqueue_msg(sock, |msg| !msg.is_txtime_error(), 0)? {
+        ret.push(msg.tx_timestamp()?);
+    }
+    Ok(ret)
+}
 
-    let err = unsafe {
-        // Not useless conversion because aarch64 has different type
-        #[allow(clippy::useless_conversion)]
-        libc::ioctl(sockfd, libc::SIOCSHWTSTAMP.try_into().unwrap(), &ifreq)
-    };
-    if err < 0 {
-        // XXX: While ioctl failed, SW timestamp is still enabled.
-        return Err(Error::new(
-            ErrorKind::PermissionDenied,
-            "Hardware timestamping is not supported. Falling back to software timestamping.",
-        ));
+/// Next error queue message for which `wanted` holds, waiting up to `timeout_ms`.
+///
+/// Other messages read on the way are kept in the socket's backlog, so TX
+/// timestamps and txtime reports can be consumed independently.
+fn next_errqueue_msg(
+    sock: &TsnSocket,
+    wanted: fn(&ErrQueueMsg) -> bool,
+    timeout_ms: i32,
+) -> Result<Option<ErrQueueMsg>, TsnError> {
+    {
+        let mut backlog = sock.errqueue.lock().unwrap();
+        if let Some(pos) = backlog.iter().position(wanted) {
+            return Ok(backlog.remove(pos));
+        }
     }
 
-    sock.rx_timestamp_enabled = iov.is_some();
+    loop {
+        match recv_errqueue(sock, libc::MSG_DONTWAIT) {
+            Ok(msg) if wanted(&msg) => return Ok(Some(msg)),
+            Ok(msg) => {
+                let mut backlog = sock.errqueue.lock().unwrap();
+                if backlog.len() >= ERRQUEUE_BACKLOG {
+                    backlog.pop_front();
+                }
+                backlog.push_back(msg);
+            }
+            Err(e) if e.raw_os_error() == Some(libc::EAGAIN) => {
+                if timeout_ms == 0 {
+                    return Ok(None);
+                }
+                let pfd = libc::pollfd {
+                    fd: sock.fd,
+                    events: libc::POLLPRI,
+                    revents: 0,
+                };
+
+                let res =
+                    unsafe { libc::poll(&pfd as *const _ as *mut libc::pollfd, 1, timeout_ms) };
+
+                match res {
+                    0 => return Ok(None),
+                    res if res < 0 => return Err(TsnError::socket("Poll")),
+                    _ => {}
+                }
 
-    Ok(())
+                // XXX: IDK why but this doesn't work on NXP
+                // Commenting this out for now
+                // if !(pfd.revents & libc::POLLPRI) != 0 {
+                //     return Err(Error::new(ErrorKind::Other, format!("unexpected revents {}", pfd.revents)));
+                // }
+            }
+            Err(e) => return Err(e),
+        }
+    }
 }
 
-pub fn get_tx_timestamp(sock: &TsnSocket) -> Result<time::Timespec, Error> {
-    let sockfd = sock.fd;
+/// Control data of one message from the socket error queue
+struct ErrQueueMsg {
+    timestamps: Option<[libc::timespec; 3]>,
+    ee: Option<libc::sock_extended_err>,
+}
 
-    let buf: [u8; 256] = [0u8; 256];
-    let buflen = std::mem::size_of_val(&buf);
+impl ErrQueueMsg {
+    fn is_txtime_error(&self) -> bool {
+        matches!(self.ee, Some(ee) if ee.ee_origin == sys::SO_EE_ORIGIN_TXTIME)
+    }
+
+    fn tx_timestamp(&self) -> Result<TxTimestamp, TsnError> {
+        let ts = self.timestamps.ok_or_else(|| {
+            TsnError::TimestampMissing("No SO_TIMESTAMPING control message".to_string())
+        })?;
+        let (time, source) = select_timestamp(&ts).ok_or_else(|| {
+            TsnError::TimestampMissing("All TX timestamp slots are zero (HW bug?)".to_string())
+        })?;
+        let id = match self.ee {
+            Some(ee) if ee.ee_origin == libc::SO_EE_ORIGIN_TIMESTAMPING => ee.ee_data,
+            _ => 0,
+        };
+        Ok(TxTimestamp { id, time, source })
+    }
+
+    fn txtime_error(&self) -> Result<(), TsnError> {
+        match self.ee {
+            Some(ee) if ee.ee_origin == sys::SO_EE_ORIGIN_TXTIME => {
+                Err(TsnError::TxtimeDropped {
+                    // kernel splits the txtime of the dropped frame into two halves
+                    txtime: ((ee.ee_data as u64) << 32) | ee.ee_info as u64,
+                    reason: match ee.ee_code {
+                        sys::SO_EE_CODE_TXTIME_INVALID_PARAM => TxtimeDropReason::InvalidParam,
+                        sys::SO_EE_CODE_TXTIME_MISSED => TxtimeDropReason::Missed,
+                        code => TxtimeDropReason::Unknown(code),
+                    },
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+}
 
+fn recv_errqueue(sock: &TsnSocket, flags: libc::c_int) -> Result<ErrQueueMsg, TsnError> {
+    let buf: [u8; 256] = [0u8; 256];
     let control: [u8; 256] = [0u8; 256];
 
     let iov = libc::iovec {
         iov_base: buf.as_ptr() as *mut libc::c_void,
-        iov_len: buflen,
+        iov_len: buf.len(),
     };
 
     let msg: libc::msghdr = unsafe {
@@ -384,45 +1858,22 @@ pub fn get_tx_timestamp(sock: &TsnSocket) -> Result<time::Timespec, Error> {
         msg
     };
 
-    let pfd = libc::pollfd {
-        fd: sockfd,
-        events: libc::POLLPRI,
-        revents: 0,
-    };
-
-    let res = unsafe { libc::poll(&pfd as *const _ as *mut libc::pollfd, 1, 1000) };
-
-    match res {
-        0 => {
-            return Err(Error::new(ErrorKind::TimedOut, "poll timeout"));
-        }
-        res if res < 0 => {
-            return Err(Error::last_os_error());
-        }
-        _ => {}
-    }
-
-    // XXX: IDK why but this doesn't work on NXP
-    // Commenting this out for now
-    // if !(pfd.revents & libc::POLLPRI) != 0 {
-    //     return Err(Error::new(ErrorKind::Other, format!("unexpected revents {}", pfd.revents)));
-    // }
-
-    // Poll done. Now read the timestamp
-
     let cnt = unsafe {
         libc::recvmsg(
-            sockfd,
+            sock.fd,
             &msg as *const _ as *mut libc::msghdr,
-            libc::MSG_ERRQUEUE,
+            libc::MSG_ERRQUEUE | flags,
         )
     };
 
     if cnt < 0 {
-        return Err(Error::last_os_error());
+        return Err(TsnError::socket("Recv error queue"));
     }
 
-    // Recvmsg done. Parse the timestamp
+    let mut ret = ErrQueueMsg {
+        timestamps: None,
+        ee: None,
+    };
     let mut cm = unsafe { libc::CMSG_FIRSTHDR(&msg) };
     // Loop while cm is not null
     while !cm.is_null() {
@@ -430,49 +1881,29 @@ pub fn get_tx_timestamp(sock: &TsnSocket) -> Result<time::Timespec, Error> {
         let cmsg_type = unsafe { (*cm).cmsg_type };
 
         if cmsg_level == libc::SOL_SOCKET && cmsg_type == libc::SO_TIMESTAMPING {
-            let ts = unsafe {
-                let ts = libc::CMSG_DATA(cm) as *const [libc::timespec; 3];
-                *ts
-            };
-
-            // 0 - SW tx timestamp
-            // 1 - Legacy HW tx timestamp
-            // 2 - HW tx timestamp
-            let ts = match ts {
-                ts if ts[2].tv_sec != 0 || ts[2].tv_nsec != 0 => ts[2],
-                ts if ts[1].tv_sec != 0 || ts[1].tv_nsec != 0 => ts[1],
-                ts if ts[0].tv_sec != 0 || ts[0].tv_nsec != 0 => {
-                    // Log warning
-                    eprintln!("SW TX timestamp(from driver) used");
-                    ts[0]
-                }
-                _ => {
-                    return Err(Error::other("All TX timestamp slots are zero (HW bug?)"));
-                }
-            };
-            return Ok(time::Timespec {
-                tv_sec: ts.tv_sec,
-                tv_nsec: ts.tv_nsec,
+            ret.timestamps = Some(unsafe {
+                std::ptr::read_unaligned(libc::CMSG_DATA(cm) as *const [libc::timespec; 3])
+            });
+        } else if cmsg_level == libc::SOL_PACKET && cmsg_type == sys::PACKET_TX_TIMESTAMP {
+            ret.ee = Some(unsafe {
+                std::ptr::read_unaligned(libc::CMSG_DATA(cm) as *const libc::sock_extended_err)
             });
         }
 
         cm = unsafe { libc::CMSG_NXTHDR(&msg, cm) };
     }
 
-    Err(Error::new(ErrorKind::NotFound, "No timestamp found"))
+    Ok(ret)
 }
 
-pub fn get_rx_timestamp(sock: &TsnSocket) -> Result<time::Timespec, Error> {
+pub fn get_rx_timestamp(sock: &TsnSocket) -> Result<time::Timespec, TsnError> {
     if !sock.rx_timestamp_enabled {
-        return Err(Error::new(
-            ErrorKind::InvalidInput,
-            "rx timestamp not enabled",
-        ));
+        return Err(TsnError::TimestampNotEnabled);
     }
 
-    Err(Error::new(
-        ErrorKind::Unsupported,
-        "get_rx_timestamp() requires the recvmsg msghdr; parse control data from the same recvmsg call",
+    Err(TsnError::Unsupported(
+        "get_rx_timestamp() requires the recvmsg msghdr; use recv_with_timestamp() instead"
+            .to_string(),
     ))
 }
 
@@ -501,127 +1932,56 @@ pub fn timespecff_diff(start: &mut TimeSpec, stop: &mut TimeSpec, result: &mut T
     }
 }
 
-fn open_shmem(shm_name: &str) -> Result<*mut c_void, String> {
-    let shm_fd = shm_open(
-        shm_name,
-        OFlag::O_CREAT | OFlag::O_RDWR,
-        Mode::S_IRWXU | Mode::S_IRWXG | Mode::S_IRWXO,
-    );
-    let shm_fd = match shm_fd {
-        Ok(v) => v,
-        Err(_) => return Err(format!("Open shmem fails: {}", Error::last_os_error())),
-    };
-    let shm_ptr = unsafe {
-        ftruncate(shm_fd, SHM_SIZE as libc::off_t);
-        mmap(
-            None,
-            NonZeroUsize::new_unchecked(SHM_SIZE),
-            ProtFlags::PROT_READ | ProtFlags::PROT_WRITE,
-            MapFlags::MAP_SHARED,
-            shm_fd,
-            0,
-        )
-    };
-    let shm_ptr = match shm_ptr {
-        Ok(v) => v,
-        Err(_) => return Err(format!("Open shmem fails: {}", Error::last_os_error())),
-    };
-
-    unsafe { msync(shm_ptr, SHM_SIZE, MS_SYNC) };
-
-    Ok(shm_ptr)
+/// Lock serializing VLAN setup and teardown between processes, released on drop
+fn lock_run_dir() -> Result<File, TsnError> {
+    fs::create_dir_all(RUN_DIR).map_err(TsnError::holder("Create dir"))?;
+    let file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(format!("{}/{}", RUN_DIR, RUN_LOCK))
+        .map_err(TsnError::holder("Open"))?;
+    flock(&file, libc::LOCK_EX)?;
+    Ok(file)
 }
 
-fn read_shmem(shm_name: &str) -> Result<Vec<u32>, String> {
-    let shm_ptr = open_shmem(shm_name)?;
-
-    let mut vec_data: Vec<u32> = unsafe {
-        let data = slice::from_raw_parts(shm_ptr as *const u8, SHM_SIZE);
-        slice::from_raw_parts(
-            data.to_vec().as_ptr() as *const u32,
-            data.len() / size_of::<u32>(),
-        )
-        .to_vec()
-    };
-    vec_data.retain(|&x| x != 0);
-    unsafe {
-        match munmap(shm_ptr, SHM_SIZE) {
-            Ok(_) => Ok(vec_data),
-            Err(_) => Err(format!("Read shmem fails: {}", Error::last_os_error())),
-        }
+fn flock(file: &File, op: libc::c_int) -> Result<(), TsnError> {
+    if unsafe { libc::flock(file.as_raw_fd(), op) } < 0 {
+        return Err(TsnError::holder("Lock")(Error::last_os_error()));
     }
+    Ok(())
 }
 
-fn write_shmem(shm_name: &str, input: &Vec<u32>) -> Result<String, String> {
-    let shm_ptr = open_shmem(shm_name)?;
-    let shm_byte = unsafe {
-        slice::from_raw_parts(input.as_ptr() as *const u8, size_of::<u32>() * input.len())
-    };
-    let addr = shm_ptr as *mut u8;
-    for (i, item) in shm_byte.iter().enumerate() {
-        unsafe { *addr.add(i) = *item };
-    }
-    unsafe {
-        match munmap(shm_ptr, SHM_SIZE) {
-            Ok(_) => Ok("".to_string()),
-            Err(_) => Err(format!("Write shmem fails: {}", Error::last_os_error())),
-        }
+/// Like [`flock`], but `Ok(false)` if another holder has a conflicting lock
+fn try_flock(file: &File, op: libc::c_int) -> Result<bool, TsnError> {
+    match flock(file, op | libc::LOCK_NB) {
+        Ok(()) => Ok(true),
+        Err(e) if e.raw_os_error() == Some(libc::EWOULDBLOCK) => Ok(false),
+        Err(e) => Err(e),
     }
 }
 
-fn lock_shmem(shm_fd: &i32) -> Result<i32, String> {
-    let lock = flock {
-        l_type: libc::F_WRLCK as i16,
-        l_whence: libc::SEEK_SET as i16,
-        l_start: 0,
-        l_len: 0,
-        l_pid: 0,
-    };
-    match fcntl(*shm_fd, F_SETLKW(&lock)) {
-        Ok(v) => Ok(v),
-        Err(_) => Err(format!("Lock shmem fails: {}", Error::last_os_error())),
-    }
+fn holder_path(vlan_name: &str) -> String {
+    format!("{}/{}", RUN_DIR, vlan_name)
 }
 
-fn unlock_shmem(shm_fd: &i32) -> Result<i32, String> {
-    let lock = flock {
-        l_type: libc::F_UNLCK as i16,
-        l_whence: libc::SEEK_SET as i16,
-        l_start: 0,
-        l_len: 0,
-        l_pid: 0,
-    };
-    match fcntl(*shm_fd, F_SETLKW(&lock)) {
-        Ok(v) => Ok(v),
-        Err(_) => Err(format!("Unlock shmem fails: {}", Error::last_os_error())),
-    }
+fn vlan_exists(vlan_name: &str) -> bool {
+    Path::new("/sys/class/net").join(vlan_name).exists()
 }
 
-fn get_config(ifname: &str) -> Result<config::Config, String> {
+/// Config of `ifname` in the file at `CONFIG_PATH`, `./config.yaml` by default
+fn get_config(ifname: &str) -> Result<config::Config, TsnError> {
     let config_path = env::var("CONFIG_PATH").unwrap_or("./config.yaml".to_string());
-    let configs = config::read_config(&config_path);
-    let configs = match configs {
-        Ok(v) => v,
-        Err(_) => return Err(format!("Read config fails: {}", Error::last_os_error())),
-    };
-    let config = configs.get(ifname);
-    match config {
-        Some(v) => Ok(v.clone()),
-        None => Err(format!("{} not found in {}", ifname, config_path)),
-    }
+    read_config_of(ifname, &config_path)
 }
 
-fn get_shmem_name(ifname: &str, vlanid: u16) -> String {
-    format!("libtsn_vlan_{}", vlan::get_vlan_name(ifname, vlanid))
-}
-
-fn get_shmem_fd(shm_name: &str) -> Result<i32, String> {
-    match shm_open(
-        shm_name,
-        OFlag::O_CREAT | OFlag::O_RDWR,
-        Mode::S_IRWXU | Mode::S_IRWXG | Mode::S_IRWXO,
-    ) {
-        Ok(v) => Ok(v),
-        Err(_) => Err(format!("Open shmem fails: {}", Error::last_os_error())),
+fn read_config_of(ifname: &str, config_path: &str) -> Result<config::Config, TsnError> {
+    let configs = config::read_config(config_path)?;
+    match configs.get(ifname) {
+        Some(v) => Ok(v.clone()),
+        None => Err(TsnError::ConfigNotFound {
+            ifname: ifname.to_string(),
+            path: config_path.to_string(),
+        }),
     }
 }
@@ -1,13 +1,10 @@
-use crate::{
+use clap::{arg, Arg, ArgMatches, Command as ClapCommand};
+use tsn::{
     config::read_config,
-    vlan::{create_vlan, delete_vlan},
+    gc_vlans, ptp,
+    vlan::{create_vlan, create_vlan_with, delete_vlan, RecordingExecutor},
 };
-use clap::{arg, Arg, ArgMatches, Command as ClapCommand};
-mod cbs;
-mod config;
 mod info;
-mod tas;
-mod vlan;
 fn main() {
     let arg_config = arg!(-c --config <config> "Config file path")
         .required(false)
@@ -21,7 +18,8 @@ fn main() {
                 .help("Interface name to create")
                 .required(true),
         )
-        .arg(Arg::new("vlanid").help("VLAN ID to create").required(true));
+        .arg(Arg::new("vlanid").help("VLAN ID to create").required(true))
+        .arg(arg!(--"dry-run" "Print the ip/tc commands instead of running them"));
     let delete_parser = ClapCommand::new("delete")
         .about("Delete a TSN interface")
         .arg(&arg_config)
@@ -32,7 +30,7 @@ fn main() {
         )
         .arg(Arg::new("vlanid").help("VLAN ID to delete").required(true));
     let info_parser = ClapCommand::new("info")
-        .about("Show TSN interface information")
+        .about("Show TSN interface config and capabilities")
         .arg(&arg_config)
         .arg(
             Arg::new("interface")
@@ -40,17 +38,32 @@ fn main() {
                 .required(false)
                 .multiple_values(true),
         );
+    let ptp_config_parser = ClapCommand::new("ptp-config")
+        .about("Print the ptp4l config of the interfaces with a ptp section")
+        .arg(&arg_config)
+        .arg(arg!(--phc2sys "Print the phc2sys config instead"))
+        .arg(
+            Arg::new("interface")
+                .help("Interfaces to serve, all with a ptp section by default")
+                .required(false)
+                .multiple_values(true),
+        );
+    let gc_parser =
+        ClapCommand::new("gc").about("Delete TSN interfaces left behind by exited processes");
     let matched_command: ArgMatches = ClapCommand::new("tsnlib")
         .about("TSN socket manager")
         .arg_required_else_help(true)
         .subcommand(create_parser)
         .subcommand(delete_parser)
         .subcommand(info_parser)
+        .subcommand(ptp_config_parser)
+        .subcommand(gc_parser)
         .get_matches();
     match matched_command.subcommand() {
         Some(("create", create_matches)) => {
             let config = read_config(create_matches.value_of("config").unwrap());
-            if config.is_err() {
+            if let Err(e) = &config {
+                eprintln!("{}", e);
                 return;
             }
             let interface = create_matches.value_of("interface").unwrap();
@@ -60,7 +73,18 @@ fn main() {
                 .parse::<u16>()
                 .unwrap();
             let config = config.as_ref().unwrap().get(interface).unwrap();
-            create_vlan(config, interface, vlan_id).unwrap();
+            if create_matches.is_present("dry-run") {
+                let mut recorder = RecordingExecutor::new();
+                if let Err(e) = create_vlan_with(&mut recorder, config, interface, vlan_id) {
+                    eprintln!("{}", e);
+                    return;
+                }
+                for cmd in recorder.commands() {
+                    println!("{}", cmd);
+                }
+            } else {
+                create_vlan(config, interface, vlan_id).unwrap();
+            }
         }
         Some(("delete", delete_matches)) => {
             let interface = delete_matches.value_of("interface").unwrap();
@@ -73,7 +97,8 @@ fn main() {
         }
         Some(("info", info_matches)) => {
             let config = read_config(info_matches.value_of("config").unwrap());
-            if config.is_err() {
+            if let Err(e) = &config {
+                eprintln!("{}", e);
                 return;
             }
             let config = config.as_ref().unwrap();
@@ -81,16 +106,69 @@ fn main() {
                 let interfaces = info_matches.values_of("interface").unwrap();
                 for interface in interfaces {
                     println!("{}:", interface);
-                    let config = config.get(interface).unwrap();
-                    info::get_info(config);
+                    if let Some(config) = config.get(interface) {
+                        info::get_info(config);
+                    }
+                    info::get_capabilities(interface);
                 }
             } else {
                 for (interface, config) in config {
                     println!("{}:", interface);
                     info::get_info(config);
+                    info::get_capabilities(interface);
+                }
+            }
+        }
+        Some(("ptp-config", ptp_matches)) => {
+            let path = ptp_matches.value_of("config").unwrap();
+            let config = match read_config(path) {
+                Ok(config) => config,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    return;
+                }
+            };
+            let mut nics = Vec::new();
+            if let Some(interfaces) = ptp_matches.values_of("interface") {
+                for interface in interfaces {
+                    match config.get(interface).map(|config| &config.ptp) {
+                        Some(Some(ptp)) => nics.push((interface, ptp)),
+                        Some(None) => {
+                            eprintln!("{} has no ptp section in {}", interface, path);
+                            return;
+                        }
+                        None => {
+                            eprintln!("{} not found in {}", interface, path);
+                            return;
+                        }
+                    }
                 }
+            } else {
+                for (interface, config) in &config {
+                    if let Some(ptp) = &config.ptp {
+                        nics.push((interface.as_str(), ptp));
+                    }
+                }
+                nics.sort_by_key(|(interface, _)| *interface);
+            }
+            let rendered = if ptp_matches.is_present("phc2sys") {
+                ptp::phc2sys_config(&nics)
+            } else {
+                ptp::ptp4l_config(&nics)
+            };
+            match rendered {
+                Ok(rendered) => print!("{}", rendered),
+                Err(e) => eprintln!("{}", e),
             }
         }
+        Some(("gc", _)) => match gc_vlans() {
+            Ok(deleted) => {
+                for name in deleted {
+                    println!("Deleted {}", name);
+                }
+            }
+            Err(e) => eprintln!("{}", e),
+        },
         _ => unreachable!(),
     }
 }
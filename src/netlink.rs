@@ -0,0 +1,287 @@
+//! Minimal rtnetlink client, enough to set up VLAN links and qdiscs, and generic
+//! netlink for the few ethtool queries of [`caps`](crate::caps)
+//!
+//! Requests are sent one at a time and each waits for its ACK, so errors carry the
+//! errno and the extended ACK message of the kernel instead of an exit code.
+
+use std::cell::Cell;
+use std::{io, mem, slice};
+
+use nix::libc;
+use nix::unistd::close;
+
+use crate::sys::rtnl::{NLMSGERR_ATTR_MSG, NLM_F_ACK_TLVS, NLM_F_CAPPED};
+use crate::TsnError;
+
+const NLMSG_HDRLEN: usize = mem::size_of::<libc::nlmsghdr>();
+const NLA_HDRLEN: usize = mem::size_of::<libc::nlattr>();
+/// Length of the `genlmsghdr` before the attributes of a generic netlink message
+pub(crate) const GENL_HDRLEN: usize = mem::size_of::<libc::genlmsghdr>();
+
+fn align(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+/// `NETLINK_ROUTE` or `NETLINK_GENERIC` socket
+pub(crate) struct Netlink {
+    fd: i32,
+    seq: Cell<u32>,
+}
+
+impl Netlink {
+    pub(crate) fn open() -> Result<Netlink, TsnError> {
+        Netlink::open_protocol(libc::NETLINK_ROUTE)
+    }
+
+    pub(crate) fn open_generic() -> Result<Netlink, TsnError> {
+        Netlink::open_protocol(libc::NETLINK_GENERIC)
+    }
+
+    fn open_protocol(protocol: libc::c_int) -> Result<Netlink, TsnError> {
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+                protocol,
+            )
+        };
+        if fd < 0 {
+            return Err(TsnError::socket("Netlink socket"));
+        }
+        let nl = Netlink {
+            fd,
+            seq: Cell::new(0),
+        };
+        // Best effort: older kernels just give no extended ACK and echo whole requests
+        for option in [libc::NETLINK_EXT_ACK, libc::NETLINK_CAP_ACK] {
+            let one: libc::c_int = 1;
+            unsafe {
+                libc::setsockopt(
+                    fd,
+                    libc::SOL_NETLINK,
+                    option,
+                    &one as *const libc::c_int as *const libc::c_void,
+                    mem::size_of::<libc::c_int>() as u32,
+                )
+            };
+        }
+        let mut addr: libc::sockaddr_nl = unsafe { mem::zeroed() };
+        addr.nl_family = libc::AF_NETLINK as u16;
+        let res = unsafe {
+            libc::bind(
+                fd,
+                &addr as *const libc::sockaddr_nl as *const libc::sockaddr,
+                mem::size_of::<libc::sockaddr_nl>() as u32,
+            )
+        };
+        if res < 0 {
+            return Err(TsnError::socket("Netlink bind"));
+        }
+        Ok(nl)
+    }
+
+    /// Send `msg` and wait for its ACK. `op` describes the request in errors
+    pub(crate) fn request(&self, msg: Message, op: &str) -> Result<(), TsnError> {
+        self.query(msg, op).map(|_| ())
+    }
+
+    /// Send `msg` and return the payloads of the replies which came before its ACK
+    pub(crate) fn query(&self, mut msg: Message, op: &str) -> Result<Vec<Vec<u8>>, TsnError> {
+        let seq = self.seq.get().wrapping_add(1);
+        self.seq.set(seq);
+        let buf = msg.finish(seq);
+        let res = unsafe { libc::send(self.fd, buf.as_ptr() as *const libc::c_void, buf.len(), 0) };
+        if res < 0 {
+            return Err(TsnError::socket("Netlink send"));
+        }
+
+        let mut replies = Vec::new();
+        let mut buf = vec![0u8; 32768];
+        loop {
+            let len =
+                unsafe { libc::recv(self.fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len(), 0) };
+            if len < 0 {
+                return Err(TsnError::socket("Netlink recv"));
+            }
+            let mut msgs = &buf[..len as usize];
+            while msgs.len() >= NLMSG_HDRLEN {
+                let hdr: libc::nlmsghdr = read(msgs);
+                let msg_len = hdr.nlmsg_len as usize;
+                if msg_len < NLMSG_HDRLEN || msg_len > msgs.len() {
+                    break;
+                }
+                if hdr.nlmsg_seq == seq && hdr.nlmsg_type == libc::NLMSG_ERROR as u16 {
+                    return ack_result(&hdr, &msgs[NLMSG_HDRLEN..msg_len], op).map(|_| replies);
+                }
+                if hdr.nlmsg_seq == seq {
+                    replies.push(msgs[NLMSG_HDRLEN..msg_len].to_vec());
+                }
+                msgs = &msgs[align(msg_len).min(msgs.len())..];
+            }
+        }
+    }
+
+    /// ID of the generic netlink family `name`
+    pub(crate) fn family_id(&self, name: &str) -> Result<u16, TsnError> {
+        let header = libc::genlmsghdr {
+            cmd: libc::CTRL_CMD_GETFAMILY as u8,
+            version: 1,
+            reserved: 0,
+        };
+        let mut msg = Message::new(libc::GENL_ID_CTRL as u16, 0, &header);
+        msg.attr_str(libc::CTRL_ATTR_FAMILY_NAME as u16, name);
+        let op = format!("Find generic netlink family {}", name);
+        let replies = self.query(msg, &op)?;
+        let id = replies
+            .iter()
+            .flat_map(|reply| attrs(reply.get(GENL_HDRLEN..).unwrap_or_default()))
+            .find(|(attr_type, _)| *attr_type == libc::CTRL_ATTR_FAMILY_ID as u16)
+            .and_then(|(_, value)| Some(u16::from_ne_bytes(value.get(..2)?.try_into().ok()?)))
+            .ok_or_else(|| TsnError::Netlink {
+                op,
+                source: io::Error::new(io::ErrorKind::InvalidData, "no family ID in reply"),
+                message: None,
+            });
+        id
+    }
+}
+
+impl Drop for Netlink {
+    fn drop(&mut self) {
+        let _ = close(self.fd);
+    }
+}
+
+/// Turn the payload of an `NLMSG_ERROR` into the result of the request
+fn ack_result(hdr: &libc::nlmsghdr, payload: &[u8], op: &str) -> Result<(), TsnError> {
+    if payload.len() < mem::size_of::<libc::nlmsgerr>() {
+        return Err(TsnError::Netlink {
+            op: op.to_string(),
+            source: io::Error::new(io::ErrorKind::InvalidData, "truncated ACK"),
+            message: None,
+        });
+    }
+    let error: libc::c_int = read(payload);
+    if error == 0 {
+        return Ok(());
+    }
+
+    let mut message = None;
+    if hdr.nlmsg_flags & NLM_F_ACK_TLVS != 0 {
+        // The request is echoed after the error code, only its header if capped
+        let echoed: libc::nlmsghdr = read(&payload[mem::size_of::<libc::c_int>()..]);
+        let echoed_len = if hdr.nlmsg_flags & NLM_F_CAPPED != 0 {
+            NLMSG_HDRLEN
+        } else {
+            echoed.nlmsg_len as usize
+        };
+        let start = mem::size_of::<libc::c_int>() + align(echoed_len);
+        message = attrs(payload.get(start..).unwrap_or_default())
+            .find(|(attr_type, _)| *attr_type == NLMSGERR_ATTR_MSG)
+            .map(|(_, text)| attr_string(text));
+    }
+    Err(TsnError::Netlink {
+        op: op.to_string(),
+        source: io::Error::from_raw_os_error(-error),
+        message,
+    })
+}
+
+/// Type and payload of the attributes in `buf`, e.g. a reply after its family header
+pub(crate) fn attrs(mut buf: &[u8]) -> impl Iterator<Item = (u16, &[u8])> {
+    std::iter::from_fn(move || {
+        if buf.len() < NLA_HDRLEN {
+            return None;
+        }
+        let attr: libc::nlattr = read(buf);
+        let attr_len = attr.nla_len as usize;
+        if attr_len < NLA_HDRLEN || attr_len > buf.len() {
+            return None;
+        }
+        let value = &buf[NLA_HDRLEN..attr_len];
+        buf = &buf[align(attr_len).min(buf.len())..];
+        Some((attr.nla_type & libc::NLA_TYPE_MASK as u16, value))
+    })
+}
+
+/// Payload of a NUL terminated string attribute
+pub(crate) fn attr_string(value: &[u8]) -> String {
+    let text = value.split(|&b| b == 0).next().unwrap_or_default();
+    String::from_utf8_lossy(text).into_owned()
+}
+
+fn read<T: Copy>(buf: &[u8]) -> T {
+    assert!(buf.len() >= mem::size_of::<T>());
+    unsafe { (buf.as_ptr() as *const T).read_unaligned() }
+}
+
+fn bytes_of<T: Copy>(value: &T) -> &[u8] {
+    unsafe { slice::from_raw_parts(value as *const T as *const u8, mem::size_of::<T>()) }
+}
+
+/// Netlink request under construction: header, family header, then attributes
+pub(crate) struct Message {
+    buf: Vec<u8>,
+}
+
+impl Message {
+    /// Request of `msg_type`. `NLM_F_REQUEST | NLM_F_ACK` are added to `flags`
+    pub(crate) fn new<T: Copy>(msg_type: u16, flags: libc::c_int, header: &T) -> Message {
+        let hdr = libc::nlmsghdr {
+            nlmsg_len: 0,
+            nlmsg_type: msg_type,
+            nlmsg_flags: (flags | libc::NLM_F_REQUEST | libc::NLM_F_ACK) as u16,
+            nlmsg_seq: 0,
+            nlmsg_pid: 0,
+        };
+        let mut msg = Message { buf: Vec::new() };
+        msg.put(bytes_of(&hdr));
+        msg.put(bytes_of(header));
+        msg
+    }
+
+    fn put(&mut self, data: &[u8]) {
+        self.buf.extend_from_slice(data);
+        self.buf.resize(align(self.buf.len()), 0);
+    }
+
+    pub(crate) fn attr_bytes(&mut self, attr_type: u16, data: &[u8]) -> &mut Self {
+        let attr = libc::nlattr {
+            nla_len: (NLA_HDRLEN + data.len()) as u16,
+            nla_type: attr_type,
+        };
+        self.buf.extend_from_slice(bytes_of(&attr));
+        self.put(data);
+        self
+    }
+
+    /// Attribute holding `value` as is, e.g. an integer or a kernel struct
+    pub(crate) fn attr<T: Copy>(&mut self, attr_type: u16, value: &T) -> &mut Self {
+        self.attr_bytes(attr_type, bytes_of(value))
+    }
+
+    /// NUL terminated string attribute
+    pub(crate) fn attr_str(&mut self, attr_type: u16, value: &str) -> &mut Self {
+        let mut data = value.as_bytes().to_vec();
+        data.push(0);
+        self.attr_bytes(attr_type, &data)
+    }
+
+    /// Attribute holding the attributes added by `f`
+    pub(crate) fn nested(&mut self, attr_type: u16, f: impl FnOnce(&mut Message)) -> &mut Self {
+        let start = self.buf.len();
+        self.attr_bytes(attr_type | libc::NLA_F_NESTED as u16, &[]);
+        f(self);
+        let len = (self.buf.len() - start) as u16;
+        self.buf[start..start + 2].copy_from_slice(&len.to_ne_bytes());
+        self
+    }
+
+    fn finish(&mut self, seq: u32) -> &[u8] {
+        let mut hdr: libc::nlmsghdr = read(&self.buf);
+        hdr.nlmsg_len = self.buf.len() as u32;
+        hdr.nlmsg_seq = seq;
+        self.buf[..NLMSG_HDRLEN].copy_from_slice(bytes_of(&hdr));
+        &self.buf
+    }
+}
@@ -0,0 +1,356 @@
+//! PTP hardware clocks (PHC) of NICs
+//!
+//! Hardware timestamps ([`TimestampSource::HwRaw`](crate::TimestampSource::HwRaw)) are in
+//! the time base of the NIC's PHC, which runs on its own unless ptp4l and phc2sys keep
+//! it in step with the system. [`Phc::offset`] measures how far apart the two are, to
+//! convert such timestamps to system time and back. A PHC opened with
+//! [`Phc::open_adjustable`] can be stepped and slewed, as the servo of
+//! [`gptp`](crate::gptp) does.
+//!
+//! ```no_run
+//! use nix::libc;
+//! use tsn::phc::Phc;
+//!
+//! let phc = Phc::open_interface("eth0")?;
+//! let offset = phc.offset(libc::CLOCK_TAI)?;
+//! println!("PHC is {} ns ahead of TAI", offset.offset);
+//! println!("TAI now: {:?}", offset.to_clock(phc.now()?));
+//! # Ok::<(), tsn::TsnError>(())
+//! ```
+
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::mem;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+
+use nix::libc;
+
+use crate::sys::ethtool::{ethtool_ts_info, ETHTOOL_GET_TS_INFO, SIOCETHTOOL};
+use crate::time::{clock_gettime, tai_offset, Clock, Timespec};
+use crate::TsnError;
+
+/// Readings of the system clock around the PHC taken by [`Phc::offset`], of which the
+/// narrowest window counts
+const SAMPLES: usize = 9;
+
+/// Timestamping capabilities of an interface, from `ETHTOOL_GET_TS_INFO`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TsInfo {
+    /// `SOF_TIMESTAMPING_*` flags the interface supports
+    pub so_timestamping: u32,
+    /// N of the interface's `/dev/ptpN`, `None` if it has no PHC
+    pub phc_index: Option<u32>,
+    /// Bit mask of the supported `HWTSTAMP_TX_*` types
+    pub tx_types: u32,
+    /// Bit mask of the supported `HWTSTAMP_FILTER_*` filters
+    pub rx_filters: u32,
+}
+
+/// Timestamping capabilities of `ifname`
+pub fn ts_info(ifname: &str) -> Result<TsInfo, TsnError> {
+    if ifname.len() >= libc::IFNAMSIZ {
+        return Err(TsnError::InterfaceNotFound(ifname.to_string()));
+    }
+    let fd = unsafe { libc::socket(libc::AF_INET, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0) };
+    if fd < 0 {
+        return Err(TsnError::socket("Open ethtool socket"));
+    }
+    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+    let mut info = ethtool_ts_info {
+        cmd: ETHTOOL_GET_TS_INFO,
+        ..Default::default()
+    };
+    let mut ifr_name: [libc::c_char; libc::IFNAMSIZ] = [0; libc::IFNAMSIZ];
+    for (source, target) in ifname.as_bytes().iter().zip(ifr_name.iter_mut()) {
+        *target = *source as libc::c_char;
+    }
+    let ifreq = libc::ifreq {
+        ifr_name,
+        ifr_ifru: libc::__c_anonymous_ifr_ifru {
+            ifru_data: (&mut info as *mut ethtool_ts_info) as *mut libc::c_char,
+        },
+    };
+    let res = unsafe { libc::ioctl(fd.as_raw_fd(), SIOCETHTOOL as _, &ifreq) };
+    if res < 0 {
+        let source = io::Error::last_os_error();
+        if source.raw_os_error() == Some(libc::ENODEV) {
+            return Err(TsnError::InterfaceNotFound(ifname.to_string()));
+        }
+        return Err(TsnError::Socket {
+            op: "Get timestamping info",
+            source,
+        });
+    }
+    Ok(TsInfo {
+        so_timestamping: info.so_timestamping,
+        phc_index: u32::try_from(info.phc_index).ok(),
+        tx_types: info.tx_types,
+        rx_filters: info.rx_filters,
+    })
+}
+
+/// How [`Phc::offset`] read the two clocks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OffsetMethod {
+    /// Cross timestamp taken by the NIC, `PTP_SYS_OFFSET_PRECISE`
+    Precise,
+    /// System clock read by the driver around the PHC, `PTP_SYS_OFFSET_EXTENDED`
+    Extended,
+    /// `clock_gettime` of the system clock around one of the PHC, for drivers without
+    /// either ioctl
+    Read,
+}
+
+/// Offset of a PHC from a system clock
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClockOffset {
+    /// System clock the offset is from
+    pub clockid: libc::clockid_t,
+    /// PHC time minus system time, in ns
+    pub offset: i64,
+    /// Window the system clock was read in around the PHC, in ns. The offset is accurate
+    /// to about half of it. 0 for [`OffsetMethod::Precise`]
+    pub delay: i64,
+    pub method: OffsetMethod,
+}
+
+impl ClockOffset {
+    /// System time of the PHC time `phc`
+    pub fn to_clock(&self, phc: Timespec) -> Timespec {
+        Timespec::from_nanos(phc.as_nanos() - self.offset)
+    }
+
+    /// PHC time of the system time `time`
+    pub fn to_phc(&self, time: Timespec) -> Timespec {
+        Timespec::from_nanos(time.as_nanos() + self.offset)
+    }
+}
+
+/// A PHC, opened as a dynamic POSIX clock
+pub struct Phc {
+    file: File,
+    path: String,
+}
+
+impl Phc {
+    /// Open the PHC at `path`, e.g. `/dev/ptp0`. Read only: the clock can be read but
+    /// not adjusted
+    pub fn open(path: &str) -> Result<Phc, TsnError> {
+        Phc::open_with(path, false)
+    }
+
+    /// Open the PHC at `path` to read and adjust it, which needs write access
+    pub fn open_adjustable(path: &str) -> Result<Phc, TsnError> {
+        Phc::open_with(path, true)
+    }
+
+    /// Open the PHC of `ifname`
+    pub fn open_interface(ifname: &str) -> Result<Phc, TsnError> {
+        Phc::open(&interface_path(ifname)?)
+    }
+
+    /// Open the PHC of `ifname` to adjust it, see [`Phc::open_adjustable`]
+    pub fn open_interface_adjustable(ifname: &str) -> Result<Phc, TsnError> {
+        Phc::open_adjustable(&interface_path(ifname)?)
+    }
+
+    fn open_with(path: &str, write: bool) -> Result<Phc, TsnError> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(write)
+            .open(path)
+            .map_err(|source| TsnError::Clock {
+                op: "Open PHC",
+                source,
+            })?;
+        Ok(Phc {
+            file,
+            path: path.to_string(),
+        })
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Clock ID to give `clock_gettime` and friends, `FD_TO_CLOCKID` of the kernel
+    pub fn clockid(&self) -> libc::clockid_t {
+        ((!self.file.as_raw_fd()) << 3) | 3
+    }
+
+    pub fn now(&self) -> Result<Timespec, TsnError> {
+        clock_gettime(self.clockid())
+    }
+
+    /// The PHC as a clock to sleep on, valid while `self` is open
+    pub fn clock(&self) -> Clock {
+        Clock::Phc(self.file.as_raw_fd())
+    }
+
+    /// Largest frequency adjustment the driver takes, in ppb
+    pub fn max_frequency(&self) -> Result<f64, TsnError> {
+        let mut caps: libc::ptp_clock_caps = unsafe { mem::zeroed() };
+        if !self.ioctl(libc::PTP_CLOCK_GETCAPS, &mut caps, "PTP_CLOCK_GETCAPS")? {
+            return Err(TsnError::Unsupported(format!(
+                "{} does not report its capabilities",
+                self.path
+            )));
+        }
+        Ok(caps.max_adj as f64)
+    }
+
+    /// Run the clock `ppb` parts per billion faster than its oscillator, replacing the
+    /// adjustment before. Needs [`Phc::open_adjustable`]
+    pub fn set_frequency(&self, ppb: f64) -> Result<(), TsnError> {
+        let mut tx: libc::timex = unsafe { mem::zeroed() };
+        tx.modes = libc::ADJ_FREQUENCY;
+        // In ppm with 16 fractional bits
+        tx.freq = (ppb * 65.536) as libc::c_long;
+        self.adjtime(&mut tx, "Set PHC frequency")
+    }
+
+    /// Move the clock by `ns` at once. Needs [`Phc::open_adjustable`]
+    pub fn step(&self, ns: i64) -> Result<(), TsnError> {
+        let offset = Timespec::from_nanos(ns);
+        let mut tx: libc::timex = unsafe { mem::zeroed() };
+        tx.modes = libc::ADJ_SETOFFSET | libc::ADJ_NANO;
+        // Nanoseconds with ADJ_NANO, never negative
+        tx.time.tv_sec = offset.tv_sec;
+        tx.time.tv_usec = offset.tv_nsec as libc::suseconds_t;
+        self.adjtime(&mut tx, "Step PHC")
+    }
+
+    fn adjtime(&self, tx: &mut libc::timex, op: &'static str) -> Result<(), TsnError> {
+        if unsafe { libc::clock_adjtime(self.clockid(), tx) } < 0 {
+            return Err(TsnError::clock(op));
+        }
+        Ok(())
+    }
+
+    /// Measure the offset of the PHC from the system clock `clockid`.
+    ///
+    /// `CLOCK_REALTIME` and `CLOCK_TAI` use the most accurate method the driver has,
+    /// other clocks are read with [`OffsetMethod::Read`].
+    pub fn offset(&self, clockid: libc::clockid_t) -> Result<ClockOffset, TsnError> {
+        // Both ioctls read CLOCK_REALTIME, TAI is ahead of it by whole seconds
+        let shift = match clockid {
+            libc::CLOCK_REALTIME => Some(0),
+            libc::CLOCK_TAI => Some(tai_offset()?),
+            _ => None,
+        };
+        if let Some(shift) = shift {
+            let measured = match self.sys_offset_precise()? {
+                Some(offset) => Some(offset),
+                None => self.sys_offset_extended()?,
+            };
+            if let Some((offset, delay, method)) = measured {
+                return Ok(ClockOffset {
+                    clockid,
+                    offset: offset - shift,
+                    delay,
+                    method,
+                });
+            }
+        }
+        let (offset, delay) = self.read_offset(clockid)?;
+        Ok(ClockOffset {
+            clockid,
+            offset,
+            delay,
+            method: OffsetMethod::Read,
+        })
+    }
+
+    /// Offset from CLOCK_REALTIME, `None` if the driver cannot cross timestamp
+    fn sys_offset_precise(&self) -> Result<Option<(i64, i64, OffsetMethod)>, TsnError> {
+        let mut req: libc::ptp_sys_offset_precise = unsafe { mem::zeroed() };
+        if !self.ioctl(
+            libc::PTP_SYS_OFFSET_PRECISE,
+            &mut req,
+            "PTP_SYS_OFFSET_PRECISE",
+        )? {
+            return Ok(None);
+        }
+        let offset = nanos(&req.device) - nanos(&req.sys_realtime);
+        Ok(Some((offset, 0, OffsetMethod::Precise)))
+    }
+
+    /// Offset from CLOCK_REALTIME, `None` if the driver does not support the ioctl
+    fn sys_offset_extended(&self) -> Result<Option<(i64, i64, OffsetMethod)>, TsnError> {
+        let mut req: libc::ptp_sys_offset_extended = unsafe { mem::zeroed() };
+        req.n_samples = SAMPLES as u32;
+        req.clockid = libc::CLOCK_REALTIME;
+        if !self.ioctl(
+            libc::PTP_SYS_OFFSET_EXTENDED,
+            &mut req,
+            "PTP_SYS_OFFSET_EXTENDED",
+        )? {
+            return Ok(None);
+        }
+        let samples = req.ts[..SAMPLES]
+            .iter()
+            .map(|[before, phc, after]| (nanos(before), nanos(phc), nanos(after)));
+        let (offset, delay) = narrowest(samples);
+        Ok(Some((offset, delay, OffsetMethod::Extended)))
+    }
+
+    fn read_offset(&self, clockid: libc::clockid_t) -> Result<(i64, i64), TsnError> {
+        let mut samples = Vec::with_capacity(SAMPLES);
+        for _ in 0..SAMPLES {
+            let before = clock_gettime(clockid)?;
+            let phc = self.now()?;
+            let after = clock_gettime(clockid)?;
+            samples.push((before.as_nanos(), phc.as_nanos(), after.as_nanos()));
+        }
+        Ok(narrowest(samples.into_iter()))
+    }
+
+    /// Run a `PTP_*` ioctl on the clock. `false` if the driver does not support it
+    fn ioctl<T>(
+        &self,
+        request: libc::Ioctl,
+        arg: &mut T,
+        op: &'static str,
+    ) -> Result<bool, TsnError> {
+        let res = unsafe { libc::ioctl(self.file.as_raw_fd(), request, arg as *mut T) };
+        if res == 0 {
+            return Ok(true);
+        }
+        let source = io::Error::last_os_error();
+        match source.raw_os_error() {
+            Some(libc::EOPNOTSUPP) | Some(libc::ENOTTY) | Some(libc::EINVAL) => Ok(false),
+            _ => Err(TsnError::Clock { op, source }),
+        }
+    }
+}
+
+impl AsRawFd for Phc {
+    fn as_raw_fd(&self) -> RawFd {
+        self.file.as_raw_fd()
+    }
+}
+
+/// `/dev/ptpN` of `ifname`
+fn interface_path(ifname: &str) -> Result<String, TsnError> {
+    match ts_info(ifname)?.phc_index {
+        Some(index) => Ok(format!("/dev/ptp{}", index)),
+        None => Err(TsnError::Unsupported(format!(
+            "{} has no PTP hardware clock",
+            ifname
+        ))),
+    }
+}
+
+fn nanos(t: &libc::ptp_clock_time) -> i64 {
+    t.sec * 1_000_000_000 + t.nsec as i64
+}
+
+/// Offset and delay of the `(before, phc, after)` sample read in the narrowest window,
+/// taking the PHC as read halfway through it
+fn narrowest(samples: impl Iterator<Item = (i64, i64, i64)>) -> (i64, i64) {
+    samples
+        .map(|(before, phc, after)| (phc - (before + (after - before) / 2), after - before))
+        .min_by_key(|&(_, delay)| delay)
+        .unwrap_or_default()
+}
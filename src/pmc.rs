@@ -0,0 +1,490 @@
+//! PTP management client of ptp4l, like `pmc -u`
+//!
+//! [`PmcClient`] sends management GET requests to the UNIX domain socket of ptp4l and
+//! returns its answers as typed data sets, e.g. to check that the clocks are in step
+//! before measuring one-way latencies. ptp4l only answers requests of its own domain
+//! and `transportSpecific`, which is 1 with `dist/gPTP.conf`.
+//!
+//! ```no_run
+//! use tsn::pmc::PmcClient;
+//!
+//! let mut pmc = PmcClient::connect()?;
+//! pmc.transport_specific = 1;
+//! let status = pmc.sync_status()?;
+//! println!(
+//!     "{}, {} ns from grandmaster {}",
+//!     status.state, status.offset_from_master, status.grandmaster
+//! );
+//! # Ok::<(), tsn::TsnError>(())
+//! ```
+
+use std::fmt;
+use std::io::{Error, ErrorKind};
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::time::Duration;
+use std::{fs, process};
+
+use crate::gptp::{ClockIdentity, ClockQuality, PortIdentity};
+use crate::TsnError;
+
+/// Management socket of ptp4l, `uds_address` of its config
+pub const PTP4L_SOCKET: &str = "/var/run/ptp4l";
+
+pub const CURRENT_DATA_SET: u16 = 0x2001;
+pub const PARENT_DATA_SET: u16 = 0x2002;
+pub const PORT_DATA_SET: u16 = 0x2004;
+/// linuxptp extension
+pub const TIME_STATUS_NP: u16 = 0xc000;
+
+const MANAGEMENT: u8 = 0xd;
+const VERSION_PTP: u8 = 2;
+const HEADER_LEN: usize = 34;
+/// Header, target port identity, boundary hops and action
+const MANAGEMENT_LEN: usize = HEADER_LEN + 14;
+const ACTION_GET: u8 = 0;
+const ACTION_RESPONSE: u8 = 2;
+const TLV_MANAGEMENT: u16 = 0x0001;
+const TLV_MANAGEMENT_ERROR_STATUS: u16 = 0x0002;
+/// How long to wait for ptp4l
+const TIMEOUT: Duration = Duration::from_millis(500);
+/// How long to wait for the answers of further ports after the first one
+const MORE_RESPONSES: Duration = Duration::from_millis(20);
+
+/// State of a PTP port, IEEE 1588 clause 8.2.5.3.1
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PortState {
+    Initializing,
+    Faulty,
+    Disabled,
+    Listening,
+    PreMaster,
+    Master,
+    Passive,
+    /// Following a master, but not in step yet
+    Uncalibrated,
+    Slave,
+    Other(u8),
+}
+
+impl PortState {
+    fn from_raw(state: u8) -> PortState {
+        match state {
+            1 => PortState::Initializing,
+            2 => PortState::Faulty,
+            3 => PortState::Disabled,
+            4 => PortState::Listening,
+            5 => PortState::PreMaster,
+            6 => PortState::Master,
+            7 => PortState::Passive,
+            8 => PortState::Uncalibrated,
+            9 => PortState::Slave,
+            other => PortState::Other(other),
+        }
+    }
+}
+
+impl fmt::Display for PortState {
+    /// Names as printed by pmc
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            PortState::Initializing => "INITIALIZING",
+            PortState::Faulty => "FAULTY",
+            PortState::Disabled => "DISABLED",
+            PortState::Listening => "LISTENING",
+            PortState::PreMaster => "PRE_MASTER",
+            PortState::Master => "MASTER",
+            PortState::Passive => "PASSIVE",
+            PortState::Uncalibrated => "UNCALIBRATED",
+            PortState::Slave => "SLAVE",
+            PortState::Other(state) => return write!(f, "UNKNOWN({})", state),
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// `CURRENT_DATA_SET`, times in ns
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CurrentDataSet {
+    pub steps_removed: u16,
+    /// Local clock minus master
+    pub offset_from_master: f64,
+    pub mean_path_delay: f64,
+}
+
+/// `PARENT_DATA_SET`, the master and grandmaster followed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParentDataSet {
+    pub parent: PortIdentity,
+    pub parent_stats: bool,
+    pub observed_parent_offset_scaled_log_variance: u16,
+    pub observed_parent_clock_phase_change_rate: i32,
+    pub grandmaster_priority1: u8,
+    pub grandmaster_clock_quality: ClockQuality,
+    pub grandmaster_priority2: u8,
+    pub grandmaster: ClockIdentity,
+}
+
+/// `TIME_STATUS_NP` of linuxptp, times in ns
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TimeStatus {
+    /// Local clock minus grandmaster at the last Sync
+    pub master_offset: i64,
+    /// When the last Sync was received
+    pub ingress_time: i64,
+    /// Rate of the grandmaster over the local clock
+    pub cumulative_rate_ratio: f64,
+    pub scaled_last_gm_phase_change: i32,
+    pub gm_time_base_indicator: u16,
+    pub last_gm_phase_change: f64,
+    /// Whether a grandmaster other than the local clock is followed
+    pub gm_present: bool,
+    pub grandmaster: ClockIdentity,
+}
+
+/// `PORT_DATA_SET` of one port, times in ns
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PortDataSet {
+    pub port: PortIdentity,
+    pub state: PortState,
+    pub log_min_delay_req_interval: i8,
+    /// Link delay measured with the peer delay mechanism
+    pub peer_mean_path_delay: f64,
+    pub log_announce_interval: i8,
+    pub announce_receipt_timeout: u8,
+    pub log_sync_interval: i8,
+    /// 1 for E2E, 2 for P2P (gPTP), 0xfe for none
+    pub delay_mechanism: u8,
+    pub log_min_pdelay_req_interval: i8,
+    pub version: u8,
+}
+
+/// Whether the local clock is in step, from several data sets
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SyncStatus {
+    /// State of the port following the grandmaster, of the first port otherwise
+    pub state: PortState,
+    /// Local clock minus grandmaster in ns
+    pub offset_from_master: i64,
+    pub mean_path_delay: f64,
+    pub grandmaster: ClockIdentity,
+    pub gm_present: bool,
+}
+
+impl SyncStatus {
+    /// Whether the clock follows a grandmaster or is the grandmaster itself
+    pub fn is_synchronized(&self) -> bool {
+        match self.state {
+            PortState::Slave => self.gm_present,
+            PortState::Master => !self.gm_present,
+            _ => false,
+        }
+    }
+}
+
+/// Client of the management socket of ptp4l
+pub struct PmcClient {
+    sock: UnixDatagram,
+    path: PathBuf,
+    server: PathBuf,
+    identity: PortIdentity,
+    sequence_id: u16,
+    /// `domainNumber` of ptp4l, 0 by default
+    pub domain: u8,
+    /// `transportSpecific` of ptp4l, 0 by default and 1 for gPTP
+    pub transport_specific: u8,
+}
+
+impl PmcClient {
+    /// Client of ptp4l at [`PTP4L_SOCKET`]
+    pub fn connect() -> Result<PmcClient, TsnError> {
+        PmcClient::connect_to(PTP4L_SOCKET)
+    }
+
+    /// Client of the management socket at `server`
+    pub fn connect_to(server: impl AsRef<Path>) -> Result<PmcClient, TsnError> {
+        static NEXT_ID: AtomicU16 = AtomicU16::new(0);
+
+        // ptp4l answers to the address of the request
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        let path = std::env::temp_dir().join(format!("tsn-pmc.{}.{}", process::id(), id));
+        let _ = fs::remove_file(&path);
+        let sock = UnixDatagram::bind(&path).map_err(|source| TsnError::Socket {
+            op: "Bind management socket",
+            source,
+        })?;
+        let client = PmcClient {
+            sock,
+            path,
+            server: server.as_ref().to_path_buf(),
+            identity: PortIdentity {
+                clock: ClockIdentity([0; 8]),
+                port: process::id() as u16,
+            },
+            sequence_id: 0,
+            domain: 0,
+            transport_specific: 0,
+        };
+        client.set_timeout(TIMEOUT)?;
+        Ok(client)
+    }
+
+    /// How long to wait for ptp4l, 500 ms by default
+    pub fn set_timeout(&self, timeout: Duration) -> Result<(), TsnError> {
+        self.sock
+            .set_read_timeout(Some(timeout))
+            .map_err(|source| TsnError::Socket {
+                op: "Set timeout",
+                source,
+            })
+    }
+
+    pub fn current_data_set(&mut self) -> Result<CurrentDataSet, TsnError> {
+        let data = self.get(CURRENT_DATA_SET)?;
+        let data = check_len(&data, 18)?;
+        Ok(CurrentDataSet {
+            steps_removed: u16::from_be_bytes([data[0], data[1]]),
+            offset_from_master: time_interval(&data[2..10]),
+            mean_path_delay: time_interval(&data[10..18]),
+        })
+    }
+
+    pub fn parent_data_set(&mut self) -> Result<ParentDataSet, TsnError> {
+        let data = self.get(PARENT_DATA_SET)?;
+        let data = check_len(&data, 32)?;
+        Ok(ParentDataSet {
+            parent: port_identity(&data[..10]),
+            parent_stats: data[10] & 1 != 0,
+            observed_parent_offset_scaled_log_variance: u16::from_be_bytes([data[12], data[13]]),
+            observed_parent_clock_phase_change_rate: i32::from_be_bytes(
+                data[14..18].try_into().unwrap(),
+            ),
+            grandmaster_priority1: data[18],
+            grandmaster_clock_quality: ClockQuality {
+                class: data[19],
+                accuracy: data[20],
+                offset_scaled_log_variance: u16::from_be_bytes([data[21], data[22]]),
+            },
+            grandmaster_priority2: data[23],
+            grandmaster: ClockIdentity(data[24..32].try_into().unwrap()),
+        })
+    }
+
+    pub fn time_status(&mut self) -> Result<TimeStatus, TsnError> {
+        let data = self.get(TIME_STATUS_NP)?;
+        let data = check_len(&data, 50)?;
+        let phase_change = (u16::from_be_bytes([data[26], data[27]]) as u128) << 64
+            | u64::from_be_bytes(data[28..36].try_into().unwrap()) as u128;
+        let fractional = u16::from_be_bytes([data[36], data[37]]);
+        Ok(TimeStatus {
+            master_offset: i64::from_be_bytes(data[..8].try_into().unwrap()),
+            ingress_time: i64::from_be_bytes(data[8..16].try_into().unwrap()),
+            cumulative_rate_ratio: 1.0
+                + i32::from_be_bytes(data[16..20].try_into().unwrap()) as f64 / (1u64 << 41) as f64,
+            scaled_last_gm_phase_change: i32::from_be_bytes(data[20..24].try_into().unwrap()),
+            gm_time_base_indicator: u16::from_be_bytes([data[24], data[25]]),
+            last_gm_phase_change: phase_change as f64 + fractional as f64 / 65536.0,
+            gm_present: i32::from_be_bytes(data[38..42].try_into().unwrap()) != 0,
+            grandmaster: ClockIdentity(data[42..50].try_into().unwrap()),
+        })
+    }
+
+    /// `PORT_DATA_SET` of every port of ptp4l
+    pub fn port_data_sets(&mut self) -> Result<Vec<PortDataSet>, TsnError> {
+        self.get_all(PORT_DATA_SET)?
+            .into_iter()
+            .map(|data| {
+                let data = check_len(&data, 26)?;
+                Ok(PortDataSet {
+                    port: port_identity(&data[..10]),
+                    state: PortState::from_raw(data[10]),
+                    log_min_delay_req_interval: data[11] as i8,
+                    peer_mean_path_delay: time_interval(&data[12..20]),
+                    log_announce_interval: data[20] as i8,
+                    announce_receipt_timeout: data[21],
+                    log_sync_interval: data[22] as i8,
+                    delay_mechanism: data[23],
+                    log_min_pdelay_req_interval: data[24] as i8,
+                    version: data[25] & 0xf,
+                })
+            })
+            .collect()
+    }
+
+    /// Port state, offset and grandmaster in one
+    pub fn sync_status(&mut self) -> Result<SyncStatus, TsnError> {
+        let ports = self.port_data_sets()?;
+        let state = ports
+            .iter()
+            .map(|port| port.state)
+            .find(|state| matches!(state, PortState::Slave | PortState::Uncalibrated))
+            .or_else(|| ports.first().map(|port| port.state))
+            .unwrap_or(PortState::Other(0));
+        let current = self.current_data_set()?;
+        let time = self.time_status()?;
+        Ok(SyncStatus {
+            state,
+            offset_from_master: time.master_offset,
+            mean_path_delay: current.mean_path_delay,
+            grandmaster: time.grandmaster,
+            gm_present: time.gm_present,
+        })
+    }
+
+    /// Data of the answer to a GET of `id`
+    fn get(&mut self, id: u16) -> Result<Vec<u8>, TsnError> {
+        let mut responses = self.request(id, false)?;
+        Ok(responses.swap_remove(0))
+    }
+
+    /// Data of the answers of all ports to a GET of `id`
+    fn get_all(&mut self, id: u16) -> Result<Vec<Vec<u8>>, TsnError> {
+        self.request(id, true)
+    }
+
+    fn request(&mut self, id: u16, all: bool) -> Result<Vec<Vec<u8>>, TsnError> {
+        self.sequence_id = self.sequence_id.wrapping_add(1);
+        let request = self.encode_get(id);
+        self.sock
+            .send_to(&request, &self.server)
+            .map_err(|source| TsnError::Socket {
+                op: "Send management request",
+                source,
+            })?;
+
+        // Later ports get a shorter timeout, the caller's is back on every exit
+        let timeout = self.sock.read_timeout().ok().flatten();
+        let res = self.receive(id, all);
+        if all {
+            self.sock
+                .set_read_timeout(timeout)
+                .map_err(|source| TsnError::Socket {
+                    op: "Set timeout",
+                    source,
+                })?;
+        }
+        res
+    }
+
+    /// Answers to the request for `id`, of all ports if `all`
+    fn receive(&mut self, id: u16, all: bool) -> Result<Vec<Vec<u8>>, TsnError> {
+        let mut responses = Vec::new();
+        let mut buf = [0u8; 1500];
+        loop {
+            let len = match self.sock.recv(&mut buf) {
+                Ok(len) => len,
+                // Every port has answered
+                Err(e) if !responses.is_empty() && is_timeout(&e) => break,
+                Err(source) => {
+                    return Err(TsnError::Socket {
+                        op: "Receive management response",
+                        source,
+                    })
+                }
+            };
+            match self.decode_response(&buf[..len], id)? {
+                Some(data) => responses.push(data),
+                // Stale answer to an earlier request
+                None => continue,
+            }
+            if !all {
+                break;
+            }
+            self.set_timeout(MORE_RESPONSES)?;
+        }
+        Ok(responses)
+    }
+
+    fn encode_get(&self, id: u16) -> Vec<u8> {
+        let len = MANAGEMENT_LEN + 6;
+        let mut buf = vec![0u8; len];
+        buf[0] = self.transport_specific << 4 | MANAGEMENT;
+        buf[1] = VERSION_PTP;
+        buf[2..4].copy_from_slice(&(len as u16).to_be_bytes());
+        buf[4] = self.domain;
+        buf[20..28].copy_from_slice(&self.identity.clock.0);
+        buf[28..30].copy_from_slice(&self.identity.port.to_be_bytes());
+        buf[30..32].copy_from_slice(&self.sequence_id.to_be_bytes());
+        // controlField of management messages
+        buf[32] = 0x04;
+        buf[33] = 0x7f;
+        // Target all clocks and ports
+        buf[34..44].fill(0xff);
+        buf[44] = 0;
+        buf[45] = 0;
+        buf[46] = ACTION_GET;
+        buf[48..50].copy_from_slice(&TLV_MANAGEMENT.to_be_bytes());
+        buf[50..52].copy_from_slice(&2u16.to_be_bytes());
+        buf[52..54].copy_from_slice(&id.to_be_bytes());
+        buf
+    }
+
+    /// Data of a response to the pending GET of `id`, None for anything else
+    fn decode_response(&self, buf: &[u8], id: u16) -> Result<Option<Vec<u8>>, TsnError> {
+        if buf.len() < MANAGEMENT_LEN + 6
+            || buf[0] & 0x0f != MANAGEMENT
+            || u16::from_be_bytes([buf[30], buf[31]]) != self.sequence_id
+            || buf[46] & 0x0f != ACTION_RESPONSE
+        {
+            return Ok(None);
+        }
+        let tlv_type = u16::from_be_bytes([buf[48], buf[49]]);
+        let tlv_len = u16::from_be_bytes([buf[50], buf[51]]) as usize;
+        let value = match buf.get(52..52 + tlv_len) {
+            Some(value) if tlv_len >= 2 => value,
+            _ => return Ok(None),
+        };
+        match tlv_type {
+            TLV_MANAGEMENT if u16::from_be_bytes([value[0], value[1]]) == id => {
+                Ok(Some(value[2..].to_vec()))
+            }
+            // managementErrorId, managementId, reserved and displayData
+            TLV_MANAGEMENT_ERROR_STATUS if value.len() >= 8 => {
+                let display = match value.get(8) {
+                    Some(&len) => value.get(9..9 + len as usize).unwrap_or(&[]),
+                    None => &[],
+                };
+                Err(TsnError::PtpManagement {
+                    id: u16::from_be_bytes([value[2], value[3]]),
+                    error: u16::from_be_bytes([value[0], value[1]]),
+                    message: String::from_utf8_lossy(display).into_owned(),
+                })
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+impl Drop for PmcClient {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+fn is_timeout(e: &Error) -> bool {
+    matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut)
+}
+
+/// Data of at least `len` bytes, as a management TLV might carry padding
+fn check_len(data: &[u8], len: usize) -> Result<&[u8], TsnError> {
+    if data.len() < len {
+        return Err(TsnError::Socket {
+            op: "Decode management response",
+            source: Error::new(ErrorKind::InvalidData, "management data set too short"),
+        });
+    }
+    Ok(&data[..len])
+}
+
+/// TimeInterval in ns, scaled by 2^16
+fn time_interval(data: &[u8]) -> f64 {
+    i64::from_be_bytes(data.try_into().unwrap()) as f64 / 65536.0
+}
+
+fn port_identity(data: &[u8]) -> PortIdentity {
+    PortIdentity {
+        clock: ClockIdentity(data[..8].try_into().unwrap()),
+        port: u16::from_be_bytes([data[8], data[9]]),
+    }
+}
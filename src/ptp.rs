@@ -0,0 +1,326 @@
+//! `ptp` section of a NIC and the linuxptp configs rendered from it
+//!
+//! One ptp4l serves all selected NICs: the profile and priorities are global, the
+//! intervals, delay mechanism and transport are per port. The gPTP profile has the
+//! values of `dist/gPTP.conf`.
+
+use crate::pmc::PTP4L_SOCKET;
+use crate::TsnError;
+use serde_yaml::{self, Value};
+use std::fmt::Write;
+
+const KEYS: [&str; 8] = [
+    "profile",
+    "priority1",
+    "priority2",
+    "log_announce_interval",
+    "log_sync_interval",
+    "log_delay_req_interval",
+    "delay_mechanism",
+    "transport",
+];
+
+/// Options of `dist/gPTP.conf` which are not in [`PtpConfig`]
+const GPTP_GLOBAL: [(&str, &str); 9] = [
+    ("gmCapable", "1"),
+    ("syncReceiptTimeout", "3"),
+    ("neighborPropDelayThresh", "800"),
+    ("min_neighbor_prop_delay", "-20000000"),
+    ("assume_two_step", "1"),
+    ("path_trace_enabled", "1"),
+    ("follow_up_info", "1"),
+    ("transportSpecific", "0x1"),
+    ("ptp_dst_mac", "01:80:C2:00:00:0E"),
+];
+
+/// PTP profile, the `profile` key of `ptp`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PtpProfile {
+    /// IEEE 802.1AS, as `dist/gPTP.conf`
+    #[default]
+    Gptp,
+    /// Default profile of IEEE 1588, as ptp4l without a config
+    Default,
+}
+
+impl PtpProfile {
+    pub fn name(&self) -> &'static str {
+        match self {
+            PtpProfile::Gptp => "gPTP",
+            PtpProfile::Default => "default",
+        }
+    }
+}
+
+/// `delay_mechanism` of ptp4l
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DelayMechanism {
+    E2E,
+    P2P,
+    /// E2E until a peer delay request arrives
+    Auto,
+}
+
+impl DelayMechanism {
+    pub fn name(&self) -> &'static str {
+        match self {
+            DelayMechanism::E2E => "E2E",
+            DelayMechanism::P2P => "P2P",
+            DelayMechanism::Auto => "Auto",
+        }
+    }
+}
+
+/// `network_transport` of ptp4l
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transport {
+    L2,
+    UDPv4,
+    UDPv6,
+}
+
+impl Transport {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Transport::L2 => "L2",
+            Transport::UDPv4 => "UDPv4",
+            Transport::UDPv6 => "UDPv6",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PtpConfig {
+    pub profile: PtpProfile,
+    pub priority1: u8,
+    pub priority2: u8,
+    pub log_announce_interval: i8,
+    pub log_sync_interval: i8,
+    /// `logMinPdelayReqInterval` with P2P, `logMinDelayReqInterval` otherwise
+    pub log_delay_req_interval: i8,
+    pub delay_mechanism: DelayMechanism,
+    pub transport: Transport,
+}
+
+impl PtpConfig {
+    /// Defaults of `profile`
+    pub fn new(profile: PtpProfile) -> PtpConfig {
+        match profile {
+            PtpProfile::Gptp => PtpConfig {
+                profile,
+                priority1: 248,
+                priority2: 248,
+                log_announce_interval: 0,
+                log_sync_interval: -3,
+                log_delay_req_interval: 0,
+                delay_mechanism: DelayMechanism::P2P,
+                transport: Transport::L2,
+            },
+            PtpProfile::Default => PtpConfig {
+                profile,
+                priority1: 128,
+                priority2: 128,
+                log_announce_interval: 1,
+                log_sync_interval: 0,
+                log_delay_req_interval: 0,
+                delay_mechanism: DelayMechanism::E2E,
+                transport: Transport::UDPv4,
+            },
+        }
+    }
+}
+
+impl Default for PtpConfig {
+    fn default() -> Self {
+        Self::new(PtpProfile::default())
+    }
+}
+
+/// `ptp` section of a NIC, empty for the gPTP defaults
+pub fn normalise_ptp(config: &Value) -> Result<PtpConfig, TsnError> {
+    if config.is_null() {
+        return Ok(PtpConfig::default());
+    }
+    let mapping = config
+        .as_mapping()
+        .ok_or_else(|| TsnError::ConfigInvalid("ptp should be a dictionary".into()))?;
+    for (key, _) in mapping {
+        if !key.as_str().is_some_and(|key| KEYS.contains(&key)) {
+            return Err(TsnError::ConfigInvalid(format!(
+                "ptp key {:?} is not one of {}",
+                key,
+                KEYS.join(", ")
+            )));
+        }
+    }
+    let profiles = [PtpProfile::Gptp, PtpProfile::Default];
+    let profile = to_name(config, "profile", &profiles, PtpProfile::name)?.unwrap_or_default();
+    let defaults = PtpConfig::new(profile);
+    let delay_mechanisms = [
+        DelayMechanism::E2E,
+        DelayMechanism::P2P,
+        DelayMechanism::Auto,
+    ];
+    let transports = [Transport::L2, Transport::UDPv4, Transport::UDPv6];
+    let ptp = PtpConfig {
+        profile,
+        priority1: to_int(config, "priority1")?.unwrap_or(defaults.priority1),
+        priority2: to_int(config, "priority2")?.unwrap_or(defaults.priority2),
+        log_announce_interval: to_int(config, "log_announce_interval")?
+            .unwrap_or(defaults.log_announce_interval),
+        log_sync_interval: to_int(config, "log_sync_interval")?
+            .unwrap_or(defaults.log_sync_interval),
+        log_delay_req_interval: to_int(config, "log_delay_req_interval")?
+            .unwrap_or(defaults.log_delay_req_interval),
+        delay_mechanism: to_name(
+            config,
+            "delay_mechanism",
+            &delay_mechanisms,
+            DelayMechanism::name,
+        )?
+        .unwrap_or(defaults.delay_mechanism),
+        transport: to_name(config, "transport", &transports, Transport::name)?
+            .unwrap_or(defaults.transport),
+    };
+    // 802.1AS runs on Ethernet with the peer delay mechanism only
+    if profile == PtpProfile::Gptp
+        && (ptp.delay_mechanism != DelayMechanism::P2P || ptp.transport != Transport::L2)
+    {
+        return Err(TsnError::ConfigInvalid(format!(
+            "gPTP needs delay_mechanism P2P and transport L2, not {} and {}",
+            ptp.delay_mechanism.name(),
+            ptp.transport.name()
+        )));
+    }
+    Ok(ptp)
+}
+
+fn to_int<T: TryFrom<i64>>(config: &Value, key: &str) -> Result<Option<T>, TsnError> {
+    config
+        .get(key)
+        .map(|val| {
+            val.as_i64()
+                .and_then(|val| T::try_from(val).ok())
+                .ok_or_else(|| {
+                    TsnError::ConfigInvalid(format!("ptp {} {:?} is out of range", key, val))
+                })
+        })
+        .transpose()
+}
+
+fn to_name<T: Copy>(
+    config: &Value,
+    key: &str,
+    choices: &[T],
+    name: fn(&T) -> &'static str,
+) -> Result<Option<T>, TsnError> {
+    let Some(val) = config.get(key) else {
+        return Ok(None);
+    };
+    val.as_str()
+        .and_then(|val| choices.iter().find(|choice| name(choice) == val))
+        .map(|choice| Some(*choice))
+        .ok_or_else(|| {
+            let names: Vec<_> = choices.iter().map(name).collect();
+            TsnError::ConfigInvalid(format!(
+                "ptp {} {:?} is not one of {}",
+                key,
+                val,
+                names.join(", ")
+            ))
+        })
+}
+
+/// The global part of the configs of `nics`, which have to agree on it
+fn global(nics: &[(&str, &PtpConfig)]) -> Result<PtpConfig, TsnError> {
+    let Some((first, ptp)) = nics.first() else {
+        return Err(TsnError::ConfigInvalid(
+            "no interface has a ptp section".into(),
+        ));
+    };
+    for (ifname, other) in &nics[1..] {
+        if (other.profile, other.priority1, other.priority2)
+            != (ptp.profile, ptp.priority1, ptp.priority2)
+        {
+            return Err(TsnError::ConfigInvalid(format!(
+                "ptp profile and priorities of {} and {} differ, but one ptp4l serves both",
+                first, ifname
+            )));
+        }
+    }
+    Ok((*ptp).clone())
+}
+
+fn option(out: &mut String, key: &str, value: impl std::fmt::Display) {
+    writeln!(out, "{:<24}{}", key, value).unwrap();
+}
+
+fn interfaces(nics: &[(&str, &PtpConfig)]) -> String {
+    nics.iter()
+        .map(|(ifname, _)| *ifname)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// ptp4l config serving `nics`, for `ptp4l -f`
+pub fn ptp4l_config(nics: &[(&str, &PtpConfig)]) -> Result<String, TsnError> {
+    let ptp = global(nics)?;
+    let mut out = String::new();
+    writeln!(
+        out,
+        "# ptp4l -f <this file>, {} profile on {}",
+        ptp.profile.name(),
+        interfaces(nics)
+    )
+    .unwrap();
+    writeln!(out, "[global]").unwrap();
+    option(&mut out, "priority1", ptp.priority1);
+    option(&mut out, "priority2", ptp.priority2);
+    if ptp.profile == PtpProfile::Gptp {
+        for (key, value) in GPTP_GLOBAL {
+            option(&mut out, key, value);
+        }
+    }
+    for (ifname, ptp) in nics {
+        writeln!(out, "[{}]", ifname).unwrap();
+        option(&mut out, "logAnnounceInterval", ptp.log_announce_interval);
+        option(&mut out, "logSyncInterval", ptp.log_sync_interval);
+        match ptp.delay_mechanism {
+            DelayMechanism::P2P => option(
+                &mut out,
+                "logMinPdelayReqInterval",
+                ptp.log_delay_req_interval,
+            ),
+            _ => option(
+                &mut out,
+                "logMinDelayReqInterval",
+                ptp.log_delay_req_interval,
+            ),
+        }
+        option(&mut out, "delay_mechanism", ptp.delay_mechanism.name());
+        option(&mut out, "network_transport", ptp.transport.name());
+    }
+    Ok(out)
+}
+
+/// phc2sys config following the ptp4l of [`ptp4l_config`], for `phc2sys -a -r -f`
+pub fn phc2sys_config(nics: &[(&str, &PtpConfig)]) -> Result<String, TsnError> {
+    let ptp = global(nics)?;
+    let mut out = String::new();
+    writeln!(
+        out,
+        "# phc2sys -a -r -f <this file>, {} profile on {}",
+        ptp.profile.name(),
+        interfaces(nics)
+    )
+    .unwrap();
+    writeln!(out, "[global]").unwrap();
+    option(&mut out, "uds_address", PTP4L_SOCKET);
+    // Management requests of phc2sys have to match those ptp4l answers
+    let transport_specific = match ptp.profile {
+        PtpProfile::Gptp => "0x1",
+        PtpProfile::Default => "0x0",
+    };
+    option(&mut out, "transportSpecific", transport_specific);
+    Ok(out)
+}
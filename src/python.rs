@@ -0,0 +1,445 @@
+//! Python bindings, the `tsn._tsn` extension module of the `tsn` package in `python/`
+//!
+//! Built with `maturin develop` or `maturin build`, see `pyproject.toml`. Errors are
+//! raised as `tsn.TsnError`, an `OSError` with the errno [`crate::capi`] would set.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use nix::libc;
+use pyo3::create_exception;
+use pyo3::exceptions::{PyOSError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+
+use crate::builder::ConfigSource;
+use crate::config::{self, read_config};
+use crate::vlan::{self, RecordingExecutor};
+use crate::{Backend, PacketType, TimestampMode, TimestampSource, VlanHold};
+
+create_exception!(
+    tsn,
+    TsnError,
+    PyOSError,
+    "Error of the TSN library, `errno` tells its kind"
+);
+
+impl From<crate::TsnError> for PyErr {
+    fn from(e: crate::TsnError) -> PyErr {
+        TsnError::new_err((crate::capi::errno(&e), e.to_string()))
+    }
+}
+
+/// TAS/CBS setup of an interface, as under `nics` in `config.yaml`
+#[pyclass(name = "Config", module = "tsn", frozen, from_py_object)]
+#[derive(Clone, Default)]
+pub struct PyConfig(config::Config);
+
+#[pymethods]
+impl PyConfig {
+    /// Config without TAS or CBS, for a VLAN with the default qdisc
+    #[new]
+    fn new() -> PyConfig {
+        PyConfig::default()
+    }
+
+    #[getter]
+    fn has_tas(&self) -> bool {
+        self.0.tas.is_some()
+    }
+
+    #[getter]
+    fn has_cbs(&self) -> bool {
+        self.0.cbs.is_some()
+    }
+
+    /// `ip` and `tc` commands which would create the VLAN `vlanid` of `ifname`
+    fn commands(&self, ifname: &str, vlanid: u16) -> PyResult<Vec<String>> {
+        let mut recorder = RecordingExecutor::new();
+        vlan::create_vlan_with(&mut recorder, &self.0, ifname, vlanid)?;
+        Ok(recorder.commands().to_vec())
+    }
+
+    fn __repr__(&self) -> String {
+        format!("Config(tas={}, cbs={})", self.has_tas(), self.has_cbs())
+    }
+}
+
+/// Configs of the interfaces in the file at `path`, validated
+#[pyfunction(name = "read_config")]
+fn py_read_config(path: &str) -> PyResult<HashMap<String, PyConfig>> {
+    let configs = read_config(path)?;
+    Ok(configs
+        .into_iter()
+        .map(|(ifname, config)| (ifname, PyConfig(config)))
+        .collect())
+}
+
+/// Config file path, `Config`, or `None` for the file at `CONFIG_PATH`
+fn config_source(config: Option<&Bound<'_, PyAny>>) -> PyResult<ConfigSource> {
+    let Some(config) = config else {
+        return Ok(ConfigSource::Env);
+    };
+    if let Ok(config) = config.extract::<PyRef<'_, PyConfig>>() {
+        return Ok(config.0.clone().into());
+    }
+    Ok(ConfigSource::Path(config.extract::<String>()?))
+}
+
+/// Create the VLAN `vlanid` of `ifname` with the qdiscs of `config`, without
+/// refcounting. See `VlanHold` to share it with sockets
+#[pyfunction(name = "create_vlan")]
+fn py_create_vlan(py: Python<'_>, config: PyConfig, ifname: &str, vlanid: u16) -> PyResult<()> {
+    py.detach(|| vlan::create_vlan(&config.0, ifname, vlanid))?;
+    Ok(())
+}
+
+/// Delete the VLAN `vlanid` of `ifname` and the qdiscs of its interface
+#[pyfunction(name = "delete_vlan")]
+fn py_delete_vlan(py: Python<'_>, ifname: &str, vlanid: u16) -> PyResult<()> {
+    py.detach(|| vlan::delete_vlan(ifname, vlanid))?;
+    Ok(())
+}
+
+/// Set up the taprio (and etf) qdiscs of `config` on `ifname`
+#[pyfunction(name = "setup_tas")]
+fn py_setup_tas(py: Python<'_>, ifname: &str, config: PyConfig) -> PyResult<()> {
+    let tas = config
+        .0
+        .tas
+        .ok_or_else(|| PyValueError::new_err("config has no TAS"))?;
+    py.detach(|| vlan::setup_tas(ifname, &tas))?;
+    Ok(())
+}
+
+/// Set up the mqprio and cbs qdiscs of `config` on `ifname`
+#[pyfunction(name = "setup_cbs")]
+fn py_setup_cbs(py: Python<'_>, ifname: &str, config: PyConfig) -> PyResult<()> {
+    let cbs = config
+        .0
+        .cbs
+        .ok_or_else(|| PyValueError::new_err("config has no CBS"))?;
+    py.detach(|| vlan::setup_cbs(ifname, &cbs))?;
+    Ok(())
+}
+
+/// Delete VLANs whose holders all exited without closing. Returns their names
+#[pyfunction(name = "gc_vlans")]
+fn py_gc_vlans(py: Python<'_>) -> PyResult<Vec<String>> {
+    Ok(py.detach(crate::gc_vlans)?)
+}
+
+/// Hold on a VLAN without a socket, counted like the hold of a socket. A context
+/// manager which releases the hold on exit
+#[pyclass(name = "VlanHold", module = "tsn")]
+pub struct PyVlanHold(VlanHold);
+
+#[pymethods]
+impl PyVlanHold {
+    #[new]
+    #[pyo3(signature = (ifname, vlanid, config=None))]
+    fn new(
+        py: Python<'_>,
+        ifname: &str,
+        vlanid: u16,
+        config: Option<&Bound<'_, PyAny>>,
+    ) -> PyResult<PyVlanHold> {
+        let config = config_source(config)?;
+        let hold = py.detach(|| {
+            let config = config.resolve(ifname)?;
+            VlanHold::new(ifname, vlanid, &config)
+        })?;
+        Ok(PyVlanHold(hold))
+    }
+
+    #[getter]
+    fn name(&self) -> String {
+        self.0.name()
+    }
+
+    /// Release the hold, deleting the VLAN if it was the last one
+    fn release(&mut self, py: Python<'_>) -> PyResult<()> {
+        py.detach(|| self.0.release())?;
+        Ok(())
+    }
+
+    fn __enter__(slf: Py<Self>) -> Py<Self> {
+        slf
+    }
+
+    fn __exit__(
+        &mut self,
+        py: Python<'_>,
+        _exc_type: &Bound<'_, PyAny>,
+        _exc: &Bound<'_, PyAny>,
+        _tb: &Bound<'_, PyAny>,
+    ) -> PyResult<bool> {
+        self.release(py)?;
+        Ok(false)
+    }
+}
+
+/// Socket on a TSN interface, see `TsnSocket::builder` of the Rust library.
+///
+/// `backend` is "packet" or "virtual", `timestamps` "off", "any" or "hardware", and
+/// `timeout` the receive timeout in seconds. A context manager which closes the socket
+/// on exit.
+#[pyclass(name = "TsnSocket", module = "tsn")]
+pub struct PyTsnSocket(Option<crate::TsnSocket>);
+
+#[pymethods]
+impl PyTsnSocket {
+    #[new]
+    #[pyo3(signature = (
+        ifname, vlan=None, *, priority=0, proto=libc::ETH_P_ALL as u16, config=None,
+        backend="packet", timestamps="off", timeout=None
+    ))]
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        py: Python<'_>,
+        ifname: &str,
+        vlan: Option<u16>,
+        priority: u32,
+        proto: u16,
+        config: Option<&Bound<'_, PyAny>>,
+        backend: &str,
+        timestamps: &str,
+        timeout: Option<f64>,
+    ) -> PyResult<PyTsnSocket> {
+        let mut builder = crate::TsnSocket::builder(ifname)
+            .priority(priority)
+            .protocol(proto)
+            .config(config_source(config)?)
+            .backend(backend_of(backend)?)
+            .timestamps(timestamp_mode_of(timestamps)?);
+        if let Some(vlan) = vlan {
+            builder = builder.vlan(vlan);
+        }
+        if let Some(timeout) = timeout {
+            builder = builder.timeout(duration(timeout)?);
+        }
+        let sock = py.detach(|| builder.open())?;
+        Ok(PyTsnSocket(Some(sock)))
+    }
+
+    fn fileno(&self) -> PyResult<i32> {
+        Ok(self.sock()?.fd)
+    }
+
+    #[getter]
+    fn ifname(&self) -> PyResult<String> {
+        Ok(self.sock()?.ifname.clone())
+    }
+
+    #[getter]
+    fn vlanid(&self) -> PyResult<u16> {
+        Ok(self.sock()?.vlanid)
+    }
+
+    fn mac_address<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyBytes>> {
+        Ok(PyBytes::new(py, &self.sock()?.mac_address()?))
+    }
+
+    /// Receive timeout in seconds, 0 to block forever
+    fn set_timeout(&self, timeout: f64) -> PyResult<()> {
+        let timeout = duration(timeout)?;
+        self.sock()?.set_timeout(timeout)?;
+        Ok(())
+    }
+
+    /// Send a frame. Returns the number of bytes sent
+    fn send(&self, py: Python<'_>, frame: &[u8]) -> PyResult<isize> {
+        let sock = self.sock()?;
+        Ok(py.detach(|| sock.send(frame))?)
+    }
+
+    /// Send `payload` to the MAC address `dst` with `ethertype`, the header is built
+    /// from the MAC address of the interface. Returns the number of bytes sent
+    fn send_to(
+        &self,
+        py: Python<'_>,
+        dst: &[u8],
+        ethertype: u16,
+        payload: &[u8],
+    ) -> PyResult<isize> {
+        let dst: [u8; 6] = dst
+            .try_into()
+            .map_err(|_| PyValueError::new_err("dst must be 6 bytes"))?;
+        let sock = self.sock()?;
+        Ok(py.detach(|| sock.send_to(dst, ethertype, payload))?)
+    }
+
+    /// Receive a frame of up to `size` bytes
+    #[pyo3(signature = (size=2048))]
+    fn recv<'py>(&self, py: Python<'py>, size: usize) -> PyResult<Bound<'py, PyBytes>> {
+        let sock = self.sock()?;
+        let mut buf = vec![0; size];
+        let len = py.detach(|| sock.recv(&mut buf))?;
+        Ok(PyBytes::new(py, &buf[..len as usize]))
+    }
+
+    /// Receive a frame with where it came from. Returns
+    /// `(frame, (src, ethertype, pkttype, vlan))`, `vlan` is `(vid, pcp)` or `None`
+    /// and `pcp` is `None` if the tag was dropped before the socket got the frame
+    #[pyo3(signature = (size=2048))]
+    #[allow(clippy::type_complexity)]
+    fn recv_from<'py>(
+        &self,
+        py: Python<'py>,
+        size: usize,
+    ) -> PyResult<(
+        Bound<'py, PyBytes>,
+        (
+            Bound<'py, PyBytes>,
+            u16,
+            &'static str,
+            Option<(u16, Option<u8>)>,
+        ),
+    )> {
+        let sock = self.sock()?;
+        let mut buf = vec![0; size];
+        let (len, info) = py.detach(|| sock.recv_from(&mut buf))?;
+        Ok((
+            PyBytes::new(py, &buf[..len]),
+            (
+                PyBytes::new(py, &info.src),
+                info.ethertype,
+                pkttype_name(info.pkttype),
+                info.vlan.map(|vlan| (vlan.vid, vlan.pcp)),
+            ),
+        ))
+    }
+
+    /// Receive a frame with its RX timestamp. Returns `(frame, (ns, source))`
+    #[pyo3(signature = (size=2048))]
+    fn recv_with_timestamp<'py>(
+        &self,
+        py: Python<'py>,
+        size: usize,
+    ) -> PyResult<(Bound<'py, PyBytes>, (i64, &'static str))> {
+        let sock = self.sock()?;
+        let mut buf = vec![0; size];
+        let (len, ts) = py.detach(|| sock.recv_with_timestamp(&mut buf))?;
+        Ok((
+            PyBytes::new(py, &buf[..len]),
+            (ts.time.as_nanos(), source_name(ts.source)),
+        ))
+    }
+
+    /// Enable TX and RX timestamps. Raises with `errno.EOPNOTSUPP` if the NIC cannot
+    /// timestamp, software timestamps are still enabled then
+    fn enable_timestamps(&mut self) -> PyResult<()> {
+        self.sock_mut()?.enable_timestamps(None)?;
+        Ok(())
+    }
+
+    /// Hardware timestamp config of the interface as `enable_timestamps` left it,
+    /// `(tx_type, rx_filter)` or `None` before hardware timestamps are enabled
+    fn hw_timestamp_config(&self) -> PyResult<Option<(u32, u32)>> {
+        let config = self.sock()?.hw_timestamp_config();
+        Ok(config.map(|config| (config.tx_type, config.rx_filter)))
+    }
+
+    /// Wait for the next TX timestamp. Returns `(id, ns, source)`
+    fn get_tx_timestamp(&self, py: Python<'_>) -> PyResult<(u32, i64, &'static str)> {
+        let sock = self.sock()?;
+        let ts = py.detach(|| sock.get_tx_timestamp())?;
+        Ok((ts.id, ts.time.as_nanos(), source_name(ts.source)))
+    }
+
+    /// Close the socket and release its VLAN. Closing twice is a no-op
+    fn close(&mut self, py: Python<'_>) -> PyResult<()> {
+        if let Some(mut sock) = self.0.take() {
+            py.detach(|| sock.close())?;
+        }
+        Ok(())
+    }
+
+    fn __enter__(slf: Py<Self>) -> Py<Self> {
+        slf
+    }
+
+    fn __exit__(
+        &mut self,
+        py: Python<'_>,
+        _exc_type: &Bound<'_, PyAny>,
+        _exc: &Bound<'_, PyAny>,
+        _tb: &Bound<'_, PyAny>,
+    ) -> PyResult<bool> {
+        self.close(py)?;
+        Ok(false)
+    }
+}
+
+impl PyTsnSocket {
+    fn sock(&self) -> PyResult<&crate::TsnSocket> {
+        self.0.as_ref().ok_or_else(closed)
+    }
+
+    fn sock_mut(&mut self) -> PyResult<&mut crate::TsnSocket> {
+        self.0.as_mut().ok_or_else(closed)
+    }
+}
+
+fn closed() -> PyErr {
+    TsnError::new_err((libc::EBADF, "Socket is closed"))
+}
+
+fn backend_of(name: &str) -> PyResult<Backend> {
+    match name {
+        "packet" => Ok(Backend::Packet),
+        "virtual" => Ok(Backend::Virtual(Default::default())),
+        _ => Err(PyValueError::new_err(format!("unknown backend {}", name))),
+    }
+}
+
+fn timestamp_mode_of(name: &str) -> PyResult<TimestampMode> {
+    match name {
+        "off" => Ok(TimestampMode::Off),
+        "any" => Ok(TimestampMode::Any),
+        "hardware" => Ok(TimestampMode::Hardware),
+        _ => Err(PyValueError::new_err(format!(
+            "unknown timestamp mode {}",
+            name
+        ))),
+    }
+}
+
+fn duration(secs: f64) -> PyResult<Duration> {
+    Duration::try_from_secs_f64(secs).map_err(|e| PyValueError::new_err(e.to_string()))
+}
+
+fn source_name(source: TimestampSource) -> &'static str {
+    match source {
+        TimestampSource::HwRaw => "hw_raw",
+        TimestampSource::HwLegacy => "hw_legacy",
+        TimestampSource::Sw => "sw",
+        TimestampSource::User => "user",
+    }
+}
+
+fn pkttype_name(pkttype: PacketType) -> &'static str {
+    match pkttype {
+        PacketType::Host => "host",
+        PacketType::Broadcast => "broadcast",
+        PacketType::Multicast => "multicast",
+        PacketType::OtherHost => "otherhost",
+        PacketType::Outgoing => "outgoing",
+        PacketType::Other(_) => "other",
+    }
+}
+
+#[pymodule]
+fn _tsn(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add("TsnError", m.py().get_type::<TsnError>())?;
+    m.add_class::<PyConfig>()?;
+    m.add_class::<PyVlanHold>()?;
+    m.add_class::<PyTsnSocket>()?;
+    m.add_function(wrap_pyfunction!(py_read_config, m)?)?;
+    m.add_function(wrap_pyfunction!(py_create_vlan, m)?)?;
+    m.add_function(wrap_pyfunction!(py_delete_vlan, m)?)?;
+    m.add_function(wrap_pyfunction!(py_setup_tas, m)?)?;
+    m.add_function(wrap_pyfunction!(py_setup_cbs, m)?)?;
+    m.add_function(wrap_pyfunction!(py_gc_vlans, m)?)?;
+    Ok(())
+}
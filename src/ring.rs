@@ -0,0 +1,428 @@
+//! Zero-copy `PACKET_MMAP` rings (TPACKET_V3) for [`TsnSocket`]
+//!
+//! Open a socket with [`sock_open_ring`](crate::sock_open_ring), then receive whole
+//! blocks of frames with [`TsnSocket::next_rx_block`] and fill TX slots with
+//! [`TsnSocket::tx_slot`] followed by [`TsnSocket::tx_flush`].
+//!
+//! The copying calls (`send`, `recv`, ...) fail with [`TsnError::Unsupported`]
+//! on the direction that has a ring, as the kernel bypasses the socket queue then.
+
+use crate::sys::{self, tpacket3_hdr, tpacket_block_desc, tpacket_req3};
+use crate::{time, RxTimestamp, TimestampSource, TsnError, TsnSocket};
+use nix::sys::mman::{mmap, munmap, MapFlags, ProtFlags};
+use std::marker::PhantomData;
+use std::num::NonZeroUsize;
+use std::sync::atomic::{fence, Ordering};
+use std::time::{Duration, Instant};
+use std::{io, mem, ptr};
+
+/// Layout of the rings, passed to [`sock_open_ring`](crate::sock_open_ring)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RingConfig {
+    /// Size of one block, a multiple of the page size. Also bounds the largest RX frame
+    pub block_size: u32,
+    /// Blocks of the RX ring, 0 to receive with `recv`
+    pub rx_blocks: u32,
+    /// Blocks of the TX ring, 0 to send with `send`
+    pub tx_blocks: u32,
+    /// Size of one TX slot including its 48 byte header, a multiple of 16
+    pub frame_size: u32,
+    /// A partly filled RX block is handed over after this many milliseconds
+    pub block_timeout_ms: u32,
+}
+
+impl Default for RingConfig {
+    fn default() -> Self {
+        RingConfig {
+            block_size: 1 << 20,
+            rx_blocks: 8,
+            tx_blocks: 0,
+            frame_size: 2048,
+            block_timeout_ms: 10,
+        }
+    }
+}
+
+impl RingConfig {
+    fn frames_per_block(&self) -> u32 {
+        self.block_size / self.frame_size
+    }
+
+    fn req(&self, blocks: u32, tx: bool) -> tpacket_req3 {
+        tpacket_req3 {
+            tp_block_size: self.block_size,
+            tp_block_nr: blocks,
+            tp_frame_size: self.frame_size,
+            tp_frame_nr: blocks * self.frames_per_block(),
+            // Kernel rejects the block retire options on the TX ring
+            tp_retire_blk_tov: if tx { 0 } else { self.block_timeout_ms },
+            tp_sizeof_priv: 0,
+            tp_feature_req_word: 0,
+        }
+    }
+}
+
+/// Offset of the frame data in a TX slot, `TPACKET_ALIGN(sizeof(struct tpacket3_hdr))`
+const TX_DATA_OFFSET: usize =
+    (mem::size_of::<tpacket3_hdr>() + sys::TPACKET_ALIGNMENT - 1) & !(sys::TPACKET_ALIGNMENT - 1);
+
+/// The mapped RX and TX rings of a socket
+pub(crate) struct Ring {
+    map: *mut u8,
+    map_len: usize,
+    config: RingConfig,
+    /// Next RX block to hand to the user
+    rx_next: u32,
+    /// Next TX slot to fill
+    tx_next: u32,
+    /// Slots committed since the last flush
+    tx_pending: u32,
+}
+
+// The mapping is only touched through `&mut TsnSocket`
+unsafe impl Send for Ring {}
+unsafe impl Sync for Ring {}
+
+impl Ring {
+    /// Set up the rings on `fd`. Must be called before `bind`
+    pub(crate) fn setup(fd: i32, config: RingConfig) -> Result<Ring, TsnError> {
+        if config.frame_size == 0
+            || !(config.frame_size as usize).is_multiple_of(sys::TPACKET_ALIGNMENT)
+            || config.block_size < config.frame_size
+            || config.rx_blocks + config.tx_blocks == 0
+        {
+            return Err(TsnError::Socket {
+                op: "Ring setup",
+                source: io::Error::from_raw_os_error(libc::EINVAL),
+            });
+        }
+
+        set_packet_opt(
+            fd,
+            sys::PACKET_VERSION,
+            sys::TPACKET_V3,
+            "Set PACKET_VERSION",
+        )?;
+        if config.rx_blocks > 0 {
+            let req = config.req(config.rx_blocks, false);
+            set_packet_opt(fd, sys::PACKET_RX_RING, req, "Set PACKET_RX_RING")?;
+        }
+        if config.tx_blocks > 0 {
+            set_packet_opt(fd, sys::PACKET_LOSS, 1, "Set PACKET_LOSS")?;
+            let req = config.req(config.tx_blocks, true);
+            set_packet_opt(fd, sys::PACKET_TX_RING, req, "Set PACKET_TX_RING")?;
+        }
+
+        // RX ring comes first in the mapping, then the TX ring
+        let map_len = config.block_size as usize * (config.rx_blocks + config.tx_blocks) as usize;
+        let map = unsafe {
+            mmap(
+                None,
+                NonZeroUsize::new_unchecked(map_len),
+                ProtFlags::PROT_READ | ProtFlags::PROT_WRITE,
+                MapFlags::MAP_SHARED,
+                fd,
+                0,
+            )
+        }
+        .map_err(|e| TsnError::Socket {
+            op: "Ring map",
+            source: e.into(),
+        })?;
+
+        Ok(Ring {
+            map: map as *mut u8,
+            map_len,
+            config,
+            rx_next: 0,
+            tx_next: 0,
+            tx_pending: 0,
+        })
+    }
+
+    pub(crate) fn has_rx(&self) -> bool {
+        self.config.rx_blocks > 0
+    }
+
+    pub(crate) fn has_tx(&self) -> bool {
+        self.config.tx_blocks > 0
+    }
+
+    fn rx_block(&self, index: u32) -> *mut tpacket_block_desc {
+        unsafe {
+            self.map
+                .add(index as usize * self.config.block_size as usize) as *mut _
+        }
+    }
+
+    fn tx_frame(&self, index: u32) -> *mut tpacket3_hdr {
+        let per_block = self.config.frames_per_block();
+        let offset = (self.config.rx_blocks + index / per_block) as usize
+            * self.config.block_size as usize
+            + (index % per_block) as usize * self.config.frame_size as usize;
+        unsafe { self.map.add(offset) as *mut _ }
+    }
+
+    fn rx_ready(&self) -> bool {
+        let block = self.rx_block(self.rx_next);
+        let status = unsafe { ptr::read_volatile(&(*block).hdr.block_status) };
+        fence(Ordering::Acquire);
+        status & sys::TP_STATUS_USER != 0
+    }
+}
+
+impl Drop for Ring {
+    fn drop(&mut self) {
+        let _ = unsafe { munmap(self.map as *mut libc::c_void, self.map_len) };
+    }
+}
+
+fn set_packet_opt<T>(fd: i32, opt: libc::c_int, val: T, op: &'static str) -> Result<(), TsnError> {
+    let res = unsafe {
+        libc::setsockopt(
+            fd,
+            libc::SOL_PACKET,
+            opt,
+            &val as *const T as *const libc::c_void,
+            mem::size_of::<T>() as u32,
+        )
+    };
+    if res < 0 {
+        Err(TsnError::socket(op))
+    } else {
+        Ok(())
+    }
+}
+
+/// Ask the kernel for hardware timestamps on ring frames, see `enable_timestamps`
+pub(crate) fn enable_timestamps(fd: i32) -> Result<(), TsnError> {
+    let flags = libc::SOF_TIMESTAMPING_RAW_HARDWARE | libc::SOF_TIMESTAMPING_SYS_HARDWARE;
+    set_packet_opt(fd, sys::PACKET_TIMESTAMP, flags, "Set PACKET_TIMESTAMP")
+}
+
+/// RX block owned by the user. Handed back to the kernel when dropped
+pub struct RxBlock<'a> {
+    ring: &'a mut Ring,
+    desc: *mut tpacket_block_desc,
+}
+
+impl<'a> RxBlock<'a> {
+    /// Number of frames in the block
+    pub fn len(&self) -> usize {
+        unsafe { (*self.desc).hdr.num_pkts as usize }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn frames(&self) -> RingFrames<'_> {
+        let first = unsafe { (*self.desc).hdr.offset_to_first_pkt as usize };
+        RingFrames {
+            next: unsafe { (self.desc as *const u8).add(first) },
+            left: self.len(),
+            _block: PhantomData,
+        }
+    }
+}
+
+impl<'a, 'b> IntoIterator for &'b RxBlock<'a> {
+    type Item = RingFrame<'b>;
+    type IntoIter = RingFrames<'b>;
+
+    fn into_iter(self) -> RingFrames<'b> {
+        self.frames()
+    }
+}
+
+impl<'a> Drop for RxBlock<'a> {
+    fn drop(&mut self) {
+        fence(Ordering::Release);
+        unsafe { ptr::write_volatile(&mut (*self.desc).hdr.block_status, sys::TP_STATUS_KERNEL) };
+        self.ring.rx_next = (self.ring.rx_next + 1) % self.ring.config.rx_blocks;
+    }
+}
+
+/// Iterator over the frames of an [`RxBlock`]
+pub struct RingFrames<'b> {
+    _block: PhantomData<&'b [u8]>,
+    next: *const u8,
+    left: usize,
+}
+
+impl<'b> Iterator for RingFrames<'b> {
+    type Item = RingFrame<'b>;
+
+    fn next(&mut self) -> Option<RingFrame<'b>> {
+        if self.left == 0 {
+            return None;
+        }
+        self.left -= 1;
+
+        let hdr = unsafe { &*(self.next as *const tpacket3_hdr) };
+        let data = unsafe {
+            std::slice::from_raw_parts(self.next.add(hdr.tp_mac as usize), hdr.tp_snaplen as usize)
+        };
+        let source = if hdr.tp_status & sys::TP_STATUS_TS_RAW_HARDWARE != 0 {
+            TimestampSource::HwRaw
+        } else if hdr.tp_status & sys::TP_STATUS_TS_SYS_HARDWARE != 0 {
+            TimestampSource::HwLegacy
+        } else {
+            // Without a flag the kernel took the time when it copied the frame to the ring
+            TimestampSource::Sw
+        };
+        let frame = RingFrame {
+            data,
+            len: hdr.tp_len as usize,
+            timestamp: RxTimestamp {
+                time: time::Timespec {
+                    tv_sec: hdr.tp_sec as i64,
+                    tv_nsec: hdr.tp_nsec as i64,
+                },
+                source,
+            },
+        };
+        self.next = unsafe { self.next.add(hdr.tp_next_offset as usize) };
+        Some(frame)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.left, Some(self.left))
+    }
+}
+
+/// Frame in the RX ring
+#[derive(Debug, Clone, Copy)]
+pub struct RingFrame<'b> {
+    /// Frame starting at the Ethernet header
+    pub data: &'b [u8],
+    /// Length on the wire, larger than `data.len()` if the frame was truncated
+    pub len: usize,
+    pub timestamp: RxTimestamp,
+}
+
+/// Free TX ring slot. Nothing is sent unless [`commit`](TxSlot::commit) is called
+pub struct TxSlot<'a> {
+    ring: &'a mut Ring,
+    hdr: *mut tpacket3_hdr,
+}
+
+impl<'a> TxSlot<'a> {
+    /// Buffer to write the frame into, starting at the Ethernet header
+    pub fn buf(&mut self) -> &mut [u8] {
+        unsafe {
+            std::slice::from_raw_parts_mut(
+                (self.hdr as *mut u8).add(TX_DATA_OFFSET),
+                self.ring.config.frame_size as usize - TX_DATA_OFFSET,
+            )
+        }
+    }
+
+    /// Queue the first `len` bytes of the buffer. They go out on the next [`tx_flush`]
+    pub fn commit(self, len: usize) -> Result<(), TsnError> {
+        if len > self.ring.config.frame_size as usize - TX_DATA_OFFSET {
+            return Err(TsnError::Socket {
+                op: "Send",
+                source: io::Error::from_raw_os_error(libc::EMSGSIZE),
+            });
+        }
+        unsafe {
+            (*self.hdr).tp_len = len as u32;
+            (*self.hdr).tp_snaplen = len as u32;
+            (*self.hdr).tp_next_offset = 0;
+            fence(Ordering::Release);
+            ptr::write_volatile(&mut (*self.hdr).tp_status, sys::TP_STATUS_SEND_REQUEST);
+        }
+        let frames = self.ring.config.tx_blocks * self.ring.config.frames_per_block();
+        self.ring.tx_next = (self.ring.tx_next + 1) % frames;
+        self.ring.tx_pending += 1;
+        Ok(())
+    }
+}
+
+fn ring_mut<'a>(sock: &'a mut TsnSocket, op: &str) -> Result<&'a mut Ring, TsnError> {
+    sock.ring
+        .as_mut()
+        .ok_or_else(|| TsnError::Unsupported(format!("{} needs a socket opened with a ring", op)))
+}
+
+/// Wait for the next filled RX block. `None` waits forever
+pub fn next_rx_block(
+    sock: &mut TsnSocket,
+    timeout: Option<Duration>,
+) -> Result<RxBlock<'_>, TsnError> {
+    let fd = sock.fd;
+    let ring = ring_mut(sock, "next_rx_block")?;
+    if !ring.has_rx() {
+        return Err(TsnError::Unsupported("Socket has no RX ring".into()));
+    }
+
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    while !ring.rx_ready() {
+        let timeout_ms = match deadline {
+            Some(deadline) => {
+                let left = deadline.saturating_duration_since(Instant::now());
+                if left.is_zero() {
+                    return Err(TsnError::Socket {
+                        op: "Recv",
+                        source: io::Error::from_raw_os_error(libc::EAGAIN),
+                    });
+                }
+                left.as_millis().max(1) as libc::c_int
+            }
+            None => -1,
+        };
+        let mut pfd = libc::pollfd {
+            fd,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        if unsafe { libc::poll(&mut pfd, 1, timeout_ms) } < 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() != io::ErrorKind::Interrupted {
+                return Err(TsnError::Socket {
+                    op: "Poll",
+                    source: err,
+                });
+            }
+        }
+    }
+
+    let desc = ring.rx_block(ring.rx_next);
+    Ok(RxBlock { ring, desc })
+}
+
+/// Next free TX slot, or `None` while the ring is full of unsent frames
+pub fn tx_slot(sock: &mut TsnSocket) -> Result<Option<TxSlot<'_>>, TsnError> {
+    let ring = ring_mut(sock, "tx_slot")?;
+    if !ring.has_tx() {
+        return Err(TsnError::Unsupported("Socket has no TX ring".into()));
+    }
+
+    let hdr = ring.tx_frame(ring.tx_next);
+    let status = unsafe { ptr::read_volatile(&(*hdr).tp_status) };
+    fence(Ordering::Acquire);
+    if status != sys::TP_STATUS_AVAILABLE {
+        return Ok(None);
+    }
+    Ok(Some(TxSlot { ring, hdr }))
+}
+
+/// Send all committed TX slots. Returns the number of frames handed to the kernel
+pub fn tx_flush(sock: &mut TsnSocket) -> Result<usize, TsnError> {
+    let fd = sock.fd;
+    let pending = ring_mut(sock, "tx_flush")?.tx_pending;
+    if pending == 0 {
+        return Ok(0);
+    }
+
+    let res = unsafe { libc::send(fd, ptr::null(), 0, 0) };
+    if res < 0 {
+        return Err(TsnError::socket("Send"));
+    }
+    if let Some(ring) = sock.ring.as_mut() {
+        ring.tx_pending = 0;
+    }
+    sock.sent(pending);
+    Ok(pending as usize)
+}
@@ -0,0 +1,479 @@
+//! Kernel constants and structures which are not (yet) exported by the `libc` crate
+
+/// `SO_EE_ORIGIN_TXTIME` from `linux/errqueue.h`
+pub const SO_EE_ORIGIN_TXTIME: u8 = 6;
+/// `SO_EE_CODE_TXTIME_INVALID_PARAM` from `linux/errqueue.h`
+pub const SO_EE_CODE_TXTIME_INVALID_PARAM: u8 = 1;
+/// `SO_EE_CODE_TXTIME_MISSED` from `linux/errqueue.h`
+pub const SO_EE_CODE_TXTIME_MISSED: u8 = 2;
+
+/// `PACKET_TX_TIMESTAMP` from `linux/if_packet.h`, cmsg type of error queue messages
+pub const PACKET_TX_TIMESTAMP: libc::c_int = 16;
+
+/// `PACKET_RX_RING` from `linux/if_packet.h`
+pub const PACKET_RX_RING: libc::c_int = 5;
+/// `PACKET_VERSION` from `linux/if_packet.h`
+pub const PACKET_VERSION: libc::c_int = 10;
+/// `PACKET_TX_RING` from `linux/if_packet.h`
+pub const PACKET_TX_RING: libc::c_int = 13;
+/// `PACKET_LOSS` from `linux/if_packet.h`, skip malformed TX ring frames instead of stalling
+pub const PACKET_LOSS: libc::c_int = 14;
+/// `PACKET_TIMESTAMP` from `linux/if_packet.h`, timestamp source of ring frames
+pub const PACKET_TIMESTAMP: libc::c_int = 17;
+
+/// `TPACKET_V3` of `enum tpacket_versions`
+pub const TPACKET_V3: libc::c_int = 2;
+/// `TPACKET_ALIGNMENT` from `linux/if_packet.h`
+pub const TPACKET_ALIGNMENT: usize = 16;
+
+// tp_status / block_status bits from `linux/if_packet.h`
+pub const TP_STATUS_KERNEL: u32 = 0;
+pub const TP_STATUS_USER: u32 = 1 << 0;
+pub const TP_STATUS_TS_SYS_HARDWARE: u32 = 1 << 30;
+pub const TP_STATUS_TS_RAW_HARDWARE: u32 = 1 << 31;
+pub const TP_STATUS_AVAILABLE: u32 = 0;
+pub const TP_STATUS_SEND_REQUEST: u32 = 1 << 0;
+
+/// `struct tpacket_req3` from `linux/if_packet.h`
+#[allow(non_camel_case_types)]
+#[repr(C)]
+pub struct tpacket_req3 {
+    pub tp_block_size: libc::c_uint,
+    pub tp_block_nr: libc::c_uint,
+    pub tp_frame_size: libc::c_uint,
+    pub tp_frame_nr: libc::c_uint,
+    pub tp_retire_blk_tov: libc::c_uint,
+    pub tp_sizeof_priv: libc::c_uint,
+    pub tp_feature_req_word: libc::c_uint,
+}
+
+/// `struct tpacket_bd_ts` from `linux/if_packet.h`
+#[allow(non_camel_case_types)]
+#[repr(C)]
+pub struct tpacket_bd_ts {
+    pub ts_sec: u32,
+    pub ts_nsec: u32,
+}
+
+/// `struct tpacket_hdr_v1` from `linux/if_packet.h`
+#[allow(non_camel_case_types)]
+#[repr(C)]
+pub struct tpacket_hdr_v1 {
+    pub block_status: u32,
+    pub num_pkts: u32,
+    pub offset_to_first_pkt: u32,
+    pub blk_len: u32,
+    pub seq_num: u64,
+    pub ts_first_pkt: tpacket_bd_ts,
+    pub ts_last_pkt: tpacket_bd_ts,
+}
+
+/// `struct tpacket_block_desc` from `linux/if_packet.h`, header of a TPACKET_V3 RX block
+#[allow(non_camel_case_types)]
+#[repr(C)]
+pub struct tpacket_block_desc {
+    pub version: u32,
+    pub offset_to_priv: u32,
+    pub hdr: tpacket_hdr_v1,
+}
+
+/// `struct tpacket_hdr_variant1` from `linux/if_packet.h`
+#[allow(non_camel_case_types)]
+#[repr(C)]
+pub struct tpacket_hdr_variant1 {
+    pub tp_rxhash: u32,
+    pub tp_vlan_tci: u32,
+    pub tp_vlan_tpid: u16,
+    pub tp_padding: u16,
+}
+
+/// `struct tpacket3_hdr` from `linux/if_packet.h`, header of a TPACKET_V3 frame
+#[allow(non_camel_case_types)]
+#[repr(C)]
+pub struct tpacket3_hdr {
+    pub tp_next_offset: u32,
+    pub tp_sec: u32,
+    pub tp_nsec: u32,
+    pub tp_snaplen: u32,
+    pub tp_len: u32,
+    pub tp_status: u32,
+    pub tp_mac: u16,
+    pub tp_net: u16,
+    pub hv1: tpacket_hdr_variant1,
+    pub tp_padding: [u8; 8],
+}
+
+/// rtnetlink definitions from `linux/netlink.h`, `linux/rtnetlink.h`, `linux/if_link.h`
+/// and `linux/pkt_sched.h`
+#[allow(non_camel_case_types)]
+pub mod rtnl {
+    // nlmsg_flags of NLMSG_ERROR
+    pub const NLM_F_CAPPED: u16 = 0x100;
+    pub const NLM_F_ACK_TLVS: u16 = 0x200;
+    /// `NLMSGERR_ATTR_MSG` of `enum nlmsgerr_attrs`, extended ACK message
+    pub const NLMSGERR_ATTR_MSG: u16 = 1;
+
+    // IFLA_VLAN_* of IFLA_INFO_DATA of a vlan link
+    pub const IFLA_VLAN_ID: u16 = 1;
+    pub const IFLA_VLAN_EGRESS_QOS: u16 = 3;
+    pub const IFLA_VLAN_QOS_MAPPING: u16 = 1;
+
+    pub const TC_H_ROOT: u32 = 0xffff_ffff;
+    pub const TC_QOPT_MAX_QUEUE: usize = 16;
+
+    // TCA_OPTIONS of taprio
+    pub const TCA_TAPRIO_ATTR_PRIOMAP: u16 = 1;
+    pub const TCA_TAPRIO_ATTR_SCHED_ENTRY_LIST: u16 = 2;
+    pub const TCA_TAPRIO_ATTR_SCHED_BASE_TIME: u16 = 3;
+    pub const TCA_TAPRIO_ATTR_SCHED_CLOCKID: u16 = 5;
+    pub const TCA_TAPRIO_ATTR_FLAGS: u16 = 10;
+    pub const TCA_TAPRIO_ATTR_TXTIME_DELAY: u16 = 11;
+    pub const TCA_TAPRIO_ATTR_FLAG_TXTIME_ASSIST: u32 = 1 << 0;
+    pub const TCA_TAPRIO_ATTR_FLAG_FULL_OFFLOAD: u32 = 1 << 1;
+    pub const TCA_TAPRIO_SCHED_ENTRY: u16 = 1;
+    pub const TCA_TAPRIO_SCHED_ENTRY_CMD: u16 = 2;
+    pub const TCA_TAPRIO_SCHED_ENTRY_GATE_MASK: u16 = 3;
+    pub const TCA_TAPRIO_SCHED_ENTRY_INTERVAL: u16 = 4;
+    pub const TC_TAPRIO_CMD_SET_GATES: u8 = 0;
+    pub const TC_TAPRIO_CMD_SET_AND_HOLD: u8 = 1;
+    pub const TC_TAPRIO_CMD_SET_AND_RELEASE: u8 = 2;
+
+    // TCA_OPTIONS of etf
+    pub const TCA_ETF_PARMS: u16 = 1;
+    pub const TC_ETF_DEADLINE_MODE_ON: u32 = 1 << 0;
+    pub const TC_ETF_OFFLOAD_ON: u32 = 1 << 1;
+    pub const TC_ETF_SKIP_SOCK_CHECK: u32 = 1 << 2;
+
+    // TCA_OPTIONS of cbs
+    pub const TCA_CBS_PARMS: u16 = 1;
+
+    /// `struct ifinfomsg` from `linux/rtnetlink.h`
+    #[repr(C)]
+    #[derive(Clone, Copy, Default)]
+    pub struct ifinfomsg {
+        pub ifi_family: u8,
+        pub __ifi_pad: u8,
+        pub ifi_type: u16,
+        pub ifi_index: i32,
+        pub ifi_flags: u32,
+        pub ifi_change: u32,
+    }
+
+    /// `struct tcmsg` from `linux/rtnetlink.h`
+    #[repr(C)]
+    #[derive(Clone, Copy, Default)]
+    pub struct tcmsg {
+        pub tcm_family: u8,
+        pub tcm_pad1: u8,
+        pub tcm_pad2: u16,
+        pub tcm_ifindex: i32,
+        pub tcm_handle: u32,
+        pub tcm_parent: u32,
+        pub tcm_info: u32,
+    }
+
+    /// `struct ifla_vlan_qos_mapping` from `linux/if_link.h`
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    pub struct ifla_vlan_qos_mapping {
+        pub from: u32,
+        pub to: u32,
+    }
+
+    /// `struct tc_mqprio_qopt` from `linux/pkt_sched.h`, also the priomap of taprio
+    #[repr(C)]
+    #[derive(Clone, Copy, Default)]
+    pub struct tc_mqprio_qopt {
+        pub num_tc: u8,
+        pub prio_tc_map: [u8; TC_QOPT_MAX_QUEUE],
+        pub hw: u8,
+        pub count: [u16; TC_QOPT_MAX_QUEUE],
+        pub offset: [u16; TC_QOPT_MAX_QUEUE],
+    }
+
+    /// `struct tc_etf_qopt` from `linux/pkt_sched.h`
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    pub struct tc_etf_qopt {
+        pub delta: i32,
+        pub clockid: i32,
+        pub flags: u32,
+    }
+
+    /// `struct tc_cbs_qopt` from `linux/pkt_sched.h`
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    pub struct tc_cbs_qopt {
+        pub offload: u8,
+        pub _pad: [u8; 3],
+        pub hicredit: i32,
+        pub locredit: i32,
+        pub idleslope: i32,
+        pub sendslope: i32,
+    }
+}
+
+/// AF_XDP and bpf(2) definitions from `linux/if_xdp.h` and `linux/bpf.h`, and the
+/// ethtool ones of RX queues and ntuple rules from `linux/ethtool.h`
+#[cfg(feature = "xdp")]
+#[allow(non_camel_case_types)]
+pub mod xdp {
+    pub const AF_XDP: libc::c_int = 44;
+    pub const SOL_XDP: libc::c_int = 283;
+
+    // setsockopt/getsockopt names of SOL_XDP
+    pub const XDP_MMAP_OFFSETS: libc::c_int = 1;
+    pub const XDP_RX_RING: libc::c_int = 2;
+    pub const XDP_TX_RING: libc::c_int = 3;
+    pub const XDP_UMEM_REG: libc::c_int = 4;
+    pub const XDP_UMEM_FILL_RING: libc::c_int = 5;
+    pub const XDP_UMEM_COMPLETION_RING: libc::c_int = 6;
+
+    // mmap offsets of the rings
+    pub const XDP_PGOFF_RX_RING: libc::off_t = 0;
+    pub const XDP_PGOFF_TX_RING: libc::off_t = 0x80000000;
+    pub const XDP_UMEM_PGOFF_FILL_RING: libc::off_t = 0x100000000;
+    pub const XDP_UMEM_PGOFF_COMPLETION_RING: libc::off_t = 0x180000000;
+
+    // sxdp_flags of bind
+    pub const XDP_COPY: u16 = 1 << 1;
+    pub const XDP_USE_NEED_WAKEUP: u16 = 1 << 3;
+
+    #[repr(C)]
+    pub struct xdp_umem_reg {
+        pub addr: u64,
+        pub len: u64,
+        pub chunk_size: u32,
+        pub headroom: u32,
+        pub flags: u32,
+        pub tx_metadata_len: u32,
+    }
+
+    #[repr(C)]
+    #[derive(Default)]
+    pub struct xdp_ring_offset {
+        pub producer: u64,
+        pub consumer: u64,
+        pub desc: u64,
+        pub flags: u64,
+    }
+
+    #[repr(C)]
+    #[derive(Default)]
+    pub struct xdp_mmap_offsets {
+        pub rx: xdp_ring_offset,
+        pub tx: xdp_ring_offset,
+        pub fr: xdp_ring_offset,
+        pub cr: xdp_ring_offset,
+    }
+
+    #[repr(C)]
+    pub struct sockaddr_xdp {
+        pub sxdp_family: u16,
+        pub sxdp_flags: u16,
+        pub sxdp_ifindex: u32,
+        pub sxdp_queue_id: u32,
+        pub sxdp_shared_umem_fd: u32,
+    }
+
+    #[repr(C)]
+    #[derive(Clone, Copy, Default)]
+    pub struct xdp_desc {
+        pub addr: u64,
+        pub len: u32,
+        pub options: u32,
+    }
+
+    // bpf(2) commands
+    pub const BPF_MAP_CREATE: libc::c_int = 0;
+    pub const BPF_MAP_UPDATE_ELEM: libc::c_int = 2;
+    pub const BPF_PROG_LOAD: libc::c_int = 5;
+    pub const BPF_LINK_CREATE: libc::c_int = 28;
+
+    pub const BPF_MAP_TYPE_XSKMAP: u32 = 17;
+    pub const BPF_PROG_TYPE_XDP: u32 = 6;
+    /// `BPF_XDP` of `enum bpf_attach_type`
+    pub const BPF_XDP: u32 = 37;
+    pub const XDP_FLAGS_SKB_MODE: u32 = 1 << 1;
+    pub const XDP_FLAGS_DRV_MODE: u32 = 1 << 2;
+    /// `XDP_PASS` of `enum xdp_action`
+    pub const XDP_PASS: i32 = 2;
+    /// `BPF_FUNC_redirect_map` of `enum bpf_func_id`
+    pub const BPF_FUNC_REDIRECT_MAP: i32 = 51;
+
+    /// `bpf_attr` of `BPF_MAP_CREATE`
+    #[repr(C)]
+    pub struct bpf_map_create_attr {
+        pub map_type: u32,
+        pub key_size: u32,
+        pub value_size: u32,
+        pub max_entries: u32,
+        pub map_flags: u32,
+    }
+
+    /// `bpf_attr` of `BPF_MAP_UPDATE_ELEM`
+    #[repr(C)]
+    pub struct bpf_map_elem_attr {
+        pub map_fd: u32,
+        pub key: u64,
+        pub value: u64,
+        pub flags: u64,
+    }
+
+    /// `bpf_attr` of `BPF_PROG_LOAD`
+    #[repr(C)]
+    pub struct bpf_prog_load_attr {
+        pub prog_type: u32,
+        pub insn_cnt: u32,
+        pub insns: u64,
+        pub license: u64,
+        pub log_level: u32,
+        pub log_size: u32,
+        pub log_buf: u64,
+        pub kern_version: u32,
+        pub prog_flags: u32,
+        pub prog_name: [u8; 16],
+        pub prog_ifindex: u32,
+        pub expected_attach_type: u32,
+    }
+
+    /// `bpf_attr` of `BPF_LINK_CREATE`
+    #[repr(C)]
+    pub struct bpf_link_create_attr {
+        pub prog_fd: u32,
+        pub target_ifindex: u32,
+        pub attach_type: u32,
+        pub flags: u32,
+    }
+
+    /// `struct bpf_insn`
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    pub struct bpf_insn {
+        pub code: u8,
+        /// dst_reg in the low nibble, src_reg in the high nibble
+        pub regs: u8,
+        pub off: i16,
+        pub imm: i32,
+    }
+
+    pub const ETHTOOL_GCHANNELS: u32 = 0x3c;
+
+    /// `struct ethtool_channels`
+    #[repr(C)]
+    #[derive(Clone, Copy, Default)]
+    pub struct ethtool_channels {
+        pub cmd: u32,
+        pub max_rx: u32,
+        pub max_tx: u32,
+        pub max_other: u32,
+        pub max_combined: u32,
+        pub rx_count: u32,
+        pub tx_count: u32,
+        pub other_count: u32,
+        pub combined_count: u32,
+    }
+
+    pub const ETHTOOL_GFLAGS: u32 = 0x25;
+    /// Flag of `ETHTOOL_GFLAGS`, the NIC strips the VLAN tag of received frames
+    pub const ETH_FLAG_RXVLAN: u32 = 1 << 8;
+
+    /// `struct ethtool_value`
+    #[repr(C)]
+    #[derive(Clone, Copy, Default)]
+    pub struct ethtool_value {
+        pub cmd: u32,
+        pub data: u32,
+    }
+
+    // ntuple rules of the RX classifier
+    pub const ETHTOOL_GRXCLSRLCNT: u32 = 0x2e;
+    pub const ETHTOOL_GRXCLSRULE: u32 = 0x2f;
+    pub const ETHTOOL_GRXCLSRLALL: u32 = 0x30;
+    pub const ETHER_FLOW: u32 = 0x12;
+    pub const FLOW_EXT: u32 = 0x80000000;
+    pub const ETHTOOL_RX_FLOW_SPEC_RING: u64 = 0xffffffff;
+
+    /// `struct ethtool_flow_ext`, big endian
+    #[repr(C)]
+    #[derive(Clone, Copy, Default)]
+    pub struct ethtool_flow_ext {
+        pub padding: [u8; 2],
+        pub h_dest: [u8; 6],
+        pub vlan_etype: u16,
+        pub vlan_tci: u16,
+        pub data: [u32; 2],
+    }
+
+    /// `struct ethtool_rx_flow_spec`. `h_u` and `m_u` start with a `struct ethhdr`
+    /// for `ETHER_FLOW`, set bits of the masks are matched
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    pub struct ethtool_rx_flow_spec {
+        pub flow_type: u32,
+        pub h_u: [u8; 52],
+        pub h_ext: ethtool_flow_ext,
+        pub m_u: [u8; 52],
+        pub m_ext: ethtool_flow_ext,
+        pub ring_cookie: u64,
+        pub location: u32,
+    }
+
+    /// `struct ethtool_rxnfc`, followed by `rule_cnt` locations for
+    /// `ETHTOOL_GRXCLSRLALL`
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    pub struct ethtool_rxnfc {
+        pub cmd: u32,
+        pub flow_type: u32,
+        pub data: u64,
+        pub fs: ethtool_rx_flow_spec,
+        pub rule_cnt: u32,
+    }
+}
+
+/// ethtool definitions from `linux/sockios.h`, `linux/ethtool.h` and
+/// `linux/ethtool_netlink.h`
+#[allow(non_camel_case_types)]
+pub mod ethtool {
+    pub const SIOCETHTOOL: libc::c_ulong = 0x8946;
+    pub const ETHTOOL_GET_TS_INFO: u32 = 0x41;
+
+    /// `struct ethtool_ts_info`
+    #[repr(C)]
+    #[derive(Clone, Copy, Default)]
+    pub struct ethtool_ts_info {
+        pub cmd: u32,
+        pub so_timestamping: u32,
+        /// -1 if the interface has no PHC
+        pub phc_index: i32,
+        pub tx_types: u32,
+        pub tx_reserved: [u32; 3],
+        pub rx_filters: u32,
+        pub rx_reserved: [u32; 3],
+    }
+
+    // Generic netlink family "ethtool", from `linux/ethtool_netlink.h`
+    pub const ETHTOOL_GENL_NAME: &str = "ethtool";
+    pub const ETHTOOL_GENL_VERSION: u8 = 1;
+    pub const ETHTOOL_MSG_FEATURES_GET: u8 = 11;
+    pub const ETHTOOL_MSG_MM_GET: u8 = 42;
+
+    pub const ETHTOOL_A_HEADER_DEV_NAME: u16 = 2;
+
+    // ETHTOOL_MSG_FEATURES_GET_REPLY and its bitsets. A bitset with NOMASK lists the
+    // set bits only, otherwise set bits have the VALUE flag
+    pub const ETHTOOL_A_FEATURES_HEADER: u16 = 1;
+    pub const ETHTOOL_A_FEATURES_HW: u16 = 2;
+    pub const ETHTOOL_A_FEATURES_ACTIVE: u16 = 4;
+    pub const ETHTOOL_A_BITSET_NOMASK: u16 = 1;
+    pub const ETHTOOL_A_BITSET_BITS: u16 = 3;
+    pub const ETHTOOL_A_BITSET_BITS_BIT: u16 = 1;
+    pub const ETHTOOL_A_BITSET_BIT_NAME: u16 = 2;
+    pub const ETHTOOL_A_BITSET_BIT_VALUE: u16 = 3;
+
+    // ETHTOOL_MSG_MM_GET_REPLY, MAC merge layer of frame preemption (802.3br)
+    pub const ETHTOOL_A_MM_HEADER: u16 = 1;
+    pub const ETHTOOL_A_MM_PMAC_ENABLED: u16 = 2;
+    pub const ETHTOOL_A_MM_TX_ENABLED: u16 = 3;
+}
@@ -1,8 +1,10 @@
+use crate::TsnError;
 use serde_yaml::{self, Value};
 use std::collections::HashMap;
 
 #[derive(Clone)]
 pub struct TasConfig {
+    pub mode: TasMode,
     pub txtime_delay: i64,
     #[allow(dead_code)] // Leave this field since it might be used for debug
     pub schedule: Vec<TasSchedule>,
@@ -11,19 +13,62 @@ pub struct TasConfig {
     pub queues: Vec<String>,
     pub base_time: i64,
     pub sched_entries: Vec<String>,
+    pub etf: Vec<EtfConfig>,
+}
+/// How taprio runs the schedule, the `mode` key of `tas`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TasMode {
+    /// Full offload if the NIC has TC offload, else software. Txtime assist if
+    /// `txtime_delay` is set
+    #[default]
+    Auto,
+    /// The NIC runs the gates, `flags 0x2`
+    FullOffload,
+    /// The kernel sets the txtime of each frame for an ETF child to send, `flags 0x1`
+    TxtimeAssist,
+    /// The kernel runs the gates
+    Software,
+}
+
+impl TasMode {
+    pub fn name(&self) -> &'static str {
+        match self {
+            TasMode::Auto => "auto",
+            TasMode::FullOffload => "full-offload",
+            TasMode::TxtimeAssist => "txtime-assist",
+            TasMode::Software => "software",
+        }
+    }
+}
+
+/// ETF child qdisc for the traffic class of `prio`, needed by `TsnSocket::send_at`
+#[derive(Debug, Clone)]
+pub struct EtfConfig {
+    pub prio: i64,
+    pub delta: i64,
+    /// `None` to offload if the NIC has TC offload
+    pub offload: Option<bool>,
+    pub deadline_mode: bool,
+    pub skip_sock_check: bool,
 }
 #[derive(Debug, Clone)]
 pub struct TasSchedule {
     pub time: i64,
     pub prio: Vec<i64>,
 }
-pub fn to_ns(input: &Value) -> Result<i64, String> {
+pub fn to_ns(input: &Value) -> Result<i64, TsnError> {
     if let Some(value) = input.as_str() {
         let matched = regex::Regex::new(r"^(?P<v>[\d_]+)\s*(?P<unit>|ns|us|µs|ms)$")
             .unwrap()
             .captures(value)
-            .unwrap();
-        let v = matched.name("v").unwrap().as_str().parse::<i64>().unwrap();
+            .ok_or_else(|| TsnError::ConfigInvalid(format!("{} is not valid time", value)))?;
+        let v = matched
+            .name("v")
+            .unwrap()
+            .as_str()
+            .replace('_', "")
+            .parse::<i64>()
+            .map_err(|e| TsnError::ConfigInvalid(format!("{} is not valid time: {}", value, e)))?;
         let unit = matched.name("unit").unwrap().as_str();
         return {
             match unit {
@@ -36,36 +81,94 @@ pub fn to_ns(input: &Value) -> Result<i64, String> {
             }
         };
     }
-    Ok(input.as_i64().expect("Cannot convert input to i64"))
+    input
+        .as_i64()
+        .ok_or_else(|| TsnError::ConfigInvalid(format!("Cannot convert {:?} to time", input)))
 }
-pub fn normalise_tas(config: &Value) -> Result<TasConfig, String> {
+fn to_bool(config: &Value, key: &str, default: bool) -> Result<bool, TsnError> {
+    Ok(to_opt_bool(config, key)?.unwrap_or(default))
+}
+pub(crate) fn to_opt_bool(config: &Value, key: &str) -> Result<Option<bool>, TsnError> {
+    config
+        .get(key)
+        .map(|val| {
+            val.as_bool()
+                .ok_or_else(|| TsnError::ConfigInvalid(format!("{} should be a boolean", key)))
+        })
+        .transpose()
+}
+fn to_mode(config: &Value) -> Result<TasMode, TsnError> {
+    let Some(mode) = config.get("mode") else {
+        return Ok(TasMode::Auto);
+    };
+    let modes = [
+        TasMode::Auto,
+        TasMode::FullOffload,
+        TasMode::TxtimeAssist,
+        TasMode::Software,
+    ];
+    mode.as_str()
+        .and_then(|name| modes.into_iter().find(|mode| mode.name() == name))
+        .ok_or_else(|| {
+            TsnError::ConfigInvalid(format!(
+                "tas mode {:?} is not one of auto, full-offload, txtime-assist, software",
+                mode
+            ))
+        })
+}
+pub fn normalise_etf(config: &Value, tc_map: &HashMap<i64, i64>) -> Result<EtfConfig, TsnError> {
+    let prio = config
+        .get("prio")
+        .and_then(|prio| prio.as_i64())
+        .ok_or_else(|| TsnError::ConfigInvalid("etf should have an integer prio".into()))?;
+    if !(0..16).contains(&prio) || !tc_map.contains_key(&prio) {
+        return Err(TsnError::ConfigInvalid(format!(
+            "etf prio {} is not used in the schedule",
+            prio
+        )));
+    }
+    let delta = match config.get("delta") {
+        Some(val) => to_ns(val)?,
+        None => 0,
+    };
+    Ok(EtfConfig {
+        prio,
+        delta,
+        offload: to_opt_bool(config, "offload")?,
+        deadline_mode: to_bool(config, "deadline_mode", false)?,
+        skip_sock_check: to_bool(config, "skip_sock_check", false)?,
+    })
+}
+pub fn normalise_tas(config: &Value) -> Result<TasConfig, TsnError> {
     let mut tas_schedule: Vec<TasSchedule> = Vec::new();
     let mut tc_map: HashMap<i64, i64> = HashMap::new();
     let mut ret_map = HashMap::new();
     let schedules = config
-        .get(&Value::String("schedule".to_string()))
-        .expect("tas should have a schedule")
+        .get(Value::String("schedule".to_string()))
+        .ok_or_else(|| TsnError::ConfigInvalid("tas should have a schedule".into()))?
         .as_sequence()
-        .expect("schedule should be a list");
+        .ok_or_else(|| TsnError::ConfigInvalid("schedule should be a list".into()))?;
     for schedule in schedules {
         let mut v = Vec::new();
 
         for prio in schedule
-            .get(&Value::String("prio".to_string()))
-            .expect("schedule should have a prio")
+            .get(Value::String("prio".to_string()))
+            .ok_or_else(|| TsnError::ConfigInvalid("schedule should have a prio".into()))?
             .as_sequence()
-            .expect("prio should be a list")
+            .ok_or_else(|| TsnError::ConfigInvalid("prio should be a list".into()))?
         {
-            let prio = prio.as_i64().expect("prio should be an integer");
-            v.push(prio.clone());
+            let prio = prio
+                .as_i64()
+                .ok_or_else(|| TsnError::ConfigInvalid("prio should be an integer".into()))?;
+            v.push(prio);
             if prio >= 0 && !tc_map.contains_key(&prio) {
-                tc_map.insert(prio.clone(), tc_map.len() as i64);
+                tc_map.insert(prio, tc_map.len() as i64);
             }
         }
         let time = to_ns(
             schedule
-                .get(&Value::String("time".to_string()))
-                .expect("schedule must have 'time'"),
+                .get(Value::String("time".to_string()))
+                .ok_or_else(|| TsnError::ConfigInvalid("schedule must have 'time'".into()))?,
         )?;
         tas_schedule.push(TasSchedule { time, prio: v });
     }
@@ -94,11 +197,28 @@ pub fn normalise_tas(config: &Value) -> Result<TasConfig, String> {
         }
         sched_entries.push(format!("S 0x{:x} {}", sum, sch.time));
     }
-    let txtime_delay = match config.get(&Value::String("txtime_delay".to_string())) {
-        Some(val) => to_ns(val).unwrap_or(0),
+    let txtime_delay = match config.get(Value::String("txtime_delay".to_string())) {
+        Some(val) => to_ns(val)?,
         None => 0,
     };
+    let mode = to_mode(config)?;
+    if txtime_delay != 0 && !matches!(mode, TasMode::Auto | TasMode::TxtimeAssist) {
+        return Err(TsnError::ConfigInvalid(format!(
+            "txtime_delay needs tas mode txtime-assist, not {}",
+            mode.name()
+        )));
+    }
+    let mut etf = Vec::new();
+    if let Some(entries) = config.get(Value::String("etf".to_string())) {
+        for entry in entries
+            .as_sequence()
+            .ok_or_else(|| TsnError::ConfigInvalid("etf should be a list".into()))?
+        {
+            etf.push(normalise_etf(entry, &tc_map)?);
+        }
+    }
     Ok(TasConfig {
+        mode,
         txtime_delay,
         schedule: tas_schedule,
         tc_map: ret_map,
@@ -106,5 +226,6 @@ pub fn normalise_tas(config: &Value) -> Result<TasConfig, String> {
         queues,
         base_time: 0,
         sched_entries,
+        etf,
     })
 }
@@ -1,14 +1,207 @@
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+//! Clocks to read and sleep on, and the errors of doing so
+//!
+//! taprio and ETF schedules run on CLOCK_TAI and hardware timestamps on the PHC, so a
+//! transmit loop aligned to the gates sleeps on the same clock with [`sleep_until`].
+//! UTC times convert to TAI with [`utc_to_tai`], which counts a leap second the kernel
+//! has been told about.
+//!
+//! ```no_run
+//! use tsn::time::{self, Clock, Timespec};
+//!
+//! let cycle = 1_000_000;
+//! let now = Clock::Tai.now()?.as_nanos();
+//! time::sleep_until(Clock::Tai, Timespec::from_nanos((now / cycle + 1) * cycle))?;
+//! # Ok::<(), tsn::TsnError>(())
+//! ```
+
+use std::io;
+use std::mem;
+use std::os::fd::RawFd;
+use std::time::{Duration, SystemTime};
+
+use crate::TsnError;
 
 static mut ERROR_CLOCK_GETTIME: Duration = Duration::new(1, 0);
 static mut ERROR_NANOSLEEP: Duration = Duration::new(1, 0);
 
-#[derive(Debug)]
+const NSEC_PER_SEC: i64 = 1_000_000_000;
+const SEC_PER_DAY: i64 = 86_400;
+/// `time_state` of `adjtimex` with a leap second pending for the coming midnight
+const TIME_INS: i32 = 1;
+const TIME_DEL: i32 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Timespec {
     pub tv_sec: i64,
     pub tv_nsec: i64,
 }
 
+impl Timespec {
+    pub fn now(clockid: libc::clockid_t) -> Timespec {
+        let mut ts = libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+        unsafe { libc::clock_gettime(clockid, &mut ts) };
+        Timespec {
+            tv_sec: ts.tv_sec,
+            tv_nsec: ts.tv_nsec,
+        }
+    }
+
+    /// Nanoseconds since the epoch of its clock
+    pub fn as_nanos(&self) -> i64 {
+        self.tv_sec * NSEC_PER_SEC + self.tv_nsec
+    }
+
+    pub fn from_nanos(ns: i64) -> Timespec {
+        Timespec {
+            tv_sec: ns.div_euclid(NSEC_PER_SEC),
+            tv_nsec: ns.rem_euclid(NSEC_PER_SEC),
+        }
+    }
+}
+
+/// Clock to read and sleep on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Clock {
+    /// UTC, steps back by a second when a leap second is inserted
+    Realtime,
+    Monotonic,
+    /// UTC plus the offset set by ptp4l or phc2sys, the clock of taprio and ETF
+    Tai,
+    /// PHC of a NIC, by the fd of its `/dev/ptpN`, e.g. [`Phc::clock`](crate::phc::Phc::clock)
+    Phc(RawFd),
+}
+
+impl Clock {
+    /// Clock of a clock ID, `FD_TO_CLOCKID` of a PHC fd included. `None` for the
+    /// other clocks of the kernel
+    pub fn from_clockid(clockid: libc::clockid_t) -> Option<Clock> {
+        match clockid {
+            libc::CLOCK_REALTIME => Some(Clock::Realtime),
+            libc::CLOCK_MONOTONIC => Some(Clock::Monotonic),
+            libc::CLOCK_TAI => Some(Clock::Tai),
+            // Dynamic clock IDs are negative, CLOCK_THREAD_CPUTIME_ID is 3 as well
+            id if id < 0 && id & 7 == 3 => Some(Clock::Phc(!(id >> 3))),
+            _ => None,
+        }
+    }
+
+    pub fn clockid(&self) -> libc::clockid_t {
+        match self {
+            Clock::Realtime => libc::CLOCK_REALTIME,
+            Clock::Monotonic => libc::CLOCK_MONOTONIC,
+            Clock::Tai => libc::CLOCK_TAI,
+            Clock::Phc(fd) => ((!fd) << 3) | 3,
+        }
+    }
+
+    pub fn now(&self) -> Result<Timespec, TsnError> {
+        clock_gettime(self.clockid())
+    }
+}
+
+pub(crate) fn clock_gettime(clockid: libc::clockid_t) -> Result<Timespec, TsnError> {
+    let mut ts = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    if unsafe { libc::clock_gettime(clockid, &mut ts) } < 0 {
+        return Err(TsnError::clock("Read clock"));
+    }
+    Ok(Timespec {
+        tv_sec: ts.tv_sec,
+        tv_nsec: ts.tv_nsec,
+    })
+}
+
+/// CLOCK_TAI minus CLOCK_REALTIME in ns, the UTC offset the kernel was told about
+pub fn tai_offset() -> Result<i64, TsnError> {
+    let mut tx: libc::timex = unsafe { mem::zeroed() };
+    if unsafe { libc::adjtimex(&mut tx) } < 0 {
+        return Err(TsnError::clock("Read TAI offset"));
+    }
+    Ok(tx.tai as i64 * NSEC_PER_SEC)
+}
+
+/// TAI minus UTC in ns at the UTC time `utc`, counting the leap second the kernel
+/// has pending for the coming midnight
+pub fn tai_offset_at(utc: Timespec) -> Result<i64, TsnError> {
+    let mut tx: libc::timex = unsafe { mem::zeroed() };
+    let state = unsafe { libc::adjtimex(&mut tx) };
+    if state < 0 {
+        return Err(TsnError::clock("Read TAI offset"));
+    }
+    // Leap seconds end a UTC day: 23:59:60 is inserted or 23:59:59 left out
+    let midnight = (tx.time.tv_sec.div_euclid(SEC_PER_DAY) + 1) * SEC_PER_DAY;
+    let leap = match state {
+        TIME_INS if utc.tv_sec >= midnight => 1,
+        TIME_DEL if utc.tv_sec >= midnight - 1 => -1,
+        _ => 0,
+    };
+    Ok((tx.tai as i64 + leap) * NSEC_PER_SEC)
+}
+
+/// TAI of the UTC time `utc`
+pub fn utc_to_tai(utc: Timespec) -> Result<Timespec, TsnError> {
+    Ok(Timespec::from_nanos(utc.as_nanos() + tai_offset_at(utc)?))
+}
+
+/// UTC of the TAI time `tai`. An inserted leap second repeats the UTC second before
+pub fn tai_to_utc(tai: Timespec) -> Result<Timespec, TsnError> {
+    let utc = Timespec::from_nanos(tai.as_nanos() - tai_offset()?);
+    Ok(Timespec::from_nanos(tai.as_nanos() - tai_offset_at(utc)?))
+}
+
+/// Sleep until `deadline` of `clock`, spinning for the last part once
+/// [`tsn_time_analyze`] has measured the errors. Fails with `EINTR` if a signal
+/// interrupts the sleep
+pub fn sleep_until(clock: Clock, deadline: Timespec) -> Result<(), TsnError> {
+    let (error_clock_gettime, error_nanosleep) = if is_analysed() {
+        unsafe { (ERROR_CLOCK_GETTIME, ERROR_NANOSLEEP) }
+    } else {
+        (Duration::ZERO, Duration::ZERO)
+    };
+    let deadline = deadline.as_nanos();
+    let wake = deadline - error_nanosleep.as_nanos() as i64;
+    match clock {
+        // The kernel cannot sleep on a PHC. It runs at about the rate of the monotonic
+        // clock, which sleeps for what is left until the PHC has caught up
+        Clock::Phc(_) => loop {
+            let left = wake - clock.now()?.as_nanos();
+            if left <= 0 {
+                break;
+            }
+            nanosleep(libc::CLOCK_MONOTONIC, 0, Timespec::from_nanos(left))?;
+        },
+        _ => nanosleep(
+            clock.clockid(),
+            libc::TIMER_ABSTIME,
+            Timespec::from_nanos(wake),
+        )?,
+    }
+    while clock.now()?.as_nanos() + (error_clock_gettime.as_nanos() as i64) < deadline {
+        std::hint::spin_loop();
+    }
+    Ok(())
+}
+
+fn nanosleep(clockid: libc::clockid_t, flags: i32, ts: Timespec) -> Result<(), TsnError> {
+    let ts = libc::timespec {
+        tv_sec: ts.tv_sec,
+        tv_nsec: ts.tv_nsec,
+    };
+    // Returns the error instead of setting errno
+    match unsafe { libc::clock_nanosleep(clockid, flags, &ts, std::ptr::null_mut()) } {
+        0 => Ok(()),
+        errno => Err(TsnError::Clock {
+            op: "Sleep",
+            source: io::Error::from_raw_os_error(errno),
+        }),
+    }
+}
+
 fn is_analysed() -> bool {
     unsafe {
         // Direct access to mutable static variable causes warning
@@ -67,28 +260,14 @@ pub fn tsn_time_analyze() {
     }
 }
 
+/// Sleep until `endtime` since the UNIX epoch of CLOCK_REALTIME, see [`sleep_until`].
+/// Fails with the errno
 pub fn tsn_time_sleep_until(endtime: &Duration) -> Result<i64, i64> {
-    let now = SystemTime::now();
-
-    let now = now.duration_since(UNIX_EPOCH).unwrap();
-    // If already future, Don't need to sleep
-    if endtime.saturating_sub(now) == Duration::new(0, 0) {
-        return Ok(0);
-    }
-    let mut diff = endtime.saturating_sub(now);
-    if diff < unsafe { ERROR_NANOSLEEP } {
-        std::thread::sleep(diff);
-    }
-
-    unsafe {
-        loop {
-            let now = SystemTime::now();
-            let now = now.duration_since(UNIX_EPOCH).unwrap();
-            diff = endtime.saturating_sub(now);
-            if diff < ERROR_CLOCK_GETTIME {
-                break;
-            }
-        }
-    }
-    Ok(0)
+    let deadline = Timespec {
+        tv_sec: endtime.as_secs() as i64,
+        tv_nsec: endtime.subsec_nanos() as i64,
+    };
+    sleep_until(Clock::Realtime, deadline)
+        .map(|_| 0)
+        .map_err(|e| e.raw_os_error().unwrap_or(libc::EINVAL) as i64)
 }
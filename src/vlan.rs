@@ -1,25 +1,130 @@
-use crate::{cbs::CbsConfig, config::Config, tas::TasConfig};
+use crate::caps::{self, Capabilities};
+use crate::netlink::{Message, Netlink};
+use crate::sys::rtnl::*;
+use crate::tas::{TasConfig, TasMode};
+use crate::{cbs::CbsConfig, config::Config, TsnError};
 use itertools::Itertools;
-use std::io::Error;
+use nix::libc;
+use nix::net::if_::if_nametoindex;
+use std::collections::HashMap;
+use std::process::Stdio;
 
-fn run_cmd(input: &str) -> Result<i32, String> {
+/// Handle of the root qdisc set up for TAS and CBS, as given to tc
+const ROOT_HANDLE: i64 = 100;
+
+/// rtnetlink socket, or `None` to fall back to the `ip` and `tc` commands where
+/// netlink sockets cannot be opened
+fn netlink() -> Option<Netlink> {
+    Netlink::open().ok()
+}
+
+/// Runs the `ip` and `tc` command lines of the VLAN and qdisc setup.
+///
+/// The `*_with` functions take one, e.g. a [`RecordingExecutor`] to see what they would do.
+pub trait Executor {
+    fn run(&mut self, cmd: &str) -> Result<(), TsnError>;
+}
+
+/// Spawns the commands. What the functions without `_with` use when netlink is unavailable
+pub struct CommandExecutor;
+
+impl Executor for CommandExecutor {
+    fn run(&mut self, cmd: &str) -> Result<(), TsnError> {
+        run_cmd(cmd)
+    }
+}
+
+/// Keeps the commands instead of running them, for dry runs and tests
+#[derive(Debug, Default)]
+pub struct RecordingExecutor {
+    commands: Vec<String>,
+}
+
+impl RecordingExecutor {
+    pub fn new() -> RecordingExecutor {
+        RecordingExecutor::default()
+    }
+
+    /// Commands in the order they were given
+    pub fn commands(&self) -> &[String] {
+        &self.commands
+    }
+}
+
+impl Executor for RecordingExecutor {
+    fn run(&mut self, cmd: &str) -> Result<(), TsnError> {
+        self.commands.push(cmd.to_string());
+        Ok(())
+    }
+}
+
+fn run_cmd(input: &str) -> Result<(), TsnError> {
     eprintln!("{}", input);
-    let split = input.split(char::is_whitespace);
-    let cmd = split.clone().next().unwrap();
-    let cmd = std::process::Command::new(cmd)
-        .args(split.skip(1))
-        .spawn()
-        .unwrap();
-    let output = cmd.wait_with_output().unwrap();
+    let mut split = input.split_whitespace();
+    let cmd = split.next().unwrap();
+    let output = std::process::Command::new(cmd)
+        .args(split)
+        .stderr(Stdio::piped())
+        .output()
+        .map_err(|source| TsnError::CommandSpawn {
+            cmd: input.to_string(),
+            source,
+        })?;
     if output.status.success() {
-        Ok(0)
+        Ok(())
     } else {
-        Err(Error::from_raw_os_error(output.status.code().unwrap()).to_string())
+        Err(TsnError::CommandFailed {
+            cmd: input.to_string(),
+            status: output.status.code(),
+            stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+        })
     }
 }
 
-pub fn setup_tas(ifname: &str, config: &TasConfig) -> Result<i32, String> {
-    let handle = 100;
+/// Set up taprio, and the ETF children of `config`. Modes left to `auto` are chosen
+/// by the [`caps`] of `ifname`
+pub fn setup_tas(ifname: &str, config: &TasConfig) -> Result<(), TsnError> {
+    let caps = caps::probe_or_unknown(ifname);
+    match netlink() {
+        Some(nl) => setup_tas_rtnl(&nl, ifname, config, &caps),
+        None => setup_tas_cmds(&mut CommandExecutor, ifname, config, &caps),
+    }
+}
+
+pub fn setup_tas_with(
+    exec: &mut dyn Executor,
+    ifname: &str,
+    config: &TasConfig,
+) -> Result<(), TsnError> {
+    setup_tas_cmds(exec, ifname, config, &caps::probe_or_unknown(ifname))
+}
+
+/// taprio mode and the offload of each ETF child to set `config` up with
+fn tas_modes(config: &TasConfig, caps: &Capabilities) -> Result<(TasMode, Vec<bool>), TsnError> {
+    caps.check_queues(config.num_tc)?;
+    let mode = caps.tas_mode(config)?;
+    let etf_offload = config
+        .etf
+        .iter()
+        .map(|etf| caps.offload(etf.offload, "etf"))
+        .collect::<Result<_, _>>()?;
+    Ok((mode, etf_offload))
+}
+
+/// Whether to offload the cbs qdiscs of `config`
+fn cbs_offload(config: &CbsConfig, caps: &Capabilities) -> Result<bool, TsnError> {
+    caps.check_queues(config.num_tc)?;
+    caps.offload(config.offload, "cbs")
+}
+
+fn setup_tas_cmds(
+    exec: &mut dyn Executor,
+    ifname: &str,
+    config: &TasConfig,
+    caps: &Capabilities,
+) -> Result<(), TsnError> {
+    let (mode, etf_offload) = tas_modes(config, caps)?;
+    let handle = ROOT_HANDLE;
     let num_tc = config.num_tc;
     let mut priomap = String::new();
     let mut queues = String::new();
@@ -35,26 +140,68 @@ pub fn setup_tas(ifname: &str, config: &TasConfig) -> Result<i32, String> {
     for entry in &config.sched_entries {
         sched_entries.push_str(&format!(" sched-entry {}", entry));
     }
+    let flags = match mode {
+        TasMode::FullOffload => " flags 0x2".to_string(),
+        TasMode::TxtimeAssist => {
+            format!(" clockid CLOCK_TAI flags 0x1 txtime-delay {}", txtime_delay)
+        }
+        _ => " clockid CLOCK_TAI".to_string(),
+    };
     let cmd = format!(
         "tc qdisc replace dev {} parent root handle {} taprio num_tc {} map{} \
-         queues{} base-time {}{} flags 0x2 txtime-delay {}",
-        ifname, handle, num_tc, priomap, queues, base_time, sched_entries, txtime_delay
+         queues{} base-time {}{}{}",
+        ifname, handle, num_tc, priomap, queues, base_time, sched_entries, flags
     );
-    run_cmd(&cmd)?;
-    // TSN NIC does not support ETF for now
-    /*
-    let cmd = format!(
-        "tc qdisc replace dev {} parent {}:1 etf clockid \
-         CLOCK_TAI delta {} offload skip_sock_check",
-        ifname, handle, txtime_delay
-    );
-    run_cmd(&cmd)?;
-    */
-    Ok(0)
+    exec.run(&cmd)?;
+    // TSN NIC does not support ETF for now, so it is opt-in per traffic class
+    for (etf, offload) in config.etf.iter().zip(etf_offload) {
+        // queues are "1@tc", so the class of a tc is tc + 1
+        let class = config.tc_map[&etf.prio] + 1;
+        let mut flags = String::new();
+        if offload {
+            flags.push_str(" offload");
+        }
+        if etf.deadline_mode {
+            flags.push_str(" deadline_mode");
+        }
+        if etf.skip_sock_check {
+            flags.push_str(" skip_sock_check");
+        }
+        let cmd = format!(
+            "tc qdisc replace dev {} parent {}:{:x} etf clockid CLOCK_TAI delta {}{}",
+            ifname, handle, class, etf.delta, flags
+        );
+        exec.run(&cmd)?;
+    }
+    Ok(())
+}
+
+/// Set up mqprio and its cbs children. Offload left to `auto` is chosen by the
+/// [`caps`] of `ifname`
+pub fn setup_cbs(ifname: &str, config: &CbsConfig) -> Result<(), TsnError> {
+    let caps = caps::probe_or_unknown(ifname);
+    match netlink() {
+        Some(nl) => setup_cbs_rtnl(&nl, ifname, config, &caps),
+        None => setup_cbs_cmds(&mut CommandExecutor, ifname, config, &caps),
+    }
+}
+
+pub fn setup_cbs_with(
+    exec: &mut dyn Executor,
+    ifname: &str,
+    config: &CbsConfig,
+) -> Result<(), TsnError> {
+    setup_cbs_cmds(exec, ifname, config, &caps::probe_or_unknown(ifname))
 }
 
-pub fn setup_cbs(ifname: &str, config: &CbsConfig) -> Result<i32, String> {
-    let root_handle = 100;
+fn setup_cbs_cmds(
+    exec: &mut dyn Executor,
+    ifname: &str,
+    config: &CbsConfig,
+    caps: &Capabilities,
+) -> Result<(), TsnError> {
+    let offload = cbs_offload(config, caps)? as i32;
+    let root_handle = ROOT_HANDLE;
     let num_tc = config.num_tc;
     let mut priomap = String::new();
     let mut queues = String::new();
@@ -69,8 +216,8 @@ pub fn setup_cbs(ifname: &str, config: &CbsConfig) -> Result<i32, String> {
          num_tc {} map{} queues {}hw 0",
         ifname, root_handle, num_tc, priomap, queues
     );
-    run_cmd(&cmd)?;
-    for (qid, val) in &config.children {
+    exec.run(&cmd)?;
+    for (qid, val) in config.children.iter().sorted_by_key(|(qid, _)| **qid) {
         let handle = qid * 1111;
 
         let idleslope = val.idleslope;
@@ -79,21 +226,50 @@ pub fn setup_cbs(ifname: &str, config: &CbsConfig) -> Result<i32, String> {
         let locredit = val.locredit;
         let cmd = format!(
             "tc qdisc replace dev {} parent {}:{} handle {} \
-             cbs idleslope {} sendslope {} hicredit {} locredit {} offload 1",
-            ifname, root_handle, qid, handle, idleslope, sendslope, hicredit, locredit
+             cbs idleslope {} sendslope {} hicredit {} locredit {} offload {}",
+            ifname, root_handle, qid, handle, idleslope, sendslope, hicredit, locredit, offload
         );
-        run_cmd(&cmd)?;
+        exec.run(&cmd)?;
     }
-    Ok(0)
+    Ok(())
 }
 
-pub fn create_vlan(config: &Config, ifname: &str, vlan_id: u16) -> Result<i32, String> {
-    let name = get_vlan_name(ifname, vlan_id);
-
-    if config.tas.is_some() && config.cbs.is_some() {
-        eprintln!("Does not support both TAS and CBS");
-        return Err("Does not support both TAS and CBS".to_string());
+pub fn create_vlan(config: &Config, ifname: &str, vlan_id: u16) -> Result<(), TsnError> {
+    let caps = caps::probe_or_unknown(ifname);
+    let Some(nl) = netlink() else {
+        return create_vlan_cmds(&mut CommandExecutor, config, ifname, vlan_id, &caps);
+    };
+    check_config(config, &caps)?;
+    create_link_rtnl(&nl, ifname, &get_vlan_name(ifname, vlan_id), vlan_id)?;
+    if let Some(tas) = &config.tas {
+        setup_tas_rtnl(&nl, ifname, tas, &caps)?;
     }
+    if let Some(cbs) = &config.cbs {
+        setup_cbs_rtnl(&nl, ifname, cbs, &caps)?;
+    }
+    Ok(())
+}
+
+/// Like [`create_vlan`], but as `ip` and `tc` commands given to `exec`
+pub fn create_vlan_with(
+    exec: &mut dyn Executor,
+    config: &Config,
+    ifname: &str,
+    vlan_id: u16,
+) -> Result<(), TsnError> {
+    let caps = caps::probe_or_unknown(ifname);
+    create_vlan_cmds(exec, config, ifname, vlan_id, &caps)
+}
+
+fn create_vlan_cmds(
+    exec: &mut dyn Executor,
+    config: &Config,
+    ifname: &str,
+    vlan_id: u16,
+    caps: &Capabilities,
+) -> Result<(), TsnError> {
+    let name = get_vlan_name(ifname, vlan_id);
+    check_config(config, caps)?;
 
     // 0-7: identity map, 8-15: map to 0 (not used in VLAN PCP)
     let egress_qos_map = "0:0 1:1 2:2 3:3 4:4 5:5 6:6 7:7 8:0 9:0 10:0 11:0 12:0 13:0 14:0 15:0";
@@ -101,25 +277,363 @@ pub fn create_vlan(config: &Config, ifname: &str, vlan_id: u16) -> Result<i32, S
         "ip link add link {} name {} type vlan id {} egress-qos-map {}",
         ifname, name, vlan_id, egress_qos_map
     );
-    run_cmd(&cmd)?;
+    exec.run(&cmd)?;
     let cmd = format!("ip link set up {}", name);
-    run_cmd(&cmd)?;
+    exec.run(&cmd)?;
+    if let Some(tas) = &config.tas {
+        setup_tas_cmds(exec, ifname, tas, caps)?;
+    }
+    if let Some(cbs) = &config.cbs {
+        setup_cbs_cmds(exec, ifname, cbs, caps)?;
+    }
+    Ok(())
+}
+
+/// Fail before anything is set up if `config` cannot be honoured on an interface
+/// with `caps`
+fn check_config(config: &Config, caps: &Capabilities) -> Result<(), TsnError> {
+    if config.tas.is_some() && config.cbs.is_some() {
+        return Err(TsnError::Unsupported(
+            "Does not support both TAS and CBS".to_string(),
+        ));
+    }
     if let Some(tas) = &config.tas {
-        setup_tas(ifname, tas)?;
+        tas_modes(tas, caps)?;
     }
     if let Some(cbs) = &config.cbs {
-        setup_cbs(ifname, cbs)?;
+        cbs_offload(cbs, caps)?;
     }
-    Ok(0)
+    Ok(())
 }
 
-pub fn delete_vlan(ifname: &str, vlanid: u16) -> Result<i32, String> {
-    let name = get_vlan_name(ifname, vlanid);
-    let cmd = format!("ip link del {}", name);
-    run_cmd(&cmd)?;
+/// Send the qdisc request `msg` builds with `offload`. If the offload was chosen
+/// automatically and the driver turns it down, send it again without
+fn request_offload(
+    nl: &Netlink,
+    op: &str,
+    auto: bool,
+    offload: bool,
+    msg: impl Fn(bool) -> Message,
+) -> Result<(), TsnError> {
+    match nl.request(msg(offload), op) {
+        Err(e) if auto && offload && e.raw_os_error() == Some(libc::EOPNOTSUPP) => {
+            nl.request(msg(false), op)
+        }
+        res => res,
+    }
+}
+
+pub fn delete_vlan(ifname: &str, vlanid: u16) -> Result<(), TsnError> {
+    match netlink() {
+        Some(nl) => delete_vlan_rtnl(&nl, ifname, &get_vlan_name(ifname, vlanid)),
+        None => delete_vlan_with(&mut CommandExecutor, ifname, vlanid),
+    }
+}
+
+pub fn delete_vlan_with(
+    exec: &mut dyn Executor,
+    ifname: &str,
+    vlanid: u16,
+) -> Result<(), TsnError> {
+    let cmd = format!("ip link del {}", get_vlan_name(ifname, vlanid));
+    exec.run(&cmd)?;
     let cmd = format!("tc qdisc delete dev {} root", ifname);
-    run_cmd(&cmd)?;
-    Ok(0)
+    exec.run(&cmd)?;
+    Ok(())
+}
+
+fn create_link_rtnl(nl: &Netlink, ifname: &str, name: &str, vlan_id: u16) -> Result<(), TsnError> {
+    let parent = ifindex(ifname)? as u32;
+    // Created up, like `ip link add` followed by `ip link set up`
+    let header = ifinfomsg {
+        ifi_flags: libc::IFF_UP as u32,
+        ifi_change: libc::IFF_UP as u32,
+        ..Default::default()
+    };
+    let mut msg = Message::new(
+        libc::RTM_NEWLINK,
+        libc::NLM_F_CREATE | libc::NLM_F_EXCL,
+        &header,
+    );
+    msg.attr(libc::IFLA_LINK, &parent)
+        .attr_str(libc::IFLA_IFNAME, name)
+        .nested(libc::IFLA_LINKINFO, |msg| {
+            msg.attr_str(libc::IFLA_INFO_KIND, "vlan")
+                .nested(libc::IFLA_INFO_DATA, |msg| {
+                    msg.attr(IFLA_VLAN_ID, &vlan_id)
+                        .nested(IFLA_VLAN_EGRESS_QOS, |msg| {
+                            // 0-7: identity map, 8-15: map to 0 (not used in VLAN PCP)
+                            for from in 0..16 {
+                                let to = if from < 8 { from } else { 0 };
+                                msg.attr(
+                                    IFLA_VLAN_QOS_MAPPING,
+                                    &ifla_vlan_qos_mapping { from, to },
+                                );
+                            }
+                        });
+                });
+        });
+    nl.request(msg, &format!("Add link {}", name))
+}
+
+fn delete_vlan_rtnl(nl: &Netlink, ifname: &str, name: &str) -> Result<(), TsnError> {
+    let header = ifinfomsg {
+        ifi_index: ifindex(name)?,
+        ..Default::default()
+    };
+    nl.request(
+        Message::new(libc::RTM_DELLINK, 0, &header),
+        &format!("Delete link {}", name),
+    )?;
+    let header = tcmsg {
+        tcm_ifindex: ifindex(ifname)?,
+        tcm_parent: TC_H_ROOT,
+        ..Default::default()
+    };
+    match nl.request(
+        Message::new(libc::RTM_DELQDISC, 0, &header),
+        &format!("Delete root qdisc of {}", ifname),
+    ) {
+        // Only the default qdisc, the VLAN was set up without TAS or CBS
+        Err(e) if e.raw_os_error() == Some(libc::ENOENT) => Ok(()),
+        res => res,
+    }
+}
+
+fn setup_tas_rtnl(
+    nl: &Netlink,
+    ifname: &str,
+    config: &TasConfig,
+    caps: &Capabilities,
+) -> Result<(), TsnError> {
+    let (mode, etf_offload) = tas_modes(config, caps)?;
+    let index = ifindex(ifname)?;
+    let priomap = mqprio_qopt(&config.tc_map, config.num_tc, &config.queues)?;
+    let entries = config
+        .sched_entries
+        .iter()
+        .map(|entry| parse_sched_entry(entry))
+        .collect::<Result<Vec<_>, _>>()?;
+    let header = tcmsg {
+        tcm_ifindex: index,
+        tcm_handle: tc_handle(ROOT_HANDLE, 0),
+        tcm_parent: TC_H_ROOT,
+        ..Default::default()
+    };
+    // Without offload, e.g. when full offload was chosen but is turned down
+    let taprio = |offload: bool| {
+        let mode = if offload { mode } else { TasMode::Software };
+        let mut msg = Message::new(
+            libc::RTM_NEWQDISC,
+            libc::NLM_F_CREATE | libc::NLM_F_REPLACE,
+            &header,
+        );
+        msg.attr_str(libc::TCA_KIND, "taprio")
+            .nested(libc::TCA_OPTIONS, |msg| {
+                msg.attr(TCA_TAPRIO_ATTR_PRIOMAP, &priomap)
+                    .nested(TCA_TAPRIO_ATTR_SCHED_ENTRY_LIST, |msg| {
+                        for (cmd, gate_mask, interval) in &entries {
+                            msg.nested(TCA_TAPRIO_SCHED_ENTRY, |msg| {
+                                msg.attr(TCA_TAPRIO_SCHED_ENTRY_CMD, cmd)
+                                    .attr(TCA_TAPRIO_SCHED_ENTRY_GATE_MASK, gate_mask)
+                                    .attr(TCA_TAPRIO_SCHED_ENTRY_INTERVAL, interval);
+                            });
+                        }
+                    })
+                    .attr(TCA_TAPRIO_ATTR_SCHED_BASE_TIME, &config.base_time);
+                match mode {
+                    TasMode::FullOffload => {
+                        msg.attr(TCA_TAPRIO_ATTR_FLAGS, &TCA_TAPRIO_ATTR_FLAG_FULL_OFFLOAD);
+                    }
+                    TasMode::TxtimeAssist => {
+                        msg.attr(TCA_TAPRIO_ATTR_SCHED_CLOCKID, &libc::CLOCK_TAI)
+                            .attr(TCA_TAPRIO_ATTR_FLAGS, &TCA_TAPRIO_ATTR_FLAG_TXTIME_ASSIST)
+                            .attr(TCA_TAPRIO_ATTR_TXTIME_DELAY, &(config.txtime_delay as u32));
+                    }
+                    _ => {
+                        msg.attr(TCA_TAPRIO_ATTR_SCHED_CLOCKID, &libc::CLOCK_TAI);
+                    }
+                }
+            });
+        msg
+    };
+    request_offload(
+        nl,
+        &format!("Replace taprio qdisc of {}", ifname),
+        config.mode == TasMode::Auto,
+        mode == TasMode::FullOffload,
+        taprio,
+    )?;
+
+    // TSN NIC does not support ETF for now, so it is opt-in per traffic class
+    for (etf, offload) in config.etf.iter().zip(etf_offload) {
+        // queues are "1@tc", so the class of a tc is tc + 1
+        let class = config.tc_map[&etf.prio] + 1;
+        let mut flags = 0;
+        if etf.deadline_mode {
+            flags |= TC_ETF_DEADLINE_MODE_ON;
+        }
+        if etf.skip_sock_check {
+            flags |= TC_ETF_SKIP_SOCK_CHECK;
+        }
+        let header = tcmsg {
+            tcm_ifindex: index,
+            // The class is a number, unlike the handles tc_handle reads as hex
+            tcm_parent: tc_handle(ROOT_HANDLE, 0) | class as u32,
+            ..Default::default()
+        };
+        let etf_msg = |offload: bool| {
+            let qopt = tc_etf_qopt {
+                delta: etf.delta as i32,
+                clockid: libc::CLOCK_TAI,
+                flags: if offload {
+                    flags | TC_ETF_OFFLOAD_ON
+                } else {
+                    flags
+                },
+            };
+            let mut msg = Message::new(
+                libc::RTM_NEWQDISC,
+                libc::NLM_F_CREATE | libc::NLM_F_REPLACE,
+                &header,
+            );
+            msg.attr_str(libc::TCA_KIND, "etf")
+                .nested(libc::TCA_OPTIONS, |msg| {
+                    msg.attr(TCA_ETF_PARMS, &qopt);
+                });
+            msg
+        };
+        request_offload(
+            nl,
+            &format!("Replace etf qdisc of {} class {}", ifname, class),
+            etf.offload.is_none(),
+            offload,
+            etf_msg,
+        )?;
+    }
+    Ok(())
+}
+
+fn setup_cbs_rtnl(
+    nl: &Netlink,
+    ifname: &str,
+    config: &CbsConfig,
+    caps: &Capabilities,
+) -> Result<(), TsnError> {
+    let offload = cbs_offload(config, caps)?;
+    let index = ifindex(ifname)?;
+    let header = tcmsg {
+        tcm_ifindex: index,
+        tcm_handle: tc_handle(ROOT_HANDLE, 0),
+        tcm_parent: TC_H_ROOT,
+        ..Default::default()
+    };
+    let mut msg = Message::new(
+        libc::RTM_NEWQDISC,
+        libc::NLM_F_CREATE | libc::NLM_F_EXCL,
+        &header,
+    );
+    msg.attr_str(libc::TCA_KIND, "mqprio").attr(
+        libc::TCA_OPTIONS,
+        &mqprio_qopt(&config.tc_map, config.num_tc, &config.queues)?,
+    );
+    nl.request(msg, &format!("Add mqprio qdisc of {}", ifname))?;
+
+    for (qid, val) in config.children.iter().sorted_by_key(|(qid, _)| **qid) {
+        let header = tcmsg {
+            tcm_ifindex: index,
+            tcm_handle: tc_handle(qid * 1111, 0),
+            tcm_parent: tc_handle(ROOT_HANDLE, *qid),
+            ..Default::default()
+        };
+        let cbs = |offload: bool| {
+            let qopt = tc_cbs_qopt {
+                offload: offload as u8,
+                _pad: [0; 3],
+                hicredit: val.hicredit as i32,
+                locredit: val.locredit as i32,
+                idleslope: val.idleslope as i32,
+                sendslope: val.sendslope as i32,
+            };
+            let mut msg = Message::new(
+                libc::RTM_NEWQDISC,
+                libc::NLM_F_CREATE | libc::NLM_F_REPLACE,
+                &header,
+            );
+            msg.attr_str(libc::TCA_KIND, "cbs")
+                .nested(libc::TCA_OPTIONS, |msg| {
+                    msg.attr(TCA_CBS_PARMS, &qopt);
+                });
+            msg
+        };
+        request_offload(
+            nl,
+            &format!("Replace cbs qdisc of {} queue {}", ifname, qid),
+            config.offload.is_none(),
+            offload,
+            cbs,
+        )?;
+    }
+    Ok(())
+}
+
+fn ifindex(ifname: &str) -> Result<i32, TsnError> {
+    if_nametoindex(ifname)
+        .map(|index| index as i32)
+        .map_err(|_| TsnError::InterfaceNotFound(ifname.to_string()))
+}
+
+/// `major:minor` handle the way tc reads it: both numbers are hex, so handle 100 is 0x100
+fn tc_handle(major: i64, minor: i64) -> u32 {
+    let hex = |n: i64| u32::from_str_radix(&n.to_string(), 16).unwrap_or_default();
+    hex(major) << 16 | hex(minor)
+}
+
+/// `num_tc`, `map` and `queues` of mqprio and taprio
+fn mqprio_qopt(
+    tc_map: &HashMap<i64, i64>,
+    num_tc: i64,
+    queues: &[String],
+) -> Result<tc_mqprio_qopt, TsnError> {
+    let mut qopt = tc_mqprio_qopt {
+        num_tc: num_tc as u8,
+        ..Default::default()
+    };
+    for (tc, key) in qopt.prio_tc_map.iter_mut().zip(tc_map.keys().sorted()) {
+        *tc = tc_map[key] as u8;
+    }
+    for (i, queue) in queues.iter().take(TC_QOPT_MAX_QUEUE).enumerate() {
+        let (count, offset) = queue
+            .split_once('@')
+            .and_then(|(count, offset)| Some((count.parse().ok()?, offset.parse().ok()?)))
+            .ok_or_else(|| {
+                TsnError::ConfigInvalid(format!("{} is not a count@offset queue", queue))
+            })?;
+        qopt.count[i] = count;
+        qopt.offset[i] = offset;
+    }
+    Ok(qopt)
+}
+
+/// Command, gate mask and interval of a `sched-entry` of taprio
+fn parse_sched_entry(entry: &str) -> Result<(u8, u32, u32), TsnError> {
+    let invalid = || TsnError::ConfigInvalid(format!("{} is not a sched-entry", entry));
+    let mut fields = entry.split_whitespace();
+    let cmd = match fields.next() {
+        Some("S") => TC_TAPRIO_CMD_SET_GATES,
+        Some("H") => TC_TAPRIO_CMD_SET_AND_HOLD,
+        Some("R") => TC_TAPRIO_CMD_SET_AND_RELEASE,
+        _ => return Err(invalid()),
+    };
+    let gate_mask = fields
+        .next()
+        .and_then(|mask| u32::from_str_radix(mask.trim_start_matches("0x"), 16).ok())
+        .ok_or_else(invalid)?;
+    let interval = fields
+        .next()
+        .and_then(|interval| interval.parse().ok())
+        .ok_or_else(invalid)?;
+    Ok((cmd, gate_mask, interval))
 }
 
 pub fn get_vlan_name(ifname: &str, vlanid: u16) -> String {
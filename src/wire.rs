@@ -0,0 +1,302 @@
+//! Virtual wire, a [`Backend`](crate::Backend) for tests without a NIC or root
+//!
+//! All sockets opened on the same interface name (and VLAN) with
+//! [`Backend::Virtual`](crate::Backend::Virtual) are ports of one wire, also across
+//! processes: a frame sent on one port is delivered to all others, like on a hub.
+//! Ports are Unix datagram sockets in a directory under the temp dir, so nothing
+//! needs privileges and no interface or config file has to exist.
+//!
+//! The sender's [`WireConfig`] adds delay and loss. A sender waits up to
+//! `SEND_TIMEOUT` for a port with a full receive queue, then drops the frame for that
+//! port. Only software timestamps are available: TX timestamps are taken when a frame
+//! is sent, RX timestamps when it is delivered, so their difference is the configured
+//! delay.
+//!
+//! ```no_run
+//! use std::time::Duration;
+//! use tsn::wire::WireConfig;
+//! use tsn::{Backend, TsnSocket};
+//!
+//! let config = WireConfig {
+//!     delay: Duration::from_micros(100),
+//!     loss_ppm: 1000,
+//! };
+//! let a = TsnSocket::builder("test0").backend(Backend::Virtual(config)).open()?;
+//! let b = TsnSocket::builder("test0").backend(Backend::Virtual(config)).open()?;
+//! a.send(&[0xff; 60])?;
+//! let mut buf = [0u8; 1514];
+//! b.recv(&mut buf)?;
+//! # Ok::<(), tsn::TsnError>(())
+//! ```
+
+use std::collections::VecDeque;
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+use std::{mem, process, thread};
+
+use nix::libc;
+use rand::Rng;
+
+use crate::{time, TsnError, TxTimestamp};
+
+/// Directory in the temp dir holding one directory of ports per wire
+const WIRE_DIR: &str = "tsn-wire";
+/// How long a sender waits for room in the receive queue of a port
+const SEND_TIMEOUT: Duration = Duration::from_millis(10);
+/// Deliver time in ns of `CLOCK_REALTIME`, sent in front of each frame
+pub(crate) const HEADER_LEN: usize = mem::size_of::<u64>();
+
+/// Impairments a port adds to the frames it sends
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct WireConfig {
+    /// Time between sending a frame and its delivery
+    pub delay: Duration,
+    /// Frames lost per million sent
+    pub loss_ppm: u32,
+}
+
+/// Port of a socket on the wire. The socket's fd is the port's datagram socket
+pub(crate) struct WirePort {
+    dir: PathBuf,
+    path: PathBuf,
+    config: WireConfig,
+    proto: u16,
+    id: u16,
+    /// TX timestamps of sent frames, taken by [`drain_tx_timestamps`](crate::drain_tx_timestamps)
+    tx_timestamps: Mutex<VecDeque<TxTimestamp>>,
+}
+
+impl WirePort {
+    /// Join the wire `name`. Returns the fd to receive on together with the port
+    pub(crate) fn open(
+        name: &str,
+        proto: u16,
+        config: WireConfig,
+    ) -> Result<(i32, WirePort), TsnError> {
+        static NEXT_ID: AtomicU16 = AtomicU16::new(0);
+
+        let dir = std::env::temp_dir().join(WIRE_DIR).join(name);
+        fs::create_dir_all(&dir).map_err(|source| TsnError::Socket {
+            op: "Create wire",
+            source,
+        })?;
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        let path = dir.join(format!("{}-{}", process::id(), id));
+        // Left over by a process with the same pid
+        let _ = fs::remove_file(&path);
+        let (addr, len) =
+            sockaddr_un(&path).map_err(|source| TsnError::Socket { op: "Bind", source })?;
+
+        let fd = unsafe { libc::socket(libc::AF_UNIX, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0) };
+        if fd < 0 {
+            return Err(TsnError::socket("Socket"));
+        }
+        let res = unsafe {
+            libc::bind(
+                fd,
+                &addr as *const libc::sockaddr_un as *const libc::sockaddr,
+                len,
+            )
+        };
+        if res < 0 {
+            let err = TsnError::socket("Bind");
+            unsafe { libc::close(fd) };
+            return Err(err);
+        }
+        let timeout = libc::timeval {
+            tv_sec: 0,
+            tv_usec: SEND_TIMEOUT.as_micros() as libc::suseconds_t,
+        };
+        let res = unsafe {
+            libc::setsockopt(
+                fd,
+                libc::SOL_SOCKET,
+                libc::SO_SNDTIMEO,
+                &timeout as *const libc::timeval as *const libc::c_void,
+                mem::size_of::<libc::timeval>() as u32,
+            )
+        };
+        if res < 0 {
+            let err = TsnError::socket("Set timeout");
+            unsafe { libc::close(fd) };
+            return Err(err);
+        }
+
+        let port = WirePort {
+            dir,
+            path,
+            config,
+            proto,
+            id,
+            tx_timestamps: Mutex::new(VecDeque::new()),
+        };
+        Ok((fd, port))
+    }
+
+    /// Locally administered MAC address, unique per port
+    pub(crate) fn mac_address(&self) -> [u8; 6] {
+        let [p0, p1] = (process::id() as u16).to_be_bytes();
+        let [i0, i1] = self.id.to_be_bytes();
+        [0x02, 0x74, p0, p1, i0, i1]
+    }
+
+    /// Put `buf` on the wire. The TX timestamp is queued as `tx_id` if `timestamp` is set
+    pub(crate) fn send(
+        &self,
+        fd: i32,
+        buf: &[u8],
+        timestamp: bool,
+        tx_id: u32,
+    ) -> Result<isize, TsnError> {
+        let now = time::Timespec::now(libc::CLOCK_REALTIME);
+        if timestamp {
+            let mut queue = self.tx_timestamps.lock().unwrap();
+            if queue.len() >= crate::ERRQUEUE_BACKLOG {
+                queue.pop_front();
+            }
+            queue.push_back(TxTimestamp {
+                id: tx_id,
+                time: now,
+                source: crate::TimestampSource::Sw,
+            });
+        }
+        let lost = self.config.loss_ppm > 0
+            && rand::thread_rng().gen_range(0..1_000_000) < self.config.loss_ppm;
+        if lost {
+            return Ok(buf.len() as isize);
+        }
+
+        let deliver_at = nanos(&now) + self.config.delay.as_nanos() as u64;
+        let header = deliver_at.to_ne_bytes();
+        let peers =
+            fs::read_dir(&self.dir).map_err(|source| TsnError::Socket { op: "Send", source })?;
+        for peer in peers.filter_map(Result::ok) {
+            let path = peer.path();
+            if path == self.path {
+                continue;
+            }
+            match send_to(fd, &path, &header, buf) {
+                Ok(()) => {}
+                // Port of a socket that is gone without cleaning up
+                Err(e) if matches!(e.raw_os_error(), Some(libc::ECONNREFUSED | libc::ENOENT)) => {
+                    let _ = fs::remove_file(&path);
+                }
+                // Receive queue still full after SEND_TIMEOUT
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+                Err(source) => return Err(TsnError::Socket { op: "Send", source }),
+            }
+        }
+        Ok(buf.len() as isize)
+    }
+
+    /// Receive the next frame of our protocol, waiting until it is due unless `flags`
+    /// has `MSG_DONTWAIT`.
+    ///
+    /// Returns its length and the time it is delivered.
+    pub(crate) fn recv(
+        &self,
+        fd: i32,
+        buf: &mut [u8],
+        flags: libc::c_int,
+    ) -> Result<(usize, time::Timespec), TsnError> {
+        loop {
+            let mut header = [0u8; HEADER_LEN];
+            let mut iovs = [
+                libc::iovec {
+                    iov_base: header.as_mut_ptr() as *mut libc::c_void,
+                    iov_len: header.len(),
+                },
+                libc::iovec {
+                    iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+                    iov_len: buf.len(),
+                },
+            ];
+            let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+            msg.msg_iov = iovs.as_mut_ptr();
+            msg.msg_iovlen = iovs.len() as _;
+            let res = unsafe { libc::recvmsg(fd, &mut msg, flags) };
+            if res < 0 {
+                return Err(TsnError::socket("Recv"));
+            }
+            let len = (res as usize).saturating_sub(HEADER_LEN);
+            if !self.wants(&buf[..len]) {
+                continue;
+            }
+
+            let deliver_at = u64::from_ne_bytes(header);
+            let now = nanos(&time::Timespec::now(libc::CLOCK_REALTIME));
+            if deliver_at > now && flags & libc::MSG_DONTWAIT == 0 {
+                thread::sleep(Duration::from_nanos(deliver_at - now));
+            }
+            // Not the time we woke up, which adds the latency of the scheduler
+            return Ok((len, time::Timespec::from_nanos(deliver_at as i64)));
+        }
+    }
+
+    pub(crate) fn next_tx_timestamp(&self) -> Option<TxTimestamp> {
+        self.tx_timestamps.lock().unwrap().pop_front()
+    }
+
+    /// Whether a socket bound to our protocol gets `frame`
+    fn wants(&self, frame: &[u8]) -> bool {
+        if self.proto == libc::ETH_P_ALL as u16 {
+            return true;
+        }
+        frame.len() >= 14 && u16::from_be_bytes([frame[12], frame[13]]) == self.proto
+    }
+}
+
+impl Drop for WirePort {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+fn nanos(ts: &time::Timespec) -> u64 {
+    ts.tv_sec as u64 * 1_000_000_000 + ts.tv_nsec as u64
+}
+
+fn sockaddr_un(path: &Path) -> Result<(libc::sockaddr_un, libc::socklen_t), Error> {
+    let mut addr: libc::sockaddr_un = unsafe { mem::zeroed() };
+    addr.sun_family = libc::AF_UNIX as libc::sa_family_t;
+    let bytes = path.as_os_str().as_bytes();
+    // Keep the terminating NUL
+    if bytes.len() >= addr.sun_path.len() {
+        return Err(Error::new(ErrorKind::InvalidInput, "wire path too long"));
+    }
+    for (source, target) in bytes.iter().zip(addr.sun_path.iter_mut()) {
+        *target = *source as libc::c_char;
+    }
+    let len = mem::size_of::<libc::sa_family_t>() + bytes.len() + 1;
+    Ok((addr, len as libc::socklen_t))
+}
+
+fn send_to(fd: i32, path: &Path, header: &[u8], buf: &[u8]) -> Result<(), Error> {
+    let (mut addr, len) = sockaddr_un(path)?;
+    let mut iovs = [
+        libc::iovec {
+            iov_base: header.as_ptr() as *mut libc::c_void,
+            iov_len: header.len(),
+        },
+        libc::iovec {
+            iov_base: buf.as_ptr() as *mut libc::c_void,
+            iov_len: buf.len(),
+        },
+    ];
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_name = &mut addr as *mut libc::sockaddr_un as *mut libc::c_void;
+    msg.msg_namelen = len;
+    msg.msg_iov = iovs.as_mut_ptr();
+    msg.msg_iovlen = iovs.len() as _;
+    let res = unsafe { libc::sendmsg(fd, &msg, 0) };
+    if res < 0 {
+        Err(Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
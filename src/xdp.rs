@@ -0,0 +1,823 @@
+//! AF_XDP backend of [`TsnSocket`](crate::TsnSocket), enabled by the `xdp` feature
+//!
+//! The socket is bound to the queue the socket priority maps to in `tc_map` and
+//! gets frames from a small XDP program which redirects frames of the VLAN and
+//! protocol to it. Everything else goes on to the kernel stack.
+//!
+//! An AF_XDP socket only receives frames of the RX queue it is bound to, so the
+//! program only redirects frames arriving on that queue. On an interface with more
+//! than one RX queue, an ntuple rule has to steer the stream there, e.g.
+//! `ethtool -N eth0 flow-type ether proto 0x22f0 vlan 10 m 0xf000 action 2`, or the
+//! socket fails to open with [`TsnError::Unsupported`].
+//!
+//! The program tells VLANs apart by the 802.1Q tag in the frame, so the socket
+//! fails to open while RX VLAN offload strips it before XDP runs. Turn it off with
+//! `ethtool -K eth0 rxvlan off`.
+//!
+//! Frames are copied between the caller's buffers and the UMEM, so `send` and
+//! `recv` keep the semantics of the VLAN interface: the 802.1Q tag is inserted
+//! on send and stripped on receive. Timestamps and launch time are not available.
+
+use crate::sys::ethtool::SIOCETHTOOL;
+use crate::sys::xdp::*;
+use crate::TsnError;
+use nix::net::if_::if_nametoindex;
+use nix::sys::mman::{mmap, munmap, MapFlags, ProtFlags};
+use nix::unistd::close;
+use std::num::NonZeroUsize;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use std::{io, mem, ptr};
+
+/// How the XDP program is attached
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum XdpMode {
+    /// In the driver. Needs driver support, allows zero-copy
+    Native,
+    /// On the skb path (`XDP_FLAGS_SKB_MODE`). Works on any interface, e.g. veth
+    Generic,
+}
+
+/// UMEM and ring sizes, passed with [`Backend::Xdp`](crate::Backend::Xdp)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct XdpConfig {
+    pub mode: XdpMode,
+    /// Frames of the UMEM, half for RX and half for TX. A power of 2
+    pub frame_count: u32,
+    /// Size of one UMEM frame, 2048 or 4096
+    pub frame_size: u32,
+    /// Entries of each of the four rings, a power of 2
+    pub ring_size: u32,
+}
+
+impl XdpConfig {
+    pub fn new(mode: XdpMode) -> XdpConfig {
+        XdpConfig {
+            mode,
+            frame_count: 4096,
+            frame_size: 2048,
+            ring_size: 2048,
+        }
+    }
+}
+
+impl Default for XdpConfig {
+    fn default() -> Self {
+        Self::new(XdpMode::Native)
+    }
+}
+
+/// One of the four mmaped rings of an XDP socket
+struct XskRing<T> {
+    map: *mut libc::c_void,
+    map_len: usize,
+    producer: *const AtomicU32,
+    consumer: *const AtomicU32,
+    descs: *mut T,
+    size: u32,
+}
+
+impl<T: Copy> XskRing<T> {
+    fn map(
+        fd: i32,
+        off: &xdp_ring_offset,
+        size: u32,
+        pgoff: libc::off_t,
+    ) -> Result<XskRing<T>, TsnError> {
+        let map_len = off.desc as usize + size as usize * mem::size_of::<T>();
+        let map = unsafe {
+            mmap(
+                None,
+                NonZeroUsize::new_unchecked(map_len),
+                ProtFlags::PROT_READ | ProtFlags::PROT_WRITE,
+                MapFlags::MAP_SHARED | MapFlags::MAP_POPULATE,
+                fd,
+                pgoff,
+            )
+        }
+        .map_err(|e| TsnError::Socket {
+            op: "XDP ring map",
+            source: e.into(),
+        })?;
+        let at = |offset: u64| unsafe { (map as *mut u8).add(offset as usize) };
+        Ok(XskRing {
+            map,
+            map_len,
+            producer: at(off.producer) as *const AtomicU32,
+            consumer: at(off.consumer) as *const AtomicU32,
+            descs: at(off.desc) as *mut T,
+            size,
+        })
+    }
+
+    fn producer(&self) -> &AtomicU32 {
+        unsafe { &*self.producer }
+    }
+
+    fn consumer(&self) -> &AtomicU32 {
+        unsafe { &*self.consumer }
+    }
+
+    /// Producer side: append `desc`, false if the ring is full
+    fn push(&self, desc: T) -> bool {
+        let prod = self.producer().load(Ordering::Relaxed);
+        let cons = self.consumer().load(Ordering::Acquire);
+        if prod.wrapping_sub(cons) >= self.size {
+            return false;
+        }
+        unsafe { ptr::write(self.descs.add((prod & (self.size - 1)) as usize), desc) };
+        self.producer()
+            .store(prod.wrapping_add(1), Ordering::Release);
+        true
+    }
+
+    /// Consumer side: take the oldest descriptor
+    fn pop(&self) -> Option<T> {
+        let cons = self.consumer().load(Ordering::Relaxed);
+        let prod = self.producer().load(Ordering::Acquire);
+        if cons == prod {
+            return None;
+        }
+        let desc = unsafe { ptr::read(self.descs.add((cons & (self.size - 1)) as usize)) };
+        self.consumer()
+            .store(cons.wrapping_add(1), Ordering::Release);
+        Some(desc)
+    }
+}
+
+impl<T> Drop for XskRing<T> {
+    fn drop(&mut self) {
+        if !self.map.is_null() {
+            let _ = unsafe { munmap(self.map, self.map_len) };
+        }
+    }
+}
+
+struct XdpState {
+    fill: XskRing<u64>,
+    comp: XskRing<u64>,
+    rx: XskRing<xdp_desc>,
+    tx: XskRing<xdp_desc>,
+    /// UMEM frames owned by userspace, ready for TX
+    free: Vec<u64>,
+}
+
+/// UMEM, rings and XDP program of an AF_XDP [`TsnSocket`](crate::TsnSocket)
+pub(crate) struct XdpSocket {
+    fd: i32,
+    umem: *mut u8,
+    umem_len: usize,
+    frame_size: u32,
+    /// 802.1Q TCI inserted on send and expected on receive
+    tci: u16,
+    state: Mutex<XdpState>,
+    /// Apart from `state`, which a blocking receive holds
+    timeout: Mutex<Option<Duration>>,
+    map_fd: i32,
+    prog_fd: i32,
+    /// The program is detached when this is closed
+    link_fd: i32,
+}
+
+// UMEM and rings are only touched with `state` locked
+unsafe impl Send for XdpSocket {}
+unsafe impl Sync for XdpSocket {}
+
+/// Queue of `priority`: queues are set up as "1@tc", so the queue is the traffic class
+pub(crate) fn queue_of(config: &crate::config::Config, priority: u32) -> u32 {
+    config
+        .tas
+        .as_ref()
+        .map(|tas| &tas.tc_map)
+        .or_else(|| config.cbs.as_ref().map(|cbs| &cbs.tc_map))
+        .and_then(|tc_map| tc_map.get(&(priority as i64)))
+        .map_or(0, |tc| *tc as u32)
+}
+
+/// `SIOCETHTOOL` of `data`, an ethtool struct starting with its command
+fn ethtool<T>(ifname: &str, data: *mut T) -> io::Result<()> {
+    let fd = unsafe { libc::socket(libc::AF_INET, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+    let mut ifr_name: [libc::c_char; libc::IFNAMSIZ] = [0; libc::IFNAMSIZ];
+    for (source, target) in ifname.as_bytes().iter().zip(ifr_name.iter_mut()) {
+        *target = *source as libc::c_char;
+    }
+    let ifreq = libc::ifreq {
+        ifr_name,
+        ifr_ifru: libc::__c_anonymous_ifr_ifru {
+            ifru_data: data as *mut libc::c_char,
+        },
+    };
+    let res = unsafe { libc::ioctl(fd.as_raw_fd(), SIOCETHTOOL as _, &ifreq) };
+    if res < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+/// RX queues of `ifname`, from sysfs where the driver does not report its channels
+fn rx_queues(ifname: &str) -> usize {
+    let mut channels = ethtool_channels {
+        cmd: ETHTOOL_GCHANNELS,
+        ..Default::default()
+    };
+    if ethtool(ifname, &mut channels).is_ok() {
+        return (channels.rx_count + channels.combined_count) as usize;
+    }
+    std::fs::read_dir(format!("/sys/class/net/{}/queues", ifname))
+        .map(|dir| {
+            dir.flatten()
+                .filter(|entry| entry.file_name().to_string_lossy().starts_with("rx-"))
+                .count()
+        })
+        .unwrap_or(1)
+}
+
+/// ntuple rules of `ifname`
+fn ntuple_rules(ifname: &str) -> io::Result<Vec<ethtool_rx_flow_spec>> {
+    let mut nfc: ethtool_rxnfc = unsafe { mem::zeroed() };
+    nfc.cmd = ETHTOOL_GRXCLSRLCNT;
+    ethtool(ifname, &mut nfc)?;
+    let count = nfc.rule_cnt as usize;
+
+    // The locations follow `rule_cnt`
+    let locs_at = mem::offset_of!(ethtool_rxnfc, rule_cnt) + 4;
+    let mut buf = vec![0u64; (locs_at + count * 4).div_ceil(8)];
+    let all = buf.as_mut_ptr() as *mut ethtool_rxnfc;
+    unsafe {
+        (*all).cmd = ETHTOOL_GRXCLSRLALL;
+        (*all).rule_cnt = count as u32;
+    }
+    ethtool(ifname, all)?;
+    let locs =
+        unsafe { std::slice::from_raw_parts((all as *const u8).add(locs_at) as *const u32, count) };
+
+    let mut rules = Vec::with_capacity(count);
+    for loc in locs {
+        let mut nfc: ethtool_rxnfc = unsafe { mem::zeroed() };
+        nfc.cmd = ETHTOOL_GRXCLSRULE;
+        nfc.fs.location = *loc;
+        ethtool(ifname, &mut nfc)?;
+        rules.push(nfc.fs);
+    }
+    Ok(rules)
+}
+
+/// Whether `rule` sends frames of `vlanid` and `proto` to `queue`
+fn steers(rule: &ethtool_rx_flow_spec, queue: u32, vlanid: u16, proto: u16) -> bool {
+    if rule.flow_type & !FLOW_EXT != ETHER_FLOW
+        || rule.ring_cookie & ETHTOOL_RX_FLOW_SPEC_RING != queue as u64
+    {
+        return false;
+    }
+    let proto_mask = u16::from_be_bytes([rule.m_u[12], rule.m_u[13]]);
+    let rule_proto = u16::from_be_bytes([rule.h_u[12], rule.h_u[13]]);
+    let proto_matches = proto_mask == 0
+        || (proto != libc::ETH_P_ALL as u16 && rule_proto & proto_mask == proto & proto_mask);
+    let vid_mask = u16::from_be(rule.m_ext.vlan_tci) & 0x0fff;
+    let vid_matches = rule.flow_type & FLOW_EXT == 0
+        || u16::from_be(rule.h_ext.vlan_tci) & vid_mask == vlanid & vid_mask;
+    proto_matches && vid_matches
+}
+
+/// Fail unless frames of `vlanid` and `proto` arrive on `queue`, see the module docs
+fn check_steering(ifname: &str, queue: u32, vlanid: u16, proto: u16) -> Result<(), TsnError> {
+    let queues = rx_queues(ifname);
+    if queues <= 1 {
+        return Ok(());
+    }
+    // No ntuple support is as good as no rule
+    let steered = ntuple_rules(ifname)
+        .map(|rules| rules.iter().any(|rule| steers(rule, queue, vlanid, proto)))
+        .unwrap_or(false);
+    if steered {
+        return Ok(());
+    }
+    Err(TsnError::Unsupported(format!(
+        "{} has {} RX queues but XDP only receives on queue {}, steer the stream there \
+         with `ethtool -N {} flow-type ether proto {:#06x} vlan {} m 0xf000 action {}`",
+        ifname, queues, queue, ifname, proto, vlanid, queue
+    )))
+}
+
+/// Fail if the NIC strips the VLAN tag before the program can check it
+fn check_vlan_offload(ifname: &str) -> Result<(), TsnError> {
+    let mut flags = ethtool_value {
+        cmd: ETHTOOL_GFLAGS,
+        data: 0,
+    };
+    // Drivers without the flags have no VLAN offload either
+    if ethtool(ifname, &mut flags).is_err() || flags.data & ETH_FLAG_RXVLAN == 0 {
+        return Ok(());
+    }
+    Err(TsnError::Unsupported(format!(
+        "RX VLAN offload of {} hides the VLAN of frames from XDP, turn it off with \
+         `ethtool -K {} rxvlan off`",
+        ifname, ifname
+    )))
+}
+
+fn setsockopt<T>(fd: i32, opt: libc::c_int, val: &T, op: &'static str) -> Result<(), TsnError> {
+    let res = unsafe {
+        libc::setsockopt(
+            fd,
+            SOL_XDP,
+            opt,
+            val as *const T as *const libc::c_void,
+            mem::size_of::<T>() as u32,
+        )
+    };
+    if res < 0 {
+        Err(TsnError::socket(op))
+    } else {
+        Ok(())
+    }
+}
+
+fn bpf<T>(cmd: libc::c_int, attr: &T, op: &'static str) -> Result<i32, TsnError> {
+    let res = unsafe { libc::syscall(libc::SYS_bpf, cmd, attr as *const T, mem::size_of::<T>()) };
+    if res < 0 {
+        Err(TsnError::socket(op))
+    } else {
+        Ok(res as i32)
+    }
+}
+
+/// Build `struct bpf_insn`
+fn insn(code: u8, dst: u8, src: u8, off: i16, imm: i32) -> bpf_insn {
+    bpf_insn {
+        code,
+        regs: (src << 4) | dst,
+        off,
+        imm,
+    }
+}
+
+/// A 16-bit big endian header field as a `BPF_H` load sees it
+fn wire_u16(value: u16) -> i32 {
+    u16::from_ne_bytes(value.to_be_bytes()) as i32
+}
+
+/// XDP program redirecting frames of `vlanid` and `proto` to the XSKMAP entry of their queue.
+///
+/// Untagged frames are passed, they belong to no VLAN. `ETH_P_ALL` takes every protocol.
+fn program(map_fd: i32, vlanid: u16, proto: u16) -> Vec<bpf_insn> {
+    const LDX_W: u8 = 0x61; // BPF_LDX | BPF_MEM | BPF_W
+    const LDX_H: u8 = 0x69; // BPF_LDX | BPF_MEM | BPF_H
+    const MOV_X: u8 = 0xbf; // BPF_ALU64 | BPF_MOV | BPF_X
+    const MOV_K: u8 = 0xb7; // BPF_ALU64 | BPF_MOV | BPF_K
+    const ADD_K: u8 = 0x07; // BPF_ALU64 | BPF_ADD | BPF_K
+    const AND_K: u8 = 0x57; // BPF_ALU64 | BPF_AND | BPF_K
+    const JGT_X: u8 = 0x2d; // BPF_JMP | BPF_JGT | BPF_X
+    const JNE_K: u8 = 0x55; // BPF_JMP | BPF_JNE | BPF_K
+    const JA: u8 = 0x05; // BPF_JMP | BPF_JA
+    const LD_DW: u8 = 0x18; // BPF_LD | BPF_DW | BPF_IMM
+    const CALL: u8 = 0x85; // BPF_JMP | BPF_CALL
+    const EXIT: u8 = 0x95; // BPF_JMP | BPF_EXIT
+    const PSEUDO_MAP_FD: u8 = 1;
+
+    // Jump offsets are relative to the next instruction
+    const PASS: i16 = 19;
+    let to = |from: i16, target: i16| target - from - 1;
+    let tpid = wire_u16(libc::ETH_P_8021Q as u16);
+    let vid_mask = wire_u16(0x0fff);
+    let match_proto = if proto == libc::ETH_P_ALL as u16 {
+        insn(JA, 0, 0, 0, 0)
+    } else {
+        insn(JNE_K, 5, 0, to(12, PASS), wire_u16(proto))
+    };
+
+    vec![
+        insn(MOV_X, 6, 1, 0, 0),                           // 0: r6 = ctx
+        insn(LDX_W, 2, 6, 0, 0),                           // 1: r2 = ctx->data
+        insn(LDX_W, 3, 6, 4, 0),                           // 2: r3 = ctx->data_end
+        insn(MOV_X, 4, 2, 0, 0),                           // 3
+        insn(ADD_K, 4, 0, 0, 18),                          // 4: r4 = data + VLAN_ETH_HLEN
+        insn(JGT_X, 4, 3, to(5, PASS), 0),                 // 5
+        insn(LDX_H, 5, 2, 12, 0),                          // 6: r5 = h_proto
+        insn(JNE_K, 5, 0, to(7, PASS), tpid),              // 7
+        insn(LDX_H, 5, 2, 14, 0),                          // 8: r5 = TCI
+        insn(AND_K, 5, 0, 0, vid_mask),                    // 9: r5 &= VLAN_VID_MASK
+        insn(JNE_K, 5, 0, to(10, PASS), wire_u16(vlanid)), // 10
+        insn(LDX_H, 5, 2, 16, 0),                          // 11: r5 = encapsulated proto
+        match_proto,                                       // 12
+        insn(LDX_W, 2, 6, 16, 0),                          // 13: r2 = rx queue
+        insn(LD_DW, 1, PSEUDO_MAP_FD, 0, map_fd),          // 14: r1 = map
+        insn(0, 0, 0, 0, 0),                               // 15
+        insn(MOV_K, 3, 0, 0, XDP_PASS),                    // 16: r3 = fallback action
+        insn(CALL, 0, 0, 0, BPF_FUNC_REDIRECT_MAP),        // 17
+        insn(EXIT, 0, 0, 0, 0),                            // 18
+        insn(MOV_K, 0, 0, 0, XDP_PASS),                    // 19: PASS
+        insn(EXIT, 0, 0, 0, 0),                            // 20
+    ]
+}
+
+impl XdpSocket {
+    /// Open an XDP socket on `queue` of `ifname` and attach the program
+    pub(crate) fn open(
+        ifname: &str,
+        queue: u32,
+        vlanid: u16,
+        priority: u32,
+        proto: u16,
+        config: XdpConfig,
+    ) -> Result<XdpSocket, TsnError> {
+        if !config.frame_count.is_power_of_two()
+            || !config.ring_size.is_power_of_two()
+            || !config.frame_size.is_power_of_two()
+        {
+            return Err(TsnError::Socket {
+                op: "XDP setup",
+                source: io::Error::from_raw_os_error(libc::EINVAL),
+            });
+        }
+        let ifindex = if_nametoindex(ifname).map_err(|e| TsnError::Socket {
+            op: "if_nametoindex",
+            source: e.into(),
+        })?;
+        check_steering(ifname, queue, vlanid, proto)?;
+        check_vlan_offload(ifname)?;
+
+        let fd = unsafe { libc::socket(AF_XDP, libc::SOCK_RAW, 0) };
+        if fd < 0 {
+            return Err(TsnError::socket("Socket"));
+        }
+
+        let umem_len = config.frame_count as usize * config.frame_size as usize;
+        let umem = unsafe {
+            mmap(
+                None,
+                NonZeroUsize::new_unchecked(umem_len),
+                ProtFlags::PROT_READ | ProtFlags::PROT_WRITE,
+                MapFlags::MAP_PRIVATE | MapFlags::MAP_ANONYMOUS,
+                -1,
+                0,
+            )
+        };
+        let umem = match umem {
+            Ok(umem) => umem as *mut u8,
+            Err(e) => {
+                let _ = close(fd);
+                return Err(TsnError::Socket {
+                    op: "UMEM map",
+                    source: e.into(),
+                });
+            }
+        };
+
+        // Same priority to PCP mapping as the egress-qos-map of the VLAN interface
+        let pcp = if priority < 8 { priority as u16 } else { 0 };
+        // From here on dropping `sock` releases everything set up so far
+        let mut sock = XdpSocket {
+            fd,
+            umem,
+            umem_len,
+            frame_size: config.frame_size,
+            tci: (pcp << 13) | vlanid,
+            state: Mutex::new(XdpState {
+                fill: XskRing::null(),
+                comp: XskRing::null(),
+                rx: XskRing::null(),
+                tx: XskRing::null(),
+                free: Vec::new(),
+            }),
+            timeout: Mutex::new(None),
+            map_fd: -1,
+            prog_fd: -1,
+            link_fd: -1,
+        };
+        let result = sock.setup(ifindex, queue, vlanid, priority, proto, config);
+        if result.is_err() {
+            let _ = close(fd);
+        }
+        result.map(|_| sock)
+    }
+
+    fn setup(
+        &mut self,
+        ifindex: u32,
+        queue: u32,
+        vlanid: u16,
+        priority: u32,
+        proto: u16,
+        config: XdpConfig,
+    ) -> Result<(), TsnError> {
+        let fd = self.fd;
+        let res = unsafe {
+            libc::setsockopt(
+                fd,
+                libc::SOL_SOCKET,
+                libc::SO_PRIORITY,
+                &priority as *const u32 as *const libc::c_void,
+                mem::size_of::<u32>() as u32,
+            )
+        };
+        if res < 0 {
+            return Err(TsnError::socket("Socket option"));
+        }
+
+        let reg = xdp_umem_reg {
+            addr: self.umem as u64,
+            len: self.umem_len as u64,
+            chunk_size: config.frame_size,
+            headroom: 0,
+            flags: 0,
+            tx_metadata_len: 0,
+        };
+        setsockopt(fd, XDP_UMEM_REG, &reg, "Set XDP_UMEM_REG")?;
+        let size = config.ring_size;
+        setsockopt(fd, XDP_UMEM_FILL_RING, &size, "Set XDP_UMEM_FILL_RING")?;
+        setsockopt(
+            fd,
+            XDP_UMEM_COMPLETION_RING,
+            &size,
+            "Set XDP_UMEM_COMPLETION_RING",
+        )?;
+        setsockopt(fd, XDP_RX_RING, &size, "Set XDP_RX_RING")?;
+        setsockopt(fd, XDP_TX_RING, &size, "Set XDP_TX_RING")?;
+
+        let mut off = xdp_mmap_offsets::default();
+        let mut optlen = mem::size_of::<xdp_mmap_offsets>() as libc::socklen_t;
+        let res = unsafe {
+            libc::getsockopt(
+                fd,
+                SOL_XDP,
+                XDP_MMAP_OFFSETS,
+                &mut off as *mut _ as *mut libc::c_void,
+                &mut optlen,
+            )
+        };
+        if res < 0 {
+            return Err(TsnError::socket("Get XDP_MMAP_OFFSETS"));
+        }
+
+        {
+            let state = self.state.get_mut().unwrap();
+            state.fill = XskRing::map(fd, &off.fr, size, XDP_UMEM_PGOFF_FILL_RING)?;
+            state.comp = XskRing::map(fd, &off.cr, size, XDP_UMEM_PGOFF_COMPLETION_RING)?;
+            state.rx = XskRing::map(fd, &off.rx, size, XDP_PGOFF_RX_RING)?;
+            state.tx = XskRing::map(fd, &off.tx, size, XDP_PGOFF_TX_RING)?;
+
+            // First half of the UMEM receives, second half sends
+            let frames = (0..config.frame_count as u64).map(|i| i * config.frame_size as u64);
+            let (rx_frames, tx_frames): (Vec<u64>, Vec<u64>) =
+                frames.partition(|addr| *addr < self.umem_len as u64 / 2);
+            for addr in rx_frames {
+                if !state.fill.push(addr) {
+                    break;
+                }
+            }
+            state.free = tx_frames;
+        }
+
+        let addr = sockaddr_xdp {
+            sxdp_family: AF_XDP as u16,
+            sxdp_flags: XDP_USE_NEED_WAKEUP
+                | match config.mode {
+                    XdpMode::Native => 0,
+                    XdpMode::Generic => XDP_COPY,
+                },
+            sxdp_ifindex: ifindex,
+            sxdp_queue_id: queue,
+            sxdp_shared_umem_fd: 0,
+        };
+        let res = unsafe {
+            libc::bind(
+                fd,
+                &addr as *const sockaddr_xdp as *const libc::sockaddr,
+                mem::size_of::<sockaddr_xdp>() as u32,
+            )
+        };
+        if res < 0 {
+            return Err(TsnError::socket("Bind"));
+        }
+
+        self.map_fd = bpf(
+            BPF_MAP_CREATE,
+            &bpf_map_create_attr {
+                map_type: BPF_MAP_TYPE_XSKMAP,
+                key_size: 4,
+                value_size: 4,
+                max_entries: queue + 1,
+                map_flags: 0,
+            },
+            "Create XSKMAP",
+        )?;
+        let value = fd as u32;
+        bpf(
+            BPF_MAP_UPDATE_ELEM,
+            &bpf_map_elem_attr {
+                map_fd: self.map_fd as u32,
+                key: &queue as *const u32 as u64,
+                value: &value as *const u32 as u64,
+                flags: 0,
+            },
+            "Update XSKMAP",
+        )?;
+
+        let insns = program(self.map_fd, vlanid, proto);
+        let license = b"GPL\0";
+        let mut prog_name = [0u8; 16];
+        prog_name[..7].copy_from_slice(b"tsn_xsk");
+        self.prog_fd = bpf(
+            BPF_PROG_LOAD,
+            &bpf_prog_load_attr {
+                prog_type: BPF_PROG_TYPE_XDP,
+                insn_cnt: insns.len() as u32,
+                insns: insns.as_ptr() as u64,
+                license: license.as_ptr() as u64,
+                log_level: 0,
+                log_size: 0,
+                log_buf: 0,
+                kern_version: 0,
+                prog_flags: 0,
+                prog_name,
+                prog_ifindex: 0,
+                expected_attach_type: BPF_XDP,
+            },
+            "Load XDP program",
+        )?;
+        self.link_fd = bpf(
+            BPF_LINK_CREATE,
+            &bpf_link_create_attr {
+                prog_fd: self.prog_fd as u32,
+                target_ifindex: ifindex,
+                attach_type: BPF_XDP,
+                flags: match config.mode {
+                    XdpMode::Native => XDP_FLAGS_DRV_MODE,
+                    XdpMode::Generic => XDP_FLAGS_SKB_MODE,
+                },
+            },
+            "Attach XDP program",
+        )?;
+        Ok(())
+    }
+
+    pub(crate) fn fd(&self) -> i32 {
+        self.fd
+    }
+
+    pub(crate) fn set_timeout(&self, timeout: Duration) {
+        // Zero means no timeout, as for SO_RCVTIMEO
+        *self.timeout.lock().unwrap() = Some(timeout).filter(|t| !t.is_zero());
+    }
+
+    /// UMEM frame at `addr`. Borrowing the locked state keeps frames exclusive
+    fn frame<'a>(&self, _state: &'a mut XdpState, addr: u64, len: usize) -> &'a mut [u8] {
+        unsafe { std::slice::from_raw_parts_mut(self.umem.add(addr as usize), len) }
+    }
+
+    fn wait(&self, events: libc::c_short, deadline: Option<Instant>) -> Result<(), TsnError> {
+        let timeout_ms = match deadline {
+            Some(deadline) => {
+                let left = deadline.saturating_duration_since(Instant::now());
+                if left.is_zero() {
+                    return Err(TsnError::Socket {
+                        op: if events == libc::POLLIN {
+                            "Recv"
+                        } else {
+                            "Send"
+                        },
+                        source: io::Error::from_raw_os_error(libc::EAGAIN),
+                    });
+                }
+                left.as_millis().max(1) as libc::c_int
+            }
+            None => -1,
+        };
+        let mut pfd = libc::pollfd {
+            fd: self.fd,
+            events,
+            revents: 0,
+        };
+        if unsafe { libc::poll(&mut pfd, 1, timeout_ms) } < 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() != io::ErrorKind::Interrupted {
+                return Err(TsnError::Socket {
+                    op: "Poll",
+                    source: err,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Let the kernel process the TX ring
+    fn kick(&self) {
+        unsafe { libc::sendto(self.fd, ptr::null(), 0, libc::MSG_DONTWAIT, ptr::null(), 0) };
+    }
+
+    /// Send one frame, inserting the VLAN tag after the MAC addresses
+    pub(crate) fn send(&self, buf: &[u8]) -> Result<isize, TsnError> {
+        if buf.len() < 12 || buf.len() + 4 > self.frame_size as usize {
+            return Err(TsnError::Socket {
+                op: "Send",
+                source: io::Error::from_raw_os_error(libc::EMSGSIZE),
+            });
+        }
+
+        let timeout = *self.timeout.lock().unwrap();
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let mut state = self.state.lock().unwrap();
+        let addr = loop {
+            while let Some(addr) = state.comp.pop() {
+                state.free.push(addr);
+            }
+            if let Some(addr) = state.free.pop() {
+                break addr;
+            }
+            self.kick();
+            self.wait(libc::POLLOUT, deadline)?;
+        };
+
+        let len = buf.len() + 4;
+        let frame = self.frame(&mut state, addr, len);
+        frame[..12].copy_from_slice(&buf[..12]);
+        frame[12..14].copy_from_slice(&(libc::ETH_P_8021Q as u16).to_be_bytes());
+        frame[14..16].copy_from_slice(&self.tci.to_be_bytes());
+        frame[16..].copy_from_slice(&buf[12..]);
+
+        let desc = xdp_desc {
+            addr,
+            len: len as u32,
+            options: 0,
+        };
+        if !state.tx.push(desc) {
+            state.free.push(addr);
+            return Err(TsnError::Socket {
+                op: "Send",
+                source: io::Error::from_raw_os_error(libc::ENOBUFS),
+            });
+        }
+        // Copy mode transmits only when kicked, whatever the wakeup flag of the ring says
+        self.kick();
+        Ok(buf.len() as isize)
+    }
+
+    /// Receive one frame without its VLAN tag, returns its length and the TCI of the
+    /// tag. Without `wait`, `None` if nothing is queued
+    pub(crate) fn recv(
+        &self,
+        buf: &mut [u8],
+        wait: bool,
+    ) -> Result<Option<(usize, u16)>, TsnError> {
+        let timeout = *self.timeout.lock().unwrap();
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let mut state = self.state.lock().unwrap();
+        let desc = loop {
+            if let Some(desc) = state.rx.pop() {
+                break desc;
+            }
+            if !wait {
+                return Ok(None);
+            }
+            // poll also wakes the driver up if it waits for the fill ring
+            self.wait(libc::POLLIN, deadline)?;
+        };
+
+        // The program only redirects frames with the tag of the VLAN
+        let frame = self.frame(&mut state, desc.addr, desc.len as usize);
+        let len = (frame.len() - 4).min(buf.len());
+        let head = len.min(12);
+        buf[..head].copy_from_slice(&frame[..head]);
+        buf[head..len].copy_from_slice(&frame[16..16 + len - head]);
+        let tci = u16::from_be_bytes([frame[14], frame[15]]);
+
+        // Aligned UMEM: the frame start is the address with the offset bits cleared
+        state.fill.push(desc.addr & !(self.frame_size as u64 - 1));
+        Ok(Some((len, tci)))
+    }
+}
+
+impl<T> XskRing<T> {
+    /// Placeholder until the ring is mapped
+    fn null() -> XskRing<T> {
+        XskRing {
+            map: ptr::null_mut(),
+            map_len: 0,
+            producer: ptr::null(),
+            consumer: ptr::null(),
+            descs: ptr::null_mut(),
+            size: 0,
+        }
+    }
+}
+
+impl Drop for XdpSocket {
+    fn drop(&mut self) {
+        // Detach first, so no frame is redirected to a closing socket
+        for fd in [self.link_fd, self.prog_fd, self.map_fd] {
+            if fd >= 0 {
+                let _ = close(fd);
+            }
+        }
+        // Rings are unmapped by their own Drop
+        let _ = unsafe { munmap(self.umem as *mut libc::c_void, self.umem_len) };
+    }
+}
@@ -0,0 +1,75 @@
+//! Tests of `AsyncTsnSocket` on a veth pair, with software timestamps. They need root
+//! and pass after printing why they were skipped without it.
+
+#![cfg(feature = "async")]
+
+mod common;
+
+use std::future::poll_fn;
+use std::pin::Pin;
+use std::time::Duration;
+
+use futures_core::Stream;
+use tsn::time::Timespec;
+use tsn::{AsyncTsnSocket, TimestampMode, TimestampSource, TsnSocket};
+
+use common::ETH_P_TEST;
+
+fn open_async(ifname: &str) -> AsyncTsnSocket {
+    let sock = TsnSocket::builder(ifname)
+        .protocol(ETH_P_TEST)
+        .timestamps(TimestampMode::Any)
+        .open()
+        .unwrap();
+    AsyncTsnSocket::new(sock).unwrap()
+}
+
+fn frame(src: [u8; 6], id: u8) -> Vec<u8> {
+    let mut frame = vec![0xff; 6];
+    frame.extend_from_slice(&src);
+    frame.extend_from_slice(&ETH_P_TEST.to_be_bytes());
+    frame.extend_from_slice(&[id; 46]);
+    frame
+}
+
+#[tokio::test]
+async fn send_and_recv() {
+    let Some((a, b)) = common::veth("async", 1) else {
+        return;
+    };
+    let tx = open_async(&a);
+    let rx = open_async(&b);
+    let src = tx.get_ref().mac_address().unwrap();
+    let mut buf = [0u8; 1514];
+
+    let test = async {
+        // recv waits on the reactor until the frame comes in
+        let sent = async {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            tx.send(&frame(src, 1)).await
+        };
+        let (len, sent) = tokio::join!(rx.recv(&mut buf), sent);
+        assert_eq!(sent.unwrap(), 60);
+        assert_eq!(buf[..len.unwrap() as usize], frame(src, 1));
+
+        let before = Timespec::now(libc::CLOCK_REALTIME).as_nanos();
+        tx.send(&frame(src, 2)).await.unwrap();
+        let (len, ts) = rx.recv_with_timestamp(&mut buf).await.unwrap();
+        let after = Timespec::now(libc::CLOCK_REALTIME).as_nanos();
+        assert_eq!(buf[..len], frame(src, 2));
+        assert_eq!(ts.source, TimestampSource::Sw);
+        assert!((before..=after).contains(&ts.time.as_nanos()));
+
+        // One timestamp per frame sent, in order
+        let mut stream = tx.tx_timestamps();
+        for id in 0..2 {
+            let ts = poll_fn(|cx| Pin::new(&mut stream).poll_next(cx)).await;
+            let ts = ts.unwrap().unwrap();
+            assert_eq!(ts.id, id);
+            assert!(ts.time.as_nanos() <= after);
+        }
+    };
+    tokio::time::timeout(Duration::from_secs(5), test)
+        .await
+        .unwrap();
+}
@@ -0,0 +1,97 @@
+//! Tests of the errno and message the C ABI reports, through calls which fail before
+//! touching a NIC.
+
+use std::ffi::CStr;
+use std::io;
+use std::ptr;
+
+use nix::libc;
+use tsn::capi::*;
+
+fn last_error() -> String {
+    unsafe { CStr::from_ptr(tsn_last_error()) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+fn errno() -> i32 {
+    io::Error::last_os_error().raw_os_error().unwrap()
+}
+
+#[test]
+fn unknown_socket() {
+    let buf = [0u8; 64];
+    let sent = unsafe { tsn_send(12345, buf.as_ptr().cast(), buf.len()) };
+    assert_eq!(sent, -1);
+    assert_eq!(errno(), libc::EBADF);
+    assert!(last_error().contains("Find socket"), "{}", last_error());
+
+    assert_eq!(tsn_sock_close(12345), -1);
+    assert_eq!(errno(), libc::EBADF);
+}
+
+#[test]
+fn null_arguments() {
+    assert_eq!(unsafe { tsn_sock_open(ptr::null(), 10, 3, 0x1337) }, -1);
+    assert_eq!(errno(), libc::EINVAL);
+    assert_eq!(last_error(), "ifname is NULL");
+
+    assert_eq!(
+        unsafe { tsn_get_tx_timestamp(3, ptr::null_mut(), ptr::null_mut()) },
+        -1
+    );
+    assert_eq!(errno(), libc::EINVAL);
+}
+
+#[test]
+fn sleep_until_past() {
+    let past = libc::timespec {
+        tv_sec: 1,
+        tv_nsec: 0,
+    };
+    assert_eq!(unsafe { tsn_time_sleep_until(&past) }, 0);
+
+    // errno of the failed sleep, not always EINTR
+    let before_epoch = libc::timespec {
+        tv_sec: -5,
+        tv_nsec: 0,
+    };
+    assert_eq!(unsafe { tsn_time_sleep_until(&before_epoch) }, -1);
+    assert_eq!(errno(), libc::EINVAL);
+}
+
+#[test]
+fn time_clocks() {
+    let mut ts = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    assert_eq!(unsafe { tsn_time_now(libc::CLOCK_TAI, &mut ts) }, 0);
+    assert!(ts.tv_sec > 0);
+    let mut tai = ts;
+    assert_eq!(unsafe { tsn_time_utc_to_tai(&ts, &mut tai) }, 0);
+    assert!(tai.tv_sec >= ts.tv_sec);
+    assert_eq!(
+        unsafe { tsn_time_sleep_until_clock(libc::CLOCK_TAI, &ts) },
+        0
+    );
+
+    assert_eq!(unsafe { tsn_time_now(libc::CLOCK_BOOTTIME, &mut ts) }, -1);
+    assert_eq!(errno(), libc::EINVAL);
+    assert_eq!(last_error(), "clock is not supported");
+    assert_eq!(
+        unsafe { tsn_time_sleep_until_clock(libc::CLOCK_TAI, ptr::null()) },
+        -1
+    );
+    assert_eq!(errno(), libc::EINVAL);
+}
+
+#[test]
+fn timeout_out_of_range() {
+    for (tv_sec, tv_usec) in [(0, 5_000_000), (0, -1), (-1, 0)] {
+        let timeout = libc::timeval { tv_sec, tv_usec };
+        assert_eq!(unsafe { tsn_sock_set_timeout(12345, &timeout) }, -1);
+        assert_eq!(errno(), libc::EINVAL);
+        assert_eq!(last_error(), "timeout is out of range");
+    }
+}
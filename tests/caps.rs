@@ -0,0 +1,107 @@
+//! Tests of the capability probe and of the modes setup picks from it. Loopback is
+//! always there and offloads nothing, other capabilities are made up.
+
+use nix::libc;
+use tsn::caps::{self, Capabilities, Feature};
+use tsn::config::{read_config, Config};
+use tsn::tas::{TasConfig, TasMode};
+use tsn::vlan::{create_vlan_with, RecordingExecutor};
+use tsn::TsnError;
+
+fn tas_config(ifname: &str) -> TasConfig {
+    let configs = read_config(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/data/golden.yaml"
+    ))
+    .unwrap();
+    configs[ifname].tas.clone().unwrap()
+}
+
+#[test]
+fn loopback() {
+    let caps = caps::probe("lo").unwrap();
+    assert_eq!(caps.tx_queues, Some(1));
+    assert!(matches!(
+        caps.tc_offload,
+        Feature::Unsupported | Feature::Unknown
+    ));
+    assert_ne!(caps.preemption, Feature::On);
+    assert_eq!(caps.rx_filter(), None);
+}
+
+#[test]
+fn missing_interface() {
+    assert!(matches!(
+        caps::probe("tsn-missing0"),
+        Err(TsnError::InterfaceNotFound(_))
+    ));
+}
+
+#[test]
+fn unmet_config_is_rejected_up_front() {
+    // 4 traffic classes, loopback has 1 TX queue
+    let config = Config {
+        tas: Some(tas_config("tsngold0")),
+        cbs: None,
+        ptp: None,
+    };
+    let mut recorder = RecordingExecutor::new();
+    let res = create_vlan_with(&mut recorder, &config, "lo", 10);
+    assert!(matches!(res, Err(TsnError::Unsupported(_))), "{:?}", res);
+    assert!(recorder.commands().is_empty());
+}
+
+#[test]
+fn modes() {
+    let off = Capabilities {
+        tc_offload: Feature::Off,
+        ..Capabilities::unknown("tsn0")
+    };
+    let on = Capabilities {
+        tc_offload: Feature::On,
+        ..Capabilities::unknown("tsn0")
+    };
+    let mut tas = tas_config("tsngold0");
+    assert_eq!(off.tas_mode(&tas).unwrap(), TasMode::Software);
+    assert_eq!(on.tas_mode(&tas).unwrap(), TasMode::FullOffload);
+    tas.txtime_delay = 100_000;
+    assert_eq!(on.tas_mode(&tas).unwrap(), TasMode::TxtimeAssist);
+
+    tas.mode = TasMode::FullOffload;
+    let err = off.tas_mode(&tas).unwrap_err().to_string();
+    assert!(err.contains("ethtool -K tsn0 hw-tc-offload on"), "{}", err);
+    assert!(!off.offload(None, "etf").unwrap());
+    assert!(!on.offload(Some(false), "etf").unwrap());
+    assert!(on.offload(None, "cbs").unwrap());
+
+    let few_queues = Capabilities {
+        tx_queues: Some(2),
+        ..on
+    };
+    assert!(few_queues.check_queues(2).is_ok());
+    assert!(matches!(
+        few_queues.check_queues(4),
+        Err(TsnError::Unsupported(_))
+    ));
+}
+
+#[test]
+fn names() {
+    assert_eq!(
+        caps::timestamping_names(
+            libc::SOF_TIMESTAMPING_TX_HARDWARE
+                | libc::SOF_TIMESTAMPING_RX_HARDWARE
+                | libc::SOF_TIMESTAMPING_RAW_HARDWARE
+        ),
+        [
+            "hardware-transmit",
+            "hardware-receive",
+            "hardware-raw-clock"
+        ]
+    );
+    assert_eq!(caps::tx_type_names(0b11), ["off", "on"]);
+    assert_eq!(
+        caps::rx_filter_names(1 << 1 | 1 << 12),
+        ["all", "ptpv2-event"]
+    );
+}
@@ -0,0 +1,46 @@
+use std::{fs, os::unix::fs::PermissionsExt};
+
+use tsn::{cbs, TsnError};
+
+/// Puts a fake `ethtool` running `script` in front of PATH. The only test of
+/// this binary, so no other test sees the fake
+fn fake_ethtool(script: &str) {
+    let dir = std::env::temp_dir().join(format!("tsn-ethtool-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("ethtool");
+    // Write a new file each time, replacing a file that is being executed fails with ETXTBSY
+    let _ = fs::remove_file(&path);
+    fs::write(&path, format!("#!/bin/sh\n{}\n", script)).unwrap();
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+    let paths = std::env::var("PATH").unwrap_or_default();
+    std::env::set_var("PATH", format!("{}:{}", dir.display(), paths));
+}
+
+#[test]
+fn linkspeed() {
+    fake_ethtool("echo 'Settings for lo:'; echo '	Speed: 1000Mb/s'");
+    assert_eq!(cbs::get_linkspeed("lo").unwrap(), "1000Mb/s");
+
+    // Link is down
+    fake_ethtool("echo 'Settings for lo:'; echo '	Speed: Unknown!'");
+    let e = cbs::get_linkspeed("lo").unwrap_err();
+    assert!(
+        matches!(&e, TsnError::LinkSpeedUnknown(ifname) if ifname == "lo"),
+        "{}",
+        e
+    );
+
+    // ethtool itself fails, its stderr is kept
+    fake_ethtool("echo 'netlink error: Operation not permitted' >&2; exit 75");
+    let e = cbs::get_linkspeed("lo").unwrap_err();
+    match &e {
+        TsnError::CommandFailed { status, stderr, .. } => {
+            assert_eq!(*status, Some(75));
+            assert!(stderr.contains("Operation not permitted"), "{}", e);
+        }
+        _ => panic!("{}", e),
+    }
+
+    let e = cbs::get_linkspeed("tsn-no-such-if").unwrap_err();
+    assert!(matches!(e, TsnError::InterfaceNotFound(_)), "{}", e);
+}
@@ -0,0 +1,71 @@
+//! Helpers of the socket tests: sockets on the virtual wire, and veth pairs for the
+//! backends which need root.
+//!
+//! A veth pair lives in a network namespace of its own which only the calling
+//! thread, the test, enters. It goes away with the last socket in it.
+
+// Each test binary uses some of the helpers only
+#![allow(dead_code)]
+
+use std::process::Command;
+use std::time::Duration;
+
+use nix::sched::{unshare, CloneFlags};
+use tsn::wire::WireConfig;
+use tsn::{Backend, TsnError, TsnSocket};
+
+/// EtherType of the test frames
+pub const ETH_P_TEST: u16 = 0x1337;
+
+/// Socket on the virtual wire `name`, or on its VLAN `vlanid`, bound to
+/// [`ETH_P_TEST`] with a receive timeout of 200 ms
+pub fn open_wire(name: &str, vlanid: Option<u16>, config: WireConfig) -> TsnSocket {
+    let builder = TsnSocket::builder(name)
+        .protocol(ETH_P_TEST)
+        .backend(Backend::Virtual(config))
+        .timeout(Duration::from_millis(200));
+    match vlanid {
+        Some(vlanid) => builder.vlan(vlanid).open().unwrap(),
+        None => builder.open().unwrap(),
+    }
+}
+
+/// Whether a receive failed because nothing arrived in time
+pub fn is_timeout(res: Result<isize, TsnError>) -> bool {
+    matches!(res, Err(e) if e.raw_os_error() == Some(libc::EAGAIN))
+}
+
+/// Move the calling thread to a new network namespace holding the veth pair
+/// `<tag><pid>a` and `<tag><pid>b` with `rx_queues` RX queues each, both up.
+/// `None` after printing why the test is skipped
+pub fn veth(tag: &str, rx_queues: u32) -> Option<(String, String)> {
+    if !nix::unistd::geteuid().is_root() {
+        eprintln!("skipped: needs root");
+        return None;
+    }
+    if let Err(e) = unshare(CloneFlags::CLONE_NEWNET) {
+        eprintln!("skipped: cannot create network namespaces: {}", e);
+        return None;
+    }
+    // Unique across processes, `/run/libtsn` is shared by all namespaces
+    let pid = std::process::id() % 100000;
+    let (a, b) = (format!("{}{}a", tag, pid), format!("{}{}b", tag, pid));
+    let queues = rx_queues.to_string();
+    let ip = |args: &[&str]| {
+        let status = Command::new("ip").args(args).status();
+        if !status.is_ok_and(|status| status.success()) {
+            eprintln!("skipped: ip {} failed", args.join(" "));
+            return false;
+        }
+        true
+    };
+    let add = ["link", "add", &a, "numrxqueues", &queues, "type", "veth"];
+    let peer = ["peer", "name", &b, "numrxqueues", &queues];
+    if !ip(&[&add[..], &peer[..]].concat())
+        || !ip(&["link", "set", "up", &a])
+        || !ip(&["link", "set", "up", &b])
+    {
+        return None;
+    }
+    Some((a, b))
+}
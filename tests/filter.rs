@@ -0,0 +1,113 @@
+//! Tests of socket filters. The virtual wire runs them without root, the VLAN tag test
+//! needs root for a packet socket on loopback.
+
+mod common;
+
+use std::time::Duration;
+
+use tsn::filter::Filter;
+use tsn::wire::WireConfig;
+use tsn::{TsnError, TsnSocket};
+
+use common::{is_timeout, open_wire, ETH_P_TEST};
+
+#[test]
+fn program() {
+    let ret = (libc::BPF_RET | libc::BPF_K) as u16;
+    let accept_all = Filter::new().program(0).unwrap();
+    assert_eq!(accept_all.len(), 2);
+    assert_eq!((accept_all[0].code, accept_all[0].k), (ret, u32::MAX));
+
+    // 4 + 2 bytes of MAC, then 2 bytes of EtherType, each a load and a jump
+    let program = Filter::new()
+        .dst([0xff; 6])
+        .ethertype(ETH_P_TEST)
+        .program(8)
+        .unwrap();
+    assert_eq!(program.len(), 8);
+    assert_eq!(program[0].k, 8);
+    assert_eq!(program[4].k, 8 + 12);
+    assert_eq!(program[5].k, ETH_P_TEST as u32);
+    // Every mismatch jumps to the last instruction, which drops
+    for (pc, insn) in program.iter().enumerate().skip(1).step_by(2).take(3) {
+        assert_eq!(pc + 1 + insn.jf as usize, program.len() - 1);
+    }
+    assert_eq!((program[7].code, program[7].k), (ret, 0));
+
+    let long = (0..200).fold(Filter::new(), |filter, _| filter.ethertype(ETH_P_TEST));
+    assert!(matches!(long.program(0), Err(TsnError::Unsupported(_))));
+}
+
+#[test]
+fn wire() {
+    let name = format!("filter-{}", std::process::id());
+    let a = open_wire(&name, None, WireConfig::default());
+    let b = open_wire(&name, None, WireConfig::default());
+    let c = open_wire(&name, None, WireConfig::default());
+    let stream = Filter::new().src(a.mac_address().unwrap()).payload(2, &[7]);
+    b.attach_filter(&stream).unwrap();
+
+    let payload = |id: u8| [0, 0, id, 0xee];
+    a.send_to([0xff; 6], ETH_P_TEST, &payload(6)).unwrap();
+    c.send_to([0xff; 6], ETH_P_TEST, &payload(7)).unwrap();
+    a.send_to([0xff; 6], ETH_P_TEST, &payload(7)).unwrap();
+    let mut buf = [0u8; 1514];
+    let len = b.recv(&mut buf).unwrap() as usize;
+    assert_eq!(&buf[14..len], &payload(7));
+    assert_eq!(&buf[6..12], &a.mac_address().unwrap());
+    assert!(is_timeout(b.recv(&mut buf)));
+
+    b.detach_filter().unwrap();
+    b.detach_filter().unwrap();
+    c.send_to([0xff; 6], ETH_P_TEST, &payload(1)).unwrap();
+    assert!(b.recv(&mut buf).is_ok());
+}
+
+#[test]
+fn pcp_needs_the_tag() {
+    let name = format!("filter-pcp-{}", std::process::id());
+    let sock = open_wire(&name, Some(10), WireConfig::default());
+    assert!(matches!(
+        sock.attach_filter(&Filter::new().pcp(3)),
+        Err(TsnError::Unsupported(_))
+    ));
+}
+
+#[test]
+fn pcp() {
+    if !nix::unistd::geteuid().is_root() {
+        eprintln!("skipped: needs root");
+        return;
+    }
+    // The kernel only leaves the tag to sockets bound to all protocols
+    let open = |proto: u16| {
+        // No VLAN, so config.yaml need not know lo
+        TsnSocket::builder("lo")
+            .protocol(proto)
+            .timeout(Duration::from_millis(200))
+            .open()
+            .unwrap()
+    };
+    let tx = open(ETH_P_TEST);
+    let rx = open(libc::ETH_P_ALL as u16);
+    rx.attach_filter(&Filter::new().pcp(5).ethertype(ETH_P_TEST))
+        .unwrap();
+
+    let tagged = |pcp: u16, marker: u8| {
+        let mut frame = vec![0xff; 6];
+        frame.extend_from_slice(&[0x02, 0, 0, 0, 0, 1]);
+        frame.extend_from_slice(&(libc::ETH_P_8021Q as u16).to_be_bytes());
+        frame.extend_from_slice(&(pcp << 13 | 10).to_be_bytes());
+        frame.extend_from_slice(&ETH_P_TEST.to_be_bytes());
+        frame.extend_from_slice(&[marker; 46]);
+        frame
+    };
+    tx.send(&tagged(3, 1)).unwrap();
+    tx.send_to([0xff; 6], ETH_P_TEST, &[2; 46]).unwrap();
+    tx.send(&tagged(5, 3)).unwrap();
+    let mut buf = [0u8; 1514];
+    let (len, info) = rx.recv_from(&mut buf).unwrap();
+    assert_eq!(buf[len - 1], 3);
+    assert_eq!(info.vlan.and_then(|vlan| vlan.pcp), Some(5));
+    assert!(is_timeout(rx.recv(&mut buf)));
+}
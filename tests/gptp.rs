@@ -0,0 +1,226 @@
+//! Tests of the gPTP end station. Two ports run on the virtual wire with software
+//! timestamps, so nothing needs root or a NIC.
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+use tsn::gptp::bmca::{self, PriorityVector, SystemIdentity};
+use tsn::gptp::message::{self, Announce, Body, Header, Message};
+use tsn::gptp::servo::{PiServo, ServoAction};
+use tsn::gptp::{
+    self, ClockIdentity, ClockQuality, Event, GptpConfig, LocalClock, Port, PortIdentity, PortState,
+};
+use tsn::time::Timespec;
+use tsn::wire::WireConfig;
+use tsn::{Backend, TsnSocket};
+
+const GM: ClockIdentity = ClockIdentity([0x00, 0x11, 0x22, 0xff, 0xfe, 0x33, 0x44, 0x55]);
+
+fn source(clock: ClockIdentity) -> PortIdentity {
+    PortIdentity { clock, port: 1 }
+}
+
+fn announce(priority1: u8, grandmaster: ClockIdentity) -> Announce {
+    Announce {
+        current_utc_offset: 37,
+        priority1,
+        clock_quality: GptpConfig::default().clock_quality,
+        priority2: 248,
+        grandmaster,
+        steps_removed: 0,
+        time_source: 0xa0,
+        path_trace: vec![grandmaster],
+    }
+}
+
+#[test]
+fn messages() {
+    let requesting = source(ClockIdentity::from_mac([0x02, 0, 0, 0, 0, 1]));
+    let time = Timespec {
+        tv_sec: 1 << 40,
+        tv_nsec: 999_999_999,
+    };
+    let bodies = [
+        Body::Sync,
+        Body::PdelayReq,
+        Body::FollowUp {
+            precise_origin: time,
+            rate_ratio: 1.0 + 1e-6,
+        },
+        Body::PdelayResp {
+            request_receipt: time,
+            requesting,
+        },
+        Body::PdelayRespFollowUp {
+            response_origin: time,
+            requesting,
+        },
+        Body::Announce(announce(100, GM)),
+    ];
+    for body in bodies {
+        let mut header = Header::new(source(GM), 0xbeef);
+        header.flags = message::FLAG_TWO_STEP;
+        header.correction = -3 << 16;
+        header.log_interval = -3;
+        let msg = Message { header, body };
+        let mut buf = msg.encode();
+        assert_eq!(buf[0], 0x10 | msg.message_type() as u8);
+        assert_eq!(u16::from_be_bytes([buf[2], buf[3]]) as usize, buf.len());
+        // Padding to the minimum frame size is ignored
+        buf.resize(buf.len().max(46), 0);
+        let decoded = Message::decode(&buf).unwrap();
+        match (&decoded.body, &msg.body) {
+            (Body::FollowUp { rate_ratio: a, .. }, Body::FollowUp { rate_ratio: b, .. }) => {
+                assert!((a - b).abs() < 1e-9)
+            }
+            _ => assert_eq!(decoded, msg),
+        }
+        assert_eq!(decoded.header.correction_ns(), -3);
+    }
+
+    let sync = Message {
+        header: Header::new(source(GM), 1),
+        body: Body::Sync,
+    }
+    .encode();
+    assert_eq!(sync.len(), 44);
+    // PTP of the default profile and truncated messages are not for us
+    let mut other = sync.clone();
+    other[0] &= 0x0f;
+    assert_eq!(Message::decode(&other), None);
+    assert_eq!(Message::decode(&sync[..40]), None);
+    let mut short = sync.clone();
+    short[2..4].copy_from_slice(&10u16.to_be_bytes());
+    assert_eq!(Message::decode(&short), None);
+    assert_eq!(GM.to_string(), "001122.fffe.334455");
+}
+
+#[test]
+fn best_master() {
+    let quality = GptpConfig::default().clock_quality;
+    let system = |priority1: u8, clock_identity: ClockIdentity| SystemIdentity {
+        priority1,
+        clock_quality: quality,
+        priority2: 248,
+        clock_identity,
+    };
+    let other = ClockIdentity([0xff; 8]);
+    assert!(system(100, other) < system(248, GM));
+    assert!(system(248, GM) < system(248, other));
+    let better_class = SystemIdentity {
+        clock_quality: ClockQuality {
+            class: 6,
+            ..quality
+        },
+        ..system(248, other)
+    };
+    assert!(better_class < system(248, GM));
+
+    // Same grandmaster, the shorter path wins
+    let near = PriorityVector::of_announce(&announce(248, GM), source(other), 1);
+    let far = PriorityVector {
+        steps_removed: 1,
+        ..near
+    };
+    assert!(near < far);
+
+    let own = ClockIdentity([1; 8]);
+    assert!(bmca::qualifies(&announce(248, GM), own));
+    assert!(!bmca::qualifies(&announce(248, own), own));
+    let mut looped = announce(248, GM);
+    looped.path_trace.push(own);
+    assert!(!bmca::qualifies(&looped, own));
+}
+
+#[test]
+fn servo() {
+    let interval = 0.125;
+    let mut servo = PiServo::new(interval, 500_000.0);
+    assert_eq!(servo.sample(1_000_000), ServoAction::Step(-1_000_000));
+
+    // Clock running 10 ppm fast, 5 us ahead
+    let (drift, mut offset, mut frequency) = (10_000.0, 5_000.0, 0.0);
+    for _ in 0..200 {
+        match servo.sample(offset as i64) {
+            ServoAction::Frequency(ppb) => frequency = ppb,
+            ServoAction::Step(ns) => panic!("stepped by {} ns", ns),
+        }
+        offset += (drift + frequency) * interval;
+    }
+    assert!(offset.abs() < 10.0, "offset {}", offset);
+    assert!((frequency + drift).abs() < 10.0, "frequency {}", frequency);
+}
+
+/// Run a port on the wire `name` until `done` holds for its status or `timeout`
+fn run_port(
+    name: String,
+    config: GptpConfig,
+    timeout: Duration,
+    done: fn(&gptp::Status) -> bool,
+) -> thread::JoinHandle<(PortIdentity, gptp::Status, Vec<Event>)> {
+    thread::spawn(move || {
+        let wire = WireConfig {
+            delay: Duration::from_micros(200),
+            loss_ppm: 0,
+        };
+        let sock = TsnSocket::builder(&name)
+            .protocol(gptp::ETHERTYPE)
+            .backend(Backend::Virtual(wire))
+            .open()
+            .unwrap();
+        let mut port = Port::new(sock, config).unwrap();
+        assert!(matches!(port.clock(), LocalClock::System));
+        let mut events = Vec::new();
+        let start = Instant::now();
+        while start.elapsed() < timeout && !done(&port.status()) {
+            if let Some(event) = port.poll(Duration::from_millis(50)).unwrap() {
+                events.push(event);
+            }
+        }
+        (port.identity(), port.status(), events)
+    })
+}
+
+#[test]
+fn wire() {
+    let name = format!("gptp-{}", std::process::id());
+    let fast = GptpConfig {
+        log_announce_interval: -3,
+        log_sync_interval: -4,
+        log_pdelay_req_interval: -3,
+        // Software timestamps of the wire, 200 us apart
+        neighbor_prop_delay_thresh: 10_000_000.0,
+        ..GptpConfig::default()
+    };
+    let gm_config = GptpConfig {
+        priority1: 100,
+        ..fast.clone()
+    };
+    let timeout = Duration::from_secs(10);
+    let gm = run_port(name.clone(), gm_config, Duration::from_secs(4), |_| false);
+    let slave = run_port(name, fast, timeout, |status| {
+        status.state == PortState::Slave && status.offset.is_some()
+    });
+    let (slave_id, status, events) = slave.join().unwrap();
+    let (gm_id, gm_status, _) = gm.join().unwrap();
+
+    assert_eq!(gm_status.state, PortState::Master);
+    assert_eq!(gm_status.grandmaster, Some(gm_id.clock));
+    assert_ne!(gm_id, slave_id);
+    assert_eq!(status.state, PortState::Slave);
+    assert_eq!(status.grandmaster, Some(gm_id.clock));
+    assert_eq!(status.parent, Some(gm_id));
+    assert!(status.as_capable);
+    // RX timestamps of the wire are the send time plus its delay, exactly
+    let delay = status.mean_link_delay.unwrap();
+    assert!((delay - 200_000.0).abs() < 1_000.0, "delay {}", delay);
+    // Both ends read the same clock
+    let offset = status.offset.unwrap();
+    assert!(offset.abs() < 1_000, "offset {}", offset);
+    // The system clock is left alone
+    assert!(events.contains(&Event::Offset {
+        offset,
+        action: None
+    }));
+    assert!(events.contains(&Event::AsCapable(true)));
+}
@@ -0,0 +1,63 @@
+//! Tests of the shared hardware timestamp config. Loopback and veth have no
+//! `SIOCGHWTSTAMP`, so configs are made up; the cleanup test needs root.
+
+use std::fs;
+
+use nix::libc;
+use tsn::hwtstamp::{self, HwTstampConfig};
+use tsn::TsnError;
+
+#[test]
+fn interface_without_hw_timestamps() {
+    assert_eq!(hwtstamp::get("lo").unwrap(), None);
+    assert!(matches!(
+        hwtstamp::get("tsn-missing0"),
+        Err(TsnError::InterfaceNotFound(_))
+    ));
+}
+
+#[test]
+fn widened() {
+    let ptp = HwTstampConfig {
+        tx_type: libc::HWTSTAMP_TX_ONESTEP_SYNC,
+        rx_filter: libc::HWTSTAMP_FILTER_PTP_V2_EVENT,
+    };
+    // Set up by ptp4l already, nothing to change
+    assert_eq!(
+        ptp.widened(true, Some(libc::HWTSTAMP_FILTER_PTP_V2_L2_EVENT)),
+        ptp
+    );
+    assert_eq!(ptp.widened(false, None), ptp);
+    assert_eq!(
+        ptp.widened(true, Some(libc::HWTSTAMP_FILTER_ALL)).rx_filter,
+        libc::HWTSTAMP_FILTER_ALL
+    );
+    assert_eq!(
+        HwTstampConfig::OFF.widened(true, None),
+        HwTstampConfig {
+            tx_type: libc::HWTSTAMP_TX_ON,
+            rx_filter: libc::HWTSTAMP_FILTER_NONE,
+        }
+    );
+    assert_eq!(
+        HwTstampConfig::OFF
+            .widened(false, Some(libc::HWTSTAMP_FILTER_PTP_V2_EVENT))
+            .rx_filter,
+        libc::HWTSTAMP_FILTER_PTP_V2_EVENT
+    );
+    assert_eq!(ptp.to_string(), "tx onestep-sync, rx ptpv2-event");
+}
+
+#[test]
+fn gc_removes_stale_record() {
+    if !nix::unistd::geteuid().is_root() {
+        eprintln!("skipped: needs root");
+        return;
+    }
+    // Left by a holder which exited without closing, on an interface which is gone
+    let path = "/run/libtsn/tsn-missing1.hwtstamp";
+    fs::create_dir_all("/run/libtsn").unwrap();
+    fs::write(path, "0 0 1 0\n").unwrap();
+    tsn::gc_vlans().unwrap();
+    assert!(fs::metadata(path).is_err());
+}
@@ -0,0 +1,368 @@
+//! Multi-process tests of the VLAN refcounting of `sock_open` and `sock_close`.
+//!
+//! Each test creates a veth pair in a throwaway network namespace and starts
+//! holders: copies of this test binary which open a socket in the namespace and keep
+//! it until their stdin is closed, or until they are killed.
+//!
+//! The tests need root, `ip`, and the 8021q module. The TAS and CBS tests also need
+//! the taprio, etf, mqprio and cbs qdiscs. A test whose requirements are missing
+//! passes after printing why it was skipped. The tests run one at a time, as `gc`
+//! sweeps the VLANs of all namespaces.
+
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::process::{Child, ChildStdout, Command, Stdio};
+use std::sync::{Mutex, MutexGuard};
+
+/// Set in holders to "<interface> <VLAN ID>"
+const HOLDER_ENV: &str = "TSN_NETNS_HOLDER";
+/// Prefix of the lines holders report on
+const REPORT: &str = "holder: ";
+/// Holder files of `sock_open`
+const RUN_DIR: &str = "/run/libtsn";
+
+static SERIAL: Mutex<()> = Mutex::new(());
+
+const TAS_CONFIG: &str = "
+    tas:
+      schedule:
+        - time: 300us
+          prio: [ 5 ]
+        - time: 700us
+          prio: [ -1 ]
+      etf:
+        - prio: 5
+          delta: 200us
+";
+
+const CBS_CONFIG: &str = "
+    cbs:
+      3:
+        class: a
+        max_frame: 512B
+        bandwidth: 70Mbps
+      2:
+        class: b
+        max_frame: 512B
+        bandwidth: 30Mbps
+";
+
+/// Accepted by the config, but taprio refuses entries of no time
+const BAD_TAS_CONFIG: &str = "
+    tas:
+      mode: software
+      schedule:
+        - time: 300us
+          prio: [ 5 ]
+        - time: 0
+          prio: [ -1 ]
+";
+
+/// Entry point of the holder processes, does nothing in a normal test run
+#[test]
+fn holder() {
+    let Ok(args) = std::env::var(HOLDER_ENV) else {
+        return;
+    };
+    let (ifname, vlanid) = args.split_once(' ').unwrap();
+    let vlanid = vlanid.parse().unwrap();
+    let report = |msg: &str| {
+        println!("{}{}", REPORT, msg);
+        io::stdout().flush().unwrap();
+    };
+    match tsn::sock_open(ifname, vlanid, 0, libc::ETH_P_ALL as u16) {
+        Ok(mut sock) => {
+            report("ready");
+            io::stdin().read_to_end(&mut Vec::new()).unwrap();
+            match sock.close() {
+                Ok(()) => report("closed"),
+                Err(e) => report(&format!("error: {}", e)),
+            }
+        }
+        Err(e) => report(&format!("error: {}", e)),
+    }
+}
+
+struct Netns {
+    name: String,
+    veth: String,
+    config: String,
+    _serial: MutexGuard<'static, ()>,
+}
+
+impl Netns {
+    /// Namespace with the veth pair `<tag><pid>`, configured with `nic_config`.
+    /// `None` if namespaces cannot be created here
+    fn new(tag: &str, nic_config: &str) -> Option<Netns> {
+        if !nix::unistd::geteuid().is_root() {
+            eprintln!("skipped: needs root");
+            return None;
+        }
+        // A failed test poisons the lock, which does not matter to the others
+        let serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+        let pid = std::process::id();
+        let name = format!("tsn-{}-{}", tag, pid);
+        // Short enough to keep VLAN names untruncated
+        let veth = format!("{}{}", tag, pid % 100000);
+        if !run("ip", &["netns", "add", &name]) {
+            eprintln!("skipped: cannot create network namespaces");
+            return None;
+        }
+        let config = std::env::temp_dir()
+            .join(format!("{}.yaml", name))
+            .to_string_lossy()
+            .into_owned();
+        let netns = Netns {
+            name,
+            veth,
+            config,
+            _serial: serial,
+        };
+
+        let peer = format!("{}p", netns.veth);
+        let queues = ["numtxqueues", "8", "numrxqueues", "8"];
+        let mut args = vec!["link", "add", "name", &netns.veth];
+        args.extend(queues);
+        args.extend(["type", "veth", "peer", "name", &peer]);
+        args.extend(queues);
+        assert!(netns.ip(&args));
+        assert!(netns.ip(&["link", "set", "up", &netns.veth]));
+        assert!(netns.ip(&["link", "set", "up", &peer]));
+        fs::write(
+            &netns.config,
+            format!("nics:\n  {}:{}", netns.veth, nic_config),
+        )
+        .unwrap();
+        Some(netns)
+    }
+
+    fn ip(&self, args: &[&str]) -> bool {
+        let mut ns_args = vec!["-n", &self.name];
+        ns_args.extend(args);
+        run("ip", &ns_args)
+    }
+
+    fn vlan(&self, vlanid: u16) -> String {
+        format!("{}.{}", self.veth, vlanid)
+    }
+
+    /// Start a holder of VLAN `vlanid`. `Err` holds the error of `sock_open`
+    fn holder(&self, vlanid: u16) -> Result<Holder, String> {
+        let mut child = Command::new("ip")
+            .args(["netns", "exec", &self.name])
+            .arg(std::env::current_exe().unwrap())
+            .args(["--exact", "holder", "--nocapture", "--test-threads", "1"])
+            .env(HOLDER_ENV, format!("{} {}", self.veth, vlanid))
+            .env("CONFIG_PATH", &self.config)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut stdout = BufReader::new(child.stdout.take().unwrap());
+        match read_report(&mut stdout).as_str() {
+            "ready" => Ok(Holder { child, stdout }),
+            error => {
+                child.wait().unwrap();
+                Err(error.to_string())
+            }
+        }
+    }
+
+    /// Start the first holder of VLAN `vlanid`. `None` if the kernel lacks the
+    /// link type or a qdisc
+    fn first_holder(&self, vlanid: u16) -> Option<Holder> {
+        match self.holder(vlanid) {
+            Ok(holder) => Some(holder),
+            Err(e) if e.contains("Unknown device type") || e.contains("qdisc kind is unknown") => {
+                eprintln!("skipped: {}", e);
+                None
+            }
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    fn has_link(&self, name: &str) -> bool {
+        let output = Command::new("ip")
+            .args(["-n", &self.name, "-br", "link", "show"])
+            .output()
+            .unwrap();
+        String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter_map(|line| line.split_whitespace().next())
+            .any(|link| link.split('@').next() == Some(name))
+    }
+
+    /// Kind of the root qdisc of the veth
+    fn root_qdisc(&self) -> String {
+        let output = Command::new("tc")
+            .args(["-n", &self.name, "qdisc", "show", "dev", &self.veth, "root"])
+            .output()
+            .unwrap();
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        stdout
+            .split_whitespace()
+            .nth(1)
+            .unwrap_or_default()
+            .to_string()
+    }
+
+    /// Run `tsnlib gc` in the namespace
+    fn gc(&self) -> String {
+        let output = Command::new("ip")
+            .args(["netns", "exec", &self.name, env!("CARGO_BIN_EXE_tsn"), "gc"])
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    }
+}
+
+impl Drop for Netns {
+    fn drop(&mut self) {
+        run("ip", &["netns", "del", &self.name]);
+        let _ = fs::remove_file(&self.config);
+        // Left behind if a test failed with holders still running
+        let prefix = format!("{}.", self.veth);
+        for entry in fs::read_dir(RUN_DIR).into_iter().flatten().flatten() {
+            if entry.file_name().to_string_lossy().starts_with(&prefix) {
+                let _ = fs::remove_file(entry.path());
+            }
+        }
+    }
+}
+
+struct Holder {
+    child: Child,
+    stdout: BufReader<ChildStdout>,
+}
+
+impl Holder {
+    /// Let the holder close its socket. Returns its report, "closed" on success
+    fn close(mut self) -> String {
+        drop(self.child.stdin.take());
+        let report = read_report(&mut self.stdout);
+        self.child.wait().unwrap();
+        report
+    }
+
+    /// Kill the holder without letting it close its socket
+    fn kill(mut self) {
+        self.child.kill().unwrap();
+        self.child.wait().unwrap();
+    }
+}
+
+fn run(cmd: &str, args: &[&str]) -> bool {
+    Command::new(cmd)
+        .args(args)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .map(|status| status.success())
+        .unwrap_or(false)
+}
+
+/// Next report of a holder. Other lines come from the test harness
+fn read_report(stdout: &mut impl BufRead) -> String {
+    for line in stdout.lines() {
+        let line = line.unwrap();
+        if let Some((_, report)) = line.split_once(REPORT) {
+            return report.to_string();
+        }
+    }
+    "error: exited without report".to_string()
+}
+
+/// Open, close and kill holders of one VLAN with the qdisc `kind` set up for it
+fn lifecycle(tag: &str, nic_config: &str, kind: &str) {
+    let Some(netns) = Netns::new(tag, nic_config) else {
+        return;
+    };
+    let vlan = netns.vlan(10);
+    let Some(first) = netns.first_holder(10) else {
+        return;
+    };
+    assert!(netns.has_link(&vlan));
+    assert_eq!(netns.root_qdisc(), kind);
+
+    // The last closer deletes the VLAN and the qdisc, killed holders do not count
+    let second = netns.holder(10).unwrap();
+    let third = netns.holder(10).unwrap();
+    first.kill();
+    assert_eq!(third.close(), "closed");
+    assert!(netns.has_link(&vlan));
+    assert_eq!(netns.root_qdisc(), kind);
+    assert_eq!(second.close(), "closed");
+    assert!(!netns.has_link(&vlan));
+    assert_ne!(netns.root_qdisc(), kind);
+
+    // A VLAN whose holders were all killed stays until the next opener replaces it
+    netns.holder(10).unwrap().kill();
+    assert!(netns.has_link(&vlan));
+    let holder = netns.holder(10).unwrap();
+    assert!(netns.has_link(&vlan));
+    assert_eq!(netns.root_qdisc(), kind);
+    assert_eq!(holder.close(), "closed");
+    assert!(!netns.has_link(&vlan));
+
+    // ...or until gc
+    netns.holder(10).unwrap().kill();
+    assert!(netns.gc().contains(&format!("Deleted {}", vlan)));
+    assert!(!netns.has_link(&vlan));
+    assert_ne!(netns.root_qdisc(), kind);
+}
+
+#[test]
+fn tas_lifecycle() {
+    lifecycle("tas", TAS_CONFIG, "taprio");
+}
+
+#[test]
+fn cbs_lifecycle() {
+    lifecycle("cbs", CBS_CONFIG, "mqprio");
+}
+
+#[test]
+fn vlans_are_counted_separately() {
+    let Some(netns) = Netns::new("plain", " {}\n") else {
+        return;
+    };
+    let Some(a) = netns.first_holder(10) else {
+        return;
+    };
+    let b = netns.holder(10).unwrap();
+    let c = netns.holder(20).unwrap();
+    assert!(netns.has_link(&netns.vlan(10)));
+    assert!(netns.has_link(&netns.vlan(20)));
+
+    assert_eq!(a.close(), "closed");
+    assert!(netns.has_link(&netns.vlan(10)));
+    c.kill();
+    assert!(netns.has_link(&netns.vlan(20)));
+    assert_eq!(b.close(), "closed");
+    assert!(!netns.has_link(&netns.vlan(10)));
+    assert!(netns.has_link(&netns.vlan(20)));
+
+    assert!(netns.gc().contains(&format!("Deleted {}", netns.vlan(20))));
+    assert!(!netns.has_link(&netns.vlan(20)));
+}
+
+#[test]
+fn failed_setup_is_undone() {
+    let Some(netns) = Netns::new("bad", BAD_TAS_CONFIG) else {
+        return;
+    };
+    let vlan = netns.vlan(10);
+    match netns.holder(10) {
+        Ok(_) => panic!("taprio accepted an entry of no time"),
+        Err(e) if e.contains("Unknown device type") || e.contains("qdisc kind is unknown") => {
+            eprintln!("skipped: {}", e);
+            return;
+        }
+        Err(e) => assert!(e.contains("taprio"), "{}", e),
+    }
+    // Neither left behind nor left to gc, which would not find them
+    assert!(!netns.has_link(&vlan));
+    assert_ne!(netns.root_qdisc(), "taprio");
+    assert!(!std::path::Path::new(RUN_DIR).join(&vlan).exists());
+}
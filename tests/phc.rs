@@ -0,0 +1,69 @@
+//! Tests of PHC discovery and clock offsets. Interfaces without a PHC (loopback,
+//! veth) are always available; the offset tests run on the first `/dev/ptpN`, if any.
+
+use std::path::Path;
+
+use nix::libc;
+use tsn::phc::{self, OffsetMethod, Phc};
+use tsn::time::Timespec;
+use tsn::TsnError;
+
+#[test]
+fn interface_without_phc() {
+    let info = phc::ts_info("lo").unwrap();
+    assert_eq!(info.phc_index, None);
+    assert!(matches!(
+        Phc::open_interface("lo"),
+        Err(TsnError::Unsupported(_))
+    ));
+}
+
+#[test]
+fn missing_interface() {
+    assert!(matches!(
+        phc::ts_info("tsn-missing0"),
+        Err(TsnError::InterfaceNotFound(_))
+    ));
+    assert!(matches!(
+        Phc::open("/dev/ptp-missing"),
+        Err(TsnError::Clock { .. })
+    ));
+}
+
+#[test]
+fn timespec_nanos() {
+    let ts = Timespec::from_nanos(-1);
+    assert_eq!((ts.tv_sec, ts.tv_nsec), (-1, 999_999_999));
+    assert_eq!(ts.as_nanos(), -1);
+    assert_eq!(Timespec::from_nanos(1_500_000_000).tv_nsec, 500_000_000);
+}
+
+#[test]
+fn offsets() {
+    if !Path::new("/dev/ptp0").exists() {
+        eprintln!("No /dev/ptp0, skipping");
+        return;
+    }
+    let phc = Phc::open("/dev/ptp0").unwrap();
+    let realtime = phc.offset(libc::CLOCK_REALTIME).unwrap();
+    let tai = phc.offset(libc::CLOCK_TAI).unwrap();
+    let monotonic = phc.offset(libc::CLOCK_MONOTONIC).unwrap();
+    assert_eq!(monotonic.method, OffsetMethod::Read);
+    assert!(realtime.delay >= 0);
+
+    // TAI is ahead of UTC by whole seconds, give the two measurements 1 ms of slack
+    let diff = realtime.offset - tai.offset;
+    let from_second = (diff + 500_000_000).rem_euclid(1_000_000_000) - 500_000_000;
+    assert!(from_second.abs() < 1_000_000, "{}", diff);
+
+    // PHC time converted to CLOCK_REALTIME lands near the real CLOCK_REALTIME
+    let now = Timespec::now(libc::CLOCK_REALTIME).as_nanos();
+    let converted = realtime.to_clock(phc.now().unwrap()).as_nanos();
+    assert!((converted - now).abs() < 10_000_000, "{}", converted - now);
+    assert_eq!(
+        realtime
+            .to_phc(realtime.to_clock(Timespec::from_nanos(7)))
+            .as_nanos(),
+        7
+    );
+}
@@ -0,0 +1,282 @@
+//! Tests of the PTP management client against a fake ptp4l, a thread answering on
+//! its own UNIX domain socket with canned data sets.
+
+use std::os::unix::net::UnixDatagram;
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+
+use tsn::gptp::{ClockIdentity, PortIdentity};
+use tsn::pmc::{self, PmcClient, PortState};
+use tsn::TsnError;
+
+const GM: ClockIdentity = ClockIdentity([0x00, 0x11, 0x22, 0xff, 0xfe, 0x33, 0x44, 0x55]);
+const LOCAL: ClockIdentity = ClockIdentity([0x02, 0x00, 0x00, 0xff, 0xfe, 0x00, 0x00, 0x01]);
+/// transportSpecific of the fake, as with gPTP.conf
+const TRANSPORT_SPECIFIC: u8 = 1;
+
+/// ns as TimeInterval, scaled by 2^16
+fn scaled(ns: f64) -> [u8; 8] {
+    ((ns * 65536.0) as i64).to_be_bytes()
+}
+
+fn current_data_set() -> Vec<u8> {
+    let mut data = 1u16.to_be_bytes().to_vec();
+    data.extend(scaled(-1234.5));
+    data.extend(scaled(2800.25));
+    data
+}
+
+fn parent_data_set() -> Vec<u8> {
+    let mut data = GM.0.to_vec();
+    data.extend(3u16.to_be_bytes());
+    data.extend([0, 0]);
+    data.extend(0xffffu16.to_be_bytes());
+    data.extend(0x7fffffffi32.to_be_bytes());
+    data.extend([100, 248, 0xfe]);
+    data.extend(0x436au16.to_be_bytes());
+    data.push(248);
+    data.extend(GM.0);
+    data
+}
+
+fn time_status() -> Vec<u8> {
+    let mut data = (-42i64).to_be_bytes().to_vec();
+    data.extend(1_700_000_000_000_000_000i64.to_be_bytes());
+    // 2^30 scaled by 2^-41, about 490 ppm fast
+    data.extend((1i32 << 30).to_be_bytes());
+    data.extend(0i32.to_be_bytes());
+    data.extend(0u16.to_be_bytes());
+    data.extend([0; 12]);
+    data.extend(1i32.to_be_bytes());
+    data.extend(GM.0);
+    data
+}
+
+fn port_data_set(port: u16, state: u8) -> Vec<u8> {
+    let mut data = LOCAL.0.to_vec();
+    data.extend(port.to_be_bytes());
+    data.extend([state, 0]);
+    data.extend(scaled(750.0));
+    data.extend([0, 3, (-3i8) as u8, 2, 0, 2]);
+    data
+}
+
+/// Management response to `request` carrying one TLV
+fn response(request: &[u8], port: u16, tlv_type: u16, value: &[u8]) -> Vec<u8> {
+    let mut buf = request[..48].to_vec();
+    buf[20..28].copy_from_slice(&LOCAL.0);
+    buf[28..30].copy_from_slice(&port.to_be_bytes());
+    buf[46] = 2;
+    buf.extend(tlv_type.to_be_bytes());
+    buf.extend((value.len() as u16).to_be_bytes());
+    buf.extend(value);
+    let len = buf.len() as u16;
+    buf[2..4].copy_from_slice(&len.to_be_bytes());
+    buf
+}
+
+/// MANAGEMENT_ERROR_STATUS NOT_SUPPORTED of `port` to the GET of `id`, with displayData
+fn refusal(request: &[u8], port: u16, id: u16) -> Vec<u8> {
+    let mut value = vec![0x00, 0x06];
+    value.extend(id.to_be_bytes());
+    value.extend([0, 0, 0, 0, 2, b'n', b'o']);
+    response(request, port, 2, &value)
+}
+
+/// Serve `requests` GETs like ptp4l with two ports, the second following the
+/// grandmaster. GETs of `refused` fail with NOT_SUPPORTED
+fn fake_ptp4l(
+    name: &str,
+    requests: usize,
+    refused: Option<u16>,
+) -> (PathBuf, thread::JoinHandle<Vec<Vec<u8>>>) {
+    let path = std::env::temp_dir().join(format!("tsn-ptp4l-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let sock = UnixDatagram::bind(&path).unwrap();
+    let server = path.clone();
+    let handle = thread::spawn(move || {
+        let mut seen = Vec::new();
+        let mut buf = [0u8; 1500];
+        for _ in 0..requests {
+            let (len, client) = sock.recv_from(&mut buf).unwrap();
+            let request = buf[..len].to_vec();
+            let client = client.as_pathname().unwrap().to_path_buf();
+            let id = u16::from_be_bytes([request[52], request[53]]);
+            let answer = |port: u16, tlv_type: u16, data: &[u8]| {
+                let mut value = id.to_be_bytes().to_vec();
+                value.extend(data);
+                let reply = response(&request, port, tlv_type, &value);
+                sock.send_to(&reply, &client).unwrap();
+            };
+            // ptp4l ignores requests of another transportSpecific
+            if request[0] >> 4 != TRANSPORT_SPECIFIC {
+                seen.push(request);
+                continue;
+            }
+            match id {
+                _ if Some(id) == refused => {
+                    sock.send_to(&refusal(&request, 0, id), &client).unwrap();
+                }
+                pmc::CURRENT_DATA_SET => answer(0, 1, &current_data_set()),
+                pmc::PARENT_DATA_SET => answer(0, 1, &parent_data_set()),
+                pmc::TIME_STATUS_NP => answer(0, 1, &time_status()),
+                pmc::PORT_DATA_SET => {
+                    answer(1, 1, &port_data_set(1, 6));
+                    answer(2, 1, &port_data_set(2, 9));
+                }
+                _ => {}
+            }
+            seen.push(request);
+        }
+        let _ = std::fs::remove_file(&path);
+        seen
+    });
+    (server, handle)
+}
+
+fn client(server: &PathBuf) -> PmcClient {
+    let mut pmc = PmcClient::connect_to(server).unwrap();
+    pmc.transport_specific = TRANSPORT_SPECIFIC;
+    pmc
+}
+
+#[test]
+fn data_sets() {
+    let (server, fake) = fake_ptp4l("data-sets", 4, None);
+    let mut pmc = client(&server);
+
+    let current = pmc.current_data_set().unwrap();
+    assert_eq!(current.steps_removed, 1);
+    assert_eq!(current.offset_from_master, -1234.5);
+    assert_eq!(current.mean_path_delay, 2800.25);
+
+    let parent = pmc.parent_data_set().unwrap();
+    assert_eq!(parent.parent, PortIdentity { clock: GM, port: 3 });
+    assert_eq!(parent.grandmaster, GM);
+    assert_eq!(parent.grandmaster_priority1, 100);
+    assert_eq!(parent.grandmaster_clock_quality.class, 248);
+    assert_eq!(
+        parent.grandmaster_clock_quality.offset_scaled_log_variance,
+        0x436a
+    );
+
+    let time = pmc.time_status().unwrap();
+    assert_eq!(time.master_offset, -42);
+    assert_eq!(time.ingress_time, 1_700_000_000_000_000_000);
+    assert!((time.cumulative_rate_ratio - (1.0 + 2f64.powi(-11))).abs() < 1e-12);
+    assert!(time.gm_present);
+    assert_eq!(time.grandmaster, GM);
+
+    let ports = pmc.port_data_sets().unwrap();
+    assert_eq!(ports.len(), 2);
+    assert_eq!(
+        ports[0].port,
+        PortIdentity {
+            clock: LOCAL,
+            port: 1
+        }
+    );
+    assert_eq!(ports[0].state, PortState::Master);
+    assert_eq!(ports[1].state, PortState::Slave);
+    assert_eq!(ports[1].peer_mean_path_delay, 750.0);
+    assert_eq!(ports[1].log_sync_interval, -3);
+    assert_eq!(ports[1].delay_mechanism, 2);
+    assert_eq!(ports[1].version, 2);
+
+    let requests = fake.join().unwrap();
+    for (request, id) in requests.iter().zip([
+        pmc::CURRENT_DATA_SET,
+        pmc::PARENT_DATA_SET,
+        pmc::TIME_STATUS_NP,
+        pmc::PORT_DATA_SET,
+    ]) {
+        assert_eq!(request.len(), 54);
+        assert_eq!(request[0], TRANSPORT_SPECIFIC << 4 | 0xd);
+        assert_eq!(u16::from_be_bytes([request[2], request[3]]), 54);
+        // GET of one MANAGEMENT TLV to every port
+        assert_eq!(&request[34..44], &[0xff; 10]);
+        assert_eq!(request[46], 0);
+        assert_eq!(&request[48..52], &[0, 1, 0, 2]);
+        assert_eq!(u16::from_be_bytes([request[52], request[53]]), id);
+    }
+    assert_ne!(requests[0][30..32], requests[1][30..32]);
+}
+
+#[test]
+fn sync_status() {
+    let (server, fake) = fake_ptp4l("sync-status", 3, None);
+    let status = client(&server).sync_status().unwrap();
+    fake.join().unwrap();
+
+    // The slave port counts, not the first one
+    assert_eq!(status.state, PortState::Slave);
+    assert_eq!(status.offset_from_master, -42);
+    assert_eq!(status.mean_path_delay, 2800.25);
+    assert_eq!(status.grandmaster, GM);
+    assert!(status.is_synchronized());
+    assert_eq!(status.state.to_string(), "SLAVE");
+}
+
+#[test]
+fn errors() {
+    let (server, fake) = fake_ptp4l("errors", 2, Some(pmc::TIME_STATUS_NP));
+
+    // ptp4l of another transportSpecific stays silent
+    let mut pmc = client(&server);
+    pmc.transport_specific = 0;
+    pmc.set_timeout(Duration::from_millis(100)).unwrap();
+    let e = pmc.current_data_set().unwrap_err();
+    assert_eq!(e.raw_os_error(), Some(libc::EAGAIN), "{}", e);
+
+    match client(&server).time_status() {
+        Err(TsnError::PtpManagement { id, error, message }) => {
+            assert_eq!(id, pmc::TIME_STATUS_NP);
+            assert_eq!(error, 0x0006);
+            assert_eq!(message, "no");
+        }
+        other => panic!("{:?}", other),
+    }
+    fake.join().unwrap();
+
+    // No ptp4l running
+    let mut pmc = PmcClient::connect_to(std::env::temp_dir().join("tsn-no-ptp4l")).unwrap();
+    let e = pmc.current_data_set().unwrap_err();
+    assert_eq!(e.raw_os_error(), Some(libc::ENOENT), "{}", e);
+}
+
+#[test]
+fn timeout_after_refusal() {
+    let path = std::env::temp_dir().join(format!("tsn-ptp4l-refusal-{}", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let sock = UnixDatagram::bind(&path).unwrap();
+    let fake = thread::spawn(move || {
+        let mut buf = [0u8; 1500];
+        // The second port refuses what the first answered
+        let (len, client) = sock.recv_from(&mut buf).unwrap();
+        let client = client.as_pathname().unwrap().to_path_buf();
+        let mut value = pmc::PORT_DATA_SET.to_be_bytes().to_vec();
+        value.extend(port_data_set(1, 9));
+        sock.send_to(&response(&buf[..len], 1, 1, &value), &client)
+            .unwrap();
+        sock.send_to(&refusal(&buf[..len], 2, pmc::PORT_DATA_SET), &client)
+            .unwrap();
+
+        // Slower than the timeout of later ports
+        let (len, _) = sock.recv_from(&mut buf).unwrap();
+        thread::sleep(Duration::from_millis(100));
+        let mut value = pmc::CURRENT_DATA_SET.to_be_bytes().to_vec();
+        value.extend(current_data_set());
+        sock.send_to(&response(&buf[..len], 0, 1, &value), &client)
+            .unwrap();
+    });
+
+    let mut pmc = client(&path);
+    assert!(matches!(
+        pmc.port_data_sets(),
+        Err(TsnError::PtpManagement { .. })
+    ));
+    assert_eq!(pmc.current_data_set().unwrap().steps_removed, 1);
+    fake.join().unwrap();
+    let _ = std::fs::remove_file(&path);
+}
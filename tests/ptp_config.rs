@@ -0,0 +1,141 @@
+//! Tests of the `ptp` section of `tests/data/ptp.yaml` and the linuxptp configs
+//! rendered from it.
+
+use std::collections::{HashMap, HashSet};
+
+use tsn::config::{read_config, Config};
+use tsn::ptp::{
+    normalise_ptp, phc2sys_config, ptp4l_config, DelayMechanism, PtpConfig, PtpProfile, Transport,
+};
+use tsn::TsnError;
+
+fn configs() -> HashMap<String, Config> {
+    read_config(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data/ptp.yaml")).unwrap()
+}
+
+/// Options of a linuxptp config, whatever their section
+fn options(config: &str) -> HashSet<(String, String)> {
+    config
+        .lines()
+        .filter(|line| !line.starts_with('#') && !line.starts_with('['))
+        .filter_map(|line| {
+            let mut words = line.split_whitespace();
+            Some((words.next()?.to_string(), words.next()?.to_string()))
+        })
+        .collect()
+}
+
+fn normalise(yaml: &str) -> Result<PtpConfig, TsnError> {
+    normalise_ptp(&serde_yaml::from_str(yaml).unwrap())
+}
+
+#[test]
+fn sections() {
+    let configs = configs();
+    assert_eq!(configs["tsnptp0"].ptp, Some(PtpConfig::default()));
+    assert_eq!(
+        configs["tsnptp1"].ptp,
+        Some(PtpConfig {
+            log_sync_interval: -4,
+            log_delay_req_interval: 1,
+            ..PtpConfig::new(PtpProfile::Gptp)
+        })
+    );
+    assert_eq!(
+        configs["tsnptp2"].ptp,
+        Some(PtpConfig {
+            priority1: 100,
+            delay_mechanism: DelayMechanism::Auto,
+            transport: Transport::UDPv6,
+            ..PtpConfig::new(PtpProfile::Default)
+        })
+    );
+    assert_eq!(configs["tsnptp3"].ptp, None);
+}
+
+#[test]
+fn gptp_defaults_match_dist() {
+    let ptp = PtpConfig::default();
+    let rendered = ptp4l_config(&[("eth1", &ptp)]).unwrap();
+    let dist =
+        std::fs::read_to_string(concat!(env!("CARGO_MANIFEST_DIR"), "/dist/gPTP.conf")).unwrap();
+    let missing: Vec<_> = options(&dist)
+        .difference(&options(&rendered))
+        .cloned()
+        .collect();
+    assert!(missing.is_empty(), "{:?} missing in\n{}", missing, rendered);
+}
+
+#[test]
+fn ptp4l() {
+    let configs = configs();
+    let nics = [
+        ("tsnptp0", configs["tsnptp0"].ptp.as_ref().unwrap()),
+        ("tsnptp1", configs["tsnptp1"].ptp.as_ref().unwrap()),
+    ];
+    let rendered = ptp4l_config(&nics).unwrap();
+    assert!(rendered.starts_with("# ptp4l -f <this file>, gPTP profile on tsnptp0 tsnptp1\n"));
+    assert!(rendered.ends_with(
+        "[tsnptp1]\n\
+         logAnnounceInterval     0\n\
+         logSyncInterval         -4\n\
+         logMinPdelayReqInterval 1\n\
+         delay_mechanism         P2P\n\
+         network_transport       L2\n"
+    ));
+
+    let default = configs["tsnptp2"].ptp.as_ref().unwrap();
+    assert_eq!(
+        ptp4l_config(&[("tsnptp2", default)]).unwrap(),
+        "# ptp4l -f <this file>, default profile on tsnptp2\n\
+         [global]\n\
+         priority1               100\n\
+         priority2               128\n\
+         [tsnptp2]\n\
+         logAnnounceInterval     1\n\
+         logSyncInterval         0\n\
+         logMinDelayReqInterval  0\n\
+         delay_mechanism         Auto\n\
+         network_transport       UDPv6\n"
+    );
+
+    // One ptp4l cannot serve two profiles
+    let e = ptp4l_config(&[nics[0], ("tsnptp2", default)]).unwrap_err();
+    assert!(matches!(e, TsnError::ConfigInvalid(_)), "{}", e);
+    assert!(ptp4l_config(&[]).is_err());
+}
+
+#[test]
+fn phc2sys() {
+    let configs = configs();
+    let gptp = configs["tsnptp0"].ptp.as_ref().unwrap();
+    let rendered = phc2sys_config(&[("tsnptp0", gptp)]).unwrap();
+    assert!(options(&rendered).contains(&("transportSpecific".into(), "0x1".into())));
+    assert!(options(&rendered).contains(&("uds_address".into(), tsn::pmc::PTP4L_SOCKET.into())));
+
+    let default = configs["tsnptp2"].ptp.as_ref().unwrap();
+    let rendered = phc2sys_config(&[("tsnptp2", default)]).unwrap();
+    assert!(options(&rendered).contains(&("transportSpecific".into(), "0x0".into())));
+}
+
+#[test]
+fn invalid() {
+    for yaml in [
+        "transport: UDPv4",
+        "delay_mechanism: E2E",
+        "profile: automotive",
+        "priority1: 256",
+        "log_sync_interval: -129",
+        "log_sync_interval: fast",
+        "logSyncInterval: -3",
+        "[ P2P ]",
+    ] {
+        match normalise(yaml) {
+            Err(TsnError::ConfigInvalid(_)) => {}
+            other => panic!("{}: {:?}", yaml, other),
+        }
+    }
+    // The default profile runs anywhere
+    let ptp = normalise("{profile: default, transport: L2, delay_mechanism: P2P}").unwrap();
+    assert_eq!(ptp.delay_mechanism, DelayMechanism::P2P);
+}
@@ -0,0 +1,79 @@
+//! Tests of the `PACKET_MMAP` rings on a veth pair. They need root and pass after
+//! printing why they were skipped without it.
+
+mod common;
+
+use std::time::Duration;
+
+use tsn::ring::RingConfig;
+use tsn::time::Timespec;
+use tsn::{Backend, TimestampSource, TsnSocket};
+
+use common::ETH_P_TEST;
+
+fn open_ring(ifname: &str, rx_blocks: u32, tx_blocks: u32) -> TsnSocket {
+    let config = RingConfig {
+        block_size: 1 << 16,
+        rx_blocks,
+        tx_blocks,
+        ..Default::default()
+    };
+    TsnSocket::builder(ifname)
+        .protocol(ETH_P_TEST)
+        .backend(Backend::Ring(config))
+        .open()
+        .unwrap()
+}
+
+#[test]
+fn send_and_recv() {
+    let Some((a, b)) = common::veth("ring", 1) else {
+        return;
+    };
+    let mut tx = open_ring(&a, 0, 1);
+    let mut rx = open_ring(&b, 4, 0);
+    let src = tx.mac_address().unwrap();
+
+    let before = Timespec::now(libc::CLOCK_REALTIME).as_nanos();
+    for id in 0..3u8 {
+        let mut slot = tx.tx_slot().unwrap().unwrap();
+        let buf = slot.buf();
+        buf[..6].fill(0xff);
+        buf[6..12].copy_from_slice(&src);
+        buf[12..14].copy_from_slice(&ETH_P_TEST.to_be_bytes());
+        buf[14..60].fill(id);
+        slot.commit(60).unwrap();
+    }
+    assert_eq!(tx.tx_flush().unwrap(), 3);
+    assert_eq!(tx.tx_flush().unwrap(), 0);
+
+    // A block is handed over once its timeout passed, which may split the frames
+    let mut frames = Vec::new();
+    while frames.len() < 3 {
+        let block = rx.next_rx_block(Some(Duration::from_secs(1))).unwrap();
+        for frame in &block {
+            assert_eq!(frame.len, 60);
+            assert_eq!(frame.timestamp.source, TimestampSource::Sw);
+            frames.push((frame.data.to_vec(), frame.timestamp.time.as_nanos()));
+        }
+    }
+    let after = Timespec::now(libc::CLOCK_REALTIME).as_nanos();
+
+    for (id, (data, time)) in frames.iter().enumerate() {
+        assert_eq!(&data[6..12], &src);
+        assert_eq!(&data[12..14], &ETH_P_TEST.to_be_bytes());
+        assert_eq!(data[14..], [id as u8; 46]);
+        assert!(
+            (before..=after).contains(time),
+            "{} {} {}",
+            before,
+            time,
+            after
+        );
+    }
+    let e = rx
+        .next_rx_block(Some(Duration::from_millis(50)))
+        .err()
+        .unwrap();
+    assert_eq!(e.raw_os_error(), Some(libc::EAGAIN), "{}", e);
+}
@@ -0,0 +1,84 @@
+//! Tests of reading and sleeping on the system clocks. PHCs are tested on the first
+//! `/dev/ptpN`, if any.
+
+use std::fs::File;
+use std::os::fd::AsRawFd;
+use std::path::Path;
+
+use nix::libc;
+use tsn::phc::Phc;
+use tsn::time::{self, Clock, Timespec};
+use tsn::TsnError;
+
+const NSEC_PER_SEC: i64 = 1_000_000_000;
+
+/// Sleep on `clock` until 20 ms from now, returning how late it woke
+fn lateness(clock: Clock) -> i64 {
+    let deadline = clock.now().unwrap().as_nanos() + 20_000_000;
+    time::sleep_until(clock, Timespec::from_nanos(deadline)).unwrap();
+    clock.now().unwrap().as_nanos() - deadline
+}
+
+#[test]
+fn clockids() {
+    for clock in [Clock::Realtime, Clock::Monotonic, Clock::Tai, Clock::Phc(5)] {
+        assert_eq!(Clock::from_clockid(clock.clockid()), Some(clock));
+    }
+    assert_eq!(Clock::from_clockid(libc::CLOCK_TAI), Some(Clock::Tai));
+    assert_eq!(Clock::from_clockid(libc::CLOCK_BOOTTIME), None);
+    // Has the low bits of a PHC, but is not negative
+    assert_eq!(Clock::from_clockid(libc::CLOCK_THREAD_CPUTIME_ID), None);
+}
+
+#[test]
+fn tai() {
+    let offset = time::tai_offset().unwrap();
+    assert_eq!(offset % NSEC_PER_SEC, 0);
+    let realtime = Clock::Realtime.now().unwrap().as_nanos();
+    let tai = Clock::Tai.now().unwrap().as_nanos();
+    assert!(
+        (tai - realtime - offset).abs() < 10_000_000,
+        "{}",
+        tai - realtime
+    );
+
+    // Now is before any leap second the kernel has pending
+    let utc = Timespec::from_nanos(realtime);
+    let converted = time::utc_to_tai(utc).unwrap();
+    assert_eq!(converted.as_nanos(), realtime + offset);
+    assert_eq!(time::tai_to_utc(converted).unwrap(), utc);
+}
+
+#[test]
+fn sleep() {
+    for clock in [Clock::Realtime, Clock::Monotonic, Clock::Tai] {
+        let late = lateness(clock);
+        assert!((0..50_000_000).contains(&late), "{:?} {}", clock, late);
+    }
+    // A deadline in the past returns at once
+    let past = Timespec::from_nanos(NSEC_PER_SEC);
+    time::sleep_until(Clock::Tai, past).unwrap();
+}
+
+#[test]
+fn not_a_clock() {
+    // Any fd but a PHC
+    let file = File::open("/dev/null").unwrap();
+    let clock = Clock::Phc(file.as_raw_fd());
+    assert!(matches!(clock.now(), Err(TsnError::Clock { .. })));
+    let deadline = Timespec::from_nanos(NSEC_PER_SEC);
+    let e = time::sleep_until(clock, deadline).unwrap_err();
+    assert_eq!(e.raw_os_error(), Some(libc::EINVAL), "{}", e);
+}
+
+#[test]
+fn phc() {
+    if !Path::new("/dev/ptp0").exists() {
+        eprintln!("No /dev/ptp0, skipping");
+        return;
+    }
+    let phc = Phc::open("/dev/ptp0").unwrap();
+    assert_eq!(phc.clock().clockid(), phc.clockid());
+    let late = lateness(phc.clock());
+    assert!((0..50_000_000).contains(&late), "{}", late);
+}
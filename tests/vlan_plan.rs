@@ -0,0 +1,158 @@
+//! Golden tests of the `ip`/`tc` commands generated for `tests/data/golden.yaml`.
+//!
+//! The interfaces do not exist, so CBS falls back to a 1 Gbps link speed and their
+//! capabilities are unknown: modes left to `auto` offload.
+
+use std::collections::HashMap;
+
+use tsn::config::{read_config, Config};
+use tsn::vlan::{create_vlan_with, delete_vlan_with, RecordingExecutor};
+use tsn::TsnError;
+
+const EGRESS_QOS_MAP: &str =
+    "0:0 1:1 2:2 3:3 4:4 5:5 6:6 7:7 8:0 9:0 10:0 11:0 12:0 13:0 14:0 15:0";
+
+fn configs() -> HashMap<String, Config> {
+    read_config(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/data/golden.yaml"
+    ))
+    .unwrap()
+}
+
+fn plan(config: &Config, ifname: &str, vlan_id: u16) -> Vec<String> {
+    let mut recorder = RecordingExecutor::new();
+    create_vlan_with(&mut recorder, config, ifname, vlan_id).unwrap();
+    recorder.commands().to_vec()
+}
+
+#[test]
+fn tas_with_etf() {
+    let configs = configs();
+    assert_eq!(
+        plan(&configs["tsngold0"], "tsngold0", 10),
+        [
+            format!(
+                "ip link add link tsngold0 name tsngold0.10 type vlan id 10 egress-qos-map {}",
+                EGRESS_QOS_MAP
+            ),
+            "ip link set up tsngold0.10".to_string(),
+            "tc qdisc replace dev tsngold0 parent root handle 100 taprio num_tc 4 \
+             map 3 3 1 2 3 0 3 3 3 3 3 3 3 3 3 3 queues 1@0 1@1 1@2 1@3 base-time 0 \
+             sched-entry S 0x1 300000 sched-entry S 0x6 300000 sched-entry S 0x8 400000 \
+             flags 0x2"
+                .to_string(),
+            "tc qdisc replace dev tsngold0 parent 100:1 etf clockid CLOCK_TAI delta 200000 offload"
+                .to_string(),
+        ]
+    );
+}
+
+#[test]
+fn tas_with_txtime_assist() {
+    let configs = configs();
+    assert_eq!(
+        plan(&configs["tsngold2"], "tsngold2", 30)[2..],
+        [
+            "tc qdisc replace dev tsngold2 parent root handle 100 taprio num_tc 2 \
+             map 1 1 1 0 1 1 1 1 1 1 1 1 1 1 1 1 queues 1@0 1@1 base-time 0 \
+             sched-entry S 0x1 500000 sched-entry S 0x2 500000 \
+             clockid CLOCK_TAI flags 0x1 txtime-delay 500000",
+            "tc qdisc replace dev tsngold2 parent 100:1 etf clockid CLOCK_TAI delta 200000 \
+             skip_sock_check",
+        ]
+    );
+}
+
+#[test]
+fn etf_class_is_hex() {
+    let configs = configs();
+    let plan = plan(&configs["tsngold4"], "tsngold4", 50);
+    // tc 9 is class 10 of the taprio qdisc
+    assert_eq!(
+        plan.last().unwrap(),
+        "tc qdisc replace dev tsngold4 parent 100:a etf clockid CLOCK_TAI delta 200000 offload"
+    );
+}
+
+#[test]
+fn cbs() {
+    let configs = configs();
+    assert_eq!(
+        plan(&configs["tsngold1"], "tsngold1", 20),
+        [
+            format!(
+                "ip link add link tsngold1 name tsngold1.20 type vlan id 20 egress-qos-map {}",
+                EGRESS_QOS_MAP
+            ),
+            "ip link set up tsngold1.20".to_string(),
+            "tc qdisc add dev tsngold1 parent root handle 100 mqprio num_tc 3 \
+             map 2 2 1 0 2 2 2 2 2 2 2 2 2 2 2 2 queues 1@0 1@1 1@2 hw 0"
+                .to_string(),
+            "tc qdisc replace dev tsngold1 parent 100:1 handle 1111 cbs idleslope 70000 \
+             sendslope -930000 hicredit 287 locredit -3809 offload 1"
+                .to_string(),
+            "tc qdisc replace dev tsngold1 parent 100:2 handle 2222 cbs idleslope 30000 \
+             sendslope -970000 hicredit 256 locredit -3973 offload 1"
+                .to_string(),
+        ]
+    );
+}
+
+#[test]
+fn cbs_without_offload() {
+    let configs = configs();
+    assert_eq!(
+        plan(&configs["tsngold3"], "tsngold3", 40)[2..],
+        [
+            "tc qdisc add dev tsngold3 parent root handle 100 mqprio num_tc 3 \
+             map 2 2 1 0 2 2 2 2 2 2 2 2 2 2 2 2 queues 1@0 1@1 1@2 hw 0",
+            "tc qdisc replace dev tsngold3 parent 100:1 handle 1111 cbs idleslope 70000 \
+             sendslope -930000 hicredit 287 locredit -3809 offload 0",
+            "tc qdisc replace dev tsngold3 parent 100:2 handle 2222 cbs idleslope 30000 \
+             sendslope -970000 hicredit 256 locredit -3973 offload 0",
+        ]
+    );
+}
+
+#[test]
+fn plain_vlan() {
+    assert_eq!(
+        plan(&Config::new(), "a-very-long-ifname", 3),
+        [
+            format!(
+                "ip link add link a-very-long-ifname name a-very-lon.3 type vlan id 3 \
+                 egress-qos-map {}",
+                EGRESS_QOS_MAP
+            ),
+            "ip link set up a-very-lon.3".to_string(),
+        ]
+    );
+}
+
+#[test]
+fn tas_and_cbs_is_rejected() {
+    let configs = configs();
+    let config = Config {
+        tas: configs["tsngold0"].tas.clone(),
+        cbs: configs["tsngold1"].cbs.clone(),
+        ptp: None,
+    };
+    let mut recorder = RecordingExecutor::new();
+    let res = create_vlan_with(&mut recorder, &config, "tsngold0", 10);
+    assert!(matches!(res, Err(TsnError::Unsupported(_))));
+    assert!(recorder.commands().is_empty());
+}
+
+#[test]
+fn delete() {
+    let mut recorder = RecordingExecutor::new();
+    delete_vlan_with(&mut recorder, "tsngold0", 10).unwrap();
+    assert_eq!(
+        recorder.commands(),
+        [
+            "ip link del tsngold0.10",
+            "tc qdisc delete dev tsngold0 root"
+        ]
+    );
+}
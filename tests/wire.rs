@@ -0,0 +1,274 @@
+//! Tests of the virtual wire backend and of the tools running on it.
+//!
+//! Each test uses its own wire, named after the test and the pid, so tests can run in
+//! parallel and next to other runs.
+
+mod common;
+
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::Duration;
+
+use tsn::wire::WireConfig;
+use tsn::{Backend, PacketType, TimestampMode, TimestampSource, TsnError, TsnSocket};
+
+use common::{is_timeout, open_wire, ETH_P_TEST};
+
+fn wire_name(test: &str) -> String {
+    format!("{}-{}", test, std::process::id())
+}
+
+fn frame(ethertype: u16, payload: u8) -> Vec<u8> {
+    let mut frame = vec![0xff; 6];
+    frame.extend_from_slice(&[0x02, 0, 0, 0, 0, 1]);
+    frame.extend_from_slice(&ethertype.to_be_bytes());
+    frame.extend_from_slice(&[payload; 46]);
+    frame
+}
+
+#[test]
+fn send_recv() {
+    let name = wire_name("send-recv");
+    let a = open_wire(&name, None, WireConfig::default());
+    let b = open_wire(&name, None, WireConfig::default());
+    let c = open_wire(&name, None, WireConfig::default());
+
+    let sent = frame(ETH_P_TEST, 1);
+    assert_eq!(a.send(&sent).unwrap(), sent.len() as isize);
+    let mut buf = [0u8; 1514];
+    for sock in [&b, &c] {
+        let len = sock.recv(&mut buf).unwrap() as usize;
+        assert_eq!(&buf[..len], &sent[..]);
+    }
+    // Frames are not looped back to the sender
+    assert!(is_timeout(a.recv(&mut buf)));
+    assert_ne!(a.mac_address().unwrap(), b.mac_address().unwrap());
+}
+
+#[test]
+fn send_to_recv_from() {
+    let name = wire_name("send-to");
+    let open_vlan = || {
+        TsnSocket::builder(&name)
+            .vlan(10)
+            .protocol(ETH_P_TEST)
+            .backend(Backend::Virtual(WireConfig::default()))
+            .timeout(Duration::from_millis(200))
+            .open()
+            .unwrap()
+    };
+    let a = open_vlan();
+    let b = open_vlan();
+    let a_mac = a.mac_address().unwrap();
+    let b_mac = b.mac_address().unwrap();
+
+    assert_eq!(a.send_to(b_mac, ETH_P_TEST, &[1; 46]).unwrap(), 60);
+    a.send_to([0xff; 6], ETH_P_TEST, &[2; 46]).unwrap();
+    let mut buf = [0u8; 1514];
+    let (len, info) = b.recv_from(&mut buf).unwrap();
+    assert_eq!(len, 60);
+    assert_eq!(&buf[..6], &b_mac);
+    assert_eq!(&buf[14..len], &[1; 46]);
+    assert_eq!(info.src, a_mac);
+    assert_eq!(info.ethertype, ETH_P_TEST);
+    assert_eq!(info.pkttype, PacketType::Host);
+    assert_eq!(info.vlan.map(|vlan| (vlan.vid, vlan.pcp)), Some((10, None)));
+    let (_, info) = b.recv_from(&mut buf).unwrap();
+    assert_eq!(info.pkttype, PacketType::Broadcast);
+}
+
+#[test]
+fn protocol_filter() {
+    let name = wire_name("protocol");
+    let a = open_wire(&name, None, WireConfig::default());
+    let b = open_wire(&name, None, WireConfig::default());
+
+    a.send(&frame(0x88f7, 1)).unwrap();
+    a.send(&frame(ETH_P_TEST, 2)).unwrap();
+    let mut buf = [0u8; 1514];
+    b.recv(&mut buf).unwrap();
+    assert_eq!(buf[14], 2);
+}
+
+#[test]
+fn vlans_are_separate_wires() {
+    let name = wire_name("vlan");
+    let open_vlan = |vlanid| {
+        TsnSocket::builder(&name)
+            .vlan(vlanid)
+            .protocol(ETH_P_TEST)
+            .backend(Backend::Virtual(WireConfig::default()))
+            .timeout(Duration::from_millis(200))
+            .open()
+            .unwrap()
+    };
+    let a = open_vlan(10);
+    let b = open_vlan(20);
+    assert_eq!(a.vlanid, 10);
+
+    a.send(&frame(ETH_P_TEST, 1)).unwrap();
+    let mut buf = [0u8; 1514];
+    assert!(is_timeout(b.recv(&mut buf)));
+}
+
+#[test]
+fn delay_and_timestamps() {
+    let name = wire_name("delay");
+    let delay = Duration::from_millis(20);
+    let config = WireConfig {
+        delay,
+        ..Default::default()
+    };
+    let mut a = open_wire(&name, None, config);
+    let mut b = open_wire(&name, None, config);
+    // Software ones, as on a NIC without hardware timestamps
+    for sock in [&mut a, &mut b] {
+        assert!(matches!(
+            sock.enable_timestamps(None),
+            Err(TsnError::HwTimestampUnsupported(_))
+        ));
+    }
+
+    for payload in 0..3 {
+        assert_eq!(a.next_tx_id(), payload as u32);
+        a.send(&frame(ETH_P_TEST, payload)).unwrap();
+    }
+    let tx = a.drain_tx_timestamps().unwrap();
+    assert_eq!(tx.iter().map(|ts| ts.id).collect::<Vec<_>>(), [0, 1, 2]);
+    assert!(tx.iter().all(|ts| ts.source == TimestampSource::Sw));
+
+    let mut buf = [0u8; 1514];
+    for tx in tx {
+        let (_, rx) = b.recv_with_timestamp(&mut buf).unwrap();
+        assert_eq!(rx.source, TimestampSource::Sw);
+        let elapsed =
+            (rx.time.tv_sec - tx.time.tv_sec) * 1_000_000_000 + (rx.time.tv_nsec - tx.time.tv_nsec);
+        assert!(elapsed >= delay.as_nanos() as i64, "{} ns", elapsed);
+    }
+}
+
+#[test]
+fn loss() {
+    let name = wire_name("loss");
+    let lossy = open_wire(
+        &name,
+        None,
+        WireConfig {
+            loss_ppm: 1_000_000,
+            ..Default::default()
+        },
+    );
+    let lossless = open_wire(&name, None, WireConfig::default());
+
+    let mut buf = [0u8; 1514];
+    lossy.send(&frame(ETH_P_TEST, 1)).unwrap();
+    assert!(is_timeout(lossless.recv(&mut buf)));
+
+    // Loss is a setting of the sender
+    lossless.send(&frame(ETH_P_TEST, 2)).unwrap();
+    lossy.recv(&mut buf).unwrap();
+    assert_eq!(buf[14], 2);
+}
+
+#[test]
+fn batches() {
+    let name = wire_name("batch");
+    let a = open_wire(&name, None, WireConfig::default());
+    let b = open_wire(&name, None, WireConfig::default());
+
+    let frames: Vec<_> = (0..5).map(|payload| frame(ETH_P_TEST, payload)).collect();
+    assert_eq!(a.send_batch(&frames).unwrap(), 5);
+    let mut bufs = vec![[0u8; 1514]; 8];
+    let received = b.recv_batch(&mut bufs).unwrap();
+    assert_eq!(received.len(), 5);
+    for (i, (len, ts)) in received.into_iter().enumerate() {
+        assert_eq!(&bufs[i][..len], &frames[i][..]);
+        assert!(ts.is_none());
+    }
+}
+
+#[test]
+fn unsupported_calls() {
+    let name = wire_name("unsupported");
+    let mut sock = open_wire(&name, None, WireConfig::default());
+    assert!(matches!(
+        sock.send_at(&frame(ETH_P_TEST, 1), 0, libc::CLOCK_TAI),
+        Err(TsnError::Unsupported(_))
+    ));
+    let builder = TsnSocket::builder(&name).backend(Backend::Virtual(WireConfig::default()));
+    assert!(matches!(
+        builder.clone().timestamps(TimestampMode::Hardware).open(),
+        Err(TsnError::HwTimestampUnsupported(_))
+    ));
+    let sock = builder.timestamps(TimestampMode::Any).open().unwrap();
+    assert!(sock.tx_timestamp_enabled);
+}
+
+#[test]
+fn latency_tool() {
+    let name = wire_name("latency");
+    let wire = ["-i", &name, "--vlanid", "10", "--backend", "virtual"];
+    let mut server = Command::new(env!("CARGO_BIN_EXE_latency"))
+        .arg("server")
+        .args(wire)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    // The server has to be on the wire before the first ping
+    thread::sleep(Duration::from_millis(500));
+
+    let client = Command::new(env!("CARGO_BIN_EXE_latency"))
+        .arg("client")
+        .args(wire)
+        .args(["-t", "ff:ff:ff:ff:ff:ff", "-c", "5"])
+        .stderr(Stdio::null())
+        .output()
+        .unwrap();
+    server.kill().unwrap();
+    server.wait().unwrap();
+
+    assert!(client.status.success());
+    let stdout = String::from_utf8(client.stdout).unwrap();
+    let replies = stdout.lines().filter(|line| line.ends_with(" ns")).count();
+    assert_eq!(replies, 5, "{}", stdout);
+}
+
+#[test]
+fn throughput_tool() {
+    let name = wire_name("throughput");
+    let wire = ["-i", &name, "--vlanid", "10", "--backend", "virtual"];
+    let server = Command::new(env!("CARGO_BIN_EXE_throughput"))
+        .arg("server")
+        .args(wire)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    // The server has to be on the wire before the start request
+    thread::sleep(Duration::from_millis(500));
+
+    let client = Command::new(env!("CARGO_BIN_EXE_throughput"))
+        .arg("client")
+        .args(wire)
+        .args(["-t", "ff:ff:ff:ff:ff:ff", "-d", "1"])
+        .args(["-p", "100", "-b", "10000000"])
+        .stderr(Stdio::null())
+        .output()
+        .unwrap();
+    let pid = nix::unistd::Pid::from_raw(server.id() as i32);
+    nix::sys::signal::kill(pid, nix::sys::signal::Signal::SIGINT).unwrap();
+    let server = server.wait_with_output().unwrap();
+
+    assert!(client.status.success());
+    let stdout = String::from_utf8(client.stdout).unwrap();
+    assert!(stdout.contains("Requesting end"), "{}", stdout);
+    // "<packets> packets, <bytes> bytes <bitrate> bps" once the client ends the test
+    let stdout = String::from_utf8(server.stdout).unwrap();
+    let packets: usize = stdout
+        .lines()
+        .find_map(|line| line.strip_suffix(" bps")?.split(" packets,").next())
+        .and_then(|packets| packets.parse().ok())
+        .unwrap_or_else(|| panic!("{}", stdout));
+    assert!(packets > 0, "{}", stdout);
+}
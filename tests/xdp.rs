@@ -0,0 +1,143 @@
+//! Tests of the AF_XDP backend in generic mode, on a veth pair. They need root and
+//! pass after printing why they were skipped without it.
+
+#![cfg(feature = "xdp")]
+
+mod common;
+
+use std::time::Duration;
+
+use tsn::config::Config;
+use tsn::xdp::{XdpConfig, XdpMode};
+use tsn::{Backend, TsnError, TsnSocket};
+
+use common::ETH_P_TEST;
+const VLAN: u16 = 10;
+
+/// `None` after printing why, if the kernel cannot create the VLAN
+fn open_xdp(ifname: &str) -> Option<Result<TsnSocket, TsnError>> {
+    let res = TsnSocket::builder(ifname)
+        .vlan(VLAN)
+        .priority(3)
+        .protocol(ETH_P_TEST)
+        .config(Config::new())
+        .backend(Backend::Xdp(XdpConfig::new(XdpMode::Generic)))
+        .timeout(Duration::from_millis(500))
+        .open();
+    match res {
+        Err(e) if e.to_string().contains("Unknown device type") => {
+            eprintln!("skipped: {}", e);
+            None
+        }
+        res => Some(res),
+    }
+}
+
+/// Turn RX VLAN offload of `ifname` on or off, as `ethtool -K <ifname> rxvlan`
+fn set_rxvlan(ifname: &str, on: bool) {
+    const SIOCETHTOOL: libc::c_ulong = 0x8946;
+    const ETHTOOL_GFLAGS: u32 = 0x25;
+    const ETHTOOL_SFLAGS: u32 = 0x26;
+    const ETH_FLAG_RXVLAN: u32 = 1 << 8;
+
+    let sock = unsafe { libc::socket(libc::AF_INET, libc::SOCK_DGRAM, 0) };
+    assert!(sock >= 0);
+    // `struct ethtool_value`, the flags
+    let ethtool = |cmd: u32, data: u32| {
+        let mut value = [cmd, data];
+        let mut ifreq: libc::ifreq = unsafe { std::mem::zeroed() };
+        for (source, target) in ifname.bytes().zip(ifreq.ifr_name.iter_mut()) {
+            *target = source as libc::c_char;
+        }
+        ifreq.ifr_ifru.ifru_data = value.as_mut_ptr().cast();
+        assert_eq!(unsafe { libc::ioctl(sock, SIOCETHTOOL as _, &ifreq) }, 0);
+        value[1]
+    };
+    let flags = ethtool(ETHTOOL_GFLAGS, 0) & !ETH_FLAG_RXVLAN;
+    ethtool(ETHTOOL_SFLAGS, flags | if on { ETH_FLAG_RXVLAN } else { 0 });
+    unsafe { libc::close(sock) };
+}
+
+#[test]
+fn send_and_recv() {
+    let Some((a, b)) = common::veth("xdp", 1) else {
+        return;
+    };
+    set_rxvlan(&a, false);
+    let Some(xsk) = open_xdp(&a) else {
+        return;
+    };
+    let xsk = xsk.unwrap();
+    let peer = TsnSocket::builder(&b)
+        .protocol(ETH_P_TEST)
+        .timeout(Duration::from_millis(500))
+        .open()
+        .unwrap();
+    let (mac_a, mac_b) = (xsk.mac_address().unwrap(), peer.mac_address().unwrap());
+
+    // Tagged, as the VLAN interface of the peer would send it
+    let mut frame = mac_a.to_vec();
+    frame.extend_from_slice(&mac_b);
+    frame.extend_from_slice(&(libc::ETH_P_8021Q as u16).to_be_bytes());
+    frame.extend_from_slice(&(5 << 13 | VLAN).to_be_bytes());
+    frame.extend_from_slice(&ETH_P_TEST.to_be_bytes());
+    frame.extend_from_slice(&[1; 46]);
+    // Another VLAN and untagged frames go to the kernel stack
+    frame[15] += 1;
+    peer.send(&frame).unwrap();
+    frame[15] -= 1;
+    let untagged = [&frame[..12], &frame[16..]].concat();
+    peer.send(&untagged).unwrap();
+    peer.send(&frame).unwrap();
+
+    let mut buf = [0u8; 1514];
+    let len = xsk.recv(&mut buf).unwrap() as usize;
+    assert_eq!(&buf[..6], &mac_a);
+    assert_eq!(&buf[6..12], &mac_b);
+    assert_eq!(
+        &buf[12..len],
+        &[&ETH_P_TEST.to_be_bytes()[..], &[1; 46]].concat()
+    );
+    let e = xsk.recv(&mut buf).unwrap_err();
+    assert_eq!(e.raw_os_error(), Some(libc::EAGAIN), "{}", e);
+
+    // The tag with the PCP of the priority goes on in the socket. Only sockets of
+    // all protocols see it, as the peer has no VLAN interface
+    let rx = TsnSocket::builder(&b)
+        .timeout(Duration::from_millis(500))
+        .open()
+        .unwrap();
+    xsk.send_to(mac_b, ETH_P_TEST, &[2; 46]).unwrap();
+    let (len, info) = rx.recv_from(&mut buf).unwrap();
+    assert_eq!(info.src, mac_a);
+    assert_eq!(&buf[len - 46..len], &[2; 46]);
+    let vlan = info.vlan.unwrap();
+    assert_eq!((vlan.vid, vlan.pcp), (VLAN, Some(3)));
+}
+
+#[test]
+fn needs_steering() {
+    let Some((a, _)) = common::veth("xdpq", 2) else {
+        return;
+    };
+    // veth has no ntuple rules to steer the stream to the queue of the socket
+    match open_xdp(&a) {
+        None => {}
+        Some(Err(TsnError::Unsupported(msg))) => assert!(msg.contains("ethtool -N"), "{}", msg),
+        Some(other) => panic!("{:?}", other.map(|_| ())),
+    }
+}
+
+#[test]
+fn needs_tagged_frames() {
+    let Some((a, _)) = common::veth("xdpv", 1) else {
+        return;
+    };
+    // veth strips the tag of frames from a VLAN interface, XDP would not see it
+    set_rxvlan(&a, true);
+    match open_xdp(&a) {
+        None => {}
+        Some(Err(TsnError::Unsupported(msg))) => assert!(msg.contains("rxvlan off"), "{}", msg),
+        Some(other) => panic!("{:?}", other.map(|_| ())),
+    }
+}